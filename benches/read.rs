@@ -0,0 +1,70 @@
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs)]
+
+use std::{num::NonZeroUsize, thread};
+
+use bytes::BytesMut;
+use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+use woxel::vdb::{write_vdb, Compression, VdbReader, WriteOptions};
+
+/// Reads the bundled assets as stored and re-encoded with zip and blosc, on one thread and on
+/// every core
+fn read_assets(c: &mut Criterion) {
+    let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+
+    for name in ["cube", "icosahedron"] {
+        let grid = format!("ls_{name}");
+        let stored = std::fs::read(format!("assets/{name}.vdb")).unwrap();
+        let mut vdb = VdbReader::from_slice(&stored)
+            .unwrap()
+            .read_vdb345_grid::<f32>(&grid)
+            .unwrap();
+        vdb.grid_descriptor.name = grid.clone();
+
+        let mut files = vec![("stored", stored.clone())];
+        for (encoding, compression) in [
+            ("zip", Compression::ZIP | Compression::ACTIVE_MASK),
+            ("blosc", Compression::BLOSC | Compression::ACTIVE_MASK),
+        ] {
+            let options = WriteOptions {
+                compression,
+                ..Default::default()
+            };
+            let mut b = BytesMut::new();
+            write_vdb(&mut b, &[&vdb], &options).unwrap();
+            files.push((encoding, b.to_vec()));
+        }
+
+        let mut group = c.benchmark_group(format!("read_{name}"));
+        group.sample_size(10);
+        for (encoding, bytes) in &files {
+            group.throughput(Throughput::Bytes(bytes.len() as u64));
+            for threads in [1, cores] {
+                let id = BenchmarkId::new(*encoding, format!("{threads} threads"));
+                group.bench_with_input(id, bytes, |b, bytes| {
+                    b.iter(|| {
+                        let mut reader = VdbReader::from_slice(bytes).unwrap();
+                        reader.set_threads(threads);
+                        reader.read_vdb345_grid::<f32>(&grid).unwrap()
+                    })
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, read_assets);
+
+fn main() {
+    // Grids don't fit on the default stack of the main thread
+    thread::Builder::new()
+        .stack_size(80 * 1024 * 1024)
+        .spawn(|| {
+            benches();
+            Criterion::default().configure_from_args().final_summary();
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
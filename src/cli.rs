@@ -0,0 +1,410 @@
+//! The `info`, `convert`, `scene` and `view` subcommands, their arguments are parsed in
+//! `main.rs`
+use std::{
+    fs,
+    io::{self, BufReader, Read, Seek},
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use bytes::BytesMut;
+use itertools::Itertools;
+
+use crate::{
+    scene::{GridSource, SceneFile, SceneFileError},
+    vdb::{
+        compression_name, write_vdb, ErrorKind, GridDescriptor, Map, Mesh, MeshError, Metadata,
+        TreeShape, VdbReader, WriteError, WriteOptions, N3, N4, N5, VDB345,
+    },
+};
+
+/// Grids the reader can load, with either float or half float values and any tree shape
+const FLOAT_GRID_TYPE: &str = "Tree_float_";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("Could not access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Could not read {path}: {source}")]
+    Vdb { path: PathBuf, source: ErrorKind },
+    #[error("Could not parse {path}: {source}")]
+    Mesh { path: PathBuf, source: MeshError },
+    #[error("Could not encode vdb: {0}")]
+    Write(#[from] WriteError),
+    #[error(transparent)]
+    Scene(#[from] SceneFileError),
+    #[error("{path} has no float grid named {grid}")]
+    MissingGrid { path: PathBuf, grid: String },
+    #[error("{path} has no float grids")]
+    NoGrids { path: PathBuf },
+    #[error("{0}")]
+    Usage(String),
+}
+
+/// How `convert` reads its input and encodes its output
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertOptions {
+    /// Only convert this grid of a VDB input
+    pub grid: Option<String>,
+    pub write: WriteOptions,
+    /// Voxel size of mesh inputs, in mesh units
+    pub voxel_size: f64,
+    /// Size of raw dense inputs, which hold little endian f32s
+    pub dims: Option<[usize; 3]>,
+    /// Dense values equal to the background stay inactive
+    pub background: f32,
+}
+
+/// Prints the archive header and, for every grid or just `grid`, its descriptor, metadata,
+/// transform, node counts and memory use.
+///
+/// A `path` of `-` reads the archive from stdin, its grids are then listed in file order.
+/// `.ron` scene files are summarized instead, after checking that every grid they list exists.
+pub fn info(path: &Path, grid: Option<&str>) -> Result<(), CliError> {
+    if is_scene_file(path) {
+        return print_scene(path, &SceneFile::load(path)?);
+    }
+    if path == Path::new("-") {
+        let reader =
+            VdbReader::streaming(io::stdin().lock()).map_err(|source| vdb_error(path, source))?;
+        return print_info(path, reader, grid);
+    }
+
+    print_info(path, open_vdb(path)?, grid)
+}
+
+fn print_info<R: Read + Seek>(
+    path: &Path,
+    mut reader: VdbReader<R>,
+    grid: Option<&str>,
+) -> Result<(), CliError> {
+    let header = &reader.header;
+
+    println!("{}", path.display());
+    println!("  file version:    {}", header.file_version);
+    println!(
+        "  library version: {}.{}",
+        header.library_major, header.library_minor
+    );
+    println!("  uuid:            {}", header.uuid);
+    println!("  grids:           {}", header.grid_number);
+    print_metadata(&header.meta_data);
+
+    if reader.is_streaming() {
+        let mut found = false;
+        while let Some(descriptor) = reader
+            .next_grid_descriptor()
+            .map_err(|source| vdb_error(path, source))?
+        {
+            if grid.is_some_and(|grid| grid != descriptor.name) {
+                continue;
+            }
+            found = true;
+            print_grid(path, &mut reader, &descriptor)?;
+        }
+
+        return match grid {
+            Some(grid) if !found => Err(missing_grid(path, grid)),
+            _ => Ok(()),
+        };
+    }
+
+    let names = match grid {
+        Some(grid) => vec![grid.to_string()],
+        None => reader.grid_descriptors.keys().sorted().cloned().collect(),
+    };
+
+    for name in names {
+        let Some(descriptor) = reader.grid_descriptors.get(&name).cloned() else {
+            return Err(missing_grid(path, &name));
+        };
+        print_grid(path, &mut reader, &descriptor)?;
+    }
+
+    Ok(())
+}
+
+fn print_grid<R: Read + Seek>(
+    path: &Path,
+    reader: &mut VdbReader<R>,
+    descriptor: &GridDescriptor,
+) -> Result<(), CliError> {
+    println!();
+    println!("grid {}", descriptor.name);
+    println!("  type:        {}", descriptor.grid_type);
+    println!(
+        "  compression: {}",
+        compression_name(descriptor.compression)
+    );
+    if !descriptor.instance_parent.is_empty() {
+        println!("  instance of: {}", descriptor.instance_parent);
+    }
+    print_metadata(&descriptor.meta_data);
+
+    if !is_float_grid(descriptor) {
+        println!("  tree:        not a float grid, skipped");
+        return Ok(());
+    }
+
+    let vdb = reader
+        .read_vdb345_grid::<f32>(&descriptor.name)
+        .map_err(|source| vdb_error(path, source))?;
+    print_tree(&vdb);
+
+    Ok(())
+}
+
+fn print_metadata(meta_data: &Metadata) {
+    if meta_data.0.is_empty() {
+        return;
+    }
+
+    println!("  metadata:");
+    for (name, value) in meta_data.0.iter().sorted_by_key(|(name, _)| *name) {
+        println!("    {name}: {value:?}");
+    }
+}
+
+fn print_tree(vdb: &VDB345<f32>) {
+    match &vdb.transform {
+        Map::UniformScaleMap { voxel_size, .. } => {
+            println!("  transform:   UniformScaleMap, voxel size {voxel_size:?}")
+        }
+        Map::ScaleTranslateMap {
+            voxel_size,
+            translation,
+            ..
+        } => println!(
+            "  transform:   ScaleTranslateMap, voxel size {voxel_size:?}, translation {translation:?}"
+        ),
+    }
+
+    let counts = vdb.count_nodes();
+    println!(
+        "  nodes:       {} N5, {} N4, {} N3",
+        counts[0], counts[1], counts[2]
+    );
+    println!("  voxels:      {} active", vdb.active_voxels().count());
+    if let Some((min, max)) = vdb.active_bounds() {
+        println!("  bounds:      {min:?} to {max:?}");
+    }
+
+    let memory = counts[0] * size_of::<N5<f32>>()
+        + counts[1] * size_of::<N4<f32>>()
+        + counts[2] * size_of::<N3<f32>>();
+    let pool = (0..3)
+        .map(|level| counts[level] * TreeShape::VDB345.size(level) * size_of::<u32>())
+        .sum::<usize>();
+    println!(
+        "  memory:      {} in memory, {} in the GPU node pool",
+        mib(memory),
+        mib(pool)
+    );
+}
+
+fn mib(bytes: usize) -> String {
+    format!("{:.2} MiB", bytes as f64 / (1024. * 1024.))
+}
+
+/// Writes `input` to the VDB file `output`.
+///
+/// VDB inputs are re-encoded with new compression and value type settings, `.obj` meshes are
+/// voxelized and anything else is read as a raw dense volume of `options.dims`.
+pub fn convert(input: &Path, output: &Path, options: &ConvertOptions) -> Result<(), CliError> {
+    let extension = input.extension().and_then(|e| e.to_str());
+    let grids = match (extension, options.dims) {
+        (Some("vdb"), _) => read_float_grids(input, options.grid.as_deref())?,
+        (Some("obj"), _) => {
+            let source = fs::read_to_string(input).map_err(|source| io_error(input, source))?;
+            let mesh = Mesh::from_obj(&source).map_err(|source| CliError::Mesh {
+                path: input.to_path_buf(),
+                source,
+            })?;
+            vec![named(VDB345::from_mesh(&mesh, options.voxel_size), input)]
+        }
+        (_, Some(dims)) => {
+            let bytes = fs::read(input).map_err(|source| io_error(input, source))?;
+            let expected = dims.iter().product::<usize>() * size_of::<f32>();
+            if bytes.len() != expected {
+                return Err(CliError::Usage(format!(
+                    "{} holds {} bytes but {dims:?} f32 voxels need {expected}",
+                    input.display(),
+                    bytes.len()
+                )));
+            }
+
+            let values = bytes
+                .chunks_exact(size_of::<f32>())
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect_vec();
+            let vdb = VDB345::from_dense(dims, &values, options.background);
+            vec![named(vdb, input)]
+        }
+        _ => {
+            return Err(CliError::Usage(format!(
+                "{} is not a .vdb or .obj file, pass --dims to read it as a dense volume",
+                input.display()
+            )))
+        }
+    };
+
+    let mut b = BytesMut::new();
+    write_vdb(&mut b, &grids.iter().collect_vec(), &options.write)?;
+    fs::write(output, &b).map_err(|source| io_error(output, source))
+}
+
+/// Grids created from other formats are named after their file
+fn named(mut vdb: VDB345<f32>, path: &Path) -> VDB345<f32> {
+    vdb.grid_descriptor.name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "woxel".to_string());
+    vdb
+}
+
+fn read_float_grids(path: &Path, grid: Option<&str>) -> Result<Vec<VDB345<f32>>, CliError> {
+    let mut reader = open_vdb(path)?;
+    let names = match grid {
+        Some(grid) => vec![grid.to_string()],
+        None => float_grids(&reader),
+    };
+    if names.is_empty() {
+        return Err(CliError::NoGrids {
+            path: path.to_path_buf(),
+        });
+    }
+
+    names
+        .iter()
+        .map(|name| match reader.grid_descriptors.get(name) {
+            Some(d) if is_float_grid(d) => reader
+                .read_vdb345_grid::<f32>(name)
+                .map_err(|source| vdb_error(path, source)),
+            _ => Err(missing_grid(path, name)),
+        })
+        .collect()
+}
+
+/// Scene to show for `path`: a RON scene file, or `grid` of a VDB file, its first float grid
+/// by default
+pub fn view_file(path: &Path, grid: Option<&str>) -> Result<SceneFile, CliError> {
+    if is_scene_file(path) {
+        return Ok(SceneFile::load(path)?);
+    }
+
+    Ok(SceneFile::from_grids(vec![grid_source(path, grid)?]))
+}
+
+/// Writes a scene file to `output` with `grid`, or the first float grid, of every VDB file in
+/// `inputs`, one untransformed object each seen from the default camera
+pub fn scene(inputs: &[PathBuf], output: &Path, grid: Option<&str>) -> Result<(), CliError> {
+    if inputs.is_empty() {
+        return Err(CliError::Usage(
+            "A scene needs at least one VDB file".into(),
+        ));
+    }
+    let grids = inputs
+        .iter()
+        .map(|input| grid_source(input, grid))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(SceneFile::from_grids(grids).save(output)?)
+}
+
+fn print_scene(path: &Path, scene: &SceneFile) -> Result<(), CliError> {
+    println!("{}", path.display());
+    println!("  grids:   {}", scene.grids.len());
+    for source in &scene.grids {
+        let grid_path = Path::new(&source.path);
+        if !open_vdb(grid_path)?
+            .grid_descriptors
+            .contains_key(&source.grid)
+        {
+            return Err(missing_grid(grid_path, &source.grid));
+        }
+        match &source.color {
+            Some(color) => println!("    {} {}, coloured by {color}", source.path, source.grid),
+            None => println!("    {} {}", source.path, source.grid),
+        }
+    }
+    println!("  objects: {}", scene.scene_objects().len());
+    println!(
+        "  camera:  eye {:?}, target {:?}, fovy {}",
+        scene.camera.eye, scene.camera.target, scene.camera.fovy
+    );
+    println!("  lights:  {} besides the sun", scene.lights.len());
+    println!("  render:  {:?}", scene.render.mode);
+
+    Ok(())
+}
+
+fn is_scene_file(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "ron")
+}
+
+/// `grid` of the VDB file at `path`, or its first float grid
+fn grid_source(path: &Path, grid: Option<&str>) -> Result<GridSource, CliError> {
+    let reader = open_vdb(path)?;
+    let grid = match grid {
+        Some(grid) => grid.to_string(),
+        None => float_grids(&reader)
+            .into_iter()
+            .next()
+            .ok_or_else(|| CliError::NoGrids {
+                path: path.to_path_buf(),
+            })?,
+    };
+    if !reader.grid_descriptors.contains_key(&grid) {
+        return Err(missing_grid(path, &grid));
+    }
+
+    Ok(GridSource {
+        path: path.to_string_lossy().into_owned(),
+        grid,
+        color: None,
+    })
+}
+
+fn float_grids<R: Read + Seek>(reader: &VdbReader<R>) -> Vec<String> {
+    reader
+        .grid_descriptors
+        .values()
+        .filter(|d| is_float_grid(d))
+        .map(|d| d.name.clone())
+        .sorted()
+        .collect()
+}
+
+fn is_float_grid(descriptor: &GridDescriptor) -> bool {
+    descriptor.grid_type.starts_with(FLOAT_GRID_TYPE)
+        && TreeShape::from_grid_type(&descriptor.grid_type).is_some()
+}
+
+fn open_vdb(path: &Path) -> Result<VdbReader<BufReader<fs::File>>, CliError> {
+    let f = fs::File::open(path).map_err(|source| io_error(path, source))?;
+    VdbReader::new(BufReader::new(f)).map_err(|source| vdb_error(path, source))
+}
+
+fn io_error(path: &Path, source: std::io::Error) -> CliError {
+    CliError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn vdb_error(path: &Path, source: ErrorKind) -> CliError {
+    CliError::Vdb {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn missing_grid(path: &Path, grid: &str) -> CliError {
+    CliError::MissingGrid {
+        path: path.to_path_buf(),
+        grid: grid.to_string(),
+    }
+}
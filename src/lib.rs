@@ -6,6 +6,7 @@
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+pub mod cli;
 pub mod vdb;
 
 use winit::dpi::{PhysicalSize, Size};
@@ -19,11 +20,24 @@ mod scene;
 use crate::render::WgpuContext;
 use crate::runtime::Runtime;
 use crate::scene::Scene;
+pub use crate::scene::{GridSource, SceneFile, SceneFileError};
 
 const DEFAULT_SIZE: Size = Size::Physical(PhysicalSize::new(1600, 900));
+/// Scene opened when no file is given
+const DEFAULT_SCENE: &str = "assets/scene.ron";
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    init_logging();
+
+    match SceneFile::load(DEFAULT_SCENE) {
+        Ok(file) => view(file).await.unwrap_or_else(|e| log::error!("{e}")),
+        Err(e) => log::error!("Could not open {DEFAULT_SCENE}: {e}"),
+    }
+}
+
+/// Sets up `env_logger`, or the console logger on the web. Call it once.
+pub fn init_logging() {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -32,6 +46,12 @@ pub async fn run() {
             env_logger::init();
         }
     }
+}
+
+/// Opens a window showing `file`, fails before it is created if a grid can't be read
+pub async fn view(file: SceneFile) -> Result<(), SceneFileError> {
+    let grids = file.read_grids()?;
+    let colors = file.read_colors()?;
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
@@ -73,13 +93,15 @@ pub async fn run() {
             .expect("Couldn't append canvas to document body.");
     }
 
-    let mut context = WgpuContext::new(&window).await;
+    let mut context = WgpuContext::new(&window, grids, colors, file.grids.clone()).await;
 
     context.add_shader("canvas.vert", include_str!("./shaders/canvas.vert.wgsl"));
     context.add_shader("canvas.frag", include_str!("./shaders/canvas.frag.wgsl"));
     context.add_shader("raycast.comp", include_str!("./shaders/raycast.comp.wgsl"));
+    context.add_shader("tonemap.comp", include_str!("./shaders/tonemap.comp.wgsl"));
 
-    let scene = Scene::new(&context);
+    let mut scene = Scene::new(&context);
+    scene.apply_file(&file, &mut context);
 
     let mut runtime = Runtime::new(context, window, scene);
     event_loop
@@ -1,5 +1,151 @@
-use woxel::run;
+use std::{path::PathBuf, process::ExitCode};
 
-pub fn main() {
-    pollster::block_on(run());
+use clap::{Parser, Subcommand, ValueEnum};
+use woxel::{
+    cli::{self, CliError, ConvertOptions},
+    init_logging, run,
+    vdb::{Compression, TreeShape, WriteOptions},
+    view,
+};
+
+/// Sparse voxel renderer and VDB tool, opens the default scene without a command
+#[derive(Parser)]
+#[command(name = "woxel", version)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the header, grids, metadata, transform, node counts and memory use of a VDB file,
+    /// or check the grids of a RON scene file and summarize it
+    Info {
+        /// `-` reads the file from stdin
+        file: PathBuf,
+        /// Only describe this grid
+        #[arg(long)]
+        grid: Option<String>,
+    },
+    /// Re-encode a VDB file, or turn an OBJ mesh or a raw dense f32 volume into one
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        /// Only convert this grid of a VDB input
+        #[arg(long)]
+        grid: Option<String>,
+        #[arg(long, value_enum, default_value_t = Codec::Zip)]
+        compression: Codec,
+        /// Store inactive values too instead of only the active ones
+        #[arg(long)]
+        all_values: bool,
+        #[arg(long, value_enum, default_value_t = ValueType::Float)]
+        value_type: ValueType,
+        /// Log2 dimensions of the tree nodes, root children first, like `6_5_4_3`
+        #[arg(long, default_value_t = TreeShape::VDB345)]
+        tree: TreeShape,
+        /// Voxel size of mesh inputs, in mesh units
+        #[arg(long, default_value_t = 1.)]
+        voxel_size: f64,
+        /// Size of a dense input, stored x major with little endian f32 values
+        #[arg(long, num_args = 3, value_names = ["X", "Y", "Z"])]
+        dims: Option<Vec<usize>>,
+        /// Dense values equal to this stay inactive
+        #[arg(long, default_value_t = 0., allow_negative_numbers = true)]
+        background: f32,
+    },
+    /// Write a RON scene file with a grid of every VDB file, without opening the viewer
+    Scene {
+        output: PathBuf,
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Grid to take from every file, their first float grid by default
+        #[arg(long)]
+        grid: Option<String>,
+    },
+    /// Open a VDB or RON scene file in the viewer
+    View {
+        file: PathBuf,
+        /// Grid of a VDB file to show, its first float grid by default
+        #[arg(long)]
+        grid: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Codec {
+    None,
+    Zip,
+    Blosc,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ValueType {
+    Float,
+    Half,
+}
+
+pub fn main() -> ExitCode {
+    let Some(command) = Args::parse().command else {
+        pollster::block_on(run());
+        return ExitCode::SUCCESS;
+    };
+
+    init_logging();
+    match run_command(command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("woxel: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_command(command: Command) -> Result<(), CliError> {
+    match command {
+        Command::Info { file, grid } => cli::info(&file, grid.as_deref()),
+        Command::Convert {
+            input,
+            output,
+            grid,
+            compression,
+            all_values,
+            value_type,
+            tree,
+            voxel_size,
+            dims,
+            background,
+        } => {
+            let mut compression = match compression {
+                Codec::None => Compression::NONE,
+                Codec::Zip => Compression::ZIP,
+                Codec::Blosc => Compression::BLOSC,
+            };
+            if !all_values {
+                compression |= Compression::ACTIVE_MASK;
+            }
+
+            let options = ConvertOptions {
+                grid,
+                write: WriteOptions {
+                    compression,
+                    half_float: matches!(value_type, ValueType::Half),
+                    shape: tree,
+                },
+                voxel_size,
+                dims: dims.map(|d| [d[0], d[1], d[2]]),
+                background,
+            };
+            cli::convert(&input, &output, &options)
+        }
+        Command::Scene {
+            output,
+            inputs,
+            grid,
+        } => cli::scene(&inputs, &output, grid.as_deref()),
+        Command::View { file, grid } => {
+            let scene = cli::view_file(&file, grid.as_deref())?;
+            Ok(pollster::block_on(view(scene))?)
+        }
+    }
 }
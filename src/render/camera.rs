@@ -1,8 +1,51 @@
-use cgmath::{InnerSpace, SquareMatrix, Vector3};
-use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, SquareMatrix, Vector3};
+use serde::{Deserialize, Serialize};
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
 
-use crate::scene::State;
+use crate::scene::{Aabb, State};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    /// Parallel rays, what is in view does not shrink with the distance
+    Orthographic,
+}
+
+/// Axis aligned view directions, looking at the target from the named side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewAxis {
+    Front,
+    Side,
+    Top,
+}
+
+impl ViewAxis {
+    pub const ALL: [ViewAxis; 3] = [ViewAxis::Front, ViewAxis::Side, ViewAxis::Top];
+
+    /// World space view direction
+    pub fn direction(&self) -> Vector3<f32> {
+        match self {
+            ViewAxis::Front => -Vector3::unit_z(),
+            ViewAxis::Side => -Vector3::unit_x(),
+            ViewAxis::Top => -Vector3::unit_y(),
+        }
+    }
+}
+
+impl std::fmt::Display for ViewAxis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ViewAxis::Front => "Front",
+            ViewAxis::Side => "Side",
+            ViewAxis::Top => "Top",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -10,6 +53,9 @@ pub struct Camera {
     pub aspect: f32,
     /// y-axis fov in degrees
     pub fovy: f32,
+    pub projection: Projection,
+    /// World height of the view in orthographic projection
+    pub ortho_height: f32,
 }
 
 impl Camera {
@@ -25,9 +71,64 @@ impl Camera {
             aspect,
             // In degrees
             fovy: 45.0,
+            projection: Projection::Perspective,
+            ortho_height: 2.0,
         }
     }
 
+    /// World height of the view at `distance` from the eye
+    pub fn view_height(&self, distance: f32) -> f32 {
+        match self.projection {
+            Projection::Perspective => 2. * distance * (self.fovy.to_radians() * 0.5).tan(),
+            Projection::Orthographic => self.ortho_height,
+        }
+    }
+
+    /// Switch the projection, keeping the size of what is at the target
+    pub fn set_projection(&mut self, projection: Projection) {
+        if projection == Projection::Orthographic && self.projection != projection {
+            self.ortho_height = self.view_height((self.target - self.eye).magnitude());
+        }
+        self.projection = projection;
+    }
+
+    /// Look at the target along `dir`, from the same distance
+    pub fn look_along(&mut self, dir: Vector3<f32>) {
+        let distance = (self.target - self.eye).magnitude();
+        self.eye = self.target - dir.normalize() * distance;
+        self.fit_up();
+    }
+
+    /// Keep the world up as the up of the view, unless looking straight up or down where
+    /// the view is turned so -z, or z when looking up, points up on screen
+    pub fn fit_up(&mut self) {
+        let forward = (self.target - self.eye).normalize();
+        self.up = match forward.x.abs() < 1e-6 && forward.z.abs() < 1e-6 {
+            true => Vector3::unit_z() * forward.y.signum(),
+            false => Vector3::unit_y(),
+        };
+    }
+
+    /// Look at the centre of `bounds` along the current view direction, from just far enough
+    /// that the whole box is in view
+    pub fn frame(&mut self, bounds: &Aabb) {
+        let center = Point3::from_vec(bounds.center());
+        let radius = (bounds.max - bounds.min).magnitude() * 0.5;
+        // The narrower of the vertical and horizontal field of view decides
+        let half_fovy = self.fovy.to_radians() * 0.5;
+        let half_fovx = (half_fovy.tan() * self.aspect).atan();
+        let distance = (radius / half_fovy.min(half_fovx).sin()).max(f32::EPSILON);
+
+        let forward = self.target - self.eye;
+        let forward = match forward.magnitude2() > 0. {
+            true => forward.normalize(),
+            false => -Vector3::unit_z(),
+        };
+        self.target = center;
+        self.eye = center - forward * distance;
+        self.ortho_height = 2. * radius / self.aspect.min(1.);
+    }
+
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
 
@@ -56,35 +157,131 @@ impl Camera {
 
         (x * u + y * mv + wp).truncate().normalize()
     }
+
+    /// World space origin and direction of the ray through the window pixel `point`, like the
+    /// raycaster shoots it
+    pub fn ray(
+        &self,
+        point @ [x, y]: [f32; 2],
+        resolution: [f32; 2],
+    ) -> (Point3<f32>, Vector3<f32>) {
+        match self.projection {
+            Projection::Perspective => (self.eye, self.get_ray_dir(point, resolution)),
+            Projection::Orthographic => {
+                let view = self.build_view_projection_matrix();
+                let camera_to_world = view.invert().expect("Could not invert camera matrix");
+                let height = resolution[0] / self.aspect;
+                let pixel = self.ortho_height / height;
+                let origin = self.eye
+                    + camera_to_world.x.truncate() * (x - resolution[0] / 2.) * pixel
+                    - camera_to_world.y.truncate() * (y - height / 2.) * pixel;
+                (origin, (self.target - self.eye).normalize())
+            }
+        }
+    }
+}
+
+/// How the mouse and the keys move the camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CameraMode {
+    /// WASD, Space and Shift move, the grabbed cursor looks around
+    #[default]
+    Fly,
+    /// Dragging turns around the target, middle or Shift dragging pans
+    Orbit,
+}
+
+impl CameraMode {
+    pub const ALL: [CameraMode; 2] = [CameraMode::Fly, CameraMode::Orbit];
+}
+
+impl std::fmt::Display for CameraMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CameraMode::Fly => "Fly",
+            CameraMode::Orbit => "Orbit",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Drag {
+    Rotate,
+    Pan,
 }
 
+/// Moves a [`Camera`] from window events, the wheel zooms in every mode.
+///
+/// Events only record what happened, `update_camera` applies it once per frame, so the
+/// controller can be driven by synthetic events.
 pub struct CameraController {
-    speed: f32,
+    pub mode: CameraMode,
+    /// Fly speed in world units per second, fitted to the scene by `fit_to`
+    pub speed: f32,
+    /// Multiplier of `speed` picked in the panel
+    pub speed_scale: f32,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
     is_up_pressed: bool,
     is_down_pressed: bool,
+    shift: bool,
+    drag: Option<Drag>,
+    cursor: Option<[f32; 2]>,
+    /// Cursor motion in pixels and wheel lines since the last update
+    rotate: [f32; 2],
+    pan: [f32; 2],
+    zoom: f32,
 }
 
 const CAMERA_MIN_Y_ANGLE: f32 = 20.0;
 const CAMERA_MOUSE_SENSITIVITY: f32 = 2.3 * 60.;
 const CAMERA_SPEED: f32 = 0.1 * 60. * 20.;
+/// Radians turned per pixel dragged in orbit mode
+const ORBIT_SENSITIVITY: f32 = 0.005;
+/// Distance to the target shrinks by this factor per wheel line
+const ZOOM_STEP: f32 = 1.1;
+/// Seconds of flying one wheel line moves forward
+const FLY_WHEEL_SECONDS: f32 = 0.25;
+/// Fly speed in bounding radii per second, crossing the framed box takes about 4 seconds
+const FLY_SPEED_PER_RADIUS: f32 = 0.5;
+/// Pixels of touchpad scrolling per wheel line
+const PIXELS_PER_LINE: f32 = 40.;
 
 impl CameraController {
     pub fn new() -> Self {
         Self {
+            mode: CameraMode::default(),
             speed: CAMERA_SPEED,
+            speed_scale: 1.,
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
             is_up_pressed: false,
             is_down_pressed: false,
+            shift: false,
+            drag: None,
+            cursor: None,
+            rotate: [0.; 2],
+            pan: [0.; 2],
+            zoom: 0.,
+        }
+    }
+
+    /// Scale the fly speed to `bounds`, the box the camera was framed on
+    pub fn fit_to(&mut self, bounds: &Aabb) {
+        let radius = (bounds.max - bounds.min).magnitude() * 0.5;
+        if radius > 0. {
+            self.speed = radius * FLY_SPEED_PER_RADIUS;
         }
     }
 
+    fn fly_speed(&self) -> f32 {
+        self.speed * self.speed_scale
+    }
+
     pub fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -125,45 +322,147 @@ impl CameraController {
                     _ => false,
                 }
             }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.shift = modifiers.shift();
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } if self.mode == CameraMode::Orbit => {
+                let drag = match button {
+                    MouseButton::Left if self.shift => Drag::Pan,
+                    MouseButton::Left => Drag::Rotate,
+                    MouseButton::Middle => Drag::Pan,
+                    _ => return false,
+                };
+                match state {
+                    ElementState::Pressed => self.drag = Some(drag),
+                    ElementState::Released => self.drag = None,
+                }
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = [position.x as f32, position.y as f32];
+                let previous = self.cursor.replace(position);
+                let (Some(drag), Some(previous)) = (self.drag, previous) else {
+                    return false;
+                };
+                let delta = match drag {
+                    Drag::Rotate => &mut self.rotate,
+                    Drag::Pan => &mut self.pan,
+                };
+                delta[0] += position[0] - previous[0];
+                delta[1] += position[1] - previous[1];
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.zoom += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(pixels) => pixels.y as f32 / PIXELS_PER_LINE,
+                };
+                true
+            }
             _ => false,
         }
     }
 
     pub fn update_camera(&mut self, camera: &mut Camera, state: &State) {
-        //        dbg!(camera.target);
+        let zoom = std::mem::take(&mut self.zoom);
+        let rotate = std::mem::take(&mut self.rotate);
+        let pan = std::mem::take(&mut self.pan);
+        match self.mode {
+            CameraMode::Fly => {
+                // Orthographic views look the same from anywhere along the view, they zoom
+                let forward = (camera.target - camera.eye).normalize();
+                match camera.projection {
+                    Projection::Perspective => {
+                        let step = forward * zoom * self.fly_speed() * FLY_WHEEL_SECONDS;
+                        camera.eye += step;
+                        camera.target += step;
+                    }
+                    Projection::Orthographic => camera.ortho_height /= ZOOM_STEP.powf(zoom),
+                }
+                self.fly(camera, state);
+            }
+            CameraMode::Orbit => Self::orbit(camera, state.resolution, rotate, pan, zoom),
+        }
+    }
+
+    /// Turn the eye around the target, pan both and move the eye towards the target
+    fn orbit(
+        camera: &mut Camera,
+        resolution: [f32; 2],
+        rotate: [f32; 2],
+        pan: [f32; 2],
+        zoom: f32,
+    ) {
+        let mut offset = camera.eye - camera.target;
+        let distance = offset.magnitude();
+        if distance <= f32::EPSILON {
+            return;
+        }
+
+        if rotate != [0.; 2] {
+            // Azimuth around the world up and elevation, stopping short of the poles like fly
+            // mode, so turning leaves a top view
+            let max_elevation = (90. - CAMERA_MIN_Y_ANGLE).to_radians();
+            let elevation = (offset.y / distance).asin();
+            let elevation =
+                (elevation + rotate[1] * ORBIT_SENSITIVITY).clamp(-max_elevation, max_elevation);
+            let azimuth = offset.x.atan2(offset.z) - rotate[0] * ORBIT_SENSITIVITY;
+            offset = Vector3::new(
+                elevation.cos() * azimuth.sin(),
+                elevation.sin(),
+                elevation.cos() * azimuth.cos(),
+            ) * distance;
+            camera.up = Vector3::unit_y();
+        }
+        match camera.projection {
+            Projection::Perspective => offset /= ZOOM_STEP.powf(zoom),
+            Projection::Orthographic => camera.ortho_height /= ZOOM_STEP.powf(zoom),
+        }
+
+        // The target moves with the cursor, one pixel is this much of the view at the target
+        let world_per_pixel = camera.view_height(offset.magnitude()) / resolution[1].max(1.);
+        let right = (-offset).cross(camera.up).normalize();
+        let up = right.cross(-offset).normalize();
+        camera.target += (up * pan[1] - right * pan[0]) * world_per_pixel;
+        camera.eye = camera.target + offset;
+    }
+
+    fn fly(&mut self, camera: &mut Camera, state: &State) {
+        let speed = self.fly_speed();
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
 
         // MOVEMENT
 
         if self.is_forward_pressed {
-            camera.eye += forward_norm * self.speed * state.dt;
-            camera.target += forward_norm * self.speed * state.dt;
+            camera.eye += forward_norm * speed * state.dt;
+            camera.target += forward_norm * speed * state.dt;
         }
         if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed * state.dt;
-            camera.target -= forward_norm * self.speed * state.dt;
+            camera.eye -= forward_norm * speed * state.dt;
+            camera.target -= forward_norm * speed * state.dt;
         }
 
         let right = forward_norm.cross(camera.up).normalize();
 
         if self.is_right_pressed {
-            camera.eye += right * self.speed * state.dt;
-            camera.target += right * self.speed * state.dt;
+            camera.eye += right * speed * state.dt;
+            camera.target += right * speed * state.dt;
         }
         if self.is_left_pressed {
-            camera.eye -= right * self.speed * state.dt;
-            camera.target -= right * self.speed * state.dt;
+            camera.eye -= right * speed * state.dt;
+            camera.target -= right * speed * state.dt;
         }
 
         let up = camera.up.normalize();
         if self.is_up_pressed {
-            camera.eye += up * self.speed * state.dt;
-            camera.target += up * self.speed * state.dt;
+            camera.eye += up * speed * state.dt;
+            camera.target += up * speed * state.dt;
         }
         if self.is_down_pressed {
-            camera.eye -= up * self.speed * state.dt;
-            camera.target -= up * self.speed * state.dt;
+            camera.eye -= up * speed * state.dt;
+            camera.target -= up * speed * state.dt;
         }
 
         // ROTATION
@@ -184,12 +483,201 @@ impl CameraController {
                 * (camera.target - camera.eye).magnitude();
 
         let y = Vector3::new(0.0, 1.0, 0.0);
-        let v = camera.target - camera.eye;
-        let alpha = y.angle(v).0.to_degrees();
-        let beta = (-y).angle(v).0.to_degrees();
-        if alpha < CAMERA_MIN_Y_ANGLE || beta < CAMERA_MIN_Y_ANGLE {
+        let pole_angle = |v: Vector3<f32>| {
+            let alpha = y.angle(v).0.to_degrees();
+            let beta = (-y).angle(v).0.to_degrees();
+            alpha.min(beta)
+        };
+        let angle = pole_angle(camera.target - camera.eye);
+        if angle >= CAMERA_MIN_Y_ANGLE {
+            camera.up = y;
+        } else if angle <= pole_angle(target - eye) {
+            // Only turning away from the poles is allowed close to them, e.g. from a top view
             // @TODO: allow to move to be just on the edge of max rotation
             (camera.target, camera.eye) = (target, eye);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::MetricSpace;
+    use winit::{
+        dpi::PhysicalPosition,
+        event::{DeviceId, ModifiersState, TouchPhase},
+    };
+
+    use super::*;
+
+    fn device_id() -> DeviceId {
+        // SAFETY: only compared by winit, never passed to the platform
+        unsafe { DeviceId::dummy() }
+    }
+
+    #[allow(deprecated)]
+    fn cursor(x: f32, y: f32) -> WindowEvent<'static> {
+        WindowEvent::CursorMoved {
+            device_id: device_id(),
+            position: PhysicalPosition::new(x as f64, y as f64),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[allow(deprecated)]
+    fn button(button: MouseButton, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::MouseInput {
+            device_id: device_id(),
+            state,
+            button,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[allow(deprecated)]
+    fn wheel(lines: f32) -> WindowEvent<'static> {
+        WindowEvent::MouseWheel {
+            device_id: device_id(),
+            delta: MouseScrollDelta::LineDelta(0., lines),
+            phase: TouchPhase::Moved,
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    fn drag(controller: &mut CameraController, from: [f32; 2], to: [f32; 2]) {
+        controller.process_events(&cursor(from[0], from[1]));
+        controller.process_events(&button(MouseButton::Left, ElementState::Pressed));
+        controller.process_events(&cursor(to[0], to[1]));
+        controller.process_events(&button(MouseButton::Left, ElementState::Released));
+    }
+
+    fn orbit_setup() -> (Camera, CameraController, State) {
+        let mut camera = Camera::quick_camera(1.);
+        camera.eye = (0., 0., -10.).into();
+        camera.target = (0., 0., 0.).into();
+        let mut controller = CameraController::new();
+        controller.mode = CameraMode::Orbit;
+        (camera, controller, State::new([100., 100.]))
+    }
+
+    #[test]
+    fn orbit_rotate_and_zoom_test() {
+        let (mut camera, mut controller, state) = orbit_setup();
+
+        drag(&mut controller, [10., 10.], [60., 30.]);
+        controller.update_camera(&mut camera, &state);
+        assert_eq!(camera.target, Point3::new(0., 0., 0.));
+        assert!((camera.eye.distance(camera.target) - 10.).abs() < 1e-4);
+        assert!(camera.eye.x.abs() > 1. && camera.eye.y.abs() > 0.1);
+
+        // Deltas are consumed, another update without events does not move
+        let eye = camera.eye;
+        controller.update_camera(&mut camera, &state);
+        assert_eq!(camera.eye, eye);
+
+        controller.process_events(&wheel(2.));
+        controller.update_camera(&mut camera, &state);
+        let distance = camera.eye.distance(camera.target);
+        assert!(
+            (distance - 10. / ZOOM_STEP.powi(2)).abs() < 1e-4,
+            "{distance}"
+        );
+
+        // Dragging without a pressed button does nothing
+        controller.process_events(&cursor(0., 0.));
+        controller.process_events(&cursor(50., 50.));
+        let eye = camera.eye;
+        controller.update_camera(&mut camera, &state);
+        assert_eq!(camera.eye, eye);
+    }
+
+    #[test]
+    fn orbit_pan_test() {
+        let (mut camera, mut controller, state) = orbit_setup();
+
+        controller.process_events(&WindowEvent::ModifiersChanged(ModifiersState::SHIFT));
+        drag(&mut controller, [50., 50.], [60., 50.]);
+        controller.update_camera(&mut camera, &state);
+
+        // Panning moves eye and target together, sideways only
+        assert!((camera.eye - camera.target - Vector3::new(0., 0., -10.)).magnitude() < 1e-4);
+        assert!(camera.target.x.abs() > 0.1);
+        assert!(camera.target.y.abs() < 1e-4 && camera.target.z.abs() < 1e-4);
+    }
+
+    #[test]
+    fn orthographic_axis_view_test() {
+        let (mut camera, mut controller, state) = orbit_setup();
+        camera.set_projection(Projection::Orthographic);
+        assert!((camera.ortho_height - 20. * 22.5f32.to_radians().tan()).abs() < 1e-4);
+        camera.look_along(ViewAxis::Top.direction());
+        assert!((camera.eye - Point3::new(0., 10., 0.)).magnitude() < 1e-4);
+        assert!(!camera.build_view_projection_matrix().x.x.is_nan());
+
+        // Zooming and panning keep looking straight down, zooming only narrows the view
+        let height = camera.ortho_height;
+        controller.process_events(&wheel(1.));
+        controller.process_events(&button(MouseButton::Middle, ElementState::Pressed));
+        controller.process_events(&cursor(0., 0.));
+        controller.process_events(&cursor(0., 10.));
+        controller.update_camera(&mut camera, &state);
+        assert!((camera.ortho_height - height / ZOOM_STEP).abs() < 1e-5);
+        assert!((camera.eye - camera.target - Vector3::new(0., 10., 0.)).magnitude() < 1e-4);
+        assert!(camera.target.z.abs() > 1e-3);
+    }
+
+    #[test]
+    fn orthographic_ray_test() {
+        let (mut camera, _, state) = orbit_setup();
+        let (_, perspective) = camera.ray([100., 50.], state.resolution);
+
+        // Parallel rays, a window apart at the window edges
+        camera.set_projection(Projection::Orthographic);
+        let (center, dir) = camera.ray([50., 50.], state.resolution);
+        assert!((center - camera.eye).magnitude() < 1e-5);
+        assert!((dir - Vector3::unit_z()).magnitude() < 1e-5);
+
+        let (edge, edge_dir) = camera.ray([100., 50.], state.resolution);
+        assert_eq!(edge_dir, dir);
+        assert!((edge.x.abs() - camera.ortho_height / 2.).abs() < 1e-4);
+        assert_eq!(edge.x.signum(), perspective.x.signum());
+    }
+
+    #[test]
+    fn frame_and_fly_speed_test() {
+        let bounds = Aabb {
+            min: Vector3::new(9., -1., -1.),
+            max: Vector3::new(11., 1., 1.),
+        };
+        let mut camera = Camera::quick_camera(2.);
+        camera.frame(&bounds);
+        assert_eq!(camera.target, Point3::new(10., 0., 0.));
+        let expected = 3f32.sqrt() / (22.5f32).to_radians().sin();
+        assert!((camera.eye.distance(camera.target) - expected).abs() < 1e-3);
+        // The view direction is kept
+        assert!((camera.eye.z - (-expected)).abs() < 1e-3);
+
+        let mut controller = CameraController::new();
+        controller.fit_to(&bounds);
+        controller.speed_scale = 2.;
+        let mut state = State::new([100., 100.]);
+        state.dt = 0.5;
+        #[allow(deprecated)]
+        controller.process_events(&WindowEvent::KeyboardInput {
+            device_id: device_id(),
+            input: KeyboardInput {
+                scancode: 0,
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::W),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: true,
+        });
+        let eye = camera.eye;
+        controller.update_camera(&mut camera, &state);
+        let moved = camera.eye.distance(eye);
+        assert!(
+            (moved - 3f32.sqrt() * FLY_SPEED_PER_RADIUS).abs() < 1e-4,
+            "{moved}"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use egui::Color32;
+use serde::{Deserialize, Serialize};
+
+/// Colour stops of every map, evenly spaced over `[0, 1]`
+pub const COLORMAP_STOPS: usize = 5;
+
+/// Colour ramps for showing scalar values, shared by the raycaster, the legend and the slice view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Colormap {
+    /// Dark blue over green to yellow
+    #[default]
+    Viridis,
+    /// Black over purple and red to pale yellow
+    Magma,
+    /// Blue to grey to red, for values around a midpoint
+    Diverging,
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 3] = [Colormap::Viridis, Colormap::Magma, Colormap::Diverging];
+
+    /// Linear RGB colours at the stops
+    pub fn stops(&self) -> [[f32; 3]; COLORMAP_STOPS] {
+        match self {
+            Colormap::Viridis => [
+                [0.267, 0.005, 0.329],
+                [0.231, 0.322, 0.545],
+                [0.129, 0.569, 0.549],
+                [0.369, 0.788, 0.384],
+                [0.993, 0.906, 0.144],
+            ],
+            Colormap::Magma => [
+                [0.001, 0.000, 0.014],
+                [0.232, 0.060, 0.438],
+                [0.550, 0.161, 0.506],
+                [0.868, 0.288, 0.409],
+                [0.987, 0.991, 0.750],
+            ],
+            Colormap::Diverging => [
+                [0.230, 0.299, 0.754],
+                [0.552, 0.690, 0.996],
+                [0.865, 0.865, 0.865],
+                [0.958, 0.604, 0.483],
+                [0.706, 0.016, 0.150],
+            ],
+        }
+    }
+
+    /// Colour at `t` in `[0, 1]`, interpolated between the stops like the shader does
+    pub fn sample(&self, t: f32) -> [f32; 3] {
+        let stops = self.stops();
+        let x = t.clamp(0., 1.) * (COLORMAP_STOPS - 1) as f32;
+        let i = (x as usize).min(COLORMAP_STOPS - 2);
+        let f = x - i as f32;
+        [0, 1, 2].map(|c| stops[i][c] * (1. - f) + stops[i + 1][c] * f)
+    }
+
+    pub fn color32(&self, t: f32) -> Color32 {
+        let [r, g, b] = self.sample(t).map(|c| (c * 255.).round() as u8);
+        Color32::from_rgb(r, g, b)
+    }
+}
+
+impl std::fmt::Display for Colormap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Colormap::Viridis => "Viridis",
+            Colormap::Magma => "Magma",
+            Colormap::Diverging => "Diverging",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_test() {
+        for colormap in Colormap::ALL {
+            let stops = colormap.stops();
+            assert_eq!(colormap.sample(0.), stops[0]);
+            assert_eq!(colormap.sample(1.), stops[COLORMAP_STOPS - 1]);
+            assert_eq!(colormap.sample(-3.), stops[0]);
+            assert_eq!(colormap.sample(0.5), stops[2]);
+
+            let between = colormap.sample(0.125);
+            for c in 0..3 {
+                let expected = (stops[0][c] + stops[1][c]) / 2.;
+                assert!((between[c] - expected).abs() < 1e-6);
+            }
+        }
+    }
+}
@@ -1,20 +1,43 @@
-use std::{fs, time::Instant};
+use std::{
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
-use crate::scene::Scene;
-use cgmath::Point3;
-use egui::{ClippedPrimitive, Color32, ComboBox, FontId, RichText, Slider, TexturesDelta, Vec2};
+use crate::{
+    render::{Camera, CameraMode, Colormap, Projection, ToneMapper, ViewAxis},
+    scene::{Aabb, Environment, GridSource, Light, LightKind, Material, Scene},
+    vdb::{
+        Brush, BrushMode, BrushShape, ErrorKind, LeafEncoding, Progress, TreeShape, VdbEndpoint,
+        VdbReader, VoxelInfo, MAX_TREE_LEVELS,
+    },
+};
+use cgmath::{EuclideanSpace, InnerSpace, Point3};
+use egui::{
+    ClippedPrimitive, Color32, ComboBox, FontId, ProgressBar, RichText, Slider, TextureHandle,
+    TexturesDelta, Vec2,
+};
 use egui_plot::{Bar, BarChart, Plot};
 use egui_wgpu_backend::ScreenDescriptor;
 use instant::Duration;
+use itertools::Itertools;
+use log::warn;
+use serde::{Deserialize, Serialize};
 use winit::window::Window;
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum RenderMode {
     Gray,
     Rgb,
     Ray,
     Diffuse,
     Glossy,
+    /// Colour mapped value of the hit voxel
+    Value,
+    /// Progressive path tracing, samples add up while nothing changes
+    PathTrace,
 }
 
 impl RenderMode {
@@ -25,6 +48,8 @@ impl RenderMode {
             Self::Ray => "Ray",
             Self::Diffuse => "Diffuse",
             Self::Glossy => "Glossy",
+            Self::Value => "Value",
+            Self::PathTrace => "Path trace",
         })
     }
 
@@ -37,13 +62,36 @@ const FPS_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
 pub struct EguiDev {
     pub platform: egui_winit_platform::Platform,
     pub selected_model: usize,
+    /// Index into the grids of the selected model
+    pub selected_grid: usize,
     pub render_mode: RenderMode,
     pub show_grid: [bool; 3],
     pub models: Vec<VdbFile>,
+    pub model_settings: ModelSettings,
     pub sun_settings: SunSettings,
+    pub value_settings: ValueSettings,
+    pub path_trace_settings: PathTraceSettings,
+    pub sampling_settings: SamplingSettings,
+    pub tone_settings: ToneSettings,
+    pub material_settings: MaterialSettings,
+    pub light_settings: LightSettings,
+    pub camera_settings: CameraSettings,
+    pub clip_settings: ClipSettings,
+    pub slice_settings: SliceSettings,
+    pub inspect_settings: InspectSettings,
+    pub edit_settings: EditSettings,
+    pub scene_settings: SceneSettings,
+    pub timeline: TimelineSettings,
+    pub export: ExportSettings,
     pub recording: bool,
     pub recording_file: String,
     pub show: bool,
+    /// Bytes used by each level of the node pool on the GPU, top level first
+    pub pool_bytes: [usize; MAX_TREE_LEVELS],
+    /// Shape the node pool is packed in
+    pub pool_shape: TreeShape,
+    /// How leaf values are stored on the GPU, narrower encodings shrink the leaf pool
+    pub leaf_encoding: LeafEncoding,
     last_fps_update: Instant,
     time_last_frame: Instant,
     past_fps: Vec<f32>,
@@ -55,10 +103,26 @@ impl EguiDev {
         Self {
             platform,
             selected_model: 0,
+            selected_grid: 0,
             models: get_available_vdbs(),
+            model_settings: ModelSettings::default(),
             render_mode: RenderMode::Diffuse,
             show_grid: [false; 3],
             sun_settings: SunSettings::default(),
+            value_settings: ValueSettings::default(),
+            path_trace_settings: PathTraceSettings::default(),
+            sampling_settings: SamplingSettings::default(),
+            tone_settings: ToneSettings::default(),
+            material_settings: MaterialSettings::default(),
+            light_settings: LightSettings::default(),
+            camera_settings: CameraSettings::default(),
+            clip_settings: ClipSettings::default(),
+            slice_settings: SliceSettings::default(),
+            inspect_settings: InspectSettings::default(),
+            edit_settings: EditSettings::default(),
+            scene_settings: SceneSettings::default(),
+            timeline: TimelineSettings::default(),
+            export: ExportSettings::default(),
             last_fps_update: Instant::now(),
             time_last_frame: Instant::now(),
             current_fps: 0.,
@@ -66,6 +130,9 @@ impl EguiDev {
             recording: false,
             recording_file: "output.mp4".to_string(),
             show: true,
+            pool_bytes: [0; MAX_TREE_LEVELS],
+            pool_shape: TreeShape::VDB345,
+            leaf_encoding: LeafEncoding::default(),
         }
     }
 
@@ -79,7 +146,6 @@ impl EguiDev {
         ScreenDescriptor,
         bool,
         bool,
-        bool,
     ) {
         self.update_fps();
 
@@ -97,7 +163,6 @@ impl EguiDev {
                 true => egui::CursorIcon::None,
             });
 
-        let mut model_changed = false;
         let mut reload_shaders = false;
         let mut recording_changed = false;
         egui::Window::new("Developer tools")
@@ -109,24 +174,40 @@ impl EguiDev {
                         .color(Color32::from_rgb(7, 173, 51))
                         .font(FontId::proportional(20.0)),
                 );
+                self.model_frame(ui);
+                self.timeline.get_frame(ui);
+
+                let levels = self
+                    .pool_shape
+                    .log2_dims()
+                    .iter()
+                    .zip(self.pool_bytes)
+                    .map(|(log2dim, bytes)| {
+                        format!("N{log2dim} {:.1}", bytes as f32 / (1 << 20) as f32)
+                    })
+                    .join(" ");
+                ui.label(
+                    RichText::new(format!("Node pool (MiB): {levels}"))
+                        .font(FontId::proportional(15.0)),
+                );
                 ui.horizontal(|ui| {
-                    ComboBox::from_label(RichText::new("Model").font(FontId::proportional(15.0)))
-                        .selected_text(
-                            RichText::new(self.models[self.selected_model].name.clone())
-                                .font(FontId::proportional(15.0)),
-                        )
-                        .width(150.0)
-                        .show_ui(ui, |ui| {
-                            for (id, model) in self.models.iter().enumerate() {
-                                model_changed |= ui
-                                    .selectable_value(
-                                        &mut self.selected_model,
-                                        id,
-                                        RichText::new(&model.name).font(FontId::proportional(15.0)),
-                                    )
-                                    .clicked();
-                            }
-                        });
+                    ComboBox::from_label(
+                        RichText::new("Leaf values").font(FontId::proportional(15.0)),
+                    )
+                    .selected_text(
+                        RichText::new(self.leaf_encoding.to_string())
+                            .font(FontId::proportional(15.0)),
+                    )
+                    .show_ui(ui, |ui| {
+                        for encoding in LeafEncoding::ALL {
+                            ui.selectable_value(
+                                &mut self.leaf_encoding,
+                                encoding,
+                                RichText::new(encoding.to_string())
+                                    .font(FontId::proportional(15.0)),
+                            );
+                        }
+                    });
                 });
 
                 ui.label(
@@ -145,6 +226,10 @@ impl EguiDev {
                     .font(FontId::proportional(15.0))
                     .color(Color32::WHITE),
                 );
+                self.camera_settings.get_frame(ui, &scene.camera);
+                self.clip_settings.get_frame(ui, &scene.camera);
+                self.slice_settings.get_frame(ui);
+                self.inspect_settings.get_frame(ui);
 
                 let chart = BarChart::new(
                     self.past_fps
@@ -203,6 +288,16 @@ impl EguiDev {
                         RenderMode::Glossy,
                         RenderMode::Glossy.rich_text(),
                     );
+                    ui.selectable_value(
+                        &mut self.render_mode,
+                        RenderMode::Value,
+                        RenderMode::Value.rich_text(),
+                    );
+                    ui.selectable_value(
+                        &mut self.render_mode,
+                        RenderMode::PathTrace,
+                        RenderMode::PathTrace.rich_text(),
+                    );
                 });
 
                 if self.render_mode != RenderMode::Diffuse {
@@ -233,6 +328,21 @@ impl EguiDev {
                 if self.render_mode == RenderMode::Diffuse {
                     self.sun_settings.get_frame(ui);
                 }
+                if self.render_mode == RenderMode::Value {
+                    self.value_settings.get_frame(ui);
+                }
+                if self.render_mode == RenderMode::PathTrace {
+                    self.sun_settings.get_frame(ui);
+                    self.path_trace_settings.get_frame(ui);
+                }
+                self.sampling_settings.get_frame(ui);
+                self.tone_settings.get_frame(ui);
+                self.material_settings.get_frame(ui, &self.models);
+                self.light_settings.get_frame(ui);
+
+                self.edit_settings.get_frame(ui);
+
+                self.scene_settings.get_frame(ui);
 
                 ui.collapsing(
                     RichText::new("Recording Menu").font(FontId::proportional(15.0)),
@@ -251,10 +361,21 @@ impl EguiDev {
                             recording_changed = response.clicked();
                         }
                         ui.text_edit_singleline(&mut self.recording_file);
+                        ui.separator();
+                        self.export.get_frame(ui);
                     },
                 )
             });
 
+        self.slice_settings.show_window(&self.platform.context());
+        if self.render_mode == RenderMode::Value {
+            self.value_settings.show_legend(&self.platform.context());
+        }
+
+        if self.edit_settings.enabled && scene.state.cursor_grabbed {
+            self.edit_settings.draw_crosshair(&self.platform.context());
+        }
+
         let full_output = self.platform.end_frame(Some(&window));
         let paint_jobs = self.platform.context().tessellate(full_output.shapes);
         let tdelta = full_output.textures_delta;
@@ -263,12 +384,109 @@ impl EguiDev {
             tdelta,
             paint_jobs,
             screen_descriptor,
-            model_changed,
             reload_shaders,
             recording_changed,
         )
     }
 
+    /// Model and grid pickers, and a path to open any file from
+    fn model_frame(&mut self, ui: &mut egui::Ui) {
+        let selected = |text: Option<&String>| {
+            RichText::new(text.map_or("None", |text| text.as_str()))
+                .font(FontId::proportional(15.0))
+        };
+
+        ui.horizontal(|ui| {
+            let model = self.models.get(self.selected_model);
+            ComboBox::from_label(RichText::new("Model").font(FontId::proportional(15.0)))
+                .selected_text(selected(model.map(|model| &model.name)))
+                .width(150.0)
+                .show_ui(ui, |ui| {
+                    for (id, model) in self.models.iter().enumerate() {
+                        if ui
+                            .selectable_value(
+                                &mut self.selected_model,
+                                id,
+                                RichText::new(&model.name).font(FontId::proportional(15.0)),
+                            )
+                            .clicked()
+                        {
+                            self.selected_grid = 0;
+                            self.model_settings.command = Some(ModelCommand::Load);
+                        }
+                    }
+                });
+        });
+
+        if let Some(model) = self.models.get(self.selected_model) {
+            ui.horizontal(|ui| {
+                ComboBox::from_label(RichText::new("Grid").font(FontId::proportional(15.0)))
+                    .selected_text(selected(model.grids.get(self.selected_grid)))
+                    .width(150.0)
+                    .show_ui(ui, |ui| {
+                        for (id, grid) in model.grids.iter().enumerate() {
+                            if ui
+                                .selectable_value(
+                                    &mut self.selected_grid,
+                                    id,
+                                    RichText::new(grid).font(FontId::proportional(15.0)),
+                                )
+                                .clicked()
+                            {
+                                self.model_settings.command = Some(ModelCommand::Load);
+                            }
+                        }
+                    });
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.model_settings.path);
+            if ui
+                .button(RichText::new("Open").font(FontId::proportional(15.0)))
+                .clicked()
+            {
+                let path = PathBuf::from(&self.model_settings.path);
+                self.model_settings.command = Some(ModelCommand::Open(path));
+            }
+        });
+
+        if let Some((name, progress)) = &self.model_settings.loading {
+            let (bytes_read, bytes_total) = progress.bytes();
+            let (nodes_done, nodes_total) = progress.nodes();
+            ui.label(RichText::new(format!("Loading {name}")).font(FontId::proportional(15.0)));
+            ui.add(ProgressBar::new(progress.fraction()).show_percentage());
+            ui.label(format!(
+                "{} / {} KiB read, {nodes_done} / {nodes_total} nodes processed",
+                bytes_read >> 10,
+                bytes_total >> 10
+            ));
+            if ui
+                .button(RichText::new("Cancel").font(FontId::proportional(15.0)))
+                .clicked()
+            {
+                progress.cancel();
+            }
+        } else if !self.model_settings.status.is_empty() {
+            ui.label(RichText::new(&self.model_settings.status).font(FontId::proportional(15.0)));
+        }
+    }
+
+    /// Lists `file` in the model picker, replacing an earlier copy, and selects it
+    pub fn select_model(&mut self, file: VdbFile) {
+        self.selected_grid = 0;
+        match self.models.iter().position(|model| model.path == file.path) {
+            Some(id) => {
+                self.models[id] = file;
+                self.selected_model = id;
+            }
+            None => {
+                self.models.push(file);
+                self.selected_model = self.models.len() - 1;
+            }
+        }
+    }
+
     fn facing(&self, eye: Point3<f32>, target: Point3<f32>) -> String {
         let dir = target - eye;
         let adir = dir.map(|c| c.abs());
@@ -302,137 +520,1644 @@ impl EguiDev {
     }
 }
 
+/// Folder listed in the model picker at startup
+const ASSETS_DIR: &str = "assets";
+
 #[derive(Debug, Clone)]
 pub struct VdbFile {
     pub name: String,
     pub path: String,
-    pub grid: String,
+    /// Every grid in the file, sorted by name
+    pub grids: Vec<String>,
+}
+
+impl VdbFile {
+    /// Reads the grid descriptors of the file at `path`, not its trees
+    pub fn open(path: &Path) -> Result<Self, ErrorKind> {
+        let f = fs::File::open(path)?;
+        let reader = VdbReader::new(BufReader::new(f))?;
+
+        Ok(Self {
+            name: path.file_stem().map_or_else(
+                || path.display().to_string(),
+                |stem| stem.to_string_lossy().into_owned(),
+            ),
+            path: path.display().to_string(),
+            grids: reader.grid_descriptors.keys().sorted().cloned().collect(),
+        })
+    }
 }
 
 fn get_available_vdbs() -> Vec<VdbFile> {
-    let mut files = vec![];
-    let path = "assets";
+    let entries = match fs::read_dir(ASSETS_DIR) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Could not list {ASSETS_DIR}: {e}");
+            return vec![];
+        }
+    };
 
-    for entry in fs::read_dir(path).unwrap() {
-        let Ok(entry) = entry else { continue };
-        let path = entry.path();
+    entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|e| e == "vdb"))
+        .filter_map(|path| {
+            VdbFile::open(&path)
+                .map_err(|e| warn!("Skipping {}: {e}", path.display()))
+                .ok()
+        })
+        .sorted_by(|x, y| x.name.cmp(&y.name))
+        .collect()
+}
 
-        if path.is_file() {
-            let Some(extention) = path.extension() else {
-                continue;
-            };
-            if extention != "vdb" {
-                continue;
-            }
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelCommand {
+    /// Show the selected grid of the selected model
+    Load,
+    /// Open a VDB or scene file that was typed in or dropped on the window
+    Open(PathBuf),
+}
 
-            let Some(name) = path.file_stem() else {
-                continue;
-            };
-            let Some(name) = name.to_str() else {
-                continue;
-            };
+#[derive(Debug, Default)]
+pub struct ModelSettings {
+    pub path: String,
+    /// Result of the last load
+    pub status: String,
+    /// Set by the pickers, the open button and dropped files, taken by the runtime
+    pub command: Option<ModelCommand>,
+    /// Name and progress of the grids being read in the background
+    pub loading: Option<(String, Arc<Progress>)>,
+}
 
-            files.push(VdbFile {
-                name: name.to_string(),
-                path: path.display().to_string(),
-                grid: "ls_".to_string() + &name.to_string(),
-            });
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SceneCommand {
+    Load,
+    Save,
+}
+
+#[derive(Debug)]
+pub struct SceneSettings {
+    pub file: String,
+    /// Result of the last load or save
+    pub status: String,
+    /// Set by the panel buttons, taken by the runtime
+    pub command: Option<SceneCommand>,
+}
+
+impl Default for SceneSettings {
+    fn default() -> Self {
+        Self {
+            file: "assets/scene.ron".to_string(),
+            status: String::new(),
+            command: None,
         }
     }
+}
+
+impl SceneSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Scene file").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.text_edit_singleline(&mut self.file);
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button(RichText::new("Load").font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        self.command = Some(SceneCommand::Load);
+                    }
+                    if ui
+                        .button(RichText::new("Save").font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        self.command = Some(SceneCommand::Save);
+                    }
+                });
 
-    files.sort_by(|x, y| x.name.cmp(&y.grid));
+                if !self.status.is_empty() {
+                    ui.label(RichText::new(&self.status).font(FontId::proportional(15.0)));
+                }
+            },
+        );
+    }
+}
 
-    files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraCommand {
+    /// Fit every object in view
+    FrameAll,
+    /// Fit the object under the crosshair in view
+    FrameSelected,
+    /// Store the camera under `bookmark_name`
+    SaveBookmark,
+    GoToBookmark(usize),
+    DeleteBookmark(usize),
+    Projection(Projection),
+    /// Orthographic view along an axis, framing everything
+    AxisView(ViewAxis),
 }
 
 #[derive(Debug)]
-pub struct SunSettings {
-    pub dir3: glam::Vec3,
-    pub color: [f32; 3],
-    pub intensity: f32,
+pub struct CameraSettings {
+    pub mode: CameraMode,
+    /// Multiplier of the fly speed, which follows the size of the framed objects
+    pub speed_scale: f32,
+    pub bookmark_name: String,
+    /// Names of the bookmarks in the scene
+    pub bookmarks: Vec<String>,
+    /// Set by the panel buttons, taken by the runtime
+    pub command: Option<CameraCommand>,
 }
 
-impl Default for SunSettings {
+impl Default for CameraSettings {
     fn default() -> Self {
         Self {
-            dir3: glam::Vec3 {
-                x: 1.0,
-                y: -1.0,
-                z: 0.5,
-            }
-            .normalize(),
-            color: [255. / 255., 210. / 255., 160. / 255.],
-            intensity: 1.0,
+            mode: CameraMode::default(),
+            speed_scale: 1.,
+            bookmark_name: String::new(),
+            bookmarks: vec![],
+            command: None,
         }
     }
 }
 
-impl SunSettings {
-    fn get_frame(&mut self, ui: &mut egui::Ui) {
+impl CameraSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui, camera: &Camera) {
         ui.collapsing(
-            RichText::new("Sunlight settings").font(FontId::proportional(15.0)),
+            RichText::new("Camera").font(FontId::proportional(15.0)),
             |ui| {
                 ui.horizontal(|ui| {
-                    ui.label(
-                        RichText::new(format!("X: {:.2}", self.dir3.x))
-                            .font(FontId::proportional(15.0))
-                            .color(Color32::RED),
-                    );
-                    ui.label(
-                        RichText::new(format!("Y: {:.2}", self.dir3.y))
-                            .font(FontId::proportional(15.0))
-                            .color(Color32::from_rgb(0, 136, 255)),
-                    );
-                    ui.label(
-                        RichText::new(format!("Z: {:.2}", self.dir3.z))
-                            .font(FontId::proportional(15.0))
-                            .color(Color32::GREEN),
-                    );
+                    for mode in CameraMode::ALL {
+                        ui.selectable_value(
+                            &mut self.mode,
+                            mode,
+                            RichText::new(mode.to_string()).font(FontId::proportional(15.0)),
+                        );
+                    }
                 });
+
                 ui.horizontal(|ui| {
-                    ui.vertical(|ui| {
-                        ui.set_max_width(150.);
-                        ui.set_min_height(150.);
-                        ui.centered_and_justified(|ui| {
-                            self.get_circle(ui);
-                        });
+                    ui.label(RichText::new("Speed").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.speed_scale, 0.05..=20.0).logarithmic(true));
+                });
+
+                ui.horizontal(|ui| {
+                    for (projection, text) in [
+                        (Projection::Perspective, "Perspective"),
+                        (Projection::Orthographic, "Orthographic"),
+                    ] {
+                        if ui
+                            .selectable_label(
+                                camera.projection == projection,
+                                RichText::new(text).font(FontId::proportional(15.0)),
+                            )
+                            .clicked()
+                        {
+                            self.command = Some(CameraCommand::Projection(projection));
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    for axis in ViewAxis::ALL {
+                        if ui
+                            .button(
+                                RichText::new(axis.to_string()).font(FontId::proportional(15.0)),
+                            )
+                            .clicked()
+                        {
+                            self.command = Some(CameraCommand::AxisView(axis));
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button(RichText::new("Frame all").font(FontId::proportional(15.0)))
+                        .on_hover_text("Home")
+                        .clicked()
+                    {
+                        self.command = Some(CameraCommand::FrameAll);
+                    }
+                    if ui
+                        .button(RichText::new("Frame selected").font(FontId::proportional(15.0)))
+                        .on_hover_text("F, frames the object in the middle of the view")
+                        .clicked()
+                    {
+                        self.command = Some(CameraCommand::FrameSelected);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.bookmark_name);
+                    if ui
+                        .button(RichText::new("Bookmark").font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        self.command = Some(CameraCommand::SaveBookmark);
+                    }
+                });
+
+                for (idx, name) in self.bookmarks.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let text = match idx < 9 {
+                            true => format!("{} {name}", idx + 1),
+                            false => name.clone(),
+                        };
+                        if ui
+                            .button(RichText::new(text).font(FontId::proportional(15.0)))
+                            .clicked()
+                        {
+                            self.command = Some(CameraCommand::GoToBookmark(idx));
+                        }
+                        if ui
+                            .small_button("x")
+                            .on_hover_text("Delete bookmark")
+                            .clicked()
+                        {
+                            self.command = Some(CameraCommand::DeleteBookmark(idx));
+                        }
                     });
-                    ui.vertical_centered_justified(|ui| {
-                        let prev_fg_color = ui.style().visuals.widgets.inactive.fg_stroke.color;
-                        let prev_ac_color = ui.style().visuals.widgets.active.fg_stroke.color;
-                        let prev_hv_color = ui.style().visuals.widgets.hovered.fg_stroke.color;
+                }
+            },
+        );
+    }
+}
 
-                        ui.style_mut().visuals.widgets.inactive.fg_stroke.color =
-                            Color32::from_rgb(0, 136, 255);
-                        ui.style_mut().visuals.widgets.active.fg_stroke.color =
-                            Color32::from_rgb(20, 160, 255);
-                        ui.style_mut().visuals.widgets.hovered.fg_stroke.color =
-                            Color32::from_rgb(20, 160, 255);
-                        let mut slider_val = -self.dir3.y;
-                        ui.add_space(25.);
-                        ui.add(
-                            Slider::new(&mut slider_val, -1.0..=1.0)
-                                .vertical()
-                                .show_value(false),
-                        )
-                        .rect;
-                        ui.style_mut().visuals.widgets.inactive.fg_stroke.color = prev_fg_color;
-                        ui.style_mut().visuals.widgets.active.fg_stroke.color = prev_ac_color;
-                        ui.style_mut().visuals.widgets.hovered.fg_stroke.color = prev_hv_color;
-                        self.dir3.y = -slider_val;
-                        self.dir3 = self.dir3.normalize()
+pub const MAX_CLIP_PLANES: usize = 4;
+
+/// Cuts away the half space in front of the plane, where `dot(normal, p) > offset`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipPlane {
+    pub enabled: bool,
+    pub normal: [f32; 3],
+    pub offset: f32,
+}
+
+/// World space clip planes and box, the raycaster skips everything they cut away so the
+/// voxels inside show on the cut
+#[derive(Debug)]
+pub struct ClipSettings {
+    pub planes: [ClipPlane; MAX_CLIP_PLANES],
+    pub box_enabled: bool,
+    /// Everything outside of it is cut away while `box_enabled`
+    pub clip_box: Aabb,
+    /// World bounds of the scene, the range of the sliders, set by the runtime
+    pub bounds: Option<Aabb>,
+}
+
+impl Default for ClipSettings {
+    fn default() -> Self {
+        Self {
+            planes: [ClipPlane {
+                enabled: false,
+                normal: [1., 0., 0.],
+                offset: 0.,
+            }; MAX_CLIP_PLANES],
+            box_enabled: false,
+            clip_box: Aabb::empty(),
+            bounds: None,
+        }
+    }
+}
+
+impl ClipSettings {
+    const NORMALS: [(&'static str, [f32; 3]); 6] = [
+        ("+X", [1., 0., 0.]),
+        ("-X", [-1., 0., 0.]),
+        ("+Y", [0., 1., 0.]),
+        ("-Y", [0., -1., 0.]),
+        ("+Z", [0., 0., 1.]),
+        ("-Z", [0., 0., -1.]),
+    ];
+
+    fn get_frame(&mut self, ui: &mut egui::Ui, camera: &Camera) {
+        ui.collapsing(
+            RichText::new("Clipping").font(FontId::proportional(15.0)),
+            |ui| {
+                let Some(bounds) = self.bounds else {
+                    ui.label(RichText::new("Nothing to clip").font(FontId::proportional(15.0)));
+                    return;
+                };
+
+                for (idx, plane) in self.planes.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.checkbox(
+                            &mut plane.enabled,
+                            RichText::new(format!("Plane {}", idx + 1))
+                                .font(FontId::proportional(15.0)),
+                        );
+                        let name = Self::NORMALS
+                            .iter()
+                            .find(|(_, normal)| *normal == plane.normal)
+                            .map_or("View", |(name, _)| name);
+                        ComboBox::from_id_source(("clip plane", idx))
+                            .width(50.)
+                            .selected_text(RichText::new(name).font(FontId::proportional(15.0)))
+                            .show_ui(ui, |ui| {
+                                for (name, normal) in Self::NORMALS {
+                                    ui.selectable_value(
+                                        &mut plane.normal,
+                                        normal,
+                                        RichText::new(name).font(FontId::proportional(15.0)),
+                                    );
+                                }
+                            });
+                        if ui
+                            .button(RichText::new("View").font(FontId::proportional(15.0)))
+                            .on_hover_text(
+                                "Cut away what is in front of the plane through the target",
+                            )
+                            .clicked()
+                        {
+                            let normal = (camera.eye - camera.target).normalize();
+                            plane.normal = normal.into();
+                            plane.offset = normal.dot(camera.target.to_vec());
+                        }
                     });
-                });
+
+                    let range = plane_range(&bounds, plane.normal);
+                    ui.add(Slider::new(&mut plane.offset, range).show_value(false));
+                }
+
+                let response = ui.checkbox(
+                    &mut self.box_enabled,
+                    RichText::new("Clip box").font(FontId::proportional(15.0)),
+                );
+                if response.changed() && self.clip_box == Aabb::empty() {
+                    self.clip_box = bounds;
+                }
+                if self.box_enabled {
+                    for (axis, name) in ["X", "Y", "Z"].into_iter().enumerate() {
+                        let range = bounds.min[axis]..=bounds.max[axis];
+                        ui.horizontal(|ui| {
+                            ui.label(RichText::new(name).font(FontId::proportional(15.0)));
+                            ui.add(
+                                Slider::new(&mut self.clip_box.min[axis], range.clone())
+                                    .show_value(false),
+                            );
+                            ui.add(
+                                Slider::new(&mut self.clip_box.max[axis], range).show_value(false),
+                            );
+                        });
+                    }
+                }
+            },
+        );
+    }
+}
+
+/// Offsets of planes with `normal` that touch `bounds`
+fn plane_range(bounds: &Aabb, normal: [f32; 3]) -> std::ops::RangeInclusive<f32> {
+    let offsets = (0..8).map(|corner| {
+        (0..3)
+            .map(|axis| match corner >> axis & 1 {
+                0 => bounds.min[axis] * normal[axis],
+                _ => bounds.max[axis] * normal[axis],
+            })
+            .sum::<f32>()
+    });
+    let (min, max) = offsets.fold((f32::MAX, f32::MIN), |(min, max), offset| {
+        (min.min(offset), max.max(offset))
+    });
+    min..=max
+}
+
+/// Colour mapped values of a grid on an axis aligned plane, shown in their own window
+#[derive(Default)]
+pub struct SliceSettings {
+    pub show: bool,
+    /// Index of the grid in the context
+    pub grid: usize,
+    /// Grids in the context, set by the runtime
+    pub grids: usize,
+    /// Index space axis the plane is normal to
+    pub axis: usize,
+    /// Index coordinate of the plane along `axis`
+    pub position: i32,
+    pub colormap: Colormap,
+    /// Inclusive index bounds of the active voxels of the grid, set by the runtime
+    pub active_bounds: Option<([i32; 3], [i32; 3])>,
+    /// [`SliceSettings::key`] of the image in `texture`, cleared to redo the image
+    pub shown: Option<(usize, usize, i32, Colormap)>,
+    pub texture: Option<TextureHandle>,
+    /// Index coordinates of the lower left pixel along the two axes in the plane
+    pub origin: [i32; 2],
+    /// Values at the ends of the colour map
+    pub range: [f32; 2],
+}
+
+impl std::fmt::Debug for SliceSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SliceSettings")
+            .field("show", &self.show)
+            .field("grid", &self.grid)
+            .field("axis", &self.axis)
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SliceSettings {
+    const AXES: [&'static str; 3] = ["X", "Y", "Z"];
+
+    /// Grid, axis, position and colour map of the asked for image
+    pub fn key(&self) -> (usize, usize, i32, Colormap) {
+        (self.grid, self.axis, self.position, self.colormap)
+    }
+
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Slice").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.checkbox(
+                    &mut self.show,
+                    RichText::new("Show slice").font(FontId::proportional(15.0)),
+                );
+                if self.grids > 1 {
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Grid").font(FontId::proportional(15.0)));
+                        ui.add(Slider::new(&mut self.grid, 0..=self.grids - 1));
+                    });
+                }
 
                 ui.horizontal(|ui| {
-                    ui.label(RichText::new("Intensity").font(FontId::proportional(15.0)));
-                    ui.add(Slider::new(&mut self.intensity, 0.0..=50.0));
+                    ui.label(RichText::new("Normal").font(FontId::proportional(15.0)));
+                    for (axis, name) in Self::AXES.into_iter().enumerate() {
+                        ui.selectable_value(
+                            &mut self.axis,
+                            axis,
+                            RichText::new(name).font(FontId::proportional(15.0)),
+                        );
+                    }
                 });
 
+                if let Some((min, max)) = self.active_bounds {
+                    ui.add(Slider::new(
+                        &mut self.position,
+                        min[self.axis]..=max[self.axis],
+                    ));
+                }
+
                 ui.horizontal(|ui| {
-                    ui.label(RichText::new("Light color").font(FontId::proportional(15.0)));
-                    ui.color_edit_button_rgb(&mut self.color);
+                    for colormap in Colormap::ALL {
+                        ui.selectable_value(
+                            &mut self.colormap,
+                            colormap,
+                            RichText::new(colormap.to_string()).font(FontId::proportional(15.0)),
+                        );
+                    }
                 });
+
+                if ui
+                    .button(RichText::new("Refresh").font(FontId::proportional(15.0)))
+                    .on_hover_text("Slices again, after editing the grid")
+                    .clicked()
+                {
+                    self.shown = None;
+                }
+            },
+        );
+    }
+
+    fn show_window(&mut self, ctx: &egui::Context) {
+        if !self.show {
+            return;
+        }
+
+        let [a, b] = slice_axes(self.axis).map(|axis| Self::AXES[axis]);
+        egui::Window::new("Slice")
+            .open(&mut self.show)
+            .resizable(true)
+            .show(ctx, |ui| {
+                let Some(texture) = &self.texture else {
+                    ui.label(
+                        RichText::new("No active voxels on this plane")
+                            .font(FontId::proportional(15.0)),
+                    );
+                    return;
+                };
+
+                let [low, high] = self.range;
+                ui.label(
+                    RichText::new(format!(
+                        "{} = {}, values {low:.3} to {high:.3}",
+                        Self::AXES[self.axis],
+                        self.position
+                    ))
+                    .font(FontId::proportional(15.0)),
+                );
+
+                // Whole pixels per voxel keep the voxels square and sharp
+                let size = texture.size_vec2();
+                let available = ui.available_size().max(Vec2::splat(256.));
+                let scale = (available.x / size.x).min(available.y / size.y);
+                let scale = if scale >= 1. { scale.floor() } else { scale };
+                let response = ui.image((texture.id(), size * scale));
+
+                if let Some(pointer) = response.hover_pos() {
+                    let local = (pointer - response.rect.min) / scale;
+                    let column = self.origin[0] + local.x as i32;
+                    let row = self.origin[1] + (size.y - local.y) as i32;
+                    response.on_hover_text(format!("{a} {column}, {b} {row}"));
+                }
+            });
+    }
+}
+
+/// The two index space axes spanning a slice normal to `axis`, in column and row order
+pub fn slice_axes(axis: usize) -> [usize; 2] {
+    match axis {
+        0 => [1, 2],
+        1 => [0, 2],
+        _ => [0, 1],
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueCommand {
+    /// Set the range to the lowest and highest active value of the grids
+    FitRange,
+}
+
+/// Bounces and sky of the `PathTrace` render mode
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathTraceSettings {
+    /// Surfaces a path scatters off before it stops
+    pub bounces: u32,
+    /// Linear RGB colour of the sky overhead, it fades to white at the horizon
+    pub sky_color: [f32; 3],
+    pub sky_intensity: f32,
+    /// Samples summed for each frame of offline renders
+    pub export_samples: u32,
+    /// Samples summed on screen so far, set by the context
+    #[serde(skip)]
+    pub samples: u32,
+}
+
+impl Default for PathTraceSettings {
+    fn default() -> Self {
+        Self {
+            bounces: 4,
+            sky_color: [0.35, 0.55, 0.9],
+            sky_intensity: 1.0,
+            export_samples: 64,
+            samples: 0,
+        }
+    }
+}
+
+impl PathTraceSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Path tracing").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.label(
+                    RichText::new(format!("Samples: {}", self.samples))
+                        .font(FontId::proportional(15.0)),
+                );
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Bounces").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.bounces, 1..=8));
+                });
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Sky color").font(FontId::proportional(15.0)));
+                    ui.color_edit_button_rgb(&mut self.sky_color);
+                });
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Sky intensity").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.sky_intensity, 0.0..=4.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Export samples").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.export_samples, 1..=1024).logarithmic(true));
+                });
+            },
+        );
+    }
+}
+
+/// Rays per pixel, blending over frames and the resolution the compute pass traces at
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingSettings {
+    /// Jittered rays averaged in every pixel of the modes other than `PathTrace`
+    pub samples: u32,
+    /// Blend every frame with the frames before it, reprojected onto the moved camera
+    pub temporal: bool,
+    /// Weight of the new frame in the blend, lower values smooth more but trail longer
+    pub temporal_weight: f32,
+    /// Share of the window resolution traced, upsampled to the window. Recordings trace at
+    /// the full resolution
+    pub render_scale: f32,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        Self {
+            samples: 1,
+            temporal: true,
+            temporal_weight: 0.1,
+            render_scale: 1.0,
+        }
+    }
+}
+
+impl SamplingSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Sampling").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Samples").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.samples, 1..=16))
+                        .on_hover_text("Rays per pixel and frame, outside the path traced mode");
+                });
+                ui.checkbox(
+                    &mut self.temporal,
+                    RichText::new("Temporal anti-aliasing").font(FontId::proportional(15.0)),
+                );
+                if self.temporal {
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Blend").font(FontId::proportional(15.0)));
+                        ui.add(
+                            Slider::new(&mut self.temporal_weight, 0.02..=1.0).logarithmic(true),
+                        )
+                        .on_hover_text("Weight of the new frame");
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Render scale").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.render_scale, 0.25..=1.0));
+                });
+            },
+        );
+    }
+}
+
+/// Brightness and tone mapping of the HDR frame before it is encoded to sRGB for the window,
+/// recordings and exports
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneSettings {
+    /// Stops the traced colours are brightened by, negative values darken them
+    pub exposure: f32,
+    /// Curve of every mode but `Value`, whose colours stay those of its legend
+    pub tone_mapper: ToneMapper,
+}
+
+impl Default for ToneSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::default(),
+        }
+    }
+}
+
+impl ToneSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Tone mapping").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Exposure").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.exposure, -8.0..=8.0).suffix(" EV"));
+                });
+                ComboBox::from_label(RichText::new("Tone mapper").font(FontId::proportional(15.0)))
+                    .selected_text(
+                        RichText::new(self.tone_mapper.to_string())
+                            .font(FontId::proportional(15.0)),
+                    )
+                    .show_ui(ui, |ui| {
+                        for tone_mapper in ToneMapper::ALL {
+                            ui.selectable_value(
+                                &mut self.tone_mapper,
+                                tone_mapper,
+                                RichText::new(tone_mapper.to_string())
+                                    .font(FontId::proportional(15.0)),
+                            );
+                        }
+                    });
+            },
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaterialCommand {
+    /// Read the grids again, with the colours of grid `.0` from the named grid or none
+    ColorGrid(usize, Option<String>),
+}
+
+/// Surface of every grid in the context, grids past the end of `materials` use the default
+#[derive(Debug, Default)]
+pub struct MaterialSettings {
+    pub materials: Vec<Material>,
+    /// Where each grid in the context was read from, set by the context
+    pub sources: Vec<GridSource>,
+    /// Set by the colour grid pickers, taken by the runtime
+    pub command: Option<MaterialCommand>,
+}
+
+impl MaterialSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui, models: &[VdbFile]) {
+        ui.collapsing(
+            RichText::new("Materials").font(FontId::proportional(15.0)),
+            |ui| {
+                if self.sources.is_empty() {
+                    ui.label(RichText::new("No grids").font(FontId::proportional(15.0)));
+                    return;
+                }
+                if self.materials.len() < self.sources.len() {
+                    self.materials
+                        .resize(self.sources.len(), Material::default());
+                }
+
+                for (idx, (source, material)) in
+                    self.sources.iter().zip(&mut self.materials).enumerate()
+                {
+                    ui.label(
+                        RichText::new(format!("{idx}: {}", source.grid))
+                            .font(FontId::proportional(15.0)),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Albedo").font(FontId::proportional(15.0)));
+                        ui.color_edit_button_rgb(&mut material.albedo);
+                        ui.label(RichText::new("Emission").font(FontId::proportional(15.0)));
+                        ui.color_edit_button_rgb(&mut material.emission);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Roughness").font(FontId::proportional(15.0)));
+                        ui.add(Slider::new(&mut material.roughness, 0.0..=1.0));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Metallic").font(FontId::proportional(15.0)));
+                        ui.add(Slider::new(&mut material.metallic, 0.0..=1.0));
+                    });
+
+                    // Colour grids are picked among the other grids of the same file
+                    if let Some(model) = models.iter().find(|model| model.path == source.path) {
+                        ui.horizontal(|ui| {
+                            ui.label(RichText::new("Colours").font(FontId::proportional(15.0)));
+                            if let Some(color) = Self::color_grid_picker(ui, idx, source, model) {
+                                self.command = Some(MaterialCommand::ColorGrid(idx, color));
+                            }
+                        });
+                    }
+                    ui.separator();
+                }
+            },
+        );
+    }
+
+    /// Colour grid of `source` among the grids of `model`, the newly picked one if it changed
+    fn color_grid_picker(
+        ui: &mut egui::Ui,
+        idx: usize,
+        source: &GridSource,
+        model: &VdbFile,
+    ) -> Option<Option<String>> {
+        let mut picked = None;
+        let selected = source.color.as_deref().unwrap_or("None");
+        ComboBox::from_id_source(("colour grid", idx))
+            .selected_text(RichText::new(selected).font(FontId::proportional(15.0)))
+            .show_ui(ui, |ui| {
+                let grids = model.grids.iter().filter(|grid| **grid != source.grid);
+                for choice in std::iter::once(None).chain(grids.map(Some)) {
+                    let text = choice.map_or("None", |grid| grid.as_str());
+                    let response = ui
+                        .selectable_label(
+                            source.color.as_ref() == choice,
+                            RichText::new(text).font(FontId::proportional(15.0)),
+                        )
+                        .on_hover_text("Reads the grids again, unsaved edits are lost");
+                    if response.clicked() && source.color.as_ref() != choice {
+                        picked = Some(choice.cloned());
+                    }
+                }
+            });
+        picked
+    }
+}
+
+/// Lights besides the sun and the environment map lighting the scene in place of the sky
+#[derive(Debug, Default)]
+pub struct LightSettings {
+    pub lights: Vec<Light>,
+    /// Its file is read by the context once it changes
+    pub environment: Environment,
+    /// Environment file typed into the panel, taken by the load button
+    pub environment_file: String,
+    /// Result of reading the environment file, set by the context
+    pub status: String,
+    /// The environment map was read and lights the scene, set by the context
+    pub environment_loaded: bool,
+}
+
+impl LightSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Lights").font(FontId::proportional(15.0)),
+            |ui| {
+                let mut removed = None;
+                for (idx, light) in self.lights.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ComboBox::from_id_source(("light kind", idx))
+                            .selected_text(
+                                RichText::new(light.kind.to_string())
+                                    .font(FontId::proportional(15.0)),
+                            )
+                            .show_ui(ui, |ui| {
+                                for kind in LightKind::ALL {
+                                    ui.selectable_value(
+                                        &mut light.kind,
+                                        kind,
+                                        RichText::new(kind.to_string())
+                                            .font(FontId::proportional(15.0)),
+                                    );
+                                }
+                            });
+                        ui.color_edit_button_rgb(&mut light.color);
+                        if ui.small_button("x").on_hover_text("Remove light").clicked() {
+                            removed = Some(idx);
+                        }
+                    });
+                    Self::light_frame(ui, light);
+                    ui.separator();
+                }
+                if let Some(idx) = removed {
+                    self.lights.remove(idx);
+                }
+                if ui
+                    .button(RichText::new("Add light").font(FontId::proportional(15.0)))
+                    .clicked()
+                {
+                    self.lights.push(Light::default());
+                }
+
+                ui.separator();
+                ui.label(RichText::new("Environment").font(FontId::proportional(15.0)));
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.environment_file)
+                        .on_hover_text("Equirectangular .hdr image, the sky without one");
+                    if ui
+                        .button(RichText::new("Load").font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        let file = self.environment_file.trim();
+                        self.environment.path = (!file.is_empty()).then(|| file.to_string());
+                    }
+                    if ui
+                        .button(RichText::new("Clear").font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        self.environment.path = None;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Intensity").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.environment.intensity, 0.0..=4.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Rotation").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.environment.rotation, -180.0..=180.0));
+                });
+                if !self.status.is_empty() {
+                    ui.label(RichText::new(&self.status).font(FontId::proportional(15.0)));
+                }
+            },
+        );
+    }
+
+    /// Placement, strength and shadows of `light`, only showing what its kind uses
+    fn light_frame(ui: &mut egui::Ui, light: &mut Light) {
+        if light.kind != LightKind::Directional {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Position").font(FontId::proportional(15.0)));
+                for axis in &mut light.position {
+                    ui.add(egui::DragValue::new(axis).speed(1.0));
+                }
+            });
+        }
+        if light.kind != LightKind::Point {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Direction").font(FontId::proportional(15.0)));
+                for axis in &mut light.direction {
+                    ui.add(egui::DragValue::new(axis).speed(0.01));
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label(RichText::new("Intensity").font(FontId::proportional(15.0)));
+            ui.add(Slider::new(&mut light.intensity, 0.0..=1e6).logarithmic(true))
+                .on_hover_text("Point and spot lights fall off with the squared distance");
+        });
+        if light.kind == LightKind::Spot {
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Cone").font(FontId::proportional(15.0)));
+                ui.add(Slider::new(&mut light.cone_angle, 1.0..=90.0));
+            });
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("Softness").font(FontId::proportional(15.0)));
+                ui.add(Slider::new(&mut light.cone_softness, 0.0..=1.0));
+            });
+        }
+        ui.checkbox(
+            &mut light.shadows,
+            RichText::new("Shadows").font(FontId::proportional(15.0)),
+        );
+    }
+}
+
+/// Colour map and value range of the `Value` render mode
+#[derive(Debug)]
+pub struct ValueSettings {
+    pub colormap: Colormap,
+    /// Values at the ends of the colour map, values outside get the end colours
+    pub range: [f32; 2],
+    pub command: Option<ValueCommand>,
+}
+
+impl Default for ValueSettings {
+    fn default() -> Self {
+        Self {
+            colormap: Colormap::default(),
+            range: [0., 1.],
+            command: None,
+        }
+    }
+}
+
+impl ValueSettings {
+    const LEGEND_SIZE: Vec2 = Vec2::new(200., 16.);
+
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Value colours").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.horizontal(|ui| {
+                    for colormap in Colormap::ALL {
+                        ui.selectable_value(
+                            &mut self.colormap,
+                            colormap,
+                            RichText::new(colormap.to_string()).font(FontId::proportional(15.0)),
+                        );
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    let [low, high] = &mut self.range;
+                    ui.label(RichText::new("Range").font(FontId::proportional(15.0)));
+                    ui.add(egui::DragValue::new(low).speed(0.01));
+                    ui.add(egui::DragValue::new(high).speed(0.01));
+                    if ui
+                        .button(RichText::new("Fit").font(FontId::proportional(15.0)))
+                        .on_hover_text("Lowest to highest active value of the grids")
+                        .clicked()
+                    {
+                        self.command = Some(ValueCommand::FitRange);
+                    }
+                });
+            },
+        );
+    }
+
+    /// Colour bar of the map with the values at its ends, in the lower right corner
+    fn show_legend(&self, ctx: &egui::Context) {
+        egui::Area::new("value legend")
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-10., -10.])
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    let (rect, _) = ui.allocate_exact_size(Self::LEGEND_SIZE, egui::Sense::hover());
+                    let mut mesh = egui::Mesh::default();
+                    let steps = 32;
+                    for step in 0..=steps {
+                        let t = step as f32 / steps as f32;
+                        let x = rect.left() + rect.width() * t;
+                        let color = self.colormap.color32(t);
+                        mesh.colored_vertex(egui::pos2(x, rect.top()), color);
+                        mesh.colored_vertex(egui::pos2(x, rect.bottom()), color);
+                        if step > 0 {
+                            let i = 2 * step;
+                            mesh.add_triangle(i - 2, i - 1, i);
+                            mesh.add_triangle(i - 1, i + 1, i);
+                        }
+                    }
+                    ui.painter().add(egui::Shape::mesh(mesh));
+
+                    let (labels, _) = ui.allocate_exact_size(
+                        Vec2::new(Self::LEGEND_SIZE.x, 18.),
+                        egui::Sense::hover(),
+                    );
+                    let color = ui.visuals().text_color();
+                    for (value, anchor, pos) in [
+                        (self.range[0], egui::Align2::LEFT_TOP, labels.left_top()),
+                        (self.range[1], egui::Align2::RIGHT_TOP, labels.right_top()),
+                    ] {
+                        let text = format!("{value:.3}");
+                        ui.painter()
+                            .text(pos, anchor, text, FontId::proportional(15.0), color);
+                    }
+                });
+            });
+    }
+}
+
+/// Voxel under the cursor, or under the crosshair while the cursor is grabbed
+#[derive(Debug, Clone, PartialEq)]
+pub struct InspectedVoxel {
+    /// Index of the hit object in the scene
+    pub object: usize,
+    /// Index of the object's grid in the context
+    pub grid: usize,
+    /// Position of the voxel through the grid's map
+    pub world: [f32; 3],
+    /// Position of the voxel in the scene, through the object transform as well
+    pub scene: [f32; 3],
+    pub info: VoxelInfo<u32>,
+}
+
+/// Picking of the voxel under the cursor, found by the runtime while `enabled`
+#[derive(Debug, Default)]
+pub struct InspectSettings {
+    pub enabled: bool,
+    pub hit: Option<InspectedVoxel>,
+}
+
+impl InspectSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Inspect").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.checkbox(
+                    &mut self.enabled,
+                    RichText::new("Pick the voxel under the cursor")
+                        .font(FontId::proportional(15.0)),
+                );
+                if !self.enabled {
+                    return;
+                }
+
+                let Some(hit) = &self.hit else {
+                    ui.label(
+                        RichText::new("No voxel under the cursor").font(FontId::proportional(15.0)),
+                    );
+                    return;
+                };
+
+                let info = &hit.info;
+                let [x, y, z] = [info.voxel.x, info.voxel.y, info.voxel.z];
+                let [wx, wy, wz] = hit.world;
+                let [sx, sy, sz] = hit.scene;
+                let value = match info.endpoint {
+                    VdbEndpoint::Leaf(value) => format!("Value {}", f32::from_bits(value)),
+                    VdbEndpoint::Offs(distance) => format!("Empty, {distance} voxels from a value"),
+                    VdbEndpoint::Innr(distance, level) => {
+                        format!("N{level} tile, {distance} nodes from a value")
+                    }
+                    VdbEndpoint::Root(distance) => {
+                        format!("Root tile, {distance} nodes from a value")
+                    }
+                    VdbEndpoint::Bkgr(value) => format!("Background {}", f32::from_bits(value)),
+                };
+
+                for text in [
+                    format!("Object {}, grid {}", hit.object, hit.grid),
+                    format!("Voxel {x} {y} {z}"),
+                    format!("World {wx:.3} {wy:.3} {wz:.3}"),
+                    format!("Scene {sx:.3} {sy:.3} {sz:.3}"),
+                    format!(
+                        "{value}, {}",
+                        if info.active { "active" } else { "inactive" }
+                    ),
+                ] {
+                    ui.label(RichText::new(text).font(FontId::proportional(15.0)));
+                }
+                for (name, origin) in ["N5", "N4", "N3"].into_iter().zip(info.nodes) {
+                    let text = match origin {
+                        Some(o) => format!("{name} at {} {} {}", o.x, o.y, o.z),
+                        None => format!("No {name}"),
+                    };
+                    ui.label(RichText::new(text).font(FontId::proportional(15.0)));
+                }
+            },
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimelineCommand {
+    /// Record a video of the whole sequence, one video frame per sequence frame
+    Export,
+}
+
+/// Playback controls of a frame sequence, hidden while a single grid is shown
+#[derive(Debug)]
+pub struct TimelineSettings {
+    /// Frames in the sequence, 0 without one
+    pub frames: usize,
+    /// Frame to show, moved by playback and the scrub bar
+    pub frame: usize,
+    pub playing: bool,
+    pub looping: bool,
+    pub fps: f32,
+    /// Frames read ahead of the one to show
+    pub prefetch: usize,
+    /// Frames read and waiting to be shown
+    pub buffered: usize,
+    /// Frame numbers of the sequence files
+    pub numbers: Vec<u32>,
+    /// Result of the last export or the last frame that could not be read
+    pub status: String,
+    /// Set by the panel buttons, taken by the runtime
+    pub command: Option<TimelineCommand>,
+}
+
+impl Default for TimelineSettings {
+    fn default() -> Self {
+        Self {
+            frames: 0,
+            frame: 0,
+            playing: false,
+            looping: true,
+            fps: 24.,
+            prefetch: 4,
+            buffered: 0,
+            numbers: vec![],
+            status: String::new(),
+            command: None,
+        }
+    }
+}
+
+impl TimelineSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        if self.frames == 0 {
+            return;
+        }
+
+        ui.collapsing(
+            RichText::new("Timeline").font(FontId::proportional(15.0)),
+            |ui| {
+                let number = self.numbers.get(self.frame).copied().unwrap_or_default();
+                ui.horizontal(|ui| {
+                    let text = if self.playing { "Pause" } else { "Play" };
+                    if ui
+                        .button(RichText::new(text).font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        self.playing = !self.playing;
+                    }
+                    ui.checkbox(
+                        &mut self.looping,
+                        RichText::new("Loop").font(FontId::proportional(15.0)),
+                    );
+                    ui.label(
+                        RichText::new(format!("Frame {number}")).font(FontId::proportional(15.0)),
+                    );
+                });
+
+                ui.add(Slider::new(&mut self.frame, 0..=self.frames - 1).show_value(false));
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("FPS").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.fps, 1.0..=60.0));
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Prefetch").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.prefetch, 1..=16));
+                    ui.label(
+                        RichText::new(format!("{} ready", self.buffered))
+                            .font(FontId::proportional(15.0)),
+                    );
+                });
+
+                if ui
+                    .button(RichText::new("Record playback").font(FontId::proportional(15.0)))
+                    .on_hover_text("Records every frame once to the recording file")
+                    .clicked()
+                {
+                    self.command = Some(TimelineCommand::Export);
+                }
+
+                if !self.status.is_empty() {
+                    ui.label(RichText::new(&self.status).font(FontId::proportional(15.0)));
+                }
+            },
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportCommand {
+    /// Store the current camera at `keyframe_time` in the camera path of the scene
+    AddKeyframe,
+    ClearKeyframes,
+    /// Replace the camera path with a circle around every object, `duration` seconds long
+    Turntable,
+    Start,
+    Cancel,
+}
+
+/// Offline render of the camera path at a fixed size and frame rate
+#[derive(Debug)]
+pub struct ExportSettings {
+    /// Video file, or a `.png` name for a numbered image sequence
+    pub output: String,
+    pub size: [u32; 2],
+    pub fps: u32,
+    /// Length of a turntable, and of an export without keyframes
+    pub duration: f32,
+    /// Time the next keyframe is added at, in seconds
+    pub keyframe_time: f32,
+    /// Keyframes in the camera path of the scene
+    pub keyframes: usize,
+    /// Frames rendered and frames in total while exporting
+    pub progress: Option<(u64, u64)>,
+    /// Result of the last export
+    pub status: String,
+    /// Set by the panel buttons, taken by the runtime
+    pub command: Option<ExportCommand>,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            output: "export.mp4".to_string(),
+            size: [1920, 1080],
+            fps: 30,
+            duration: 8.,
+            keyframe_time: 0.,
+            keyframes: 0,
+            progress: None,
+            status: String::new(),
+            command: None,
+        }
+    }
+}
+
+impl ExportSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.label(RichText::new("Export").font(FontId::proportional(15.0)));
+        ui.text_edit_singleline(&mut self.output);
+
+        ui.horizontal(|ui| {
+            ui.label(RichText::new("Size").font(FontId::proportional(15.0)));
+            ui.add(egui::DragValue::new(&mut self.size[0]).clamp_range(2..=8192));
+            ui.add(egui::DragValue::new(&mut self.size[1]).clamp_range(2..=8192));
+            ui.label(RichText::new("FPS").font(FontId::proportional(15.0)));
+            ui.add(egui::DragValue::new(&mut self.fps).clamp_range(1..=120));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(RichText::new("Keyframe at").font(FontId::proportional(15.0)));
+            ui.add(
+                egui::DragValue::new(&mut self.keyframe_time)
+                    .speed(0.1)
+                    .clamp_range(0.0..=3600.0)
+                    .suffix(" s"),
+            );
+            if ui
+                .button(RichText::new("Add").font(FontId::proportional(15.0)))
+                .clicked()
+            {
+                self.command = Some(ExportCommand::AddKeyframe);
+            }
+            if ui
+                .button(RichText::new("Clear").font(FontId::proportional(15.0)))
+                .clicked()
+            {
+                self.command = Some(ExportCommand::ClearKeyframes);
+            }
+            ui.label(
+                RichText::new(format!("{} keyframes", self.keyframes))
+                    .font(FontId::proportional(15.0)),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label(RichText::new("Duration").font(FontId::proportional(15.0)));
+            ui.add(
+                egui::DragValue::new(&mut self.duration)
+                    .speed(0.1)
+                    .clamp_range(0.1..=3600.0)
+                    .suffix(" s"),
+            );
+            if ui
+                .button(RichText::new("Turntable").font(FontId::proportional(15.0)))
+                .on_hover_text("Replaces the keyframes with a circle around the scene")
+                .clicked()
+            {
+                self.command = Some(ExportCommand::Turntable);
+            }
+        });
+
+        match self.progress {
+            Some((done, total)) => {
+                ui.add(
+                    ProgressBar::new(done as f32 / total as f32)
+                        .text(format!("Frame {done} of {total}")),
+                );
+                if ui
+                    .button(RichText::new("Cancel export").font(FontId::proportional(15.0)))
+                    .clicked()
+                {
+                    self.command = Some(ExportCommand::Cancel);
+                }
+            }
+            None => {
+                if ui
+                    .button(RichText::new("Export").font(FontId::proportional(15.0)))
+                    .on_hover_text("Renders the camera path offline, frame by frame")
+                    .clicked()
+                {
+                    self.command = Some(ExportCommand::Start);
+                }
+            }
+        }
+
+        if !self.status.is_empty() {
+            ui.label(RichText::new(&self.status).font(FontId::proportional(15.0)));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditCommand {
+    Undo,
+    Redo,
+    Save,
+}
+
+#[derive(Debug)]
+pub struct EditSettings {
+    /// Left click applies the brush under the crosshair instead of releasing the cursor
+    pub enabled: bool,
+    pub shape: BrushShape,
+    pub mode: BrushMode,
+    pub radius: u32,
+    /// Value of added and painted voxels, the viewer grids hold float bits
+    pub value: f32,
+    pub save_file: String,
+    /// Result of the last save, shown under the save button
+    pub status: String,
+    /// Set by the panel buttons, taken by the runtime
+    pub command: Option<EditCommand>,
+}
+
+impl Default for EditSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shape: BrushShape::Sphere,
+            mode: BrushMode::Add,
+            radius: 2,
+            value: 0.,
+            save_file: "assets/edited.vdb".to_string(),
+            status: String::new(),
+            command: None,
+        }
+    }
+}
+
+impl EditSettings {
+    pub fn brush(&self) -> Brush<u32> {
+        Brush {
+            shape: self.shape,
+            mode: self.mode,
+            radius: self.radius,
+            value: self.value.to_bits(),
+        }
+    }
+
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Editing").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.toggle_value(
+                    &mut self.enabled,
+                    RichText::new("Edit mode (E)").font(FontId::proportional(15.0)),
+                );
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Tool: ").font(FontId::proportional(15.0)));
+                    for (mode, text) in [
+                        (BrushMode::Add, "Add"),
+                        (BrushMode::Remove, "Remove"),
+                        (BrushMode::Paint, "Paint"),
+                    ] {
+                        ui.selectable_value(
+                            &mut self.mode,
+                            mode,
+                            RichText::new(text).font(FontId::proportional(15.0)),
+                        );
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Brush: ").font(FontId::proportional(15.0)));
+                    for (shape, text) in
+                        [(BrushShape::Sphere, "Sphere"), (BrushShape::Cube, "Cube")]
+                    {
+                        ui.selectable_value(
+                            &mut self.shape,
+                            shape,
+                            RichText::new(text).font(FontId::proportional(15.0)),
+                        );
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Radius").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.radius, 0..=32));
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Value").font(FontId::proportional(15.0)));
+                    ui.add(egui::DragValue::new(&mut self.value).speed(0.01));
+                });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button(RichText::new("Undo").font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        self.command = Some(EditCommand::Undo);
+                    }
+                    if ui
+                        .button(RichText::new("Redo").font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        self.command = Some(EditCommand::Redo);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button(RichText::new("Save as").font(FontId::proportional(15.0)))
+                        .clicked()
+                    {
+                        self.command = Some(EditCommand::Save);
+                    }
+                    ui.text_edit_singleline(&mut self.save_file);
+                });
+
+                if !self.status.is_empty() {
+                    ui.label(RichText::new(&self.status).font(FontId::proportional(15.0)));
+                }
+            },
+        );
+    }
+
+    fn draw_crosshair(&self, ctx: &egui::Context) {
+        let painter = ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Foreground,
+            egui::Id::new("crosshair"),
+        ));
+        let center = ctx.screen_rect().center();
+        let stroke = egui::Stroke::new(2.0, Color32::WHITE);
+
+        painter.line_segment([center - Vec2::X * 8., center + Vec2::X * 8.], stroke);
+        painter.line_segment([center - Vec2::Y * 8., center + Vec2::Y * 8.], stroke);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SunSettings {
+    pub dir3: glam::Vec3,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Darken the ambient light where the distance field closes in around a surface
+    pub ambient_occlusion: bool,
+    /// Furthest distance from the surface that occludes, in voxels
+    pub occlusion_radius: f32,
+    pub occlusion_strength: f32,
+    /// Penumbrae from how close shadow rays pass by voxels, instead of hard shadows
+    pub soft_shadows: bool,
+    /// Higher values give narrower penumbrae
+    pub shadow_hardness: f32,
+}
+
+impl Default for SunSettings {
+    fn default() -> Self {
+        Self {
+            dir3: glam::Vec3 {
+                x: 1.0,
+                y: -1.0,
+                z: 0.5,
+            }
+            .normalize(),
+            color: [255. / 255., 210. / 255., 160. / 255.],
+            intensity: 1.0,
+            ambient_occlusion: true,
+            occlusion_radius: 6.0,
+            occlusion_strength: 1.0,
+            soft_shadows: true,
+            shadow_hardness: 8.0,
+        }
+    }
+}
+
+impl SunSettings {
+    fn get_frame(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(
+            RichText::new("Sunlight settings").font(FontId::proportional(15.0)),
+            |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(
+                        RichText::new(format!("X: {:.2}", self.dir3.x))
+                            .font(FontId::proportional(15.0))
+                            .color(Color32::RED),
+                    );
+                    ui.label(
+                        RichText::new(format!("Y: {:.2}", self.dir3.y))
+                            .font(FontId::proportional(15.0))
+                            .color(Color32::from_rgb(0, 136, 255)),
+                    );
+                    ui.label(
+                        RichText::new(format!("Z: {:.2}", self.dir3.z))
+                            .font(FontId::proportional(15.0))
+                            .color(Color32::GREEN),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.vertical(|ui| {
+                        ui.set_max_width(150.);
+                        ui.set_min_height(150.);
+                        ui.centered_and_justified(|ui| {
+                            self.get_circle(ui);
+                        });
+                    });
+                    ui.vertical_centered_justified(|ui| {
+                        let prev_fg_color = ui.style().visuals.widgets.inactive.fg_stroke.color;
+                        let prev_ac_color = ui.style().visuals.widgets.active.fg_stroke.color;
+                        let prev_hv_color = ui.style().visuals.widgets.hovered.fg_stroke.color;
+
+                        ui.style_mut().visuals.widgets.inactive.fg_stroke.color =
+                            Color32::from_rgb(0, 136, 255);
+                        ui.style_mut().visuals.widgets.active.fg_stroke.color =
+                            Color32::from_rgb(20, 160, 255);
+                        ui.style_mut().visuals.widgets.hovered.fg_stroke.color =
+                            Color32::from_rgb(20, 160, 255);
+                        let mut slider_val = -self.dir3.y;
+                        ui.add_space(25.);
+                        ui.add(
+                            Slider::new(&mut slider_val, -1.0..=1.0)
+                                .vertical()
+                                .show_value(false),
+                        )
+                        .rect;
+                        ui.style_mut().visuals.widgets.inactive.fg_stroke.color = prev_fg_color;
+                        ui.style_mut().visuals.widgets.active.fg_stroke.color = prev_ac_color;
+                        ui.style_mut().visuals.widgets.hovered.fg_stroke.color = prev_hv_color;
+                        self.dir3.y = -slider_val;
+                        self.dir3 = self.dir3.normalize()
+                    });
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Intensity").font(FontId::proportional(15.0)));
+                    ui.add(Slider::new(&mut self.intensity, 0.0..=50.0));
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("Light color").font(FontId::proportional(15.0)));
+                    ui.color_edit_button_rgb(&mut self.color);
+                });
+
+                ui.checkbox(
+                    &mut self.ambient_occlusion,
+                    RichText::new("Ambient occlusion").font(FontId::proportional(15.0)),
+                );
+                if self.ambient_occlusion {
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Radius").font(FontId::proportional(15.0)));
+                        ui.add(Slider::new(&mut self.occlusion_radius, 1.0..=32.0));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Strength").font(FontId::proportional(15.0)));
+                        ui.add(Slider::new(&mut self.occlusion_strength, 0.0..=2.0));
+                    });
+                }
+
+                ui.checkbox(
+                    &mut self.soft_shadows,
+                    RichText::new("Soft shadows").font(FontId::proportional(15.0)),
+                );
+                if self.soft_shadows {
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Hardness").font(FontId::proportional(15.0)));
+                        ui.add(
+                            Slider::new(&mut self.shadow_hardness, 1.0..=64.0).logarithmic(true),
+                        );
+                    });
+                }
             },
         );
     }
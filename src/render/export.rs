@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use crate::scene::{CameraPath, Scene};
+
+use super::{
+    egui_dev::ExportSettings,
+    recorder::{Frame, FrameRecorder, RecorderError},
+    WgpuContext,
+};
+
+/// Renders a [`CameraPath`] frame by frame at a fixed size and frame rate, independent of the
+/// window and of how long each frame takes.
+///
+/// Frame `n` shows the path at `n / fps` seconds, so two exports of the same scene and path
+/// give the same frames.
+pub struct OfflineExport {
+    path: CameraPath,
+    size: [u32; 2],
+    fps: u32,
+    /// Next frame to render
+    frame: u64,
+    frames: u64,
+    recorder: FrameRecorder,
+}
+
+impl OfflineExport {
+    /// Export `path` to `settings.output`. A path without keyframes holds the current camera
+    /// for `settings.duration` seconds.
+    pub fn start(settings: &ExportSettings, path: CameraPath) -> Result<Self, RecorderError> {
+        let duration = match path.is_empty() {
+            true => settings.duration,
+            false => path.duration(),
+        };
+        let frames = ((duration * settings.fps as f32).ceil() as u64).max(1);
+
+        let mut recorder =
+            FrameRecorder::new(PathBuf::from(&settings.output), settings.size, settings.fps)?;
+        recorder.start_encoding_thread();
+
+        Ok(Self {
+            path,
+            size: settings.size,
+            fps: settings.fps,
+            frame: 0,
+            frames,
+            recorder,
+        })
+    }
+
+    /// Frames rendered and frames in total
+    pub fn progress(&self) -> (u64, u64) {
+        (self.frame, self.frames)
+    }
+
+    /// Render the next frame and queue it for encoding, `false` once every frame is queued
+    pub fn step(&mut self, context: &WgpuContext, scene: &Scene) -> bool {
+        if self.frame >= self.frames {
+            return false;
+        }
+
+        let [width, height] = self.size;
+        let mut camera = scene.camera.clone();
+        camera.aspect = width as f32 / height as f32;
+        if let Some(description) = self.path.sample(self.frame as f32 / self.fps as f32) {
+            description.apply(&mut camera);
+        }
+
+        let data = context.render_offscreen(scene, &camera, self.size);
+        self.recorder.send_frame(Frame {
+            data,
+            size: self.size,
+        });
+        self.frame += 1;
+
+        self.frame < self.frames
+    }
+
+    /// Wait until the queued frames are written and close the output
+    pub fn finish(mut self) {
+        self.recorder.end_encoder();
+    }
+}
@@ -1,19 +1,21 @@
-use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, Color, Device, Texture};
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, Color, Device, Queue, Texture};
 use winit::dpi::PhysicalSize;
 
 use crate::{
     render::{
-        gpu_types::{ComputeState, GpuUniform},
+        gpu_types::{ComputeState, GpuUniform, StateSettings},
         Camera,
     },
-    scene::State,
+    scene::{Light, Material, SceneObject, State},
+    vdb::{LeafEncoding, TreeShape, VDB345},
 };
 
 use super::{
     egui_dev::EguiDev,
     gpu_types::{
-        ComputeOutputTexture, FragmentTexture, GpuPrimitive, GpuQuad, GpuTexture, NodeAtlas,
-        StateUniform, GPU_QUAD,
+        AccumulationTexture, ComputeOutputTexture, EnvironmentMap, FragmentTexture, GpuPrimitive,
+        GpuQuad, GpuTexture, GridSlot, LeafPages, LightsUniform, NodePoolUniform, ObjectsUniform,
+        PoolSpace, StateUniform, ToneMapUniform, GPU_QUAD,
     },
 };
 
@@ -39,22 +41,37 @@ impl FrameDescriptor {
         StateUniform::from(state).bind(&device)
     }
 
-    pub fn create_compute_state_binding(
+    pub fn create_compute_state(
         camera: &Camera,
         size: PhysicalSize<u32>,
-        device: &Device,
         egui_dev: &EguiDev,
-    ) -> (Buffer, Vec<u8>, BindGroup, BindGroupLayout) {
+        leaf_encoding: LeafEncoding,
+        tree_shape: TreeShape,
+    ) -> ComputeState {
+        let settings = StateSettings {
+            render_mode: egui_dev.render_mode,
+            show_grid: egui_dev.show_grid,
+            clip: &egui_dev.clip_settings,
+            value: &egui_dev.value_settings,
+            sun: &egui_dev.sun_settings,
+            path: &egui_dev.path_trace_settings,
+            light: &egui_dev.light_settings,
+            sampling: &egui_dev.sampling_settings,
+        };
         ComputeState::build(
             camera,
             size.width as f32,
-            egui_dev.render_mode,
-            egui_dev.show_grid,
-            egui_dev.sun_settings.dir3.to_array(),
-            egui_dev.sun_settings.color,
-            egui_dev.sun_settings.intensity,
+            leaf_encoding,
+            tree_shape,
+            &settings,
         )
-        .bind(device)
+    }
+
+    pub fn create_compute_state_binding(
+        device: &Device,
+        state: &ComputeState,
+    ) -> (Buffer, Vec<u8>, BindGroup, BindGroupLayout) {
+        state.bind(device)
     }
 
     pub fn create_vertex_buffer(&self, device: &Device) -> Buffer {
@@ -77,18 +94,68 @@ impl FrameDescriptor {
         })
     }
 
+    /// Linear HDR colours the raycaster traces
     pub fn create_compute_output_texture_binding(
         device: &Device,
         size: [u32; 2],
     ) -> (Texture, BindGroup, BindGroupLayout) {
-        ComputeOutputTexture::new(size).bind(device)
+        ComputeOutputTexture::new(size, wgpu::TextureFormat::Rgba16Float).bind(device)
+    }
+
+    /// sRGB bytes of the tone mapped frame, shown, recorded and exported as they are
+    pub fn create_display_texture_binding(
+        device: &Device,
+        size: [u32; 2],
+    ) -> (Texture, BindGroup, BindGroupLayout) {
+        ComputeOutputTexture::new(size, wgpu::TextureFormat::Rgba8Unorm).bind(device)
+    }
+
+    pub fn create_tone_map_binding(
+        device: &Device,
+        egui_dev: &EguiDev,
+        hdr: &Texture,
+    ) -> (Buffer, Vec<u8>, BindGroup, BindGroupLayout) {
+        ToneMapUniform::build(&egui_dev.tone_settings, egui_dev.render_mode).bind(device, hdr)
     }
 
-    pub fn create_compute_vdb_atlas_texture_binding(
+    pub fn create_compute_node_pool_binding(
         device: &Device,
-        [size5, size4, size3]: [[u32; 3]; 3],
-    ) -> ([Texture; 3], BindGroup, BindGroupLayout) {
-        NodeAtlas::new(size5, size4, size3).bind(device)
+        queue: &Queue,
+        pools: &NodePoolUniform,
+        space: &PoolSpace,
+    ) -> (
+        [Buffer; 6],
+        Option<LeafPages>,
+        [Vec<u8>; 6],
+        BindGroup,
+        BindGroupLayout,
+    ) {
+        pools.bind(device, queue, space)
+    }
+
+    pub fn create_compute_objects_binding(
+        device: &Device,
+        objects: &[SceneObject],
+        materials: &[Material],
+        grids: &[VDB345<u32>],
+        slots: &[GridSlot],
+    ) -> ([Buffer; 2], [Vec<u8>; 2], BindGroup, BindGroupLayout) {
+        ObjectsUniform::build(objects, materials, grids, slots).bind(device)
+    }
+
+    pub fn create_compute_lights_binding(
+        device: &Device,
+        lights: &[Light],
+        environment: &EnvironmentMap,
+    ) -> (Buffer, Vec<u8>, BindGroup, BindGroupLayout) {
+        LightsUniform::build(lights).bind(device, environment)
+    }
+
+    pub fn create_accumulation_binding(
+        device: &Device,
+        size: [u32; 2],
+    ) -> ([Texture; 2], BindGroup, BindGroupLayout) {
+        AccumulationTexture::new(size).bind(device)
     }
 
     pub fn create_fragment_texture_binding(
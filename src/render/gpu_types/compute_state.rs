@@ -1,6 +1,16 @@
-use crate::render::{egui_dev::RenderMode, gpu_types::GpuUniform, Camera};
+use crate::{
+    render::{
+        egui_dev::{
+            ClipSettings, LightSettings, PathTraceSettings, RenderMode, SamplingSettings,
+            SunSettings, ValueSettings, MAX_CLIP_PLANES,
+        },
+        gpu_types::GpuUniform,
+        Camera, Projection, COLORMAP_STOPS,
+    },
+    vdb::{LeafEncoding, TreeShape},
+};
 use bytemuck_derive::{Pod, Zeroable};
-use cgmath::SquareMatrix;
+use cgmath::{InnerSpace, Matrix3, Matrix4, SquareMatrix, Vector3};
 use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, Device};
 
 #[repr(C)]
@@ -18,6 +28,9 @@ pub struct ComputeState {
     mv: [f32; 4],
     // w' = (-width / 2) u + (height / 2) v - ((height / 2) / tan(fov * 0.5)) w
     wp: [f32; 4],
+    // View direction, w is 1 for orthographic projection where u, mv and w' place the ray
+    // origins instead of directions
+    forward: [f32; 4],
     // Type of rendering we are doing
     render_mode: [u32; 4],
     // Flags that enable/disable highlighting boundry voxels
@@ -26,6 +39,58 @@ pub struct ComputeState {
     sun_dir: [f32; 4],
     // Color of sun vector, alpha channel is for intenisty
     sun_color: [f32; 4],
+    // Log2 dimensions of the node levels of the packed trees, top level first
+    tree_log2_dims: [u32; 4],
+    // Number of node levels of the packed trees
+    tree_levels: u32,
+    // How the leaf level of the node pool is stored, in `LeafEncoding::ALL` order
+    leaf_encoding: u32,
+    _padding: [u32; 2],
+    // Normal and offset of the enabled clip planes, zero normals are unused
+    clip_planes: [[f32; 4]; MAX_CLIP_PLANES],
+    // World space clip box, w of the min is 1 when it is enabled
+    clip_min: [f32; 4],
+    clip_max: [f32; 4],
+    // Values at the ends of the colour map of the value render mode
+    value_range: [f32; 4],
+    // Evenly spaced colours of the colour map
+    colormap: [[f32; 4]; COLORMAP_STOPS],
+    // Ambient occlusion radius in voxels and strength, 0 when off, and the soft shadow
+    // hardness, 0 for hard shadows
+    shading: [f32; 4],
+    // Samples summed in the accumulation texture before this frame, frames traced since the
+    // start, used to seed the random numbers, and the bounces of the path traced mode
+    frame: [u32; 4],
+    // Colour of the sky overhead, w is its intensity
+    sky: [f32; 4],
+    // Number of lights, then 1 when an environment map lights the scene in place of the sky
+    lights: [u32; 4],
+    // Intensity of the environment map and its turn around the up axis in radians
+    environment: [f32; 4],
+    // Rays per pixel, 1 when frames are blended with the history in the accumulation texture
+    // and 1 when the history holds a frame to blend with
+    sampling: [u32; 4],
+    // Weight of the new frame in the blend with the history
+    history_weight: [f32; 4],
+    // Takes offsets from `history_origin` to the pixel coordinates of the history frame in xy
+    // and the distance along its rays in z, for perspective views up to a division by z
+    history_rays: [[f32; 4]; 4],
+    // Eye of the history frame, or the ray origin of its first pixel with w at 1 for
+    // orthographic views
+    history_origin: [f32; 4],
+}
+
+/// Panel settings a [`ComputeState`] is built from
+#[derive(Debug, Clone, Copy)]
+pub struct StateSettings<'a> {
+    pub render_mode: RenderMode,
+    pub show_grid: [bool; 3],
+    pub clip: &'a ClipSettings,
+    pub value: &'a ValueSettings,
+    pub sun: &'a SunSettings,
+    pub path: &'a PathTraceSettings,
+    pub light: &'a LightSettings,
+    pub sampling: &'a SamplingSettings,
 }
 
 impl GpuUniform for ComputeState {
@@ -87,12 +152,20 @@ impl ComputeState {
     pub fn build(
         c: &Camera,
         resolution_width: f32,
-        render_mode: RenderMode,
-        show_grid: [bool; 3],
-        sun_dir3: [f32; 3],
-        sun_color3: [f32; 3],
-        sun_intensity: f32,
+        leaf_encoding: LeafEncoding,
+        shape: TreeShape,
+        settings: &StateSettings,
     ) -> Self {
+        let StateSettings {
+            render_mode,
+            show_grid,
+            clip,
+            value,
+            sun,
+            path,
+            light,
+            sampling,
+        } = *settings;
         let view_proj = c.build_view_projection_matrix();
         let camera_to_world = match view_proj.invert() {
             Some(c) => c,
@@ -103,18 +176,90 @@ impl ComputeState {
         let u = camera_to_world.x;
         let v = camera_to_world.y;
         let w = camera_to_world.z;
-        let wp = (-resolution_width / 2.0) * u + (height / 2.0) * v
-            - w * (height / 2.0) / (c.fovy.to_radians() * 0.5).tan();
-        let mv = -v;
+        let forward = -w.truncate().normalize();
+        let (u, mv, wp, forward) = match c.projection {
+            Projection::Perspective => {
+                let wp = (-resolution_width / 2.0) * u + (height / 2.0) * v
+                    - w * (height / 2.0) / (c.fovy.to_radians() * 0.5).tan();
+                (u, -v, wp, forward.extend(0.0))
+            }
+            Projection::Orthographic => {
+                // Parallel rays start on the plane of the eye, a pixel apart
+                let pixel = c.ortho_height / height;
+                let (u, mv) = (u * pixel, -v * pixel);
+                let wp =
+                    c.eye.to_homogeneous() - u * (resolution_width / 2.0) - mv * (height / 2.0);
+                (u, mv, wp, forward.extend(1.0))
+            }
+        };
+        // The path traced mode sums its samples in the accumulation texture instead
+        let temporal = sampling.temporal && render_mode != RenderMode::PathTrace;
         let render_mode = [render_mode as u32, 0, 0, 0];
         let show_grid = show_grid.map(|x| x as u32);
         let show_345 = [show_grid[0], show_grid[1], show_grid[2], 0];
 
         let mut sun_dir = [0.0; 4];
-        sun_dir[..3].copy_from_slice(&sun_dir3);
+        sun_dir[..3].copy_from_slice(&sun.dir3.to_array());
+
+        let mut sun_color = [sun.intensity; 4];
+        sun_color[..3].copy_from_slice(&sun.color);
+
+        let mut tree_log2_dims = [0; 4];
+        tree_log2_dims[..shape.levels()].copy_from_slice(shape.log2_dims());
+        let tree_levels = shape.levels() as u32;
+        let leaf_encoding = leaf_encoding as u32;
+
+        let mut clip_planes = [[0.0; 4]; MAX_CLIP_PLANES];
+        let enabled = clip.planes.iter().filter(|plane| plane.enabled);
+        for (gpu_plane, plane) in clip_planes.iter_mut().zip(enabled) {
+            let normal = Vector3::from(plane.normal);
+            if normal.magnitude2() > 0.0 {
+                *gpu_plane = normal.normalize().extend(plane.offset).into();
+            }
+        }
+        let (clip_min, clip_max) = match clip.box_enabled {
+            true => (
+                clip.clip_box.min.extend(1.0).into(),
+                clip.clip_box.max.extend(0.0).into(),
+            ),
+            false => ([0.0; 4], [0.0; 4]),
+        };
 
-        let mut sun_color = [sun_intensity; 4];
-        sun_color[..3].copy_from_slice(&sun_color3);
+        let value_range = [value.range[0], value.range[1], 0.0, 0.0];
+        let colormap = value.colormap.stops().map(|[r, g, b]| [r, g, b, 1.0]);
+
+        // Zero strength and hardness turn the effects off
+        let occlusion_strength = match sun.ambient_occlusion {
+            true => sun.occlusion_strength,
+            false => 0.0,
+        };
+        let shadow_hardness = match sun.soft_shadows {
+            true => sun.shadow_hardness,
+            false => 0.0,
+        };
+        let shading = [
+            sun.occlusion_radius,
+            occlusion_strength,
+            shadow_hardness,
+            0.0,
+        ];
+
+        let frame = [0, 0, path.bounces, 0];
+        let [r, g, b] = path.sky_color;
+        let sky = [r, g, b, path.sky_intensity];
+
+        let lights = [
+            light.lights.len() as u32,
+            light.environment_loaded as u32,
+            0,
+            0,
+        ];
+        let environment = [
+            light.environment.intensity,
+            light.environment.rotation.to_radians(),
+            0.0,
+            0.0,
+        ];
 
         Self {
             view_projection: view_proj.into(),
@@ -123,10 +268,127 @@ impl ComputeState {
             u: u.into(),
             mv: mv.into(),
             wp: wp.into(),
+            forward: forward.into(),
             render_mode,
             show_345,
             sun_dir,
             sun_color,
+            tree_log2_dims,
+            tree_levels,
+            leaf_encoding,
+            _padding: [0; 2],
+            clip_planes,
+            clip_min,
+            clip_max,
+            value_range,
+            colormap,
+            shading,
+            frame,
+            sky,
+            lights,
+            environment,
+            sampling: [sampling.samples.max(1), temporal as u32, 0, 0],
+            history_weight: [sampling.temporal_weight, 0.0, 0.0, 0.0],
+            history_rays: Matrix4::identity().into(),
+            history_origin: [0.0; 4],
+        }
+    }
+
+    /// Trace the path traced mode on top of `samples` summed samples, as the `frame`th frame
+    pub fn with_frame(mut self, samples: u32, frame: u32) -> Self {
+        self.frame[0] = samples;
+        self.frame[1] = frame;
+        self
+    }
+
+    /// Blend with the history frame traced with `previous`, reprojected onto its pixels
+    pub fn with_history(mut self, previous: &ComputeState) -> Self {
+        let [u, mv, wp, forward] = [previous.u, previous.mv, previous.wp, previous.forward]
+            .map(|v| Vector3::new(v[0], v[1], v[2]));
+        let orthographic = previous.forward[3] == 1.0;
+        // Parallel rays reach p.x * u + p.y * mv + wp + t * forward, perspective ones reach
+        // eye + t * (p.x * u + p.y * mv + wp)
+        let rays = match orthographic {
+            true => Matrix3::from_cols(u, mv, forward),
+            false => Matrix3::from_cols(u, mv, wp),
+        };
+        let Some(inverse) = rays.invert() else {
+            return self;
+        };
+
+        self.history_rays = Matrix4::from(inverse).into();
+        self.history_origin = match orthographic {
+            true => wp.extend(1.0).into(),
+            false => [previous.eye[0], previous.eye[1], previous.eye[2], 0.0],
+        };
+        self.sampling[2] = 1;
+        self
+    }
+
+    /// Blends frames with the history in the accumulation texture
+    pub fn temporal(&self) -> bool {
+        self.sampling[1] == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// World space point `distance` along the ray through pixel `p`, like `camera_ray` in the
+    /// shader
+    fn along_ray(state: &ComputeState, p: [f32; 2], distance: f32) -> Vector3<f32> {
+        let [u, mv, wp, forward, eye] = [state.u, state.mv, state.wp, state.forward, state.eye]
+            .map(|v| Vector3::new(v[0], v[1], v[2]));
+        let origin = p[0] * u + p[1] * mv + wp;
+        match state.forward[3] == 1.0 {
+            true => origin + distance * forward,
+            false => eye + distance * origin.normalize(),
+        }
+    }
+
+    #[test]
+    fn history_reprojection_test() {
+        for projection in [Projection::Perspective, Projection::Orthographic] {
+            let mut camera = Camera::quick_camera(1.5);
+            camera.projection = projection;
+            let settings = StateSettings {
+                render_mode: RenderMode::Diffuse,
+                show_grid: [false; 3],
+                clip: &ClipSettings::default(),
+                value: &ValueSettings::default(),
+                sun: &SunSettings::default(),
+                path: &PathTraceSettings::default(),
+                light: &LightSettings::default(),
+                sampling: &SamplingSettings::default(),
+            };
+            let state = ComputeState::build(
+                &camera,
+                300.0,
+                LeafEncoding::default(),
+                TreeShape::VDB345,
+                &settings,
+            );
+            let state = state.with_history(&state);
+            assert_eq!(state.sampling[1..3], [1, 1]);
+
+            let rays = Matrix4::from(state.history_rays);
+            let origin = Vector3::new(
+                state.history_origin[0],
+                state.history_origin[1],
+                state.history_origin[2],
+            );
+            for pixel in [[0.5, 0.5], [150.0, 100.0], [299.5, 199.5]] {
+                let q = rays * (along_ray(&state, pixel, 700.0) - origin).extend(0.0);
+                let p = match projection {
+                    Projection::Perspective => [q.x / q.z, q.y / q.z],
+                    Projection::Orthographic => [q.x, q.y],
+                };
+                assert!(
+                    (p[0] - pixel[0]).abs() < 1e-2 && (p[1] - pixel[1]).abs() < 1e-2,
+                    "{projection:?} pixel {pixel:?} reprojected to {p:?}"
+                );
+            }
         }
     }
 }
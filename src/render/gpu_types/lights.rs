@@ -0,0 +1,268 @@
+use bytemuck_derive::{Pod, Zeroable};
+use half::f16;
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupLayout, Buffer, Device, Queue, Sampler, Texture,
+    TextureView,
+};
+
+use crate::scene::{Light, LightKind};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct GpuLight {
+    // World space position, w is the kind in `LightKind::ALL` order
+    position: [f32; 4],
+    // Normalized direction the light shines in, w is 1 when it casts shadows
+    direction: [f32; 4],
+    // Linear RGB colour times the intensity
+    color: [f32; 4],
+    // Cosines of the angle of a spot's cone and of the angle where it starts to fade out
+    cone: [f32; 4],
+}
+
+impl From<&Light> for GpuLight {
+    fn from(light: &Light) -> Self {
+        let [x, y, z] = light.position;
+        let kind = LightKind::ALL.iter().position(|&kind| kind == light.kind);
+        let length = light.direction.iter().map(|d| d * d).sum::<f32>().sqrt();
+        let [dx, dy, dz] = match length > 0. {
+            true => light.direction.map(|d| d / length),
+            false => [0., -1., 0.],
+        };
+        let [r, g, b] = light.color.map(|c| c * light.intensity);
+        let outer = light.cone_angle.to_radians().cos();
+        // Equal edges would leave the fade undefined
+        let inner = (light.cone_angle * (1. - light.cone_softness))
+            .to_radians()
+            .cos()
+            .max(outer + 1e-4);
+
+        Self {
+            position: [x, y, z, kind.unwrap_or(0) as f32],
+            direction: [dx, dy, dz, light.shadows as u32 as f32],
+            color: [r, g, b, 0.],
+            cone: [outer, inner, 0., 0.],
+        }
+    }
+}
+
+/// Lights of the scene, bound with the environment map that lights it from every direction
+#[derive(Debug, Clone)]
+pub struct LightsUniform {
+    lights: Vec<GpuLight>,
+}
+
+impl LightsUniform {
+    pub fn build(lights: &[Light]) -> Self {
+        Self {
+            lights: lights.iter().map(GpuLight::from).collect(),
+        }
+    }
+
+    pub fn bind(
+        &self,
+        device: &Device,
+        environment: &EnvironmentMap,
+    ) -> (Buffer, Vec<u8>, BindGroup, BindGroupLayout) {
+        // Storage buffers can't be bound empty
+        let lights = match self.lights.is_empty() {
+            true => vec![<GpuLight as bytemuck::Zeroable>::zeroed()],
+            false => self.lights.clone(),
+        };
+        let buffer_contents = bytemuck::cast_slice(&lights).to_vec();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lights Buffer"),
+            contents: &buffer_contents,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let layout = self.create_bind_group_layout(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Lights Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+            ],
+        });
+
+        (buffer, buffer_contents, bind_group, layout)
+    }
+
+    fn create_bind_group_layout(&self, device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Lights Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+}
+
+/// Equirectangular HDR image with a chain of box filtered mips, the smallest of which give the
+/// ambient light
+pub struct EnvironmentMap {
+    _texture: Texture,
+    view: TextureView,
+    sampler: Sampler,
+}
+
+impl EnvironmentMap {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Single black texel, bound while the sky lights the scene
+    pub fn empty(device: &Device, queue: &Queue) -> Self {
+        Self::new(device, queue, [1, 1], vec![[0.; 4]])
+    }
+
+    /// Read an image like a Radiance `.hdr` file, its rows going from straight up to straight
+    /// down
+    pub fn read(device: &Device, queue: &Queue, path: &str) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_rgba32f();
+        let size = [image.width(), image.height()];
+        let pixels = image.pixels().map(|pixel| pixel.0).collect();
+        Ok(Self::new(device, queue, size, pixels))
+    }
+
+    /// Upload the linear RGBA `pixels` of an image of `size` along with its mips
+    pub fn new(device: &Device, queue: &Queue, size: [u32; 2], pixels: Vec<[f32; 4]>) -> Self {
+        let mips = environment_mips(size, pixels);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size[0],
+                height: size[1],
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mips.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("Environment Texture"),
+            view_formats: &[],
+        });
+
+        for (level, ([width, height], pixels)) in mips.iter().enumerate() {
+            // Half floats top out at 65504, brighter texels like the sun are clamped
+            let texels = pixels
+                .iter()
+                .flatten()
+                .map(|&c| f16::from_f32(c.clamp(0., f16::MAX.to_f32())))
+                .collect::<Vec<_>>();
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(&texels),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * 8),
+                    rows_per_image: Some(*height),
+                },
+                wgpu::Extent3d {
+                    width: *width,
+                    height: *height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Longitude wraps around, latitude stops at the poles
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            _texture: texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+/// `pixels` of an image of `size` and every mip below it down to a single texel, each texel
+/// of a mip averaging the 2x2 texels above it. Odd rows and columns at the far edge are
+/// dropped.
+fn environment_mips(size: [u32; 2], pixels: Vec<[f32; 4]>) -> Vec<([u32; 2], Vec<[f32; 4]>)> {
+    let mut mips = vec![(size, pixels)];
+    loop {
+        let ([width, height], level) = mips.last().expect("starts with the image");
+        if *width == 1 && *height == 1 {
+            return mips;
+        }
+
+        let (width, height) = (*width as usize, *height as usize);
+        let half = [(width / 2).max(1), (height / 2).max(1)];
+        let next = (0..half[1])
+            .flat_map(|y| (0..half[0]).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let texels = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                    level[(2 * y + dy).min(height - 1) * width + (2 * x + dx).min(width - 1)]
+                });
+                [0, 1, 2, 3].map(|c| texels.iter().map(|texel| texel[c]).sum::<f32>() / 4.)
+            })
+            .collect();
+        mips.push((half.map(|side| side as u32), next));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_mips_test() {
+        let pixels = (0..8).map(|i| [i as f32, 1., 0., 1.]).collect();
+        let mips = environment_mips([4, 2], pixels);
+
+        let sizes = mips.iter().map(|(size, _)| *size).collect::<Vec<_>>();
+        assert_eq!(sizes, [[4, 2], [2, 1], [1, 1]]);
+        // Rows 0..4 and 4..8, so the left half averages 0, 1, 4 and 5
+        assert_eq!(mips[1].1, [[2.5, 1., 0., 1.], [4.5, 1., 0., 1.]]);
+        assert_eq!(mips[2].1, [[3.5, 1., 0., 1.]]);
+    }
+}
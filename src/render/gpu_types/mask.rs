@@ -1,48 +1,83 @@
-use crate::vdb::{VdbValueType, VDB345};
-use bytemuck_derive::{Pod, Zeroable};
-use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, Device};
+use crate::vdb::{mask_words, EncodedPool, TreeShape, MAX_TREE_LEVELS};
+use itertools::Itertools;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, Device};
 
+use super::pool::{storage_buffer, PoolSpace};
+
+/// Masks of the nodes of every level, laid out like [`crate::vdb::NodePool::masks`] with the
+/// grids one after the other, and the origins of the top nodes
 #[derive(Debug, Clone)]
 pub struct MaskUniform {
-    kids5: Vec<Node5Mask>,
-    vals5: Vec<Node5Mask>,
-    kids4: Vec<Node4Mask>,
-    vals4: Vec<Node4Mask>,
-    vals3: Vec<Node3Mask>,
+    levels: [Vec<u32>; MAX_TREE_LEVELS],
     origins: Vec<[i32; 4]>,
+    shape: TreeShape,
 }
 
 impl MaskUniform {
-    pub fn bind(&self, device: &Device) -> ([Buffer; 6], [Vec<u8>; 6], BindGroup, BindGroupLayout) {
+    /// The buffers have room for the nodes of `space`, like the node pools
+    pub fn bind(
+        &self,
+        device: &Device,
+        space: &PoolSpace,
+    ) -> ([Buffer; 5], [Vec<u8>; 5], BindGroup, BindGroupLayout) {
         let buffer_contents = self.get_contents();
-        let buffers = self.create_buffers(device, &buffer_contents);
+        let buffers = self.create_buffers(device, &buffer_contents, space);
         let layout = self.create_bind_group_layout(device);
         let bind_group = self.create_bind_group(&buffers, &layout, device);
 
         (buffers, buffer_contents, bind_group, layout)
     }
 
-    fn get_contents(&self) -> [Vec<u8>; 6] {
+    /// Masks of several grids packed in `shape`, in the same order as their node pools
+    pub fn from_pools(pools: &[EncodedPool], shape: TreeShape) -> Self {
+        let mut masks = Self {
+            levels: Default::default(),
+            origins: vec![],
+            shape,
+        };
+
+        for EncodedPool { pool, .. } in pools {
+            for (level, level_masks) in pool.masks.iter().enumerate() {
+                masks.levels[level].extend_from_slice(level_masks);
+            }
+            let top_nodes = pool.index.levels[0]
+                .iter()
+                .sorted_by_key(|(_, &idx)| idx)
+                .map(|(&[x, y, z], _)| [x, y, z, 0]);
+            masks.origins.extend(top_nodes);
+        }
+
+        masks
+    }
+
+    fn get_contents(&self) -> [Vec<u8>; 5] {
         [
-            bytemuck::cast_slice(&self.kids5).to_vec(),
-            bytemuck::cast_slice(&self.vals5).to_vec(),
-            bytemuck::cast_slice(&self.kids4).to_vec(),
-            bytemuck::cast_slice(&self.vals4).to_vec(),
-            bytemuck::cast_slice(&self.vals3).to_vec(),
-            bytemuck::cast_slice(&self.origins).to_vec(),
+            contents_or_zeroed(&self.levels[0]),
+            contents_or_zeroed(&self.levels[1]),
+            contents_or_zeroed(&self.levels[2]),
+            contents_or_zeroed(&self.levels[3]),
+            contents_or_zeroed(&self.origins),
         ]
     }
 
-    fn create_buffers(&self, device: &Device, buffer_contents: &[Vec<u8>; 6]) -> [Buffer; 6] {
+    fn create_buffers(
+        &self,
+        device: &Device,
+        buffer_contents: &[Vec<u8>; 5],
+        space: &PoolSpace,
+    ) -> [Buffer; 5] {
+        let words = mask_words(&self.shape);
+        let [l0, l1, l2, l3] =
+            std::array::from_fn(|level| space.capacity[level] as usize * words[level] * 4);
+        let top_nodes = space.capacity[0] as usize;
+        let bytes = [l0, l1, l2, l3, top_nodes * std::mem::size_of::<[i32; 4]>()];
+
         buffer_contents
             .iter()
+            .zip(bytes)
             .enumerate()
-            .map(|(idx, contents)| {
-                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("Mask Buffer {idx}")),
-                    contents,
-                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-                })
+            .map(|(idx, (contents, bytes))| {
+                storage_buffer(device, &format!("Mask Buffer {idx}"), contents, bytes)
             })
             .collect::<Vec<_>>()
             .try_into()
@@ -50,7 +85,7 @@ impl MaskUniform {
     }
 
     fn create_bind_group_layout(&self, device: &Device) -> BindGroupLayout {
-        let entries = &(0..=5)
+        let entries = &(0..=4)
             .map(|binding| wgpu::BindGroupLayoutEntry {
                 binding,
                 visibility: wgpu::ShaderStages::COMPUTE,
@@ -71,7 +106,7 @@ impl MaskUniform {
 
     fn create_bind_group(
         &self,
-        buffers: &[Buffer; 6],
+        buffers: &[Buffer; 5],
         layout: &BindGroupLayout,
         device: &Device,
     ) -> BindGroup {
@@ -92,40 +127,11 @@ impl MaskUniform {
     }
 }
 
-impl<'a, ValueType: VdbValueType> From<&'a VDB345<ValueType>> for MaskUniform {
-    fn from(vdb: &'a VDB345<ValueType>) -> Self {
-        let masks = vdb.masks();
-
-        let kids5: Vec<Node5Mask> = masks.0.iter().map(|x| Node5Mask(*x)).collect();
-        let vals5: Vec<Node5Mask> = masks.1.iter().map(|x| Node5Mask(*x)).collect();
-        let kids4: Vec<Node4Mask> = masks.2.iter().map(|x| Node4Mask(*x)).collect();
-        let vals4: Vec<Node4Mask> = masks.3.iter().map(|x| Node4Mask(*x)).collect();
-        let vals3: Vec<Node3Mask> = masks.4.iter().map(|x| Node3Mask(*x)).collect();
-        let origins = vdb
-            .origins()
-            .iter()
-            .map(|&[x, y, z]| [x, y, z, 0])
-            .collect::<Vec<_>>();
-
-        Self {
-            kids5,
-            vals5,
-            kids4,
-            vals4,
-            vals3,
-            origins,
-        }
+/// Storage buffers can't be bound empty, so empty arrays get a single zeroed element
+fn contents_or_zeroed<T: bytemuck::Pod>(data: &[T]) -> Vec<u8> {
+    if data.is_empty() {
+        vec![0; std::mem::size_of::<T>()]
+    } else {
+        bytemuck::cast_slice(data).to_vec()
     }
 }
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod, Zeroable)]
-struct Node5Mask([u32; 32 * 32 * 32 / 32]);
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod, Zeroable)]
-struct Node4Mask([u32; 16 * 16 * 16 / 32]);
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod, Zeroable)]
-struct Node3Mask([u32; 8 * 8 * 8 / 32]);
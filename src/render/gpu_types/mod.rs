@@ -14,7 +14,7 @@ pub trait GpuPrimitive {
 }
 
 mod compute_state;
-pub use compute_state::ComputeState;
+pub use compute_state::{ComputeState, StateSettings};
 
 mod vertex;
 pub use vertex::GpuVertex;
@@ -28,8 +28,17 @@ pub use state::StateUniform;
 mod texture;
 pub use texture::*;
 
-mod atlas;
-pub use atlas::*;
+mod pool;
+pub use pool::*;
 
 mod mask;
 pub use mask::*;
+
+mod objects;
+pub use objects::*;
+
+mod lights;
+pub use lights::*;
+
+mod tone_map;
+pub use tone_map::ToneMapUniform;
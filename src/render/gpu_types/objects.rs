@@ -0,0 +1,167 @@
+use crate::{
+    scene::{Bvh, Material, SceneObject},
+    vdb::VDB345,
+};
+use bytemuck_derive::{Pod, Zeroable};
+use cgmath::SquareMatrix;
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, Device};
+
+use super::GridSlot;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct GpuObject {
+    world_to_index: [[f32; 4]; 4],
+    index_to_world: [[f32; 4]; 4],
+    // Index space bounds of the grid, the HDDA stops once it leaves them
+    index_min: [i32; 4],
+    index_max: [i32; 4],
+    // Pool index of the grid's first node on each level, top level first
+    bases: [u32; 4],
+    // Albedo of the grid's material and its roughness in w
+    albedo: [f32; 4],
+    // Emission of the grid's material and its metalness in w
+    emission: [f32; 4],
+    // First leaf of the grid in the colour pool, then 1 when the grid has colours
+    colors: [u32; 4],
+    // Number of the grid's top level nodes in x
+    top_nodes: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct GpuBvhNode {
+    min: [f32; 3],
+    // Left child of internal nodes, first object of leaves
+    left_first: u32,
+    max: [f32; 3],
+    // Number of objects in a leaf, 0 for internal nodes
+    count: u32,
+}
+
+/// Scene objects in BVH leaf order and the BVH over their world bounds
+#[derive(Debug, Clone)]
+pub struct ObjectsUniform {
+    objects: Vec<GpuObject>,
+    nodes: Vec<GpuBvhNode>,
+}
+
+impl ObjectsUniform {
+    /// Objects whose grid is missing or empty are left out, grids without a material use the
+    /// default one and grids with a slot in the colour pool take their albedo from it
+    pub fn build(
+        objects: &[SceneObject],
+        materials: &[Material],
+        grids: &[VDB345<u32>],
+        slots: &[GridSlot],
+    ) -> Self {
+        let placed = objects
+            .iter()
+            .filter_map(|object| {
+                let grid = grids.get(object.grid)?;
+                let slot = slots.get(object.grid)?;
+                let bounds = object.world_bounds(grid)?;
+                let ([x0, y0, z0], [x1, y1, z1]) = grid.index_bounds()?;
+                let index_to_world = object.index_to_world(&grid.transform);
+                let world_to_index = index_to_world.invert()?;
+                let material = materials.get(object.grid).copied().unwrap_or_default();
+                let [r, g, b] = material.albedo;
+                let [er, eg, eb] = material.emission;
+
+                let gpu_object = GpuObject {
+                    world_to_index: world_to_index.into(),
+                    index_to_world: index_to_world.into(),
+                    index_min: [x0, y0, z0, 0],
+                    index_max: [x1, y1, z1, 0],
+                    bases: slot.bases,
+                    albedo: [r, g, b, material.roughness],
+                    emission: [er, eg, eb, material.metallic],
+                    colors: [slot.colors.unwrap_or(0), slot.colors.is_some() as u32, 0, 0],
+                    top_nodes: [slot.index.counts()[0], 0, 0, 0],
+                };
+                Some((bounds, gpu_object))
+            })
+            .collect::<Vec<_>>();
+
+        let bvh = Bvh::build(&placed.iter().map(|(bounds, _)| *bounds).collect::<Vec<_>>());
+
+        Self {
+            objects: bvh.order.iter().map(|&i| placed[i as usize].1).collect(),
+            nodes: bvh
+                .nodes
+                .iter()
+                .map(|node| GpuBvhNode {
+                    min: node.aabb.min.into(),
+                    left_first: node.left_first,
+                    max: node.aabb.max.into(),
+                    count: node.count,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn bind(&self, device: &Device) -> ([Buffer; 2], [Vec<u8>; 2], BindGroup, BindGroupLayout) {
+        let buffer_contents = self.get_contents();
+        let buffers = self.create_buffers(device, &buffer_contents);
+        let layout = self.create_bind_group_layout(device);
+        let bind_group = self.create_bind_group(&buffers, &layout, device);
+
+        (buffers, buffer_contents, bind_group, layout)
+    }
+
+    fn get_contents(&self) -> [Vec<u8>; 2] {
+        // Storage buffers can't be bound empty
+        let objects = match self.objects.is_empty() {
+            true => vec![<GpuObject as bytemuck::Zeroable>::zeroed()],
+            false => self.objects.clone(),
+        };
+
+        [
+            bytemuck::cast_slice(&objects).to_vec(),
+            bytemuck::cast_slice(&self.nodes).to_vec(),
+        ]
+    }
+
+    fn create_buffers(&self, device: &Device, buffer_contents: &[Vec<u8>; 2]) -> [Buffer; 2] {
+        let [objects, nodes] = buffer_contents;
+        [("Objects Buffer", objects), ("BVH Buffer", nodes)].map(|(label, contents)| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        })
+    }
+
+    fn create_bind_group_layout(&self, device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Objects Bind Group Layout"),
+            entries: &[0, 1].map(|binding| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }),
+        })
+    }
+
+    fn create_bind_group(
+        &self,
+        buffers: &[Buffer; 2],
+        layout: &BindGroupLayout,
+        device: &Device,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Objects Bind Group"),
+            layout,
+            entries: &[0, 1].map(|binding| wgpu::BindGroupEntry {
+                binding,
+                resource: buffers[binding as usize].as_entire_binding(),
+            }),
+        })
+    }
+}
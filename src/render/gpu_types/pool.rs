@@ -0,0 +1,532 @@
+use crate::vdb::{
+    EncodedPool, LeafEncoding, PoolError, PoolIndex, TreeShape, MAX_TREE_LEVELS, VDB345,
+};
+use wgpu::{BindGroup, BindGroupLayout, Buffer, Device, Queue, Texture};
+
+/// Node pools of every grid, laid out one grid after the other.
+///
+/// The leaf ranges of the quantized encodings are bound after the [`MAX_TREE_LEVELS`]
+/// levels, then the colours of the leaves of grids with a colour grid. Leaves too large for
+/// a storage buffer binding are bound as [`LeafPages`] last, their buffer left empty.
+#[derive(Debug, Clone)]
+pub struct NodePoolUniform {
+    levels: [Vec<u8>; MAX_TREE_LEVELS],
+    leaf_ranges: Vec<u8>,
+    leaf_colors: Vec<u8>,
+    pub encoding: LeafEncoding,
+    /// Shape every grid is packed in, the shader walks a single one
+    pub shape: TreeShape,
+}
+
+/// Nodes of each level of 5-4-3 pools the buffers hold past the packed ones, so nodes created
+/// by edits are appended without packing every grid again. New N5s always repack, the shader
+/// finds a grid's N5s next to each other. Pools of other shapes repack on every edit.
+pub const SPARE_NODES: [usize; MAX_TREE_LEVELS] = [0, 64, 1024, 0];
+
+/// Texels in a row of [`LeafPages`], the leaves of 5-4-3 pools fill rows whole
+pub const PAGE_WIDTH: u32 = 4096;
+
+/// Leaf entries paged into the rows of a texture array, for leaf levels larger than a storage
+/// buffer binding. Entry `i` is texel `i % PAGE_WIDTH` of row `i / PAGE_WIDTH`, the rows
+/// filling one layer after the other.
+#[derive(Debug)]
+pub struct LeafPages {
+    texture: Texture,
+    rows: u32,
+}
+
+impl LeafPages {
+    /// Bytes of leaf entries the device can page
+    pub fn max_bytes(limits: &wgpu::Limits) -> usize {
+        PAGE_WIDTH as usize
+            * limits.max_texture_dimension_2d as usize
+            * limits.max_texture_array_layers as usize
+            * 4
+    }
+
+    /// Pages with room for `entries` leaf entries, as few layers as the device allows
+    fn new(device: &Device, entries: usize) -> Self {
+        let needed = entries.div_ceil(PAGE_WIDTH as usize).max(1);
+        let rows = needed.min(device.limits().max_texture_dimension_2d as usize);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Leaf Pages"),
+            size: wgpu::Extent3d {
+                width: PAGE_WIDTH,
+                height: rows as u32,
+                depth_or_array_layers: needed.div_ceil(rows) as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Uint,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        Self {
+            texture,
+            rows: rows as u32,
+        }
+    }
+
+    /// Write the entries in `bytes` from entry `first` on
+    pub fn write(&self, queue: &Queue, first: usize, bytes: &[u8]) {
+        for (start, [x, y, layer], [width, height]) in
+            page_copies(first, bytes.len() / 4, self.rows)
+        {
+            let len = (width * height) as usize;
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x, y, z: layer },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &bytes[start * 4..(start + len) * 4],
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * 4),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+}
+
+/// Texture copies writing `len` entries from entry `first` on into pages of `rows` rows, as
+/// the first entry of each copy, its origin and its extent. A copy covers whole rows of a
+/// single layer, or part of a single row.
+fn page_copies(first: usize, len: usize, rows: u32) -> Vec<(usize, [u32; 3], [u32; 2])> {
+    let (width, rows) = (PAGE_WIDTH as usize, rows as usize);
+    let mut copies = vec![];
+    let mut done = 0;
+
+    while done < len {
+        let entry = first + done;
+        let (row, x) = (entry / width, entry % width);
+        let left = len - done;
+        let [copy_width, copy_height] = match x == 0 && left >= width {
+            true => [width, (left / width).min(rows - row % rows)],
+            false => [(width - x).min(left), 1],
+        };
+        let origin = [x, row % rows, row / rows].map(|c| c as u32);
+        copies.push((done, origin, [copy_width as u32, copy_height as u32]));
+        done += copy_width * copy_height;
+    }
+    copies
+}
+
+/// Nodes of each level in the shared node pools and how many the buffers have room for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolSpace {
+    pub used: [u32; MAX_TREE_LEVELS],
+    pub capacity: [u32; MAX_TREE_LEVELS],
+}
+
+impl PoolSpace {
+    /// Pool index of a node appended to `level`, `None` when the buffers are full
+    pub fn append(&mut self, level: usize) -> Option<u32> {
+        let idx = self.used[level];
+        if idx >= self.capacity[level] {
+            return None;
+        }
+        self.used[level] += 1;
+        Some(idx)
+    }
+}
+
+/// Where a grid's nodes live in the shared node pools.
+///
+/// Child indices stored in the pools are local to their grid, the shader adds `bases`. Nodes
+/// appended after packing sit past every grid, their local index is still relative to `bases`.
+#[derive(Debug, Clone, Default)]
+pub struct GridSlot {
+    pub index: PoolIndex,
+    /// Pool index of the grid's first node on each level
+    pub bases: [u32; MAX_TREE_LEVELS],
+    /// Index of the grid's first leaf in the colour pool, `None` without a colour grid
+    pub colors: Option<u32>,
+}
+
+impl GridSlot {
+    /// Number the nodes created in this slot's grid after every node in the pools, `added`
+    /// being their origins on each level.
+    ///
+    /// Only 5-4-3 pools take appended nodes. Returns `false` when they can't be appended and
+    /// the grids have to be packed again: for new N5s, for new leaves of a grid with colours,
+    /// which have no room in the colour pool, and once the pools are full.
+    pub fn append(&mut self, space: &mut PoolSpace, added: &[Vec<[i32; 3]>; 3]) -> bool {
+        if !added[0].is_empty() || (self.colors.is_some() && !added[2].is_empty()) {
+            return false;
+        }
+        for level in [1, 2] {
+            for &origin in &added[level] {
+                let Some(idx) = space.append(level) else {
+                    return false;
+                };
+                self.index.levels[level].insert(origin, idx - self.bases[level]);
+            }
+        }
+        true
+    }
+}
+
+impl NodePoolUniform {
+    /// The leaves are paged when `space` has more of them than a storage buffer binding holds
+    pub fn bind(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        space: &PoolSpace,
+    ) -> (
+        [Buffer; 6],
+        Option<LeafPages>,
+        [Vec<u8>; 6],
+        BindGroup,
+        BindGroupLayout,
+    ) {
+        let leaf_level = self.shape.leaf_level();
+        let leaf_bytes = space.capacity[leaf_level] as usize
+            * self.encoding.node_sizes(&self.shape)[leaf_level]
+            * 4;
+        let pages =
+            (leaf_bytes > device.limits().max_storage_buffer_binding_size as usize).then(|| {
+                let pages = LeafPages::new(device, leaf_bytes / 4);
+                pages.write(queue, 0, &self.levels[leaf_level]);
+                pages
+            });
+
+        let buffer_contents = self.get_contents(pages.is_some());
+        let buffers = self.create_buffers(device, &buffer_contents, space, pages.is_some());
+        let layout = self.create_bind_group_layout(device);
+        let bind_group = self.create_bind_group(&buffers, pages.as_ref(), &layout, device);
+
+        (buffers, pages, buffer_contents, bind_group, layout)
+    }
+
+    /// Concatenates the pools of several grids, which all use `encoding` and `shape`
+    pub fn from_pools(
+        pools: Vec<EncodedPool>,
+        encoding: LeafEncoding,
+        shape: TreeShape,
+    ) -> Result<(Self, Vec<GridSlot>), PoolError> {
+        let mut levels: [Vec<u8>; MAX_TREE_LEVELS] = Default::default();
+        let mut leaf_ranges = vec![];
+        let mut slots = vec![];
+        let mut counts = [0usize; MAX_TREE_LEVELS];
+        let sizes = encoding.node_sizes(&shape);
+
+        for EncodedPool {
+            pool,
+            leaf_ranges: ranges,
+            ..
+        } in pools
+        {
+            let pool_counts = pool.index.counts();
+            for level in 0..MAX_TREE_LEVELS {
+                let count = counts[level] + pool_counts[level] as usize;
+                // The shader addresses every entry of a level with a u32
+                let entries = count.checked_mul(sizes[level]);
+                if !entries.is_some_and(|e| e <= u32::MAX as usize) {
+                    return Err(PoolError::IndexOverflow { level, count });
+                }
+                levels[level].extend_from_slice(bytemuck::cast_slice(&pool.levels[level]));
+            }
+            leaf_ranges.extend_from_slice(bytemuck::cast_slice(&ranges));
+
+            slots.push(GridSlot {
+                index: pool.index,
+                bases: counts.map(|c| c as u32),
+                colors: None,
+            });
+            for level in 0..MAX_TREE_LEVELS {
+                counts[level] += pool_counts[level] as usize;
+            }
+        }
+
+        Ok((
+            Self {
+                levels,
+                leaf_ranges,
+                leaf_colors: vec![],
+                encoding,
+                shape,
+            },
+            slots,
+        ))
+    }
+
+    /// Packs the leaf values of `colors`, the colour grid of each slot's grid that has one,
+    /// against the leaves of that grid and points the slots at them
+    pub fn set_leaf_colors(
+        &mut self,
+        slots: &mut [GridSlot],
+        colors: &[Option<VDB345<u32>>],
+    ) -> Result<(), PoolError> {
+        let leaf_level = self.shape.leaf_level();
+        let leaf_size = self.shape.size(leaf_level);
+        let mut leaves = 0usize;
+        self.leaf_colors.clear();
+
+        for (slot, color) in slots.iter_mut().zip(colors) {
+            slot.colors = None;
+            let Some(color) = color else {
+                continue;
+            };
+            let count = leaves + slot.index.levels[leaf_level].len();
+            // Colours are addressed with a u32 like the leaves
+            let entries = count.checked_mul(leaf_size);
+            if !entries.is_some_and(|e| e <= u32::MAX as usize) {
+                return Err(PoolError::IndexOverflow {
+                    level: leaf_level,
+                    count,
+                });
+            }
+
+            let bricks = color.leaf_bricks(&slot.index, &self.shape);
+            self.leaf_colors
+                .extend_from_slice(bytemuck::cast_slice(&bricks));
+            slot.colors = Some(leaves as u32);
+            leaves = count;
+        }
+
+        Ok(())
+    }
+
+    /// Bytes used by each level, top level first
+    pub fn level_bytes(&self) -> [usize; MAX_TREE_LEVELS] {
+        std::array::from_fn(|level| self.levels[level].len())
+    }
+
+    /// Packed nodes of each level with [`SPARE_NODES`] more room for 5-4-3 pools, as far as a
+    /// storage buffer binding allows. Leaves that don't fit one are paged and get as much room
+    /// as [`LeafPages`] have.
+    pub fn space(&self, limits: &wgpu::Limits) -> PoolSpace {
+        let node_bytes = self.encoding.node_sizes(&self.shape).map(|size| size * 4);
+        let spare = match self.shape == TreeShape::VDB345 {
+            true => SPARE_NODES,
+            false => [0; MAX_TREE_LEVELS],
+        };
+        let max_bytes = limits.max_storage_buffer_binding_size as usize;
+        let leaf_level = self.shape.leaf_level();
+
+        let mut space = PoolSpace::default();
+        for level in 0..self.shape.levels() {
+            let max_bytes = match level == leaf_level && self.levels[level].len() > max_bytes {
+                true => LeafPages::max_bytes(limits),
+                false => max_bytes,
+            };
+            let used = self.levels[level].len() / node_bytes[level];
+            let room = (max_bytes / node_bytes[level]).max(used);
+            space.used[level] = used as u32;
+            space.capacity[level] = (used + spare[level]).min(room).min(u32::MAX as usize) as u32;
+        }
+        space
+    }
+
+    /// Paged leaves are left out of their buffer
+    fn get_contents(&self, paged: bool) -> [Vec<u8>; 6] {
+        let mut levels = self.levels.clone();
+        if paged {
+            levels[self.shape.leaf_level()].clear();
+        }
+        let [l0, l1, l2, l3] = levels;
+        let (ranges, colors) = (self.leaf_ranges.clone(), self.leaf_colors.clone());
+        [l0, l1, l2, l3, ranges, colors].map(|contents| {
+            // Storage buffers can't be bound empty
+            if contents.is_empty() {
+                vec![0; 8]
+            } else {
+                contents
+            }
+        })
+    }
+
+    fn create_buffers(
+        &self,
+        device: &Device,
+        buffer_contents: &[Vec<u8>; 6],
+        space: &PoolSpace,
+        paged: bool,
+    ) -> [Buffer; 6] {
+        let node_bytes = self.encoding.node_sizes(&self.shape).map(|size| size * 4);
+        let leaf_level = self.shape.leaf_level();
+        let [l0, l1, l2, l3] = std::array::from_fn(|level| match paged && level == leaf_level {
+            true => 0,
+            false => space.capacity[level] as usize * node_bytes[level],
+        });
+        // Every leaf has an offset and a scale, the colours aren't appended to
+        let leaves = space.capacity[self.shape.leaf_level()] as usize;
+        let bytes = [l0, l1, l2, l3, leaves * 8, 0];
+
+        buffer_contents
+            .iter()
+            .zip(bytes)
+            .enumerate()
+            .map(|(idx, (contents, bytes))| {
+                storage_buffer(device, &format!("Node Pool Buffer {idx}"), contents, bytes)
+            })
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap()
+    }
+
+    fn create_bind_group_layout(&self, device: &Device) -> BindGroupLayout {
+        let mut entries = [0, 1, 2, 3, 4, 5]
+            .map(|binding| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            })
+            .to_vec();
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 6,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Uint,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        });
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Node Pool Bind Group Layout"),
+            entries: &entries,
+        })
+    }
+
+    /// Without `pages` a single texel is bound, the shader only reads pages wider than that
+    fn create_bind_group(
+        &self,
+        buffers: &[Buffer; 6],
+        pages: Option<&LeafPages>,
+        layout: &BindGroupLayout,
+        device: &Device,
+    ) -> BindGroup {
+        let empty;
+        let texture = match pages {
+            Some(pages) => &pages.texture,
+            None => {
+                empty = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Empty Leaf Pages"),
+                    size: wgpu::Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::R32Uint,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                });
+                &empty
+            }
+        };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let mut entries = [0, 1, 2, 3, 4, 5]
+            .map(|binding| wgpu::BindGroupEntry {
+                binding,
+                resource: buffers[binding as usize].as_entire_binding(),
+            })
+            .to_vec();
+        entries.push(wgpu::BindGroupEntry {
+            binding: 6,
+            resource: wgpu::BindingResource::TextureView(&view),
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Node Pool Bind Group"),
+            layout,
+            entries: &entries,
+        })
+    }
+}
+
+/// Storage buffer starting with `contents`, with room for `bytes` in total. The rest is
+/// zeroed for the nodes written later.
+pub(super) fn storage_buffer(
+    device: &Device,
+    label: &str,
+    contents: &[u8],
+    bytes: usize,
+) -> Buffer {
+    let size = bytes.max(contents.len()) as wgpu::BufferAddress;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: wgpu::util::align_to(size, wgpu::COPY_BUFFER_ALIGNMENT),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: true,
+    });
+    buffer.slice(..).get_mapped_range_mut()[..contents.len()].copy_from_slice(contents);
+    buffer.unmap();
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_slot_append_test() {
+        let mut space = PoolSpace {
+            used: [2, 5, 9, 0],
+            capacity: [2, 6, 11, 0],
+        };
+        let mut slot = GridSlot {
+            bases: [1, 3, 4, 0],
+            ..Default::default()
+        };
+
+        let added = [vec![], vec![[0, 0, 0]], vec![[0, 0, 0], [8, 0, 0]]];
+        assert!(slot.append(&mut space, &added));
+        assert_eq!(space.used, [2, 6, 11, 0]);
+        // Local to the grid like the packed nodes
+        assert_eq!(slot.index.get(1, [0, 0, 0]), Some(2));
+        assert_eq!(slot.index.get(2, [8, 0, 0]), Some(6));
+
+        // The pools are full now
+        assert!(!slot.append(&mut space, &[vec![], vec![], vec![[16, 0, 0]]]));
+        space.capacity = [2, 8, 12, 0];
+        assert!(!slot.append(&mut space, &[vec![[0, 0, 0]], vec![], vec![]]));
+        slot.colors = Some(0);
+        assert!(!slot.append(&mut space, &[vec![], vec![], vec![[16, 0, 0]]]));
+        assert!(slot.append(&mut space, &[vec![], vec![[128, 0, 0]], vec![]]));
+    }
+
+    #[test]
+    fn page_copies_test() {
+        let width = PAGE_WIDTH as usize;
+
+        // A leaf inside a row
+        assert_eq!(page_copies(512, 512, 4), vec![(0, [512, 0, 0], [512, 1])]);
+
+        // Rest of a row, whole rows up to the end of the layer, then the next layer
+        let copies = page_copies(width - 8, 8 + 5 * width + 3, 4);
+        assert_eq!(
+            copies,
+            vec![
+                (0, [width as u32 - 8, 0, 0], [8, 1]),
+                (8, [0, 1, 0], [PAGE_WIDTH, 3]),
+                (8 + 3 * width, [0, 0, 1], [PAGE_WIDTH, 2]),
+                (8 + 5 * width, [0, 2, 1], [3, 1]),
+            ]
+        );
+    }
+}
@@ -2,8 +2,11 @@ use wgpu::{BindGroup, BindGroupLayout, Device, Sampler, Texture, TextureView};
 
 use super::GpuTexture;
 
+/// Storage texture a compute pass writes, the HDR frame of the raycaster or the sRGB bytes
+/// the tone map pass makes of it
 pub struct ComputeOutputTexture {
     size: [u32; 2],
+    format: wgpu::TextureFormat,
 }
 
 impl GpuTexture for ComputeOutputTexture {
@@ -18,8 +21,8 @@ impl GpuTexture for ComputeOutputTexture {
 }
 
 impl ComputeOutputTexture {
-    pub fn new(size: [u32; 2]) -> Self {
-        Self { size }
+    pub fn new(size: [u32; 2], format: wgpu::TextureFormat) -> Self {
+        Self { size, format }
     }
 
     fn create_texture(&self, device: &Device) -> Texture {
@@ -32,7 +35,7 @@ impl ComputeOutputTexture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
+            format: self.format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING
                 | wgpu::TextureUsages::STORAGE_BINDING
                 | wgpu::TextureUsages::COPY_DST
@@ -47,7 +50,7 @@ impl ComputeOutputTexture {
     fn create_texture_view(&self, texture: &Texture) -> TextureView {
         let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
             label: Some("Compute Texture View"),
-            format: Some(wgpu::TextureFormat::Rgba8Unorm),
+            format: Some(self.format),
             base_mip_level: 0,
             mip_level_count: Some(1),
             ..Default::default()
@@ -64,7 +67,7 @@ impl ComputeOutputTexture {
                 visibility: wgpu::ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::StorageTexture {
                     access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    format: self.format,
                     view_dimension: wgpu::TextureViewDimension::D2,
                 },
                 count: None,
@@ -127,14 +130,16 @@ impl FragmentTexture {
             format: wgpu::TextureFormat::Rgba8Unorm,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             label: Some("Fragment Texture"),
-            view_formats: &[],
+            view_formats: &[wgpu::TextureFormat::Rgba8UnormSrgb],
         });
 
         texture
     }
 
     fn create_texture_view(&self, texture: &Texture) -> TextureView {
+        // Holds sRGB bytes, sampled back to linear so the sRGB surface writes the same bytes
         let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(wgpu::TextureFormat::Rgba8UnormSrgb),
             ..Default::default()
         });
 
@@ -206,3 +211,102 @@ impl FragmentTexture {
         bind_group
     }
 }
+
+/// Float sums of the path traced samples of every pixel, kept between frames.
+///
+/// The compute pass reads the sums so far from the first texture and writes the new sums to
+/// the second, which is copied back over the first once the pass is done.
+pub struct AccumulationTexture {
+    size: [u32; 2],
+}
+
+impl AccumulationTexture {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+    pub fn new(size: [u32; 2]) -> Self {
+        Self { size }
+    }
+
+    pub fn bind(&self, device: &Device) -> ([Texture; 2], BindGroup, BindGroupLayout) {
+        let textures = [
+            self.create_texture(
+                device,
+                "Accumulated Texture",
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            ),
+            self.create_texture(
+                device,
+                "Accumulation Texture",
+                wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            ),
+        ];
+        let views = textures
+            .each_ref()
+            .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        let bind_group_layout = self.create_bind_group_layout(device);
+        let bind_group = self.create_bind_group(device, &bind_group_layout, &views);
+
+        (textures, bind_group, bind_group_layout)
+    }
+
+    fn create_texture(&self, device: &Device, label: &str, usage: wgpu::TextureUsages) -> Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: self.size[0],
+                height: self.size[1],
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage,
+            label: Some(label),
+            view_formats: &[],
+        })
+    }
+
+    fn create_bind_group_layout(&self, device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Accumulation Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: Self::FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        views: &[TextureView; 2],
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Accumulation Bind Group"),
+            layout,
+            entries: &[0, 1].map(|binding| wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&views[binding as usize]),
+            }),
+        })
+    }
+}
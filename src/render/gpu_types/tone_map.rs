@@ -0,0 +1,114 @@
+use bytemuck_derive::{Pod, Zeroable};
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, Device, Texture};
+
+use crate::render::{
+    egui_dev::{RenderMode, ToneSettings},
+    ToneMapper,
+};
+
+/// Exposure and curve of the pass taking the HDR frame to the sRGB bytes that are shown,
+/// recorded and exported, bound with the HDR frame it reads
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct ToneMapUniform {
+    // Factor the colours are multiplied with before the curve
+    exposure: [f32; 4],
+    // Curve in `ToneMapper::ALL` order
+    tone_mapper: [u32; 4],
+}
+
+impl ToneMapUniform {
+    /// The value mode keeps the colours of its colour map, as shown in the legend
+    pub fn build(settings: &ToneSettings, render_mode: RenderMode) -> Self {
+        let (exposure, tone_mapper) = match render_mode {
+            RenderMode::Value => (1.0, ToneMapper::Clamp),
+            _ => (settings.exposure.exp2(), settings.tone_mapper),
+        };
+        let index = ToneMapper::ALL.iter().position(|&t| t == tone_mapper);
+
+        Self {
+            exposure: [exposure, 0.0, 0.0, 0.0],
+            tone_mapper: [index.unwrap_or(0) as u32, 0, 0, 0],
+        }
+    }
+
+    pub fn bind(
+        &self,
+        device: &Device,
+        hdr: &Texture,
+    ) -> (Buffer, Vec<u8>, BindGroup, BindGroupLayout) {
+        let buffer_contents = bytemuck::cast_slice(&[*self]).to_vec();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tone Map Buffer"),
+            contents: &buffer_contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let view = hdr.create_view(&wgpu::TextureViewDescriptor::default());
+        let layout = self.create_bind_group_layout(device);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tone Map Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+
+        (buffer, buffer_contents, bind_group, layout)
+    }
+
+    fn create_bind_group_layout(&self, device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tone Map Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_map_uniform_test() {
+        let settings = ToneSettings {
+            exposure: -2.0,
+            tone_mapper: ToneMapper::Filmic,
+        };
+        let uniform = ToneMapUniform::build(&settings, RenderMode::Diffuse);
+        assert_eq!(uniform.exposure[0], 0.25);
+        assert_eq!(uniform.tone_mapper[0], 2);
+
+        // Values keep the colours of the legend
+        let uniform = ToneMapUniform::build(&settings, RenderMode::Value);
+        assert_eq!(uniform.exposure[0], 1.0);
+        assert_eq!(uniform.tone_mapper[0], 3);
+    }
+}
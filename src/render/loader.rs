@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use futures::channel::oneshot;
+use itertools::Itertools;
+use tokio::runtime::Runtime;
+
+use crate::{
+    render::gpu_types::{GridSlot, MaskUniform, NodePoolUniform},
+    scene::{GridSource, SceneFileError},
+    vdb::{LeafEncoding, PoolError, Progress, ShapedTree, TreeShape, VDB345},
+};
+
+/// Node pools and masks of a set of grids, packed on the CPU so only the upload is left
+pub struct PackedGrids {
+    pub(super) pools: NodePoolUniform,
+    pub(super) masks: MaskUniform,
+    pub(super) grid_slots: Vec<GridSlot>,
+}
+
+impl PackedGrids {
+    /// `colors` holds the colour grid of each of `grids` that has one.
+    ///
+    /// Grids are packed in the shape of the files they were read from when they all share
+    /// one, the shader walks a single shape so mixed grids are packed as 5-4-3.
+    pub fn new(
+        grids: &[VDB345<u32>],
+        colors: &[Option<VDB345<u32>>],
+        encoding: LeafEncoding,
+    ) -> Result<Self, PoolError> {
+        let shape = grids
+            .iter()
+            .map(|grid| grid.grid_descriptor.tree_shape())
+            .dedup()
+            .exactly_one()
+            .unwrap_or_default();
+        let pools = grids
+            .iter()
+            .map(|grid| match shape == TreeShape::VDB345 {
+                true => grid.encoded_pool(encoding),
+                false => ShapedTree::from_vdb345(grid, shape)?.encoded_pool(encoding),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let masks = MaskUniform::from_pools(&pools, shape);
+        let (mut pools, mut grid_slots) = NodePoolUniform::from_pools(pools, encoding, shape)?;
+        pools.set_leaf_colors(&mut grid_slots, colors)?;
+
+        Ok(Self {
+            pools,
+            masks,
+            grid_slots,
+        })
+    }
+}
+
+/// Grids ready to replace the ones in the context, see [`super::WgpuContext::swap_grids`]
+pub struct LoadedGrids {
+    pub grids: Vec<VDB345<u32>>,
+    /// Colour grid of each of `grids` that has one
+    pub colors: Vec<Option<VDB345<u32>>>,
+    pub sources: Vec<GridSource>,
+    pub packed: PackedGrids,
+}
+
+impl LoadedGrids {
+    pub fn read(
+        sources: Vec<GridSource>,
+        encoding: LeafEncoding,
+        progress: &Progress,
+    ) -> Result<Self, SceneFileError> {
+        let grids = sources
+            .iter()
+            .map(|source| source.read_with_progress(progress))
+            .collect::<Result<Vec<_>, _>>()?;
+        let colors = sources
+            .iter()
+            .map(|source| source.read_color(progress))
+            .collect::<Result<Vec<_>, _>>()?;
+        let packed = PackedGrids::new(&grids, &colors, encoding)?;
+        if progress.is_cancelled() {
+            return Err(SceneFileError::Cancelled);
+        }
+
+        Ok(Self {
+            grids,
+            colors,
+            sources,
+            packed,
+        })
+    }
+}
+
+/// Grids being read, given their distance field and packed on a blocking thread of the
+/// tokio runtime, so the window keeps rendering the previous grids meanwhile.
+///
+/// Dropping the load cancels it.
+pub struct GridLoad {
+    pub progress: Arc<Progress>,
+    receiver: oneshot::Receiver<Result<LoadedGrids, SceneFileError>>,
+}
+
+impl GridLoad {
+    pub fn spawn(rt: &Runtime, sources: Vec<GridSource>, encoding: LeafEncoding) -> Self {
+        let progress = Arc::new(Progress::default());
+        let (sender, receiver) = oneshot::channel();
+
+        let task_progress = progress.clone();
+        rt.spawn_blocking(move || {
+            // Fails when the load was dropped, then nobody wants the grids anymore
+            let _ = sender.send(LoadedGrids::read(sources, encoding, &task_progress));
+        });
+
+        Self { progress, receiver }
+    }
+
+    /// The result once the load is done, `None` while it is still running
+    pub fn poll(&mut self) -> Option<Result<LoadedGrids, SceneFileError>> {
+        match self.receiver.try_recv() {
+            Ok(result) => result,
+            // The sender is only dropped without sending when the task panicked
+            Err(oneshot::Canceled) => Some(Err(SceneFileError::LoadPanicked)),
+        }
+    }
+}
+
+impl Drop for GridLoad {
+    fn drop(&mut self) {
+        self.progress.cancel();
+    }
+}
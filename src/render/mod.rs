@@ -1,13 +1,36 @@
 mod wgpu_context;
 pub use wgpu_context::WgpuContext;
 
+mod loader;
+pub use loader::GridLoad;
+
+mod export;
+pub use export::OfflineExport;
+
+mod playback;
+pub use playback::Playback;
+
 mod shader;
 pub use shader::Shader;
 
+mod slice;
+pub use slice::SliceImage;
+
+mod colormap;
+pub use colormap::{Colormap, COLORMAP_STOPS};
+
+mod tone_map;
+pub use tone_map::ToneMapper;
+
 mod camera;
-pub use camera::{Camera, CameraController};
+pub use camera::{Camera, CameraController, CameraMode, Projection, ViewAxis};
 
 mod egui_dev;
+pub use egui_dev::{
+    CameraCommand, EditCommand, ExportCommand, InspectedVoxel, MaterialCommand, ModelCommand,
+    PathTraceSettings, RenderMode, SamplingSettings, SceneCommand, SunSettings, TimelineCommand,
+    ToneSettings, ValueCommand, VdbFile,
+};
 mod frame_descriptor;
 mod gpu_types;
 mod pipelines;
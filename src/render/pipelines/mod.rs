@@ -8,6 +8,9 @@ pub use canvas::*;
 mod compute;
 pub use compute::*;
 
+mod tone_map;
+pub use tone_map::*;
+
 pub trait Pipeline {
     fn get(&self, context: &WgpuContext, layout: PipelineLayout) -> RenderPipeline;
 }
@@ -0,0 +1,22 @@
+use super::CPipeline;
+
+pub struct ToneMapPipeline;
+
+impl CPipeline for ToneMapPipeline {
+    fn get(
+        &self,
+        context: &crate::render::WgpuContext,
+        layout: wgpu::PipelineLayout,
+    ) -> wgpu::ComputePipeline {
+        let comp_shader = context.get_shader("tonemap.comp");
+
+        context
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Compute tone map pipeline"),
+                layout: Some(&layout),
+                module: comp_shader,
+                entry_point: "tm_main",
+            })
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Instant,
+};
+
+use log::error;
+
+use crate::scene::FrameSequence;
+
+use super::{
+    egui_dev::TimelineSettings,
+    loader::{GridLoad, LoadedGrids},
+    WgpuContext,
+};
+
+/// Plays a [`FrameSequence`] back.
+///
+/// The frames after the one on screen are read and packed in the background, and the next one
+/// is uploaded to the staged buffers of the context ahead of time, so stepping only swaps the
+/// buffers.
+pub struct Playback {
+    pub sequence: FrameSequence,
+    pub grid: String,
+    /// Frame on screen
+    pub shown: Option<usize>,
+    /// Frame in the staged buffers of the context
+    staged: Option<usize>,
+    loads: BTreeMap<usize, GridLoad>,
+    ready: BTreeMap<usize, LoadedGrids>,
+    /// Frames that could not be read, they are not retried
+    failed: BTreeSet<usize>,
+    last_step: Instant,
+    /// Recording one video frame per sequence frame, from the first to the last
+    exporting: bool,
+}
+
+impl Playback {
+    pub fn new(sequence: FrameSequence, grid: String) -> Self {
+        Self {
+            sequence,
+            grid,
+            shown: None,
+            staged: None,
+            loads: BTreeMap::new(),
+            ready: BTreeMap::new(),
+            failed: BTreeSet::new(),
+            last_step: Instant::now(),
+            exporting: false,
+        }
+    }
+
+    /// Play from the first frame while recording every frame once
+    pub fn start_export(&mut self, context: &mut WgpuContext) {
+        let timeline = &mut context.egui_dev.timeline;
+        timeline.frame = 0;
+        timeline.playing = true;
+        self.exporting = true;
+        context.capture_on_request = true;
+        // The first frame is already up, otherwise it is captured once presented
+        context.capture_requested = self.shown == Some(0);
+        context.start_recording();
+    }
+
+    fn finish_export(&mut self, context: &mut WgpuContext, timeline: &mut TimelineSettings) {
+        self.exporting = false;
+        timeline.playing = false;
+        context.capture_on_request = false;
+        context.stop_recording();
+        timeline.status = format!("Recorded {}", context.egui_dev.recording_file);
+    }
+
+    /// Step the timeline, keep the upcoming frames loading and present the wanted frame once
+    /// it is uploaded. Returns the frame presented this call.
+    pub fn update(&mut self, context: &mut WgpuContext) -> Option<usize> {
+        // The timeline is part of the panel, which the context owns
+        let mut timeline = std::mem::take(&mut context.egui_dev.timeline);
+        let presented = self.step(context, &mut timeline);
+        context.egui_dev.timeline = timeline;
+        presented
+    }
+
+    fn step(
+        &mut self,
+        context: &mut WgpuContext,
+        timeline: &mut TimelineSettings,
+    ) -> Option<usize> {
+        if self.sequence.is_empty() {
+            return None;
+        }
+        let last = self.sequence.len() - 1;
+        timeline.frame = timeline.frame.min(last);
+
+        if timeline.playing && self.shown == Some(timeline.frame) {
+            // Exports step as soon as the shown frame was captured, whatever the frame rate
+            let due = match self.exporting {
+                true => !context.capture_requested,
+                false => self.last_step.elapsed().as_secs_f32() >= 1. / timeline.fps,
+            };
+            if due {
+                match self.next(timeline.frame, timeline.looping && !self.exporting) {
+                    Some(next) => timeline.frame = next,
+                    None if self.exporting => self.finish_export(context, timeline),
+                    None => timeline.playing = false,
+                }
+            }
+        }
+
+        self.poll(&mut timeline.status);
+        if self.failed.contains(&timeline.frame) && timeline.playing {
+            match self.exporting {
+                true => self.finish_export(context, timeline),
+                false => timeline.playing = false,
+            }
+        }
+
+        // Cancel what scrubbing or stepping left behind, dropping a load cancels it
+        let wanted = self.wanted(timeline);
+        self.loads.retain(|idx, _| wanted.contains(idx));
+        self.ready.retain(|idx, _| wanted.contains(idx));
+        for &idx in &wanted {
+            let loaded = self.ready.contains_key(&idx)
+                || self.loads.contains_key(&idx)
+                || self.failed.contains(&idx)
+                || self.staged == Some(idx)
+                || self.shown == Some(idx);
+            if !loaded {
+                let source = self.sequence.source(idx, &self.grid);
+                self.loads.insert(idx, context.load_grids(vec![source]));
+            }
+        }
+        timeline.buffered = self.ready.len() + self.staged.is_some() as usize;
+
+        // Upload the wanted frame, or the one after it while the wanted one is up
+        let stage = match self.shown == Some(timeline.frame) {
+            true => wanted.get(1).copied(),
+            false => Some(timeline.frame),
+        };
+        if let Some(stage) = stage.filter(|&idx| self.staged != Some(idx)) {
+            if let Some(loaded) = self.ready.remove(&stage) {
+                match context.stage_grids(loaded) {
+                    Ok(()) => self.staged = Some(stage),
+                    Err(e) => {
+                        self.failed.insert(stage);
+                        let path = self.sequence.frames[stage].1.display();
+                        error!("Could not upload frame {path}: {e}");
+                        timeline.status = format!("Could not upload frame {path}: {e}");
+                    }
+                }
+            }
+        }
+
+        if self.shown != Some(timeline.frame) && self.staged == Some(timeline.frame) {
+            context.present_staged();
+            self.staged = None;
+            self.shown = Some(timeline.frame);
+            self.last_step = Instant::now();
+            if self.exporting {
+                context.capture_requested = true;
+            }
+            return self.shown;
+        }
+
+        None
+    }
+
+    /// Drop the staged frame and every pending load
+    pub fn stop(&mut self, context: &mut WgpuContext) {
+        if self.staged.take().is_some() {
+            context.discard_staged();
+        }
+        if self.exporting {
+            context.capture_on_request = false;
+            context.stop_recording();
+        }
+        self.loads.clear();
+        self.ready.clear();
+    }
+
+    fn next(&self, frame: usize, looping: bool) -> Option<usize> {
+        match frame + 1 < self.sequence.len() {
+            true => Some(frame + 1),
+            false => looping.then_some(0),
+        }
+    }
+
+    /// The wanted frame followed by the ones to prefetch, in playback order
+    fn wanted(&self, timeline: &TimelineSettings) -> Vec<usize> {
+        let looping = timeline.looping && !self.exporting;
+        let mut wanted = vec![timeline.frame];
+        while wanted.len() <= timeline.prefetch {
+            match self.next(*wanted.last().unwrap(), looping) {
+                Some(next) if !wanted.contains(&next) => wanted.push(next),
+                _ => break,
+            }
+        }
+        wanted
+    }
+
+    fn poll(&mut self, status: &mut String) {
+        let done = self
+            .loads
+            .iter_mut()
+            .filter_map(|(&idx, load)| Some((idx, load.poll()?)))
+            .collect::<Vec<_>>();
+
+        for (idx, result) in done {
+            self.loads.remove(&idx);
+            match result {
+                Ok(loaded) => {
+                    self.ready.insert(idx, loaded);
+                }
+                Err(e) => {
+                    self.failed.insert(idx);
+                    let path = self.sequence.frames[idx].1.display();
+                    error!("Could not load frame {path}: {e}");
+                    *status = format!("Could not load frame {path}: {e}");
+                }
+            }
+        }
+    }
+}
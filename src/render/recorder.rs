@@ -1,93 +1,182 @@
 use std::{
-    path::PathBuf,
-    sync::{
-        mpsc::{self, Receiver, Sender},
-        Arc, Mutex,
-    },
+    path::{Path, PathBuf},
+    sync::mpsc::{self, SyncSender},
     thread,
 };
 
 use itertools::Itertools;
+use log::{error, warn};
 use ndarray::Array3;
 use video_rs::{Encoder, EncoderSettings, Locator, Time};
 
+/// Frames waiting for the encoder before `send_frame` blocks, offline renders outpace it
+const QUEUED_FRAMES: usize = 8;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecorderError {
+    #[error("Could not create the video encoder: {0}")]
+    Encoder(#[from] video_rs::Error),
+    #[error("Could not write frame: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not encode png: {0}")]
+    Png(#[from] image::ImageError),
+    #[error("Frames must have an even, non zero size for h264, got {0}x{1}")]
+    Size(u32, u32),
+}
+
+/// Tightly packed RGBA pixels of a rendered frame, already tone mapped and sRGB encoded
 pub struct Frame {
     pub data: Vec<u8>,
     pub size: [u32; 2],
-    pub time: std::time::Instant,
 }
 
 impl Frame {
-    fn ndarray_frame(&self) -> Array3<u8> {
-        let rgb_data = self
-            .data
+    fn rgb(&self) -> Vec<u8> {
+        self.data
             .iter()
             .enumerate()
-            .filter_map(|(i, &b)| {
-                if i % 4 != 3 {
-                    Some(linear_to_srgb(b))
-                } else {
-                    None
-                }
-            })
-            .collect_vec();
+            .filter_map(|(i, &b)| if i % 4 != 3 { Some(b) } else { None })
+            .collect_vec()
+    }
+
+    fn ndarray_frame(&self) -> Array3<u8> {
+        Array3::from_shape_vec(
+            (self.size[1] as usize, self.size[0] as usize, 3),
+            self.rgb(),
+        )
+        .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingFormat {
+    H264,
+    /// Lossless numbered images, `turntable.png` becomes `turntable_00000.png`, ...
+    Png,
+}
+
+impl RecordingFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+        {
+            true => Self::Png,
+            false => Self::H264,
+        }
+    }
+}
+
+enum Sink {
+    Video(Encoder),
+    Png(PathBuf),
+}
+
+impl Sink {
+    fn write(&mut self, frame: &Frame, index: u64, fps: u32) -> Result<(), RecorderError> {
+        match self {
+            Sink::Video(encoder) => {
+                // Frame `index` is shown at `index / fps` seconds, whenever it was rendered
+                let position = Time::from_secs_f64(index as f64 / fps as f64);
+                encoder.encode(&frame.ndarray_frame(), &position)?;
+            }
+            Sink::Png(path) => {
+                let [width, height] = frame.size;
+                image::save_buffer(
+                    numbered_path(path, index),
+                    &frame.rgb(),
+                    width,
+                    height,
+                    image::ColorType::Rgb8,
+                )?;
+            }
+        }
+        Ok(())
+    }
 
-        Array3::from_shape_vec((self.size[1] as usize, self.size[0] as usize, 3), rgb_data).unwrap()
+    fn finish(self) -> Result<(), RecorderError> {
+        if let Sink::Video(mut encoder) = self {
+            encoder.finish()?;
+        }
+        Ok(())
     }
 }
 
+/// `dir/name.png` numbered as `dir/name_00042.png`
+fn numbered_path(path: &Path, index: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}_{index:05}.png"))
+}
+
+/// Encodes frames on its own thread, frame `n` lands at `n / fps` seconds of the output.
+///
+/// Every frame must have the size the recorder was created with, others are skipped.
 pub struct FrameRecorder {
-    sender: Option<Sender<Frame>>,
-    receiver: Arc<Mutex<Receiver<Frame>>>,
+    sender: Option<SyncSender<Frame>>,
     encoding_thread: Option<thread::JoinHandle<()>>,
-    encoder: Arc<Mutex<Encoder>>,
+    sink: Option<Sink>,
+    pub size: [u32; 2],
+    pub fps: u32,
     pub prev_frame_time: std::time::Instant,
 }
 
 impl FrameRecorder {
-    pub fn new(output_path: PathBuf) -> Self {
-        let (sender, reciever) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(reciever));
-
-        let destination: Locator = output_path.into();
-        let settings = EncoderSettings::for_h264_yuv420p(1600, 900, false);
-
-        let encoder = Encoder::new(&destination, settings).expect("failed to create encoder");
-        let encoder = Arc::new(Mutex::new(encoder));
+    pub fn new(output_path: PathBuf, size: [u32; 2], fps: u32) -> Result<Self, RecorderError> {
+        let sink = match RecordingFormat::from_path(&output_path) {
+            RecordingFormat::Png => {
+                if let Some(dir) = output_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir)?;
+                }
+                Sink::Png(output_path)
+            }
+            RecordingFormat::H264 => {
+                let [width, height] = size;
+                if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+                    return Err(RecorderError::Size(width, height));
+                }
+                let destination: Locator = output_path.into();
+                let settings =
+                    EncoderSettings::for_h264_yuv420p(width as usize, height as usize, false);
+                Sink::Video(Encoder::new(&destination, settings)?)
+            }
+        };
 
-        Self {
-            sender: Some(sender),
-            receiver,
+        Ok(Self {
+            sender: None,
             encoding_thread: None,
-            encoder,
+            sink: Some(sink),
+            size,
+            fps,
             prev_frame_time: std::time::Instant::now(),
-        }
+        })
     }
 
     pub fn start_encoding_thread(&mut self) {
-        let receiver = Arc::clone(&self.receiver);
-
-        let mut i = 0;
+        let Some(mut sink) = self.sink.take() else {
+            return;
+        };
+        let (sender, receiver) = mpsc::sync_channel::<Frame>(QUEUED_FRAMES);
+        self.sender = Some(sender);
 
-        let encoder_ptr = Arc::clone(&self.encoder);
+        let (size, fps) = (self.size, self.fps);
         self.encoding_thread = Some(thread::spawn(move || {
-            let receiver = receiver.lock().unwrap();
-
-            let mut prev_time = std::time::Instant::now();
-            let mut position = Time::zero();
+            let mut index = 0;
             while let Ok(frame) = receiver.recv() {
-                println!("Encoding {i}");
-                i += 1;
-
-                encoder_ptr
-                    .lock()
-                    .expect("fuck")
-                    .encode(&frame.ndarray_frame(), &position)
-                    .expect("failed to encode frame");
-
-                let elapsed = Time::from_secs_f64(prev_time.elapsed().as_secs_f64());
-                position = position.aligned_with(&elapsed).add();
-                prev_time = std::time::Instant::now();
+                if frame.size != size {
+                    warn!(
+                        "Skipping a {:?} frame of a {size:?} recording, the window was resized",
+                        frame.size
+                    );
+                    continue;
+                }
+                if let Err(e) = sink.write(&frame, index, fps) {
+                    error!("Could not record frame {index}: {e}");
+                }
+                index += 1;
+            }
+
+            if let Err(e) = sink.finish() {
+                error!("Could not finish recording: {e}");
             }
         }))
     }
@@ -99,42 +188,54 @@ impl FrameRecorder {
                 self.prev_frame_time = std::time::Instant::now();
             }
             _ => {
-                println!("No sender to send frame")
+                warn!("No sender to send frame")
             }
         }
     }
 
+    /// Waits until every sent frame is written
     pub fn end_encoder(&mut self) {
-        let s = self.sender.take();
-        drop(s);
-        println!("trying to join the thread");
+        drop(self.sender.take());
         if let Some(thread) = self.encoding_thread.take() {
             thread.join().unwrap();
         }
-        println!("joined the thread");
-        self.encoder
-            .lock()
-            .expect("Could not get encoder to finnish")
-            .finish()
-            .expect("Could not finish encoder");
     }
 }
 
-fn _srgb_to_linear(value: u8) -> u8 {
-    let c = value as f32 / 255.0; // Normalize to 0..1
-    let linear = if c <= 0.04045 {
-        c / 12.92
-    } else {
-        ((c + 0.055) / 1.055).powf(2.4)
-    };
-    (linear * 255.0) as u8 // Convert back to 0..255 range
-}
-fn linear_to_srgb(value: u8) -> u8 {
-    let c = value as f32 / 255.0; // Normalize to 0..1
-    let srgb = if c <= 0.0031308 {
-        12.92 * c
-    } else {
-        1.055 * c.powf(1.0 / 2.4) - 0.055
-    };
-    (srgb * 255.0).round() as u8
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_sequence_test() {
+        let dir = std::env::temp_dir().join("woxel_png_sequence_test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let path = dir.join("turntable.png");
+        assert_eq!(RecordingFormat::from_path(&path), RecordingFormat::Png);
+        assert_eq!(
+            RecordingFormat::from_path(Path::new("out.mp4")),
+            RecordingFormat::H264
+        );
+
+        let mut recorder = FrameRecorder::new(path, [3, 2], 30).unwrap();
+        recorder.start_encoding_thread();
+        for (i, size) in [[3, 2], [4, 4], [3, 2]].into_iter().enumerate() {
+            let data = vec![i as u8 * 100; (size[0] * size[1] * 4) as usize];
+            recorder.send_frame(Frame { data, size });
+        }
+        recorder.end_encoder();
+
+        // The resized frame is skipped, the numbering stays contiguous
+        let second = image::open(dir.join("turntable_00001.png"))
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(second.dimensions(), (3, 2));
+        // Written as they arrive, the tone map pass already encoded them
+        assert_eq!(second.get_pixel(0, 0).0, [200; 3]);
+        assert!(dir.join("turntable_00000.png").exists());
+        assert!(!dir.join("turntable_00002.png").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
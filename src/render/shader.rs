@@ -17,3 +17,29 @@ impl Shader {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+    #[test]
+    fn shaders_validate_test() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "wgsl") {
+                let source = std::fs::read_to_string(&path).unwrap();
+                // Same checks wgpu runs when the shader module is created
+                let module = naga::front::wgsl::parse_str(&source).unwrap_or_else(|e| {
+                    panic!("{}: {}", path.display(), e.emit_to_string(&source))
+                });
+                Validator::new(ValidationFlags::all(), Capabilities::empty())
+                    .validate(&module)
+                    .unwrap_or_else(|e| panic!("{}: {e:?}", path.display()));
+                checked += 1;
+            }
+        }
+        assert!(checked >= 4);
+    }
+}
@@ -0,0 +1,103 @@
+use egui::{Color32, ColorImage};
+
+use crate::vdb::VDB345;
+
+use super::{egui_dev::slice_axes, Colormap};
+
+/// Longest side of a slice image, larger slices are cropped at the far end
+const MAX_SLICE_SIDE: i32 = 2048;
+
+/// Active voxel values of a grid on an index space plane, colour mapped to an image
+pub struct SliceImage {
+    pub image: ColorImage,
+    /// Index coordinates of the lower left pixel along the two axes in the plane
+    pub origin: [i32; 2],
+    /// Values at the ends of the colour map
+    pub range: [f32; 2],
+}
+
+impl SliceImage {
+    /// Slice `grid` at index coordinate `position` along `axis`, `None` without active voxels
+    /// on the plane. Rows go up along the second axis of [`slice_axes`], voxels without a
+    /// value are transparent.
+    pub fn new(grid: &VDB345<u32>, axis: usize, position: i32, colormap: Colormap) -> Option<Self> {
+        let [column_axis, row_axis] = slice_axes(axis);
+        let voxels: Vec<([i32; 2], f32)> = grid
+            .plane_voxels(axis, position)
+            .map(|(p, value)| ([p[column_axis], p[row_axis]], f32::from_bits(value)))
+            .filter(|(_, value)| value.is_finite())
+            .collect();
+
+        let (min, max) = voxels.iter().fold(None, |bounds, &(p, _)| {
+            let (min, max): ([i32; 2], [i32; 2]) = bounds.unwrap_or((p, p));
+            Some((
+                [0, 1].map(|i| min[i].min(p[i])),
+                [0, 1].map(|i| max[i].max(p[i])),
+            ))
+        })?;
+        let size = [0, 1].map(|i| (max[i] - min[i] + 1).min(MAX_SLICE_SIDE));
+
+        let range = voxels.iter().fold(
+            [f32::INFINITY, f32::NEG_INFINITY],
+            |[low, high], &(_, v)| [low.min(v), high.max(v)],
+        );
+        let span = range[1] - range[0];
+
+        let mut image = ColorImage::new(size.map(|side| side as usize), Color32::TRANSPARENT);
+        for ([column, row], value) in voxels {
+            let x = column - min[0];
+            let y = row - min[1];
+            if x >= size[0] || y >= size[1] {
+                continue;
+            }
+
+            let t = match span > 0. {
+                true => (value - range[0]) / span,
+                false => 0.5,
+            };
+            let index = (size[1] - 1 - y) * size[0] + x;
+            image.pixels[index as usize] = colormap.color32(t);
+        }
+
+        Some(Self {
+            image,
+            origin: min,
+            range,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn slice_image_test() {
+        let builder = thread::Builder::new()
+            .name("slice_image_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u32>>::new();
+                vdb.set_voxel([2, 10, 5].into(), 1f32.to_bits());
+                vdb.set_voxel([2, 12, 7].into(), 3f32.to_bits());
+                vdb.set_voxel([3, 11, 6].into(), 9f32.to_bits());
+
+                let colormap = Colormap::Viridis;
+                let slice = SliceImage::new(&vdb, 0, 2, colormap).unwrap();
+                assert_eq!(slice.origin, [10, 5]);
+                assert_eq!(slice.range, [1., 3.]);
+                assert_eq!(slice.image.size, [3, 3]);
+                // Lowest value in the lower left, highest in the upper right
+                assert_eq!(slice.image.pixels[6], colormap.color32(0.));
+                assert_eq!(slice.image.pixels[2], colormap.color32(1.));
+                assert_eq!(slice.image.pixels[4], Color32::TRANSPARENT);
+
+                assert!(SliceImage::new(&vdb, 0, 4, colormap).is_none());
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+}
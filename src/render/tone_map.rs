@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// Curves taking the linear HDR colours the raycaster traces into the displayable range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ToneMapper {
+    /// Filmic fit of the ACES reference rendering transform, shifts bright colours to white
+    #[default]
+    Aces,
+    /// `c / (1 + l)` on the luminance, keeps the hue but never reaches white
+    Reinhard,
+    /// Hable's filmic curve with a toe in the shadows and a white point of 11.2
+    Filmic,
+    /// Colours above 1 are clipped, as traced
+    Clamp,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 4] = [
+        ToneMapper::Aces,
+        ToneMapper::Reinhard,
+        ToneMapper::Filmic,
+        ToneMapper::Clamp,
+    ];
+}
+
+impl std::fmt::Display for ToneMapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ToneMapper::Aces => "ACES",
+            ToneMapper::Reinhard => "Reinhard",
+            ToneMapper::Filmic => "Filmic",
+            ToneMapper::Clamp => "Clamp",
+        })
+    }
+}
@@ -1,18 +1,89 @@
-use std::{collections::HashMap, io::BufReader, sync::{Arc, Mutex}};
+use std::{collections::HashMap, sync::{Arc, Mutex}};
 
-use log::warn;
+use itertools::Itertools;
+
+use log::{error, warn};
 use wgpu::{BindGroup, BindGroupLayout, Buffer, BufferAsyncError, ShaderModule, Texture};
 use winit::window::Window;
 
-use crate::{render::gpu_types::MaskUniform, scene::Scene, vdb::VdbReader};
+use crate::{
+    render::{
+        gpu_types::{
+            ComputeState, EnvironmentMap, GridSlot, LeafPages, NodePoolUniform, PoolSpace,
+        },
+        Camera,
+    },
+    scene::{GridSource, Light, Material, Scene, SceneObject},
+    vdb::{EditHistory, LeafEncoding, PoolError, TreeShape, MAX_TREE_LEVELS, VDB345},
+};
 
 use super::{
+    loader::{GridLoad, LoadedGrids, PackedGrids},
     recorder::{Frame, FrameRecorder},
-    egui_dev::{EguiDev, VdbFile},
+    egui_dev::{EguiDev, RenderMode},
     frame_descriptor::FrameDescriptor,
-    pipelines::{CPipeline, ComputePipeline, Pipeline, VoxelPipeline},
+    pipelines::{CPipeline, ComputePipeline, Pipeline, ToneMapPipeline, VoxelPipeline},
 };
 
+const EDIT_HISTORY_LIMIT: usize = 256;
+/// Frame rate of recordings made while using the viewer, frames are captured at this rate
+const RECORDING_FPS: u32 = 30;
+
+type PoolGroup = ([Buffer; 6], Option<LeafPages>, BindGroup, BindGroupLayout);
+type MasksGroup = ([Buffer; 5], BindGroup, BindGroupLayout);
+type VdbGroups = (
+    PoolGroup,
+    MasksGroup,
+    Vec<GridSlot>,
+    PoolSpace,
+    [usize; MAX_TREE_LEVELS],
+);
+/// Compute state bytes and the scene a set of samples was traced with
+type AccumulationKey = (Vec<u8>, Vec<SceneObject>, Vec<Material>, Vec<Light>);
+
+/// Grids already uploaded to their own buffers, waiting to replace the ones on screen
+struct StagedGrids {
+    grids: Vec<VDB345<u32>>,
+    colors: Vec<Option<VDB345<u32>>>,
+    sources: Vec<GridSource>,
+    groups: VdbGroups,
+    leaf_encoding: LeafEncoding,
+    tree_shape: TreeShape,
+}
+
+/// Path traced samples summed over the frames
+struct Accumulation {
+    /// Sums so far and the sums written by the next compute pass
+    textures: [Texture; 2],
+    bind_group: BindGroup,
+    layout: BindGroupLayout,
+    size: [u32; 2],
+    /// Samples summed in `textures`
+    samples: u32,
+    /// Compute state and scene the samples were traced with, the sums start over when they
+    /// change or when it is cleared
+    key: Option<AccumulationKey>,
+    /// State of the last frame of the other modes when they blend over time, the textures
+    /// hold that frame instead of sums
+    history: Option<ComputeState>,
+}
+
+impl Accumulation {
+    fn new(device: &wgpu::Device, size: [u32; 2]) -> Self {
+        let (textures, bind_group, layout) =
+            FrameDescriptor::create_accumulation_binding(device, size);
+        Self {
+            textures,
+            bind_group,
+            layout,
+            size,
+            samples: 0,
+            key: None,
+            history: None,
+        }
+    }
+}
+
 pub struct WgpuContext {
     pub surface: wgpu::Surface,
     pub device: wgpu::Device,
@@ -21,16 +92,54 @@ pub struct WgpuContext {
     pub size: winit::dpi::PhysicalSize<u32>,
     pub egui_dev: EguiDev,
     pub egui_rpass: egui_wgpu_backend::RenderPass,
-    atlas_group: ([Texture; 3], BindGroup, BindGroupLayout),
-    masks_group: ([Buffer; 6], [Vec<u8>; 6], BindGroup, BindGroupLayout),
+    pool_group: PoolGroup,
+    masks_group: MasksGroup,
+    grid_slots: Vec<GridSlot>,
+    /// Nodes in `pool_group` and `masks_group`, nodes created by edits are appended there
+    pool_space: PoolSpace,
+    /// Encoding of the leaves in `pool_group`, `egui_dev` asks for another one with
+    /// `leaf_encoding`
+    leaf_encoding: LeafEncoding,
+    /// Shape the grids in `pool_group` are packed in, only 5-4-3 pools take appended nodes
+    tree_shape: TreeShape,
+    /// CPU copies of the grids instanced by the scene objects, edits to them are pushed
+    /// with `sync_vdb`
+    pub grids: Vec<VDB345<u32>>,
+    /// Colour grid of each of `grids` that has one, packed next to the leaves of its grid
+    colors: Vec<Option<VDB345<u32>>>,
+    /// File and grid name each of `grids` was read from
+    pub grid_sources: Vec<GridSource>,
+    /// Brush strokes applied to `grids`, cleared when another model is loaded
+    pub history: EditHistory<u32>,
     shaders: HashMap<&'static str, ShaderModule>,
     pub frame_recorder: Option<Arc<Mutex<FrameRecorder>>>,
+    /// Only capture frames when `capture_requested` is set instead of at a fixed interval,
+    /// used to record exactly one frame per step of a sequence
+    pub capture_on_request: bool,
+    pub capture_requested: bool,
+    /// Back buffers of the grids, filled by `stage_grids` while the front ones render
+    staged: Option<StagedGrids>,
+    /// Samples of the path traced mode summed on screen
+    accumulation: Accumulation,
+    /// Image lighting the scene in place of the sky, read from `environment_path`
+    environment: EnvironmentMap,
+    /// Last environment file `sync_environment` read or failed to read
+    environment_path: Option<String>,
+    /// Frames rendered to the window, seeds the random numbers of the path tracer
+    frame: u32,
     rt: tokio::runtime::Runtime,
     _textures: HashMap<&'static str, (Texture, BindGroup, BindGroupLayout)>,
 }
 
 impl WgpuContext {
-    pub async fn new(window: &Window) -> Self {
+    /// Sets up the device and uploads `grids` with their `colors`, which were read from
+    /// `grid_sources`
+    pub async fn new(
+        window: &Window,
+        grids: Vec<VDB345<u32>>,
+        colors: Vec<Option<VDB345<u32>>>,
+        grid_sources: Vec<GridSource>,
+    ) -> Self {
         let rt = tokio::runtime::Runtime::new().expect("Failed tp create tokio runtime");
 
         let size = window.inner_size();
@@ -38,7 +147,6 @@ impl WgpuContext {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             dx12_shader_compiler: Default::default(),
-            ..Default::default()
         });
 
         let surface = unsafe { instance.create_surface(&window) }.unwrap();
@@ -57,6 +165,9 @@ impl WgpuContext {
         warn!("Backend: {}", backend);
         warn!("Adaptor: {}", name);
 
+        // The node pool and objects are bound as storage buffers in their own bind groups, so
+        // ask for as much as the adapter allows
+        let adapter_limits = adapter.limits();
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
@@ -66,7 +177,15 @@ impl WgpuContext {
                     limits: if cfg!(target_arch = "wasm32") {
                         wgpu::Limits::downlevel_webgl2_defaults()
                     } else {
-                        wgpu::Limits::default()
+                        wgpu::Limits {
+                            max_bind_groups: adapter_limits.max_bind_groups,
+                            max_storage_buffers_per_shader_stage: adapter_limits
+                                .max_storage_buffers_per_shader_stage,
+                            max_storage_buffer_binding_size: adapter_limits
+                                .max_storage_buffer_binding_size,
+                            max_buffer_size: adapter_limits.max_buffer_size,
+                            ..Default::default()
+                        }
                     },
                     label: None,
                 },
@@ -98,65 +217,13 @@ impl WgpuContext {
         };
         surface.configure(&device, &config);
 
-        let f = std::fs::File::open("assets/utahteapot.vdb").unwrap();
-        let mut vdb_reader = VdbReader::new(BufReader::new(f)).unwrap();
-        let mut vdb = vdb_reader.read_vdb345_grid::<u32>("ls_utahteapot").unwrap();
-        vdb.compute_sdf();
-        warn!("Loaded vdb");
-        let atlas = vdb.atlas();
-
-
-        let atlas_size = atlas
-            .iter()
-            .map(|n| [n.len() as u32, n[0].len() as u32, n[0][0].len() as u32])
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
-
-        let (atlas_textures, bind_group, bind_group_layout) =
-            FrameDescriptor::create_compute_vdb_atlas_texture_binding(&device, atlas_size);
-
-        let flat_atlas: [Vec<u8>; 3] = atlas
-            .iter()
-            .map(|cube| {
-                let mut flat = vec![];
-                for x in 0..cube.len() {
-                    for y in 0..cube[0].len() {
-                        for z in 0..cube[0][0].len() {
-                            flat.append(&mut cube[z][y][x].to_ne_bytes().to_vec());
-                        }
-                    }
-                }
-                flat
-            })
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
-
-        for i in 0..3 {
-            queue.write_texture(
-                atlas_textures[i].as_image_copy(),
-                &flat_atlas[i],
-                wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(atlas_size[i][0] * 4), // 4 bytes per u32
-                    rows_per_image: Some(atlas_size[i][1]),
-                },
-                wgpu::Extent3d {
-                    width: atlas_size[i][0],
-                    height: atlas_size[i][1],
-                    depth_or_array_layers: atlas_size[i][2],
-                },
-            );
-        }
-
-        // TODO: Maybe export this to a function in FrameDescriptor
-        let (mask_buffers, mask_buffers_contents, mask_bind_group, mask_bind_group_layout) =
-            MaskUniform::from(&vdb).bind(&device);
-
-        for i in 0..mask_buffers.len() {
-            queue.write_buffer(&mask_buffers[i], 0, &mask_buffers_contents[i]);
-        }
+        let leaf_encoding = LeafEncoding::default();
+        let packed = PackedGrids::new(&grids, &colors, leaf_encoding)
+            .expect("Could not pack vdb into node pool");
+        let tree_shape = packed.pools.shape;
+        let (pool_group, masks_group, grid_slots, pool_space, pool_bytes) =
+            Self::create_vdb_groups(&device, &queue, packed)
+                .expect("Could not upload vdb node pool");
 
         let egui_platform =
             egui_winit_platform::Platform::new(egui_winit_platform::PlatformDescriptor {
@@ -168,10 +235,27 @@ impl WgpuContext {
             });
 
         let mut egui_dev = EguiDev::new(egui_platform);
-        // @HACK: This is kind of ugly
-        egui_dev.selected_model = egui_dev.models.binary_search_by_key(&"utahteapot".to_string(), |x| x.name.clone()).unwrap();
+        egui_dev.pool_bytes = pool_bytes;
+        egui_dev.pool_shape = tree_shape;
+        egui_dev.material_settings.sources = grid_sources.clone();
+        if let Some(source) = grid_sources.first() {
+            let model = egui_dev
+                .models
+                .iter()
+                .find_position(|model| model.path == source.path);
+            if let Some((id, model)) = model {
+                egui_dev.selected_model = id;
+                egui_dev.selected_grid = model
+                    .grids
+                    .iter()
+                    .position(|grid| *grid == source.grid)
+                    .unwrap_or(0);
+            }
+        }
 
         let egui_rpass = egui_wgpu_backend::RenderPass::new(&device, surface_format, 1);
+        let accumulation = Accumulation::new(&device, size.into());
+        let environment = EnvironmentMap::empty(&device, &queue);
 
         Self {
             surface,
@@ -181,15 +265,25 @@ impl WgpuContext {
             size,
             egui_dev,
             egui_rpass,
-            masks_group: (
-                mask_buffers,
-                mask_buffers_contents,
-                mask_bind_group,
-                mask_bind_group_layout,
-            ),
-            atlas_group: (atlas_textures, bind_group, bind_group_layout),
+            masks_group,
+            pool_group,
+            grid_slots,
+            pool_space,
+            leaf_encoding,
+            tree_shape,
+            grids,
+            colors,
+            grid_sources,
+            history: EditHistory::new(EDIT_HISTORY_LIMIT),
             shaders: HashMap::new(),
             frame_recorder: None,
+            capture_on_request: false,
+            capture_requested: false,
+            staged: None,
+            accumulation,
+            environment,
+            environment_path: None,
+            frame: 0,
             rt,
             _textures: HashMap::new(),
         }
@@ -224,34 +318,13 @@ impl WgpuContext {
 
         let num_indices = frame_descriptor.indicies().len() as u32;
 
-        let (
-            compute_state_buffer,
-            compute_state_buffer_contents,
-            compute_state_bind_group,
-            compute_state_bind_group_layout,
-        ) = FrameDescriptor::create_compute_state_binding(&scene.camera, self.size, &self.device, &self.egui_dev);
-
         let (state_buffer, state_buffer_contents, state_bind_group, state_bind_group_layout) =
             FrameDescriptor::create_screen_state_binding(&self.device, &scene.state);
 
-        let (compute_texture, compute_texture_bind_group, compute_texture_bind_group_layout) =
-            FrameDescriptor::create_compute_output_texture_binding(&self.device, self.size.into());
-
+        // Traced at the render scale, the canvas shader upsamples it to the window
+        let size = self.traced_size();
         let (fragment_texture, fragment_texture_bind_group, fragment_texture_bind_group_layout) =
-            FrameDescriptor::create_fragment_texture_binding(&self.device, self.size.into());
-
-        let compute_pipline_layout =
-            self.device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Compute Pipline Layout"),
-                    bind_group_layouts: &[
-                        &compute_state_bind_group_layout,
-                        &compute_texture_bind_group_layout,
-                        &self.atlas_group.2,
-                        &self.masks_group.3,
-                    ],
-                    push_constant_ranges: &[],
-                });
+            FrameDescriptor::create_fragment_texture_binding(&self.device, size);
 
         let render_pipeline_layout =
             self.device
@@ -264,29 +337,30 @@ impl WgpuContext {
                     push_constant_ranges: &[],
                 });
 
-        let compute_pipeline = ComputePipeline.get(self, compute_pipline_layout);
         let render_pipeline = VoxelPipeline.get(self, render_pipeline_layout);
 
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Compute Pass"),
-            });
-
-            compute_pass.set_pipeline(&compute_pipeline);
-            compute_pass.set_bind_group(0, &compute_state_bind_group, &[]);
-            compute_pass.set_bind_group(1, &compute_texture_bind_group, &[]);
-            compute_pass.set_bind_group(2, &self.atlas_group.1, &[]);
-            compute_pass.set_bind_group(3, &self.masks_group.2, &[]);
-            // @TODO: CHOOSE WORKGROUPS BASED ON ADAPTOR (32 for NVDIA, 64 for AMD)
-            compute_pass.dispatch_workgroups(self.size.width / 8, self.size.height / 4, 1);
+        self.update_accumulation(scene, size);
+        let (compute_texture, state) = self.trace(
+            &mut encoder,
+            scene,
+            &scene.camera,
+            size,
+            &self.accumulation,
+            self.frame,
+        );
+        self.accumulation.history = state.temporal().then_some(state);
+        self.frame = self.frame.wrapping_add(1);
+        if self.egui_dev.render_mode == RenderMode::PathTrace {
+            self.accumulation.samples += 1;
         }
+        self.egui_dev.path_trace_settings.samples = self.accumulation.samples;
 
         encoder.copy_texture_to_texture(
             compute_texture.as_image_copy(),
             fragment_texture.as_image_copy(),
             wgpu::Extent3d {
-                width: self.size.width,
-                height: self.size.height,
+                width: size[0],
+                height: size[1],
                 depth_or_array_layers: 1,
             },
         );
@@ -307,7 +381,6 @@ impl WgpuContext {
                     },
                 })],
                 depth_stencil_attachment: None,
-                ..Default::default()
             });
             render_pass.set_pipeline(&render_pipeline);
             render_pass.set_bind_group(0, &fragment_texture_bind_group, &[]);
@@ -317,7 +390,7 @@ impl WgpuContext {
             render_pass.draw_indexed(0..num_indices, 0, 0..1);
         }
 
-        let (tdelta, paint_jobs, screen_descriptor, model_changed, reload_shaders, recording_changed) = self.egui_dev.get_frame(scene, window);
+        let (tdelta, paint_jobs, screen_descriptor, reload_shaders, recording_changed) = self.egui_dev.get_frame(scene, window);
 
         // @HACK: refactor egui out and get shader from context
         if reload_shaders {
@@ -325,6 +398,10 @@ impl WgpuContext {
                 Ok(source) => self.reload_shader("raycast.comp", source),
                 Err(e) => panic!("Compute shaders missing {e}"),
             }
+            match std::fs::read_to_string("src/shaders/tonemap.comp.wgsl") {
+                Ok(source) => self.reload_shader("tonemap.comp", source),
+                Err(e) => panic!("Compute shaders missing {e}"),
+            }
         }
 
         if self.egui_dev.show {
@@ -346,55 +423,25 @@ impl WgpuContext {
 
         self.queue
             .write_buffer(&state_buffer, 0, &state_buffer_contents);
-        self.queue
-            .write_buffer(&compute_state_buffer, 0, &compute_state_buffer_contents);
 
         if recording_changed {
             if self.egui_dev.recording {
-                let mut fr = FrameRecorder::new(self.egui_dev.recording_file.clone().into());
-                fr.start_encoding_thread();
-                self.frame_recorder = Some(Arc::new(Mutex::new(fr)));
+                self.start_recording();
             } else {
-                match self.frame_recorder.take() {
-                    Some(fr) => {
-                        self.rt.spawn(async move {
-                            fr.lock()
-                              .expect("Could not aquire frame recorder")
-                              .end_encoder();
-                        });
-                    }
-                    _ => {
-                        println!("This would be strange");
-                   }
-                }
+                self.stop_recording();
             }
         }
 
         // Capture screen
         let should_capture = match &self.frame_recorder {
-            Some(fr) => fr.lock().expect("failed to get recording lock").prev_frame_time.elapsed().as_millis() > 33,
+            Some(_) if self.capture_on_request => std::mem::take(&mut self.capture_requested),
+            Some(fr) => fr.lock().expect("failed to get recording lock").prev_frame_time.elapsed().as_millis() > 1000 / RECORDING_FPS as u128,
             None => false,
         };
 
         if should_capture{
-            let recording_buffer = FrameDescriptor::create_recording_buffer(&self.device, self.size.into());
-
-            encoder.copy_texture_to_buffer(
-                compute_texture.as_image_copy(),
-                wgpu::ImageCopyBufferBase {
-                    buffer: &recording_buffer,
-                    layout: wgpu::ImageDataLayout {
-                        offset: 0,
-                        bytes_per_row:
-                        Some(self.size.width * 4), rows_per_image:
-                        Some(self.size.height)
-                    }
-                },
-                wgpu::Extent3d {
-                    width: self.size.width,
-                    height: self.size.height,
-                    depth_or_array_layers: 1 }
-            );
+            let recording_buffer =
+                self.copy_to_recording_buffer(&mut encoder, &compute_texture, size);
 
             self.queue.submit(std::iter::once(encoder.finish()));
 
@@ -414,11 +461,285 @@ impl WgpuContext {
 
         }
 
-        if model_changed {
-            self.change_vdb_model(self.egui_dev.models[self.egui_dev.selected_model].clone());
+        Ok(())
+    }
+
+    /// Size the compute pass traces the window at, scaled down by the render scale except
+    /// while recording, which captures every pixel of the window
+    fn traced_size(&self) -> [u32; 2] {
+        let scale = match self.frame_recorder {
+            Some(_) => 1.0,
+            None => self.egui_dev.sampling_settings.render_scale.clamp(0.1, 1.0),
+        };
+        [self.size.width, self.size.height]
+            .map(|side| ((side as f32 * scale).round() as u32).max(1))
+    }
+
+    /// Recreate the accumulation textures when the traced `size` changes, and start the sums
+    /// over when the camera, the settings or the objects change
+    fn update_accumulation(&mut self, scene: &Scene, size: [u32; 2]) {
+        if self.accumulation.size != size {
+            self.accumulation = Accumulation::new(&self.device, size);
         }
 
-        Ok(())
+        let state = FrameDescriptor::create_compute_state(
+            &scene.camera,
+            size.into(),
+            &self.egui_dev,
+            self.leaf_encoding,
+            self.tree_shape,
+        );
+        let key = (
+            bytemuck::bytes_of(&state).to_vec(),
+            scene.objects.clone(),
+            self.egui_dev.material_settings.materials.clone(),
+            self.egui_dev.light_settings.lights.clone(),
+        );
+        if self.accumulation.key.as_ref() != Some(&key) {
+            self.accumulation.key = Some(key);
+            self.accumulation.samples = 0;
+        }
+    }
+
+    /// Raytrace the scene seen from `camera` into a new texture of tone mapped sRGB bytes, along
+    /// with the state it was traced with. The path traced mode adds one sample to the sums of
+    /// `accumulation`, seeded with `frame`, and the other modes blend with its history frame
+    /// when they blend over time.
+    fn trace(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &Scene,
+        camera: &Camera,
+        size: [u32; 2],
+        accumulation: &Accumulation,
+        frame: u32,
+    ) -> (Texture, ComputeState) {
+        let mut state = FrameDescriptor::create_compute_state(
+            camera,
+            size.into(),
+            &self.egui_dev,
+            self.leaf_encoding,
+            self.tree_shape,
+        )
+        .with_frame(accumulation.samples, frame);
+        if let Some(previous) = &accumulation.history {
+            state = state.with_history(previous);
+        }
+        let (_, _, compute_state_bind_group, compute_state_bind_group_layout) =
+            FrameDescriptor::create_compute_state_binding(&self.device, &state);
+
+        let (_, _, objects_bind_group, objects_bind_group_layout) =
+            FrameDescriptor::create_compute_objects_binding(
+                &self.device,
+                &scene.objects,
+                &self.egui_dev.material_settings.materials,
+                &self.grids,
+                &self.grid_slots,
+            );
+
+        let (_, _, lights_bind_group, lights_bind_group_layout) =
+            FrameDescriptor::create_compute_lights_binding(
+                &self.device,
+                &self.egui_dev.light_settings.lights,
+                &self.environment,
+            );
+
+        let (compute_texture, compute_texture_bind_group, compute_texture_bind_group_layout) =
+            FrameDescriptor::create_compute_output_texture_binding(&self.device, size);
+
+        let compute_pipline_layout =
+            self.device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Compute Pipline Layout"),
+                    bind_group_layouts: &[
+                        &compute_state_bind_group_layout,
+                        &compute_texture_bind_group_layout,
+                        &self.pool_group.3,
+                        &self.masks_group.2,
+                        &objects_bind_group_layout,
+                        &accumulation.layout,
+                        &lights_bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
+        let compute_pipeline = ComputePipeline.get(self, compute_pipline_layout);
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Compute Pass"),
+            });
+
+            compute_pass.set_pipeline(&compute_pipeline);
+            compute_pass.set_bind_group(0, &compute_state_bind_group, &[]);
+            compute_pass.set_bind_group(1, &compute_texture_bind_group, &[]);
+            compute_pass.set_bind_group(2, &self.pool_group.2, &[]);
+            compute_pass.set_bind_group(3, &self.masks_group.1, &[]);
+            compute_pass.set_bind_group(4, &objects_bind_group, &[]);
+            compute_pass.set_bind_group(5, &accumulation.bind_group, &[]);
+            compute_pass.set_bind_group(6, &lights_bind_group, &[]);
+            // @TODO: CHOOSE WORKGROUPS BASED ON ADAPTOR (32 for NVDIA, 64 for AMD)
+            compute_pass.dispatch_workgroups(size[0].div_ceil(8), size[1].div_ceil(4), 1);
+        }
+
+        if self.egui_dev.render_mode == RenderMode::PathTrace || state.temporal() {
+            let [sums, new_sums] = &accumulation.textures;
+            encoder.copy_texture_to_texture(
+                new_sums.as_image_copy(),
+                sums.as_image_copy(),
+                wgpu::Extent3d {
+                    width: size[0],
+                    height: size[1],
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        (self.tone_map(encoder, &compute_texture, size), state)
+    }
+
+    /// Expose, tone map and encode the linear HDR colours of `hdr` into a new texture of sRGB
+    /// bytes, which the window, recordings and exports all take as they are
+    fn tone_map(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &Texture,
+        size: [u32; 2],
+    ) -> Texture {
+        let (_, _, tone_map_bind_group, tone_map_bind_group_layout) =
+            FrameDescriptor::create_tone_map_binding(&self.device, &self.egui_dev, hdr);
+        let (display_texture, display_texture_bind_group, display_texture_bind_group_layout) =
+            FrameDescriptor::create_display_texture_binding(&self.device, size);
+
+        let tone_map_pipeline_layout =
+            self.device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Tone Map Pipeline Layout"),
+                    bind_group_layouts: &[
+                        &tone_map_bind_group_layout,
+                        &display_texture_bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
+        let tone_map_pipeline = ToneMapPipeline.get(self, tone_map_pipeline_layout);
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Tone Map Pass"),
+            });
+
+            compute_pass.set_pipeline(&tone_map_pipeline);
+            compute_pass.set_bind_group(0, &tone_map_bind_group, &[]);
+            compute_pass.set_bind_group(1, &display_texture_bind_group, &[]);
+            compute_pass.dispatch_workgroups(size[0].div_ceil(8), size[1].div_ceil(4), 1);
+        }
+
+        display_texture
+    }
+
+    /// Render the scene seen from `camera` at `size`, independent of the window, and wait for
+    /// the pixels. Returns tightly packed RGBA rows.
+    ///
+    /// The path traced mode sums `export_samples` samples from scratch.
+    pub fn render_offscreen(&self, scene: &Scene, camera: &Camera, size: [u32; 2]) -> Vec<u8> {
+        let samples = match self.egui_dev.render_mode {
+            RenderMode::PathTrace => self.egui_dev.path_trace_settings.export_samples.max(1),
+            _ => 1,
+        };
+        let mut accumulation = Accumulation::new(&self.device, size);
+        // Every sample but the last is submitted on its own, so its output texture can go
+        while accumulation.samples + 1 < samples {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Offscreen Sample Encoder"),
+                });
+            let frame = accumulation.samples;
+            self.trace(&mut encoder, scene, camera, size, &accumulation, frame);
+            self.queue.submit(std::iter::once(encoder.finish()));
+            accumulation.samples += 1;
+        }
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen Encoder"),
+            });
+
+        let frame = accumulation.samples;
+        let (compute_texture, _) =
+            self.trace(&mut encoder, scene, camera, size, &accumulation, frame);
+        let buffer = self.copy_to_recording_buffer(&mut encoder, &compute_texture, size);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("Could not map offscreen frame")
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let data = unpad_rows(&slice.get_mapped_range(), size);
+        buffer.unmap();
+        data
+    }
+
+    /// Copy a compute output texture into a new mappable buffer with aligned rows
+    fn copy_to_recording_buffer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &Texture,
+        size @ [width, height]: [u32; 2],
+    ) -> Buffer {
+        let buffer = FrameDescriptor::create_recording_buffer(&self.device, size);
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBufferBase {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes(width)),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        buffer
+    }
+
+    /// Start encoding the rendered frames to `egui_dev.recording_file`
+    pub fn start_recording(&mut self) {
+        let path = self.egui_dev.recording_file.clone().into();
+        match FrameRecorder::new(path, self.size.into(), RECORDING_FPS) {
+            Ok(mut fr) => {
+                fr.start_encoding_thread();
+                self.frame_recorder = Some(Arc::new(Mutex::new(fr)));
+                self.egui_dev.recording = true;
+            }
+            Err(e) => {
+                error!("Could not record to {}: {e}", self.egui_dev.recording_file);
+                self.egui_dev.recording = false;
+            }
+        }
+    }
+
+    /// Finish the video in the background once the captured frames are encoded
+    pub fn stop_recording(&mut self) {
+        self.egui_dev.recording = false;
+        match self.frame_recorder.take() {
+            Some(fr) => {
+                self.rt.spawn(async move {
+                    fr.lock()
+                        .expect("Could not aquire frame recorder")
+                        .end_encoder();
+                });
+            }
+            _ => {
+                println!("This would be strange");
+            }
+        }
     }
 
     pub fn send_recording_buffer(&self, buffer: &Buffer) {
@@ -437,8 +758,6 @@ impl WgpuContext {
            label: Some("Buffer to Vec encoder")
         });
 
-        let frame_time = std::time::Instant::now();
-
         encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
         self.queue.submit(Some(encoder.finish()));
 
@@ -456,15 +775,11 @@ impl WgpuContext {
             match receiver.await {
                 Ok(_) => {
                     let data = staging_buffer.slice(..).get_mapped_range();
-                    let result = data.to_vec();
+                    let result = unpad_rows(&data, size);
                     drop(data);
                     staging_buffer.unmap();
 
-                    let frame = Frame {
-                        data: result,
-                        size,
-                        time: frame_time,
-                    };
+                    let frame = Frame { data: result, size };
 
                     frame_recorder.lock().expect("shit").send_frame(frame);
                 },
@@ -503,72 +818,293 @@ impl WgpuContext {
             .unwrap_or_else(|| panic!("No shader with name '{name}'"))
     }
 
-    pub fn change_vdb_model(&mut self, model: VdbFile) {
-        let f = std::fs::File::open(model.path).unwrap();
-        let mut vdb_reader = VdbReader::new(BufReader::new(f)).unwrap();
-        // TODO: Display vdb options and read accordingly
-        let mut vdb = vdb_reader
-            .read_vdb345_grid::<u32>(&model.grid)
-            .unwrap();
+    /// Create the node pool and mask buffers of grids packed one after the other. The packed
+    /// copies are dropped once the buffers hold them.
+    fn create_vdb_groups(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        packed: PackedGrids,
+    ) -> Result<VdbGroups, PoolError> {
+        let PackedGrids {
+            pools,
+            masks,
+            grid_slots,
+        } = packed;
+        Self::check_pool_limits(device, &pools)?;
+
+        let space = pools.space(&device.limits());
+        let (pool_buffers, leaf_pages, _, pool_bind_group, pool_layout) =
+            FrameDescriptor::create_compute_node_pool_binding(device, queue, &pools, &space);
+        if leaf_pages.is_some() {
+            warn!("The leaves don't fit a storage buffer binding, paging them into a texture");
+        }
+        // The buffers are created with their contents, no need to write them again
+        let (mask_buffers, _, masks_bind_group, masks_layout) = masks.bind(device, &space);
+
+        Ok((
+            (pool_buffers, leaf_pages, pool_bind_group, pool_layout),
+            (mask_buffers, masks_bind_group, masks_layout),
+            grid_slots,
+            space,
+            pools.level_bytes(),
+        ))
+    }
 
-        vdb.compute_sdf();
+    /// Levels larger than a storage buffer binding can't be bound, leaves larger than that are
+    /// paged as long as they fit [`LeafPages`]
+    fn check_pool_limits(device: &wgpu::Device, pools: &NodePoolUniform) -> Result<(), PoolError> {
+        let level_bytes = pools.level_bytes();
+        let levels = level_bytes
+            .iter()
+            .enumerate()
+            .map(|(level, bytes)| format!("level {level} {} KiB", bytes >> 10))
+            .join(", ");
+        warn!("Node pool: {levels}");
+
+        let limits = device.limits();
+        let leaf_level = pools.shape.leaf_level();
+        for (level, &bytes) in level_bytes.iter().enumerate() {
+            let max = match level == leaf_level {
+                true => LeafPages::max_bytes(&limits),
+                false => limits.max_storage_buffer_binding_size as usize,
+            };
+            if bytes > max {
+                return Err(PoolError::TooLarge { level, bytes, max });
+            }
+        }
+        Ok(())
+    }
 
-        let atlas = vdb.atlas();
+    /// Re-upload every grid, used when the node numbering or the leaf encoding changes
+    fn upload_grids(&mut self) -> Result<(), PoolError> {
+        let packed = PackedGrids::new(&self.grids, &self.colors, self.egui_dev.leaf_encoding)?;
+        self.bind_packed(packed)
+    }
 
-        let atlas_size = atlas
-            .iter()
-            .map(|n| [n.len() as u32, n[0].len() as u32, n[0][0].len() as u32])
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
+    fn bind_packed(&mut self, packed: PackedGrids) -> Result<(), PoolError> {
+        let leaf_encoding = packed.pools.encoding;
+        let tree_shape = packed.pools.shape;
+        let groups = Self::create_vdb_groups(&self.device, &self.queue, packed)?;
+        self.set_vdb_groups(groups, leaf_encoding, tree_shape);
+        Ok(())
+    }
 
-        self.atlas_group =
-            FrameDescriptor::create_compute_vdb_atlas_texture_binding(&self.device, atlas_size);
+    fn set_vdb_groups(
+        &mut self,
+        groups: VdbGroups,
+        leaf_encoding: LeafEncoding,
+        tree_shape: TreeShape,
+    ) {
+        let (pool_group, masks_group, grid_slots, pool_space, pool_bytes) = groups;
+        self.pool_group = pool_group;
+        self.masks_group = masks_group;
+        self.grid_slots = grid_slots;
+        self.pool_space = pool_space;
+        self.egui_dev.pool_bytes = pool_bytes;
+        self.leaf_encoding = leaf_encoding;
+        self.tree_shape = tree_shape;
+        self.egui_dev.pool_shape = tree_shape;
+        self.accumulation.key = None;
+    }
 
-        let flat_atlas: [Vec<u8>; 3] = atlas
-            .iter()
-            .map(|cube| {
-                let mut flat = vec![];
-                for x in 0..cube.len() {
-                    for y in 0..cube[0].len() {
-                        for z in 0..cube[0][0].len() {
-                            flat.append(&mut cube[z][y][x].to_ne_bytes().to_vec());
-                        }
-                    }
-                }
-                flat
-            })
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
+    /// Replace every grid with grids that were already packed, only the upload is left.
+    ///
+    /// The previous grids are used until this returns, so nothing renders half a load, and
+    /// stay when the new ones don't fit the device.
+    pub fn swap_grids(&mut self, loaded: LoadedGrids) -> Result<(), PoolError> {
+        self.stage_grids(loaded)?;
+        self.present_staged();
+        Ok(())
+    }
 
-        for i in 0..3 {
-            self.queue.write_texture(
-                self.atlas_group.0[i].as_image_copy(),
-                &flat_atlas[i],
-                wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(atlas_size[i][0] * 4), // 4 bytes per u32
-                    rows_per_image: Some(atlas_size[i][1]),
-                },
-                wgpu::Extent3d {
-                    width: atlas_size[i][0],
-                    height: atlas_size[i][1],
-                    depth_or_array_layers: atlas_size[i][2],
-                },
-            );
+    /// Upload grids to the back buffers while the current ones keep rendering, replacing
+    /// grids that were staged but not presented
+    pub fn stage_grids(&mut self, loaded: LoadedGrids) -> Result<(), PoolError> {
+        let leaf_encoding = loaded.packed.pools.encoding;
+        let tree_shape = loaded.packed.pools.shape;
+        self.staged = Some(StagedGrids {
+            grids: loaded.grids,
+            colors: loaded.colors,
+            sources: loaded.sources,
+            groups: Self::create_vdb_groups(&self.device, &self.queue, loaded.packed)?,
+            leaf_encoding,
+            tree_shape,
+        });
+        Ok(())
+    }
+
+    /// Swap the staged grids in, `false` when nothing was staged
+    pub fn present_staged(&mut self) -> bool {
+        let Some(staged) = self.staged.take() else {
+            return false;
+        };
+        self.set_vdb_groups(staged.groups, staged.leaf_encoding, staged.tree_shape);
+        self.grids = staged.grids;
+        self.colors = staged.colors;
+        self.egui_dev.material_settings.sources = staged.sources.clone();
+        self.grid_sources = staged.sources;
+        self.history.clear();
+        true
+    }
+
+    /// Drop the staged grids, used when they won't be shown anymore
+    pub fn discard_staged(&mut self) {
+        self.staged = None;
+    }
+
+    /// Read, prepare and pack `sources` in the background, the result goes to
+    /// [`WgpuContext::swap_grids`]
+    pub fn load_grids(&self, sources: Vec<GridSource>) -> GridLoad {
+        GridLoad::spawn(&self.rt, sources, self.egui_dev.leaf_encoding)
+    }
+
+    /// Read the environment map of the light settings once its file changes, the sky lights
+    /// the scene again when it can't be read
+    pub fn sync_environment(&mut self) {
+        let settings = &mut self.egui_dev.light_settings;
+        if settings.environment.path == self.environment_path {
+            return;
+        }
+        self.environment_path = settings.environment.path.clone();
+        self.accumulation.key = None;
+
+        let Some(path) = &self.environment_path else {
+            self.environment = EnvironmentMap::empty(&self.device, &self.queue);
+            settings.environment_loaded = false;
+            settings.status.clear();
+            return;
+        };
+        match EnvironmentMap::read(&self.device, &self.queue, path) {
+            Ok(environment) => {
+                self.environment = environment;
+                settings.environment_loaded = true;
+                settings.status = format!("Loaded {path}");
+            }
+            Err(e) => {
+                error!("Could not read environment map {path}: {e}");
+                self.environment = EnvironmentMap::empty(&self.device, &self.queue);
+                settings.environment_loaded = false;
+                settings.status = format!("Could not read {path}: {e}");
+            }
         }
+    }
 
-        self.masks_group = MaskUniform::from(&vdb).bind(&self.device);
+    /// Push the edits recorded in each grid's `dirty` set to the GPU.
+    ///
+    /// Only the bricks and masks of the touched nodes are rewritten, with the distance field
+    /// updated around them, and the colour bricks stay in place. Nodes created by the edits
+    /// are appended to the pools, see [`GridSlot::append`], and their parents rewritten to
+    /// point at them. Activated tiles renumber the nodes and a different leaf encoding changes
+    /// every brick, so like nodes that can't be appended they re-upload everything. Pools in
+    /// another shape than 5-4-3 are repacked from the grids on every edit.
+    pub fn sync_vdb(&mut self) {
+        let mut upload_all = self.egui_dev.leaf_encoding != self.leaf_encoding
+            || self.tree_shape != TreeShape::VDB345;
+
+        for grid_idx in 0..self.grids.len() {
+            let vdb = &mut self.grids[grid_idx];
+            let dirty = std::mem::take(&mut vdb.dirty);
+            if dirty.is_empty() {
+                continue;
+            }
+            self.accumulation.key = None;
+
+            if dirty.topology {
+                vdb.compute_sdf();
+                upload_all = true;
+                continue;
+            }
+            // The first edit after a full compute_sdf clamps every distance
+            if !vdb.sdf_capped {
+                vdb.cap_sdf();
+                upload_all = true;
+            }
+
+            let mut nodes = dirty.nodes;
+            for level in [0, 1] {
+                let updated = vdb.update_tile_sdf_around(level, &dirty.added[level + 1]);
+                nodes[level].extend(updated);
+            }
+            let updated = vdb.update_sdf_around(&nodes[2]);
+            nodes[2].extend(updated);
+            if upload_all {
+                continue;
+            }
 
-        for i in 0..self.masks_group.0.len() {
-            self.queue
-                .write_buffer(&self.masks_group.0[i], 0, &self.masks_group.1[i]);
+            let Some(slot) = self.grid_slots.get_mut(grid_idx) else {
+                continue;
+            };
+            if !slot.append(&mut self.pool_space, &dirty.added) {
+                upload_all = true;
+                continue;
+            }
+
+            let vdb = &self.grids[grid_idx];
+            let slot = &self.grid_slots[grid_idx];
+            let node_sizes = self.leaf_encoding.node_sizes(&self.tree_shape);
+            for (level, origins) in nodes.iter().enumerate() {
+                let brick_bytes = (node_sizes[level] * 4) as u64;
+                self.egui_dev.pool_bytes[level] += dirty.added[level].len() * brick_bytes as usize;
+                for &origin in origins {
+                    let Some(idx) = slot.index.get(level, origin) else {
+                        continue;
+                    };
+                    let idx = (slot.bases[level] + idx) as u64;
+
+                    let brick = if level == 2 {
+                        let (brick, range) = vdb.encoded_leaf(origin, self.leaf_encoding);
+                        self.queue.write_buffer(
+                            &self.pool_group.0[MAX_TREE_LEVELS],
+                            idx * 8,
+                            bytemuck::cast_slice(&range),
+                        );
+                        brick
+                    } else {
+                        vdb.pool_brick(&slot.index, level, origin)
+                    };
+                    match &self.pool_group.1 {
+                        Some(pages) if level == 2 => pages.write(
+                            &self.queue,
+                            idx as usize * node_sizes[level],
+                            bytemuck::cast_slice(&brick),
+                        ),
+                        _ => self.queue.write_buffer(
+                            &self.pool_group.0[level],
+                            idx * brick_bytes,
+                            bytemuck::cast_slice(&brick),
+                        ),
+                    }
+
+                    let masks = vdb.node_masks(level, origin);
+                    let mask_bytes: &[u8] = bytemuck::cast_slice(&masks);
+                    self.queue.write_buffer(
+                        &self.masks_group.0[level],
+                        idx * mask_bytes.len() as u64,
+                        mask_bytes,
+                    );
+                }
+            }
         }
 
-        for i in 0..self.masks_group.0.len() {
-            self.queue
-                .write_buffer(&self.masks_group.0[i], 0, &self.masks_group.1[i]);
+        if upload_all {
+            if let Err(e) = self.upload_grids() {
+                error!("Could not sync vdb: {e}");
+            }
         }
     }
 }
+
+/// Row size of a texture copy, padded to the alignment wgpu requires
+fn padded_row_bytes(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * 4).div_ceil(align) * align
+}
+
+/// Drop the row padding of a texture copy
+fn unpad_rows(data: &[u8], [width, height]: [u32; 2]) -> Vec<u8> {
+    data.chunks(padded_row_bytes(width) as usize)
+        .take(height as usize)
+        .flat_map(|row| &row[..width as usize * 4])
+        .copied()
+        .collect()
+}
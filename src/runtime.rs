@@ -1,17 +1,59 @@
-use log::warn;
+use std::{fs, path::Path};
+
+use bytes::BytesMut;
+use cgmath::{EuclideanSpace, InnerSpace, Vector3};
+use log::{error, warn};
 use winit::{
     dpi::PhysicalPosition,
-    event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
+    event::{
+        ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode,
+        WindowEvent,
+    },
     event_loop::{ControlFlow, EventLoopWindowTarget},
     window::Window,
 };
 
-use crate::{render::WgpuContext, scene::Scene};
+use crate::{
+    render::{
+        CameraCommand, CameraMode, EditCommand, ExportCommand, GridLoad, InspectedVoxel,
+        MaterialCommand, ModelCommand, OfflineExport, Playback, Projection, SceneCommand,
+        SliceImage, TimelineCommand, ValueCommand, VdbFile, WgpuContext,
+    },
+    scene::{
+        CameraBookmark, CameraKeyframe, CameraPath, FrameSequence, GridSource, Scene, SceneFile,
+        SceneFileError, SceneObject,
+    },
+    vdb::{write_vdb, RayHit, Stroke, WriteOptions},
+};
+
+/// Furthest voxel the brush can pick, in voxels
+const MAX_PICK_DISTANCE: f32 = 10000.;
+
+/// What replaces the scene once the grids of a background load are ready
+enum LoadTarget {
+    /// A single instance of the only grid
+    Model,
+    /// The objects, camera and settings of the file
+    Scene(Box<SceneFile>),
+}
+
+struct PendingLoad {
+    load: GridLoad,
+    target: LoadTarget,
+    name: String,
+}
 
 pub struct Runtime {
     context: WgpuContext,
     window: Window,
     scene: Scene,
+    modifiers: ModifiersState,
+    /// Grids being read in the background, the scene keeps the old ones until they are done
+    loading: Option<PendingLoad>,
+    /// Sequence of files played back instead of a static grid
+    playback: Option<Playback>,
+    /// Camera path being rendered offline, one frame per redraw
+    export: Option<OfflineExport>,
 }
 
 impl Runtime {
@@ -20,6 +62,10 @@ impl Runtime {
             context,
             window,
             scene,
+            modifiers: ModifiersState::empty(),
+            loading: None,
+            playback: None,
+            export: None,
         }
     }
 
@@ -43,6 +89,18 @@ impl Runtime {
                 ref event,
                 window_id,
             } if window_id == self.window.id() => {
+                // Keys typed into a text field of the ui reach neither the shortcuts nor the
+                // camera, even while the cursor is grabbed and the ui isn't asked above
+                if matches!(event, WindowEvent::KeyboardInput { .. })
+                    && self
+                        .context
+                        .egui_dev
+                        .platform
+                        .context()
+                        .wants_keyboard_input()
+                {
+                    return;
+                }
                 self.scene.input(&event);
 
                 match event {
@@ -74,12 +132,35 @@ impl Runtime {
                         self.scene.state.resolution = self.context.size.into();
                     }
                     WindowEvent::Focused(focus) => self.handle_cursor_focus(*focus),
+                    WindowEvent::ModifiersChanged(modifiers) => self.modifiers = *modifiers,
+                    // In edit mode left click paints and right click releases the cursor
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } if self.editing() => self.apply_brush(),
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Right,
+                        ..
+                    } if self.editing() => self.handle_cursor_pressed(),
+                    // The orbit camera drags with a free cursor
+                    WindowEvent::MouseInput {
+                        button: MouseButton::Left,
+                        ..
+                    } if self.scene.camera_controller.mode == CameraMode::Orbit
+                        && !self.context.egui_dev.edit_settings.enabled => {}
                     WindowEvent::MouseInput {
                         state: ElementState::Pressed,
                         button: MouseButton::Left,
                         ..
                     } => self.handle_cursor_pressed(),
                     WindowEvent::CursorMoved { position, .. } => self.handle_cursor_move(*position),
+                    WindowEvent::DroppedFile(path) => {
+                        let model_settings = &mut self.context.egui_dev.model_settings;
+                        model_settings.path = path.display().to_string();
+                        model_settings.command = Some(ModelCommand::Open(path.clone()));
+                    }
                     WindowEvent::KeyboardInput {
                         input:
                             KeyboardInput {
@@ -91,12 +172,112 @@ impl Runtime {
                     } => {
                         self.context.egui_dev.show = !self.context.egui_dev.show;
                     }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::E),
+                                ..
+                            },
+                        ..
+                    } => {
+                        let edit_settings = &mut self.context.egui_dev.edit_settings;
+                        edit_settings.enabled = !edit_settings.enabled;
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Z),
+                                ..
+                            },
+                        ..
+                    } if self.modifiers.ctrl() => self.handle_edit_command(EditCommand::Undo),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Y),
+                                ..
+                            },
+                        ..
+                    } if self.modifiers.ctrl() => self.handle_edit_command(EditCommand::Redo),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F),
+                                ..
+                            },
+                        ..
+                    } => self.handle_camera_command(CameraCommand::FrameSelected),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Home),
+                                ..
+                            },
+                        ..
+                    } => self.handle_camera_command(CameraCommand::FrameAll),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    } if (VirtualKeyCode::Key1..=VirtualKeyCode::Key9).contains(key) => {
+                        let idx = *key as usize - VirtualKeyCode::Key1 as usize;
+                        self.handle_camera_command(CameraCommand::GoToBookmark(idx));
+                    }
                     _ => {}
                 }
             }
             Event::RedrawRequested(window_id) if window_id == self.window.id() => {
+                let camera_settings = &mut self.context.egui_dev.camera_settings;
+                self.scene.camera_controller.mode = camera_settings.mode;
+                self.scene.camera_controller.speed_scale = camera_settings.speed_scale;
+                camera_settings.bookmarks = self
+                    .scene
+                    .bookmarks
+                    .iter()
+                    .map(|b| b.name.clone())
+                    .collect();
                 self.scene.update();
 
+                if let Some(command) = self.context.egui_dev.camera_settings.command.take() {
+                    self.handle_camera_command(command);
+                }
+
+                if let Some(command) = self.context.egui_dev.edit_settings.command.take() {
+                    self.handle_edit_command(command);
+                }
+                if let Some(command) = self.context.egui_dev.scene_settings.command.take() {
+                    self.handle_scene_command(command);
+                }
+                if let Some(command) = self.context.egui_dev.model_settings.command.take() {
+                    self.handle_model_command(command);
+                }
+                if let Some(command) = self.context.egui_dev.export.command.take() {
+                    self.handle_export_command(command);
+                }
+                if let Some(command) = self.context.egui_dev.value_settings.command.take() {
+                    self.handle_value_command(command);
+                }
+                if let Some(command) = self.context.egui_dev.material_settings.command.take() {
+                    self.handle_material_command(command);
+                }
+                self.poll_load();
+                self.update_playback();
+                self.context.sync_vdb();
+                self.context.sync_environment();
+                self.update_export();
+                self.update_slice();
+                self.update_inspect();
+                self.context.egui_dev.clip_settings.bounds = self.scene.bounds(&self.context.grids);
+
                 match self.context.render(&self.scene, &self.window) {
                     Ok(_) => {}
                     // Reconfigure the surface if lost
@@ -114,6 +295,511 @@ impl Runtime {
         }
     }
 
+    fn editing(&self) -> bool {
+        self.context.egui_dev.edit_settings.enabled && self.scene.state.cursor_grabbed
+    }
+
+    /// Closest object under the crosshair, i.e. along the view direction, with the voxel hit
+    fn pick_object(&self) -> Option<(usize, RayHit)> {
+        let camera = &self.scene.camera;
+        self.pick(camera.eye.to_vec(), camera.target - camera.eye)
+    }
+
+    /// Closest object hit by a world space ray and the voxel it hits
+    fn pick(&self, origin: Vector3<f32>, dir: Vector3<f32>) -> Option<(usize, RayHit)> {
+        self.scene
+            .objects
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, object)| {
+                let grid = self.context.grids.get(object.grid)?;
+                let (hit, t) = object.raycast(grid, origin, dir, MAX_PICK_DISTANCE)?;
+                Some((idx, hit, t))
+            })
+            .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+            .map(|(idx, hit, _)| (idx, hit))
+    }
+
+    /// Applies the brush to the voxel under the crosshair.
+    ///
+    /// The closest object is edited, which changes every instance of its grid.
+    fn apply_brush(&mut self) {
+        let Some((object, hit)) = self.pick_object() else {
+            return;
+        };
+        let grid = self.scene.objects[object].grid;
+
+        let brush = self.context.egui_dev.edit_settings.brush();
+        let changes = brush.apply(&mut self.context.grids[grid], &hit);
+        self.context.history.push(Stroke { grid, changes });
+    }
+
+    fn handle_edit_command(&mut self, command: EditCommand) {
+        match command {
+            EditCommand::Undo => {
+                self.context.history.undo(&mut self.context.grids);
+            }
+            EditCommand::Redo => {
+                self.context.history.redo(&mut self.context.grids);
+            }
+            EditCommand::Save => self.save_vdb(),
+        }
+    }
+
+    fn save_vdb(&mut self) {
+        let edit_settings = &mut self.context.egui_dev.edit_settings;
+
+        // @TODO: Pick the grid to save once there is a grid list in the ui
+        let Some(vdb) = self.context.grids.first() else {
+            return;
+        };
+
+        let mut b = BytesMut::new();
+        if let Err(e) = write_vdb(&mut b, &[vdb], &WriteOptions::default()) {
+            error!("Could not encode vdb: {e}");
+            edit_settings.status = format!("Could not encode vdb: {e}");
+            return;
+        }
+
+        edit_settings.status = match fs::write(&edit_settings.save_file, &b) {
+            Ok(()) => format!("Saved {}", edit_settings.save_file),
+            Err(e) => {
+                error!("Could not save {}: {e}", edit_settings.save_file);
+                format!("Could not save {}: {e}", edit_settings.save_file)
+            }
+        };
+    }
+
+    fn handle_scene_command(&mut self, command: SceneCommand) {
+        let path = self.context.egui_dev.scene_settings.file.clone();
+        let result = match command {
+            SceneCommand::Load => {
+                SceneFile::load(&path).map(|file| self.load_scene(file, path.clone()))
+            }
+            SceneCommand::Save => self
+                .scene
+                .to_file(&self.context)
+                .save(&path)
+                .map(|()| format!("Saved {path}")),
+        };
+
+        self.context.egui_dev.scene_settings.status = result.unwrap_or_else(|e| {
+            error!("Scene file {path}: {e}");
+            format!("{path}: {e}")
+        });
+    }
+
+    fn handle_model_command(&mut self, command: ModelCommand) {
+        let result = match command {
+            ModelCommand::Load => self.load_selected_model(),
+            ModelCommand::Open(path) => self.open_file(&path),
+        };
+
+        self.context.egui_dev.model_settings.status = result.unwrap_or_else(|e| {
+            error!("{e}");
+            e
+        });
+    }
+
+    /// Opens a scene file, or lists a VDB file in the model picker and shows its first grid
+    fn open_file(&mut self, path: &Path) -> Result<String, String> {
+        if path.extension().is_some_and(|e| e == "ron") {
+            return SceneFile::load(path)
+                .map(|file| self.load_scene(file, path.display().to_string()))
+                .map_err(|e| format!("{}: {e}", path.display()));
+        }
+
+        let file =
+            VdbFile::open(path).map_err(|e| format!("Could not open {}: {e}", path.display()))?;
+        self.context.egui_dev.select_model(file);
+        match FrameSequence::detect(path) {
+            Some(sequence) => self.start_playback(sequence, path),
+            None => self.load_selected_model(),
+        }
+    }
+
+    /// Plays the selected grid of every file of `sequence`, starting at the opened one
+    fn start_playback(&mut self, sequence: FrameSequence, path: &Path) -> Result<String, String> {
+        let egui_dev = &self.context.egui_dev;
+        let grid = egui_dev
+            .models
+            .get(egui_dev.selected_model)
+            .and_then(|model| model.grids.get(egui_dev.selected_grid))
+            .ok_or_else(|| format!("{} has no grids", path.display()))?
+            .clone();
+
+        self.stop_playback();
+        self.loading = None;
+        self.context.egui_dev.model_settings.loading = None;
+
+        let timeline = &mut self.context.egui_dev.timeline;
+        timeline.frames = sequence.len();
+        timeline.frame = sequence.position(path).unwrap_or(0);
+        timeline.numbers = sequence.frames.iter().map(|(number, _)| *number).collect();
+        timeline.playing = false;
+        timeline.status.clear();
+
+        let status = format!("Playing {} frames of {grid}", sequence.len());
+        self.playback = Some(Playback::new(sequence, grid));
+        Ok(status)
+    }
+
+    fn stop_playback(&mut self) {
+        if let Some(mut playback) = self.playback.take() {
+            playback.stop(&mut self.context);
+        }
+        self.context.egui_dev.timeline.frames = 0;
+    }
+
+    /// Steps the sequence, the first frame shown replaces the scene with a single instance
+    fn update_playback(&mut self) {
+        let Some(playback) = self.playback.as_mut() else {
+            return;
+        };
+
+        if let Some(TimelineCommand::Export) = self.context.egui_dev.timeline.command.take() {
+            playback.start_export(&mut self.context);
+        }
+        let first = playback.shown.is_none();
+        if playback.update(&mut self.context).is_some() && first {
+            self.scene.objects = vec![SceneObject::new(0)];
+        }
+    }
+
+    fn handle_camera_command(&mut self, command: CameraCommand) {
+        match command {
+            CameraCommand::FrameAll => {
+                if let Some(bounds) = self.scene.bounds(&self.context.grids) {
+                    self.scene.frame(&bounds);
+                }
+            }
+            CameraCommand::FrameSelected => {
+                // Nothing in the middle of the view frames everything instead
+                let picked = self.pick_object().and_then(|(idx, _)| {
+                    let object = &self.scene.objects[idx];
+                    object.world_bounds(self.context.grids.get(object.grid)?)
+                });
+                if let Some(bounds) = picked.or_else(|| self.scene.bounds(&self.context.grids)) {
+                    self.scene.frame(&bounds);
+                }
+            }
+            CameraCommand::SaveBookmark => {
+                let settings = &mut self.context.egui_dev.camera_settings;
+                let name = match settings.bookmark_name.trim() {
+                    "" => format!("View {}", self.scene.bookmarks.len() + 1),
+                    name => name.to_string(),
+                };
+                let bookmark = CameraBookmark {
+                    camera: (&self.scene.camera).into(),
+                    name,
+                };
+                match self
+                    .scene
+                    .bookmarks
+                    .iter_mut()
+                    .find(|b| b.name == bookmark.name)
+                {
+                    Some(existing) => *existing = bookmark,
+                    None => self.scene.bookmarks.push(bookmark),
+                }
+                settings.bookmark_name.clear();
+            }
+            CameraCommand::GoToBookmark(idx) => {
+                if let Some(bookmark) = self.scene.bookmarks.get(idx) {
+                    bookmark.camera.apply(&mut self.scene.camera);
+                }
+            }
+            CameraCommand::DeleteBookmark(idx) => {
+                if idx < self.scene.bookmarks.len() {
+                    self.scene.bookmarks.remove(idx);
+                }
+            }
+            CameraCommand::Projection(projection) => self.scene.camera.set_projection(projection),
+            CameraCommand::AxisView(axis) => {
+                self.scene.camera.set_projection(Projection::Orthographic);
+                self.scene.camera.look_along(axis.direction());
+                if let Some(bounds) = self.scene.bounds(&self.context.grids) {
+                    self.scene.frame(&bounds);
+                }
+            }
+        }
+    }
+
+    fn handle_export_command(&mut self, command: ExportCommand) {
+        let settings = &mut self.context.egui_dev.export;
+        match command {
+            ExportCommand::AddKeyframe => {
+                self.scene.camera_path.insert(CameraKeyframe {
+                    time: settings.keyframe_time,
+                    camera: (&self.scene.camera).into(),
+                });
+                settings.keyframe_time += 1.;
+            }
+            ExportCommand::ClearKeyframes => {
+                self.scene.camera_path = CameraPath::default();
+                settings.keyframe_time = 0.;
+            }
+            ExportCommand::Turntable => {
+                let Some(bounds) = self.scene.bounds(&self.context.grids) else {
+                    settings.status = "Nothing to turn around".to_string();
+                    return;
+                };
+
+                // Keep the bounding sphere of the scene in view all the way around
+                let fovy = self.scene.camera.fovy;
+                let radius = (bounds.max - bounds.min).magnitude() * 0.5;
+                let distance = radius / (fovy.to_radians() * 0.5).sin();
+                self.scene.camera_path = CameraPath::turntable(
+                    bounds.center().into(),
+                    distance,
+                    distance * 0.25,
+                    fovy,
+                    settings.duration,
+                );
+            }
+            ExportCommand::Start => {
+                let path = self.scene.camera_path.clone();
+                settings.status = match OfflineExport::start(settings, path) {
+                    Ok(export) => {
+                        self.export = Some(export);
+                        format!("Exporting to {}", settings.output)
+                    }
+                    Err(e) => {
+                        error!("Could not export to {}: {e}", settings.output);
+                        format!("Could not export to {}: {e}", settings.output)
+                    }
+                };
+            }
+            ExportCommand::Cancel => {
+                if let Some(export) = self.export.take() {
+                    let (done, _) = export.progress();
+                    export.finish();
+                    settings.status = format!("Cancelled, kept {done} frames");
+                }
+            }
+        }
+    }
+
+    /// Renders the next frame of the offline export, finishing it after the last one
+    fn update_export(&mut self) {
+        let settings = &mut self.context.egui_dev.export;
+        settings.keyframes = self.scene.camera_path.keyframes.len();
+        let Some(export) = self.export.as_mut() else {
+            settings.progress = None;
+            return;
+        };
+
+        let more = export.step(&self.context, &self.scene);
+        let settings = &mut self.context.egui_dev.export;
+        settings.progress = Some(export.progress());
+        if !more {
+            let (frames, _) = export.progress();
+            if let Some(export) = self.export.take() {
+                export.finish();
+            }
+            settings.progress = None;
+            settings.status = format!("Exported {frames} frames to {}", settings.output);
+        }
+    }
+
+    fn handle_value_command(&mut self, command: ValueCommand) {
+        match command {
+            ValueCommand::FitRange => {
+                let values = self.context.grids.iter().flat_map(|grid| {
+                    grid.active_voxels()
+                        .map(|(_, value)| f32::from_bits(value))
+                        .filter(|value| value.is_finite())
+                });
+                let range = values.fold(None, |range: Option<[f32; 2]>, value| {
+                    let [low, high] = range.unwrap_or([value; 2]);
+                    Some([low.min(value), high.max(value)])
+                });
+                if let Some(range) = range {
+                    self.context.egui_dev.value_settings.range = range;
+                }
+            }
+        }
+    }
+
+    fn handle_material_command(&mut self, command: MaterialCommand) {
+        match command {
+            MaterialCommand::ColorGrid(grid, color) => {
+                // The colours are packed against the numbering of the grid's nodes, so both
+                // are read again like a scene that keeps everything else
+                let mut file = self.scene.to_file(&self.context);
+                let Some(source) = file.grids.get_mut(grid) else {
+                    return;
+                };
+                source.color = color;
+                let name = format!("{} from {}", source.grid, source.path);
+                self.context.egui_dev.model_settings.status = self.load_scene(file, name);
+            }
+        }
+    }
+
+    /// Slices the selected grid again when the slice view shows a different plane than asked for
+    fn update_slice(&mut self) {
+        let settings = &mut self.context.egui_dev.slice_settings;
+        settings.grids = self.context.grids.len();
+        settings.grid = settings.grid.min(settings.grids.saturating_sub(1));
+        let Some(grid) = self.context.grids.get(settings.grid) else {
+            settings.active_bounds = None;
+            settings.texture = None;
+            return;
+        };
+
+        if !settings.show || settings.shown == Some(settings.key()) {
+            return;
+        }
+
+        // Bounds are found again for a different grid or a refresh, which may follow edits
+        let other_grid = settings
+            .shown
+            .is_some_and(|(shown, ..)| shown != settings.grid);
+        if other_grid || settings.shown.is_none() {
+            settings.active_bounds = grid.active_bounds();
+        }
+        // A different grid, or a plane off the active voxels, starts in the middle of them
+        if let Some((min, max)) = settings.active_bounds {
+            let axis = settings.axis;
+            if other_grid || !(min[axis]..=max[axis]).contains(&settings.position) {
+                settings.position = (min[axis] + max[axis]) / 2;
+            }
+        }
+
+        settings.shown = Some(settings.key());
+        let slice = SliceImage::new(grid, settings.axis, settings.position, settings.colormap);
+        settings.texture = slice.map(|slice| {
+            settings.origin = slice.origin;
+            settings.range = slice.range;
+            self.context.egui_dev.platform.context().load_texture(
+                "slice",
+                slice.image,
+                egui::TextureOptions::NEAREST,
+            )
+        });
+    }
+
+    /// Finds the voxel under the cursor for the inspect panel, the crosshair while grabbed
+    fn update_inspect(&mut self) {
+        let settings = &mut self.context.egui_dev.inspect_settings;
+        if !settings.enabled {
+            settings.hit = None;
+            return;
+        }
+        // Keep showing the last voxel while the cursor is over the panels
+        let ctx = self.context.egui_dev.platform.context();
+        if ctx.is_pointer_over_area() {
+            return;
+        }
+
+        let state = &self.scene.state;
+        let point = match state.cursor_grabbed {
+            true => state.resolution.map(|side| side / 2.),
+            false => state.curr_cursor,
+        };
+        let (origin, dir) = self.scene.camera.ray(point, state.resolution);
+        let hit = self.pick(origin.to_vec(), dir).and_then(|(object, hit)| {
+            let object_grid = self.scene.objects[object].grid;
+            let grid = self.context.grids.get(object_grid)?;
+            let voxel = hit.voxel.cast::<f32>()?.extend(1.);
+            let world = grid.transform.index_to_world().cast::<f32>()? * voxel;
+            let index_to_scene = self.scene.objects[object].index_to_world(&grid.transform);
+
+            Some(InspectedVoxel {
+                object,
+                grid: object_grid,
+                world: world.truncate().into(),
+                scene: (index_to_scene * voxel).truncate().into(),
+                info: grid.inspect(hit.voxel),
+            })
+        });
+        self.context.egui_dev.inspect_settings.hit = hit;
+    }
+
+    /// Starts reading the selected grid, a single instance of it replaces the scene once done
+    fn load_selected_model(&mut self) -> Result<String, String> {
+        let egui_dev = &self.context.egui_dev;
+        let model = egui_dev
+            .models
+            .get(egui_dev.selected_model)
+            .ok_or_else(|| "No model selected".to_string())?;
+        let grid = model
+            .grids
+            .get(egui_dev.selected_grid)
+            .ok_or_else(|| format!("{} has no grids", model.path))?;
+        let source = GridSource {
+            path: model.path.clone(),
+            grid: grid.clone(),
+            color: None,
+        };
+
+        let name = format!("{} from {}", source.grid, source.path);
+        Ok(self.start_load(vec![source], LoadTarget::Model, name))
+    }
+
+    fn load_scene(&mut self, file: SceneFile, name: String) -> String {
+        let sources = file.grids.clone();
+        self.start_load(sources, LoadTarget::Scene(Box::new(file)), name)
+    }
+
+    /// Reads `sources` in the background, replacing and cancelling an unfinished load
+    fn start_load(&mut self, sources: Vec<GridSource>, target: LoadTarget, name: String) -> String {
+        self.stop_playback();
+        let load = self.context.load_grids(sources);
+        self.context.egui_dev.model_settings.loading = Some((name.clone(), load.progress.clone()));
+        let status = format!("Loading {name}");
+        self.loading = Some(PendingLoad { load, target, name });
+        status
+    }
+
+    /// Swaps in the grids of the background load once it is done
+    fn poll_load(&mut self) {
+        let Some(result) = self
+            .loading
+            .as_mut()
+            .and_then(|pending| pending.load.poll())
+        else {
+            return;
+        };
+        let Some(PendingLoad { target, name, .. }) = self.loading.take() else {
+            return;
+        };
+        self.context.egui_dev.model_settings.loading = None;
+
+        // Grids too large for the device are reported like any other failed load
+        let result = result.and_then(|loaded| Ok(self.context.swap_grids(loaded)?));
+        let status = match result {
+            Ok(()) => {
+                match &target {
+                    LoadTarget::Model => {
+                        self.scene.objects = vec![SceneObject::new(0)];
+                        self.context.egui_dev.material_settings.materials.clear();
+                    }
+                    LoadTarget::Scene(file) => self.scene.apply_file(file, &mut self.context),
+                }
+                // A model is framed, a scene keeps its camera and only gets a fitting speed
+                if let Some(bounds) = self.scene.bounds(&self.context.grids) {
+                    match target {
+                        LoadTarget::Model => self.scene.frame(&bounds),
+                        LoadTarget::Scene(_) => self.scene.camera_controller.fit_to(&bounds),
+                    }
+                }
+                format!("Loaded {name}")
+            }
+            Err(SceneFileError::Cancelled) => format!("Cancelled loading {name}"),
+            Err(e) => {
+                error!("Could not load {name}: {e}");
+                format!("Could not load {name}: {e}")
+            }
+        };
+
+        if let LoadTarget::Scene(_) = target {
+            self.context.egui_dev.scene_settings.status = status.clone();
+        }
+        self.context.egui_dev.model_settings.status = status;
+    }
+
     const FOCUS_2_GRAB_MODE: [winit::window::CursorGrabMode; 2] = [
         winit::window::CursorGrabMode::None,
         winit::window::CursorGrabMode::Confined,
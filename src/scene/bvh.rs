@@ -0,0 +1,115 @@
+use cgmath::Vector3;
+
+/// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    /// Box that contains nothing, growing it by any point gives that point
+    pub fn empty() -> Self {
+        Self {
+            min: Vector3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vector3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vector3<f32>>) -> Self {
+        points.into_iter().fold(Self::empty(), |aabb, p| Self {
+            min: aabb.min.zip(p, f32::min),
+            max: aabb.max.zip(p, f32::max),
+        })
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: self.min.zip(other.min, f32::min),
+            max: self.max.zip(other.max, f32::max),
+        }
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BvhNode {
+    pub aabb: Aabb,
+    /// Index of the left child for internal nodes, the right child follows it. For leaves the
+    /// first entry of `Bvh::order`
+    pub left_first: u32,
+    /// Number of primitives in a leaf, 0 for internal nodes
+    pub count: u32,
+}
+
+/// Bounding volume hierarchy over the objects of a scene, the root is node 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    /// Primitive indices in leaf order, leaves reference contiguous ranges of it
+    pub order: Vec<u32>,
+}
+
+const BVH_LEAF_SIZE: usize = 2;
+
+impl Bvh {
+    /// Builds the hierarchy by splitting on the median of the longest axis
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: vec![BvhNode {
+                aabb: Aabb::empty(),
+                left_first: 0,
+                count: 0,
+            }],
+            order: (0..bounds.len() as u32).collect(),
+        };
+
+        if !bounds.is_empty() {
+            bvh.subdivide(0, 0, bounds.len(), bounds);
+        }
+
+        bvh
+    }
+
+    fn subdivide(&mut self, node: usize, first: usize, count: usize, bounds: &[Aabb]) {
+        let range = first..first + count;
+        let aabb = self.order[range.clone()]
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.union(&bounds[i as usize]));
+        self.nodes[node].aabb = aabb;
+
+        if count <= BVH_LEAF_SIZE {
+            self.nodes[node].left_first = first as u32;
+            self.nodes[node].count = count as u32;
+            return;
+        }
+
+        let extent = aabb.max - aabb.min;
+        let axis = match extent {
+            e if e.x >= e.y && e.x >= e.z => 0,
+            e if e.y >= e.z => 1,
+            _ => 2,
+        };
+        self.order[range].sort_by(|&a, &b| {
+            let a = bounds[a as usize].center()[axis];
+            let b = bounds[b as usize].center()[axis];
+            a.total_cmp(&b)
+        });
+
+        let left = self.nodes.len();
+        let empty = BvhNode {
+            aabb: Aabb::empty(),
+            left_first: 0,
+            count: 0,
+        };
+        self.nodes.extend([empty, empty]);
+        self.nodes[node].left_first = left as u32;
+
+        let half = count / 2;
+        self.subdivide(left, first, half, bounds);
+        self.subdivide(left + 1, first + half, count - half, bounds);
+    }
+}
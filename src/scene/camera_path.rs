@@ -0,0 +1,204 @@
+use cgmath::{InnerSpace, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::file::CameraDescription;
+
+/// Camera pose at `time` seconds into a [`CameraPath`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub camera: CameraDescription,
+}
+
+/// Keyframed camera motion, sampled with a Catmull-Rom spline through the keyframes.
+///
+/// Keyframes are kept sorted by time. Before the first and after the last keyframe the path
+/// holds still.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    /// A full circle around `center` at `radius`, `height` above it, in `duration` seconds
+    pub fn turntable(center: [f32; 3], radius: f32, height: f32, fovy: f32, duration: f32) -> Self {
+        const STEPS: usize = 16;
+        let keyframes = (0..=STEPS)
+            .map(|step| {
+                // The last keyframe repeats the first exactly, which closes the loop
+                let angle = (step % STEPS) as f32 / STEPS as f32 * std::f32::consts::TAU;
+                CameraKeyframe {
+                    time: step as f32 / STEPS as f32 * duration,
+                    camera: CameraDescription {
+                        eye: [
+                            center[0] + radius * angle.sin(),
+                            center[1] + height,
+                            center[2] - radius * angle.cos(),
+                        ],
+                        target: center,
+                        fovy,
+                    },
+                }
+            })
+            .collect();
+
+        Self { keyframes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0., |keyframe| keyframe.time)
+    }
+
+    /// Add a keyframe, replacing one at the same time
+    pub fn insert(&mut self, keyframe: CameraKeyframe) {
+        let idx = self
+            .keyframes
+            .partition_point(|other| other.time < keyframe.time);
+        match self.keyframes.get(idx) {
+            Some(other) if other.time == keyframe.time => self.keyframes[idx] = keyframe,
+            _ => self.keyframes.insert(idx, keyframe),
+        }
+    }
+
+    /// Camera at `time`, `None` without keyframes
+    pub fn sample(&self, time: f32) -> Option<CameraDescription> {
+        let keyframes = &self.keyframes;
+        let next = keyframes.partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 || next == keyframes.len() {
+            let keyframe = keyframes.get(next.saturating_sub(1))?;
+            return Some(keyframe.camera);
+        }
+
+        // Hermite segment between keyframes `i` and `i + 1`, with Catmull-Rom tangents
+        let i = next - 1;
+        let dt = keyframes[i + 1].time - keyframes[i].time;
+        let t = (time - keyframes[i].time) / dt;
+        let point = |k: usize, value: Value| value(&keyframes[k].camera);
+        let tangent = |k: usize, value: Value| {
+            let (before, after, span) = self.neighbours(k);
+            match span > 0. {
+                true => (point(after, value) - point(before, value)) * (dt / span),
+                false => Vector3::new(0., 0., 0.),
+            }
+        };
+
+        let h00 = 2. * t * t * t - 3. * t * t + 1.;
+        let h10 = t * t * t - 2. * t * t + t;
+        let h01 = -2. * t * t * t + 3. * t * t;
+        let h11 = t * t * t - t * t;
+        let hermite = |value: Value| {
+            point(i, value) * h00
+                + tangent(i, value) * h10
+                + point(i + 1, value) * h01
+                + tangent(i + 1, value) * h11
+        };
+
+        let eye = hermite(|camera| camera.eye.into());
+        let mut target = hermite(|camera| camera.target.into());
+        if (target - eye).magnitude2() == 0. {
+            // Keep a view direction when the spline passes the eye through the target
+            let camera = &keyframes[i + 1].camera;
+            target = eye + Vector3::from(camera.target) - Vector3::from(camera.eye);
+        }
+        let fovy = hermite(|camera| Vector3::new(camera.fovy, 0., 0.)).x;
+
+        Some(CameraDescription {
+            eye: eye.into(),
+            target: target.into(),
+            fovy,
+        })
+    }
+
+    /// Keyframes before and after keyframe `k` and the time between them.
+    ///
+    /// A path that ends where it starts is a loop, its ends take their neighbours across
+    /// the seam so it loops smoothly.
+    fn neighbours(&self, k: usize) -> (usize, usize, f32) {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len() - 1;
+        let closed = last > 1 && keyframes[0].camera == keyframes[last].camera;
+        let time = |k: usize| keyframes[k].time;
+
+        let (before, before_dt) = match k {
+            0 if closed => (last - 1, time(last) - time(last - 1)),
+            0 => (0, 0.),
+            _ => (k - 1, time(k) - time(k - 1)),
+        };
+        let (after, after_dt) = match k == last {
+            true if closed => (1, time(1) - time(0)),
+            true => (last, 0.),
+            false => (k + 1, time(k + 1) - time(k)),
+        };
+
+        (before, after, before_dt + after_dt)
+    }
+}
+
+type Value = fn(&CameraDescription) -> Vector3<f32>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, x: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            time,
+            camera: CameraDescription {
+                eye: [x, 0., 0.],
+                target: [x, 0., 1.],
+                fovy: 45.,
+            },
+        }
+    }
+
+    #[test]
+    fn sample_passes_through_keyframes_test() {
+        let mut path = CameraPath::default();
+        assert_eq!(path.sample(0.), None);
+
+        for (time, x) in [(2., 10.), (0., 0.), (1., 1.), (3., 0.)] {
+            path.insert(keyframe(time, x));
+        }
+        assert_eq!(
+            path.keyframes.iter().map(|k| k.time).collect::<Vec<_>>(),
+            vec![0., 1., 2., 3.]
+        );
+        assert_eq!(path.duration(), 3.);
+
+        for keyframe in path.keyframes.clone() {
+            assert_eq!(path.sample(keyframe.time), Some(keyframe.camera));
+        }
+        assert_eq!(path.sample(-1.), Some(path.keyframes[0].camera));
+        assert_eq!(path.sample(10.), Some(path.keyframes[3].camera));
+
+        // Between keyframes the spline is smooth and stays near the segment
+        let x = path.sample(1.5).unwrap().eye[0];
+        assert!(x > 1. && x < 10., "{x}");
+        assert_eq!(path.sample(1.5), path.sample(1.5));
+    }
+
+    #[test]
+    fn turntable_test() {
+        let path = CameraPath::turntable([1., 2., 3.], 10., 5., 45., 8.);
+        assert_eq!(path.duration(), 8.);
+
+        for time in [0., 1.3, 4., 6.7, 8.] {
+            let camera = path.sample(time).unwrap();
+            assert_eq!(camera.target, [1., 2., 3.]);
+            let [x, y, z] = camera.eye;
+            let radius = ((x - 1.).powi(2) + (z - 3.).powi(2)).sqrt();
+            assert!((radius - 10.).abs() < 0.01, "{radius}");
+            assert!((y - 7.).abs() < 1e-4);
+        }
+        let start = path.sample(0.).unwrap().eye;
+        let end = path.sample(8.).unwrap().eye;
+        for (a, b) in start.iter().zip(end) {
+            assert!((a - b).abs() < 1e-3);
+        }
+    }
+}
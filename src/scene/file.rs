@@ -0,0 +1,331 @@
+use std::{fs, io::BufReader, path::Path};
+
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    render::{
+        Camera, Colormap, PathTraceSettings, RenderMode, SamplingSettings, SunSettings,
+        ToneSettings,
+    },
+    vdb::{ErrorKind, PoolError, Progress, ProgressReader, VdbReader, VDB345},
+};
+
+use super::{CameraPath, Environment, Light, Material, SceneObject};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SceneFileError {
+    #[error("Could not access scene file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse scene file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("Could not serialize scene: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("Could not read grid {grid} from {path}: {source}")]
+    Grid {
+        path: String,
+        grid: String,
+        source: ErrorKind,
+    },
+    #[error("Object uses grid {grid} but the scene lists {count} grids")]
+    MissingGrid { grid: usize, count: usize },
+    #[error("Could not upload grids: {0}")]
+    Pool(#[from] PoolError),
+    #[error("Load cancelled")]
+    Cancelled,
+    #[error("The loading thread panicked")]
+    LoadPanicked,
+}
+
+/// Grid read from a VDB file, the path is relative to the working directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridSource {
+    pub path: String,
+    pub grid: String,
+    /// `vec3s` grid in the same file with the colour of each voxel of `grid`
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+impl GridSource {
+    /// Read the grid, with the distance field the raycaster needs
+    pub fn read(&self) -> Result<VDB345<u32>, SceneFileError> {
+        self.read_with_progress(&Progress::default())
+    }
+
+    /// [`GridSource::read`] reporting the bytes read and the nodes of the distance field pass
+    /// to `progress`, which can cancel it
+    pub fn read_with_progress(&self, progress: &Progress) -> Result<VDB345<u32>, SceneFileError> {
+        let mut vdb = self.read_grid(&self.grid, progress)?;
+        vdb.compute_sdf_with_progress(progress)
+            .map_err(|error| self.grid_error(&self.grid, progress, error))?;
+
+        Ok(vdb)
+    }
+
+    /// Read the colour grid with its colours packed by [`crate::vdb::pack_rgb`], `None`
+    /// without one
+    pub fn read_color(&self, progress: &Progress) -> Result<Option<VDB345<u32>>, SceneFileError> {
+        self.color
+            .as_ref()
+            .map(|color| self.read_grid(color, progress))
+            .transpose()
+    }
+
+    fn read_grid(&self, grid: &str, progress: &Progress) -> Result<VDB345<u32>, SceneFileError> {
+        let grid_error = |error: ErrorKind| self.grid_error(grid, progress, error);
+
+        let f = fs::File::open(&self.path).map_err(|e| grid_error(e.into()))?;
+        let reader = ProgressReader::new(BufReader::new(f), progress);
+        let mut vdb_reader = VdbReader::new(reader).map_err(grid_error)?;
+        if let Some(descriptor) = vdb_reader.grid_descriptors.get(grid) {
+            progress.add_bytes_total(descriptor.end_pos.saturating_sub(descriptor.grid_pos));
+        }
+        vdb_reader.read_vdb345_grid::<u32>(grid).map_err(grid_error)
+    }
+
+    fn grid_error(&self, grid: &str, progress: &Progress, error: ErrorKind) -> SceneFileError {
+        match progress.is_cancelled() {
+            true => SceneFileError::Cancelled,
+            false => SceneFileError::Grid {
+                path: self.path.clone(),
+                grid: grid.to_string(),
+                source: error,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraDescription {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    /// y-axis fov in degrees
+    pub fovy: f32,
+}
+
+impl From<&Camera> for CameraDescription {
+    fn from(camera: &Camera) -> Self {
+        Self {
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            fovy: camera.fovy,
+        }
+    }
+}
+
+impl CameraDescription {
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye.into();
+        camera.target = self.target.into();
+        camera.fovy = self.fovy;
+        camera.fit_up();
+    }
+}
+
+/// Named camera saved from the panel, jumped back to later
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub camera: CameraDescription,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RenderSettings {
+    pub mode: RenderMode,
+    /// Node 3, Node 4 and Node 5 grid overlays
+    #[serde(default)]
+    pub show_grid: [bool; 3],
+    /// Colour map of the value mode
+    #[serde(default)]
+    pub colormap: Colormap,
+    /// Values at the ends of the colour map
+    #[serde(default = "default_value_range")]
+    pub value_range: [f32; 2],
+    /// Bounces and sky of the path traced mode
+    #[serde(default)]
+    pub path_trace: PathTraceSettings,
+    /// Rays per pixel, temporal anti-aliasing and render scale
+    #[serde(default)]
+    pub sampling: SamplingSettings,
+    /// Exposure and tone mapper
+    #[serde(default)]
+    pub tone: ToneSettings,
+}
+
+fn default_value_range() -> [f32; 2] {
+    [0., 1.]
+}
+
+/// Serializable description of a scene, stored as RON.
+///
+/// Lists the grids to read and where they are placed, along with the camera, lights and
+/// render settings needed to reproduce a view.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneFile {
+    pub grids: Vec<GridSource>,
+    /// Instances of `grids`, one untransformed instance per grid when left empty
+    #[serde(default)]
+    pub objects: Vec<SceneObject>,
+    pub camera: CameraDescription,
+    /// Camera motion of offline renders
+    #[serde(default)]
+    pub camera_path: CameraPath,
+    #[serde(default)]
+    pub bookmarks: Vec<CameraBookmark>,
+    #[serde(default)]
+    pub sun: SunSettings,
+    /// Lights besides the sun
+    #[serde(default)]
+    pub lights: Vec<Light>,
+    /// Image lighting the scene from every direction, the sky lights it without one
+    #[serde(default)]
+    pub environment: Environment,
+    /// Surface of each of `grids`, missing ones use the default
+    #[serde(default)]
+    pub materials: Vec<Material>,
+    pub render: RenderSettings,
+}
+
+impl SceneFile {
+    /// One untransformed object per grid, seen from the default camera
+    pub fn from_grids(grids: Vec<GridSource>) -> Self {
+        Self {
+            grids,
+            objects: vec![],
+            camera: (&Camera::quick_camera(1.)).into(),
+            camera_path: CameraPath::default(),
+            bookmarks: vec![],
+            sun: SunSettings::default(),
+            lights: vec![],
+            environment: Environment::default(),
+            materials: vec![],
+            render: RenderSettings {
+                mode: RenderMode::Diffuse,
+                show_grid: [false; 3],
+                colormap: Colormap::default(),
+                value_range: default_value_range(),
+                path_trace: PathTraceSettings::default(),
+                sampling: SamplingSettings::default(),
+                tone: ToneSettings::default(),
+            },
+        }
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, SceneFileError> {
+        let file: Self = ron::from_str(source)?;
+
+        let count = file.grids.len();
+        if let Some(object) = file.objects.iter().find(|object| object.grid >= count) {
+            return Err(SceneFileError::MissingGrid {
+                grid: object.grid,
+                count,
+            });
+        }
+
+        Ok(file)
+    }
+
+    pub fn to_ron(&self) -> Result<String, SceneFileError> {
+        Ok(ron::ser::to_string_pretty(self, PrettyConfig::default())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneFileError> {
+        Self::from_ron(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SceneFileError> {
+        Ok(fs::write(path, self.to_ron()?)?)
+    }
+
+    /// Read every listed grid, with the distance field the raycaster needs
+    pub fn read_grids(&self) -> Result<Vec<VDB345<u32>>, SceneFileError> {
+        self.grids.iter().map(GridSource::read).collect()
+    }
+
+    /// Read the colour grid of every listed grid that has one
+    pub fn read_colors(&self) -> Result<Vec<Option<VDB345<u32>>>, SceneFileError> {
+        let progress = Progress::default();
+        self.grids
+            .iter()
+            .map(|source| source.read_color(&progress))
+            .collect()
+    }
+
+    pub fn scene_objects(&self) -> Vec<SceneObject> {
+        match self.objects.is_empty() {
+            true => (0..self.grids.len()).map(SceneObject::new).collect(),
+            false => self.objects.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::scene::{object::Transform, LightKind};
+
+    use super::*;
+
+    #[test]
+    fn scene_file_round_trip_test() {
+        let mut file = SceneFile::from_grids(vec![
+            GridSource {
+                path: "assets/cube.vdb".into(),
+                grid: "ls_cube".into(),
+                color: None,
+            },
+            GridSource {
+                path: "assets/scan.vdb".into(),
+                grid: "density".into(),
+                color: Some("Cd".into()),
+            },
+        ]);
+        file.objects = vec![
+            SceneObject::new(0),
+            SceneObject {
+                grid: 1,
+                transform: Transform {
+                    translation: Vector3::new(1., -2., 0.5),
+                    scale: Vector3::new(2., 2., 2.),
+                    ..Transform::default()
+                },
+            },
+        ];
+        file.camera = CameraDescription {
+            eye: [10., 4., -3.],
+            target: [0., 1., 0.],
+            fovy: 35.,
+        };
+        file.lights = vec![Light {
+            kind: LightKind::Spot,
+            position: [0., 5., 0.],
+            color: [1., 0.8, 0.6],
+            intensity: 20.,
+            ..Light::default()
+        }];
+        file.materials = vec![Material {
+            roughness: 0.7,
+            ..Material::default()
+        }];
+        file.render.mode = RenderMode::PathTrace;
+        file.render.value_range = [-1., 3.];
+        file.render.path_trace.bounces = 6;
+        file.render.sampling.samples = 4;
+        file.render.tone.exposure = -1.5;
+
+        let path = std::env::temp_dir().join("woxel_scene_file_round_trip_test.ron");
+        file.save(&path).unwrap();
+        let read = SceneFile::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, file);
+
+        // Objects must point at a listed grid
+        file.objects.push(SceneObject::new(2));
+        assert!(matches!(
+            SceneFile::from_ron(&file.to_ron().unwrap()),
+            Err(SceneFileError::MissingGrid { grid: 2, count: 2 })
+        ));
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LightKind {
+    /// Parallel light from infinitely far away, like the sun
+    Directional,
+    /// Light given off evenly in every direction from `position`
+    #[default]
+    Point,
+    /// Point light limited to a cone around `direction`
+    Spot,
+}
+
+impl LightKind {
+    pub const ALL: [LightKind; 3] = [LightKind::Directional, LightKind::Point, LightKind::Spot];
+}
+
+impl std::fmt::Display for LightKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LightKind::Directional => "Directional",
+            LightKind::Point => "Point",
+            LightKind::Spot => "Spot",
+        })
+    }
+}
+
+/// Light in world space, shading every render mode that the sun shades
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Where point and spot lights are
+    pub position: [f32; 3],
+    /// Direction directional and spot lights shine in
+    pub direction: [f32; 3],
+    /// Linear RGB
+    pub color: [f32; 3],
+    /// Point and spot lights fall off with the squared distance
+    pub intensity: f32,
+    /// Angle between the axis of a spot light and the edge of its cone, in degrees
+    pub cone_angle: f32,
+    /// Share of the cone over which a spot light fades out towards its edge
+    pub cone_softness: f32,
+    /// Trace a ray towards the light through the grids to find what shades it
+    pub shadows: bool,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            kind: LightKind::Point,
+            position: [0.; 3],
+            direction: [0., -1., 0.],
+            color: [1.; 3],
+            intensity: 1.,
+            cone_angle: 30.,
+            cone_softness: 0.2,
+            shadows: true,
+        }
+    }
+}
+
+/// HDR equirectangular image lighting the scene from every direction in place of the sky
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Environment {
+    /// Radiance `.hdr` file, the plain sky lights the scene without one
+    pub path: Option<String>,
+    pub intensity: f32,
+    /// Turn of the image around the up axis, in degrees
+    pub rotation: f32,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            path: None,
+            intensity: 1.,
+            rotation: 0.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_light_default_test() {
+        // Lights written before the other kinds existed are point lights
+        let light: Light =
+            ron::from_str("(position: (1.0, 2.0, 3.0), color: (1.0, 0.5, 0.0), intensity: 4.0)")
+                .unwrap();
+        assert_eq!(light.kind, LightKind::Point);
+        assert_eq!(light.position, [1., 2., 3.]);
+        assert_eq!(light.intensity, 4.);
+        assert!(light.shadows);
+    }
+}
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+/// Surface of a grid, voxels of a grid with a colour grid take their albedo from it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Material {
+    /// Linear RGB share of the light the surface reflects
+    pub albedo: [f32; 3],
+    /// 0 reflects like a mirror, 1 scatters light evenly
+    pub roughness: f32,
+    /// 0 for plastic like surfaces, 1 for metals which tint their reflections with the albedo
+    pub metallic: f32,
+    /// Linear RGB light given off by the surface
+    pub emission: [f32; 3],
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            albedo: [0.4, 0.2, 0.2],
+            roughness: 0.1,
+            metallic: 0.,
+            emission: [0.; 3],
+        }
+    }
+}
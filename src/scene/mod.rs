@@ -3,3 +3,24 @@ pub use scene::Scene;
 
 mod state;
 pub use state::State;
+
+mod object;
+pub use object::SceneObject;
+
+mod bvh;
+pub use bvh::{Aabb, Bvh};
+
+mod light;
+pub use light::{Environment, Light, LightKind};
+
+mod material;
+pub use material::Material;
+
+mod file;
+pub use file::{CameraBookmark, GridSource, RenderSettings, SceneFile, SceneFileError};
+
+mod sequence;
+pub use sequence::FrameSequence;
+
+mod camera_path;
+pub use camera_path::{CameraKeyframe, CameraPath};
@@ -0,0 +1,91 @@
+use cgmath::{InnerSpace, Matrix4, One, Quaternion, SquareMatrix, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+
+use crate::vdb::{Map, RayHit, VdbValueType, VDB345};
+
+use super::Aabb;
+
+/// User placement of an object on top of its grid's `Map`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0., 0., 0.),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1., 1., 1.),
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+/// Instance of a grid placed in the world, several objects can share a grid
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneObject {
+    /// Index of the grid in the context
+    pub grid: usize,
+    #[serde(default)]
+    pub transform: Transform,
+}
+
+impl SceneObject {
+    pub fn new(grid: usize) -> Self {
+        Self {
+            grid,
+            transform: Transform::default(),
+        }
+    }
+
+    /// Grid `Map` followed by the user transform
+    pub fn index_to_world(&self, map: &Map) -> Matrix4<f32> {
+        self.transform.matrix() * map.index_to_world().cast::<f32>().unwrap()
+    }
+
+    /// World space bounds of the object's active nodes, `None` for an empty grid
+    pub fn world_bounds<ValueType: VdbValueType>(&self, grid: &VDB345<ValueType>) -> Option<Aabb> {
+        let (min, max) = grid.index_bounds()?;
+        let index_to_world = self.index_to_world(&grid.transform);
+
+        let corners = (0..8).map(|corner| {
+            let pick = |axis: usize| match corner >> axis & 1 {
+                0 => min[axis] as f32,
+                _ => max[axis] as f32,
+            };
+            (index_to_world * Vector4::new(pick(0), pick(1), pick(2), 1.)).truncate()
+        });
+
+        Some(Aabb::from_points(corners))
+    }
+
+    /// Casts a world space ray against the grid, returning the hit and its world distance
+    pub fn raycast<ValueType: VdbValueType>(
+        &self,
+        grid: &VDB345<ValueType>,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+        max_t: f32,
+    ) -> Option<(RayHit, f32)> {
+        let index_to_world = self.index_to_world(&grid.transform);
+        let world_to_index = index_to_world.invert()?;
+
+        let index_origin = (world_to_index * origin.extend(1.)).truncate();
+        let index_dir = (world_to_index * dir.extend(0.)).truncate();
+        let hit = grid.raycast(index_origin, index_dir, f32::MAX)?;
+
+        let world_hit = index_to_world * (index_origin + index_dir.normalize() * hit.t).extend(1.);
+        let t = (world_hit.truncate() - origin).magnitude();
+        (t <= max_t).then_some((hit, t))
+    }
+}
@@ -1,14 +1,21 @@
 use winit::event::WindowEvent;
 
-use crate::render::{Camera, CameraController, WgpuContext};
+use crate::{
+    render::{Camera, CameraController, WgpuContext},
+    vdb::VDB345,
+};
 
-use super::State;
+use super::{Aabb, CameraBookmark, CameraPath, RenderSettings, SceneFile, SceneObject, State};
 
 pub struct Scene {
     pub state: State,
     pub camera: Camera,
     pub camera_controller: CameraController,
-    // pub voxels: VDB
+    /// Grid instances placed in the world
+    pub objects: Vec<SceneObject>,
+    /// Camera motion followed by offline renders
+    pub camera_path: CameraPath,
+    pub bookmarks: Vec<CameraBookmark>,
 }
 
 impl Scene {
@@ -16,10 +23,78 @@ impl Scene {
         let resolution = [context.config.width as f32, context.config.height as f32];
         let aspect = resolution[0] / resolution[1];
         let camera_controller = CameraController::new();
-        Self {
+        let mut scene = Self {
             state: State::new(resolution),
             camera: Camera::quick_camera(aspect),
             camera_controller,
+            objects: (0..context.grids.len()).map(SceneObject::new).collect(),
+            camera_path: CameraPath::default(),
+            bookmarks: vec![],
+        };
+        if let Some(bounds) = scene.bounds(&context.grids) {
+            scene.frame(&bounds);
+        }
+        scene
+    }
+
+    /// World bounds of every object, `None` when all their grids are empty
+    pub fn bounds(&self, grids: &[VDB345<u32>]) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .filter_map(|object| object.world_bounds(grids.get(object.grid)?))
+            .reduce(|a, b| a.union(&b))
+    }
+
+    /// Point the camera at `bounds` and fit the fly speed to them
+    pub fn frame(&mut self, bounds: &Aabb) {
+        self.camera.frame(bounds);
+        self.camera_controller.fit_to(bounds);
+    }
+
+    /// Take the objects, camera, lights and settings of `file`, its grids must already be
+    /// in the context
+    pub fn apply_file(&mut self, file: &SceneFile, context: &mut WgpuContext) {
+        self.objects = file.scene_objects();
+        file.camera.apply(&mut self.camera);
+        self.camera_path = file.camera_path.clone();
+        self.bookmarks = file.bookmarks.clone();
+
+        let egui_dev = &mut context.egui_dev;
+        egui_dev.sun_settings = file.sun.clone();
+        egui_dev.render_mode = file.render.mode;
+        egui_dev.show_grid = file.render.show_grid;
+        egui_dev.value_settings.colormap = file.render.colormap;
+        egui_dev.value_settings.range = file.render.value_range;
+        egui_dev.path_trace_settings = file.render.path_trace;
+        egui_dev.sampling_settings = file.render.sampling;
+        egui_dev.tone_settings = file.render.tone;
+        egui_dev.material_settings.materials = file.materials.clone();
+        egui_dev.light_settings.lights = file.lights.clone();
+        egui_dev.light_settings.environment = file.environment.clone();
+        egui_dev.light_settings.environment_file =
+            file.environment.path.clone().unwrap_or_default();
+    }
+
+    pub fn to_file(&self, context: &WgpuContext) -> SceneFile {
+        SceneFile {
+            grids: context.grid_sources.clone(),
+            objects: self.objects.clone(),
+            camera: (&self.camera).into(),
+            camera_path: self.camera_path.clone(),
+            bookmarks: self.bookmarks.clone(),
+            sun: context.egui_dev.sun_settings.clone(),
+            lights: context.egui_dev.light_settings.lights.clone(),
+            environment: context.egui_dev.light_settings.environment.clone(),
+            materials: context.egui_dev.material_settings.materials.clone(),
+            render: RenderSettings {
+                mode: context.egui_dev.render_mode,
+                show_grid: context.egui_dev.show_grid,
+                colormap: context.egui_dev.value_settings.colormap,
+                value_range: context.egui_dev.value_settings.range,
+                path_trace: context.egui_dev.path_trace_settings,
+                sampling: context.egui_dev.sampling_settings,
+                tone: context.egui_dev.tone_settings,
+            },
         }
     }
 
@@ -31,6 +106,9 @@ impl Scene {
 
     pub fn input(&mut self, event: &WindowEvent) {
         self.state.process_events(event);
-        self.camera_controller.process_events(event);
+        // A grabbed cursor looks around and paints, its clicks do not drag the orbit camera
+        if !(self.state.cursor_grabbed && matches!(event, WindowEvent::MouseInput { .. })) {
+            self.camera_controller.process_events(event);
+        }
     }
 }
@@ -0,0 +1,130 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+
+use super::GridSource;
+
+/// Files of an animated cache, one per frame, numbered like `smoke.0001.vdb`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSequence {
+    /// Frame numbers and paths, in frame order
+    pub frames: Vec<(u32, PathBuf)>,
+}
+
+impl FrameSequence {
+    /// The sequence `path` belongs to: the files next to it whose names only differ in the
+    /// last run of digits. `None` when there is no other frame.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (prefix, _, suffix) = split_frame_number(name)?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let frames = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let file_name = entry.file_name();
+                let (p, number, s) = split_frame_number(file_name.to_str()?)?;
+                (p == prefix && s == suffix).then(|| (number, dir.join(&file_name)))
+            })
+            .sorted_by_key(|(number, _)| *number)
+            .dedup_by(|(a, _), (b, _)| a == b)
+            .collect_vec();
+
+        (frames.len() > 1).then_some(Self { frames })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Position of the frame read from `path`
+    pub fn position(&self, path: &Path) -> Option<usize> {
+        let name = path.file_name()?;
+        self.frames
+            .iter()
+            .position(|(_, frame)| frame.file_name() == Some(name))
+    }
+
+    /// Grid `grid` of the frame at `idx`
+    pub fn source(&self, idx: usize, grid: &str) -> GridSource {
+        GridSource {
+            path: self.frames[idx].1.display().to_string(),
+            grid: grid.to_string(),
+            color: None,
+        }
+    }
+}
+
+/// Splits a file name around its last run of digits, like `smoke.`, `12`, `.vdb`
+fn split_frame_number(name: &str) -> Option<(&str, u32, &str)> {
+    let end = name.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = name[..end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+    let number = name[start..end].parse().ok()?;
+
+    Some((&name[..start], number, &name[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_frame_number_test() {
+        assert_eq!(
+            split_frame_number("smoke.0012.vdb"),
+            Some(("smoke.", 12, ".vdb"))
+        );
+        assert_eq!(
+            split_frame_number("fluid_v2_0100.vdb"),
+            Some(("fluid_v2_", 100, ".vdb"))
+        );
+        assert_eq!(split_frame_number("7.vdb"), Some(("", 7, ".vdb")));
+        assert_eq!(split_frame_number("teapot.vdb"), None);
+    }
+
+    #[test]
+    fn detect_test() {
+        let dir = std::env::temp_dir().join("woxel_sequence_detect_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "smoke.0010.vdb",
+            "smoke.0002.vdb",
+            "smoke.0001.vdb",
+            "smoke.0001.vdb.bak",
+            "fire.0003.vdb",
+            "still.vdb",
+        ] {
+            fs::write(dir.join(name), []).unwrap();
+        }
+
+        let sequence = FrameSequence::detect(&dir.join("smoke.0002.vdb")).unwrap();
+        assert_eq!(
+            sequence.frames.iter().map(|(n, _)| *n).collect_vec(),
+            vec![1, 2, 10]
+        );
+        assert_eq!(sequence.position(&dir.join("smoke.0010.vdb")), Some(2));
+        assert!(sequence
+            .source(0, "density")
+            .path
+            .ends_with("smoke.0001.vdb"));
+
+        assert_eq!(FrameSequence::detect(&dir.join("fire.0003.vdb")), None);
+        assert_eq!(FrameSequence::detect(&dir.join("still.vdb")), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
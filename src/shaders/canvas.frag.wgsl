@@ -17,11 +17,52 @@ struct Output {
     @location(0) color: vec4<f32>,
 };
 
+// Bicubic Catmull-Rom filter of the texture at `p`, folded into 9 bilinear samples by
+// merging the two middle taps of each axis. The texture has a single mip, so every sample
+// reads level 0 explicitly, which is allowed outside uniform control flow
+fn sample_catmull_rom(p: vec2<f32>) -> vec4<f32>
+{
+    let size = vec2<f32>(textureDimensions(texture));
+    let position = p * size;
+    let center = floor(position - 0.5) + 0.5;
+    let f = position - center;
+
+    let w0 = f * (-0.5 + f * (1.0 - 0.5 * f));
+    let w1 = 1.0 + f * f * (-2.5 + 1.5 * f);
+    let w2 = f * (0.5 + f * (2.0 - 1.5 * f));
+    let w3 = f * f * (-0.5 + 0.5 * f);
+    let w12 = w1 + w2;
+
+    let p0 = (center - 1.0) / size;
+    let p3 = (center + 2.0) / size;
+    let p12 = (center + w2 / w12) / size;
+
+    var color = vec4<f32>(0.0);
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p0.x, p0.y), 0.0) * w0.x * w0.y;
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p12.x, p0.y), 0.0) * w12.x * w0.y;
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p3.x, p0.y), 0.0) * w3.x * w0.y;
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p0.x, p12.y), 0.0) * w0.x * w12.y;
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p12.x, p12.y), 0.0) * w12.x * w12.y;
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p3.x, p12.y), 0.0) * w3.x * w12.y;
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p0.x, p3.y), 0.0) * w0.x * w3.y;
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p12.x, p3.y), 0.0) * w12.x * w3.y;
+    color += textureSampleLevel(texture, tsampler, vec2<f32>(p3.x, p3.y), 0.0) * w3.x * w3.y;
+
+    // The negative lobes overshoot at sharp edges
+    return clamp(color, vec4<f32>(0.0), vec4<f32>(1.0));
+}
+
 @fragment
 fn fs_main(frag: Input) -> @location(0) vec4<f32>
 {
     var p = vec2<f32>(frag.position.xy) / state.size;
-    var color = textureSample(texture, tsampler, p);
+
+    // Traced below the window size at a render scale under 1
+    if any(vec2<f32>(textureDimensions(texture)) < state.size) {
+        return sample_catmull_rom(p);
+    }
+
+    var color = textureSampleLevel(texture, tsampler, p, 0.0);
 
     return color;
 }
@@ -8,6 +8,8 @@ struct Ray {
     u: vec4<f32>,
     mv: vec4<f32>,
     wp: vec4<f32>,
+    // View direction, w is 1 for orthographic rays starting at p.x * u + p.y * mv + wp
+    forward: vec4<f32>,
 };
 
 struct State {
@@ -17,54 +19,254 @@ struct State {
     show_345: vec3<u32>,
     sun_dir: vec3<f32>,
     sun_color: vec4<f32>,
+    // Log2 dimensions of the node levels of the trees, top level first
+    tree_log2_dims: vec4<u32>,
+    tree_levels: u32,
+    // 0 f32, 1 f16, 2 16 bit and 3 8 bit quantized leaf values
+    leaf_encoding: u32,
+    // Normal and offset of the clip planes, which cut away where dot(n, p) > offset.
+    // Zero normals are unused
+    clip_planes: array<vec4<f32>, 4>,
+    // Clip box, enabled when clip_min.w is 1
+    clip_min: vec4<f32>,
+    clip_max: vec4<f32>,
+    // Values at the ends of the colour map in x and y
+    value_range: vec4<f32>,
+    // Evenly spaced colours of the value render mode
+    colormap: array<vec4<f32>, 5>,
+    // Ambient occlusion radius in voxels and strength, 0 when off, and the soft shadow
+    // hardness, 0 for hard shadows
+    shading: vec4<f32>,
+    // Samples summed in the accumulation texture before this frame, frames traced so far and
+    // the bounces of the path traced mode
+    frame: vec4<u32>,
+    // Colour of the sky overhead, w is its intensity
+    sky: vec4<f32>,
+    // Number of lights, then 1 when an environment map lights the scene in place of the sky
+    lights: vec4<u32>,
+    // Intensity of the environment map and its turn around the up axis in radians
+    environment: vec4<f32>,
+    // Rays per pixel, 1 when frames are blended with the history in the accumulation texture
+    // and 1 when the history holds a frame to blend with
+    sampling: vec4<u32>,
+    // Weight of the new frame in the blend with the history
+    history_weight: vec4<f32>,
+    // Takes offsets from history_origin to the pixel coordinates of the history frame in xy and
+    // the distance along its rays in z, for perspective views up to a division by z
+    history_rays: mat4x4<f32>,
+    // Eye of the history frame, or the ray origin of its first pixel with w at 1 for
+    // orthographic views
+    history_origin: vec4<f32>,
 };
 
 @group(0) @binding(0)
 var<uniform> s: State;
 
+// Linear HDR colours, exposed, tone mapped and encoded to sRGB by the tone map pass
 @group(1) @binding(0)
-var texture: texture_storage_2d<rgba8unorm, write>;
-
+var texture: texture_storage_2d<rgba16float, write>;
+
+// Sums of the path traced samples before this frame, with the number of samples in w, and
+// the sums after it. The other modes keep the last frame there when blending over time, with
+// the distance to each pixel's surface in w
+@group(5) @binding(0)
+var accumulated: texture_2d<f32>;
+@group(5) @binding(1)
+var accumulation: texture_storage_2d<rgba32float, write>;
+
+// Node pools of each level, top level first, levels below the leaves are empty. Node `idx` of
+// an internal level occupies entries [idx * node_size(level), (idx + 1) * node_size(level)),
+// leaves pack their entries as leaf_entry reads them
 @group(2) @binding(0)
-var node5s: texture_3d<u32>;
+var<storage, read> nodes0: array<u32>;
 @group(2) @binding(1)
-var node4s: texture_3d<u32>;
+var<storage, read> nodes1: array<u32>;
 @group(2) @binding(2)
-var node3s: texture_3d<u32>;
+var<storage, read> nodes2: array<u32>;
+@group(2) @binding(3)
+var<storage, read> nodes3: array<u32>;
+// Offset and scale of the quantized values of every leaf
+@group(2) @binding(4)
+var<storage, read> leaf_ranges: array<vec2<f32>>;
+// RGBA8 colour of every voxel of the leaves of grids with a colour grid, one leaf node_size
+// per leaf
+@group(2) @binding(5)
+var<storage, read> leaf_colors: array<u32>;
+// Leaves too large for a storage buffer binding, paged into rows of the layers instead. A
+// single texel when the leaves are in their buffer
+@group(2) @binding(6)
+var leaf_pages: texture_2d_array<u32>;
+
+// Masks of the nodes of each level, mask_words(level) per node. Internal nodes store their
+// child mask then their value mask, leaves only their value mask
+@group(3) @binding(0)
+var<storage, read> masks0: array<u32>;
+@group(3) @binding(1)
+var<storage, read> masks1: array<u32>;
+@group(3) @binding(2)
+var<storage, read> masks2: array<u32>;
+@group(3) @binding(3)
+var<storage, read> masks3: array<u32>;
+// Origins of the top level nodes
+@group(3) @binding(4)
+var<storage, read> origins: array<vec3<i32>>;
 
-struct Node5Mask {
-    m: array<u32, 1024>, // 32^3/32
+struct Object {
+    world_to_index: mat4x4<f32>,
+    index_to_world: mat4x4<f32>,
+    // Index space bounds of the grid
+    index_min: vec4<i32>,
+    index_max: vec4<i32>,
+    // Pool index of the grid's first node on each level, top level first
+    bases: vec4<u32>,
+    // Albedo of the grid's material, roughness in w
+    albedo: vec4<f32>,
+    // Emission of the grid's material, metalness in w
+    emission: vec4<f32>,
+    // First leaf of the grid in leaf_colors, then 1 when the grid has colours
+    colors: vec4<u32>,
+    // Number of the grid's top level nodes in x
+    top_nodes: vec4<u32>,
 };
 
-struct Node4Mask {
-    m: array<u32, 128>, // 16^3/32
+struct BvhNode {
+    min: vec3<f32>,
+    // Left child of internal nodes, first object of leaves
+    left_first: u32,
+    max: vec3<f32>,
+    // Number of objects in a leaf, 0 for internal nodes
+    count: u32,
 };
 
-struct Node3Mask {
-    m: array<u32, 16>, // 8^3/32
+// Objects are stored in BVH leaf order
+@group(4) @binding(0)
+var<storage, read> objects: array<Object>;
+@group(4) @binding(1)
+var<storage, read> bvh: array<BvhNode>;
+
+// Object whose grid the HDDA is currently walking
+var<private> object: Object;
+
+struct Light {
+    // World space position, w is the kind: 0 directional, 1 point and 2 spot
+    position: vec4<f32>,
+    // Direction the light shines in, w is 1 when it casts shadows
+    direction: vec4<f32>,
+    // Colour times intensity
+    color: vec4<f32>,
+    // Cosines of the angle of a spot's cone and of the angle where it starts to fade out
+    cone: vec4<f32>,
 };
 
-@group(3) @binding(0)
-var<storage, read> kids5: array<Node5Mask>;
-@group(3) @binding(1)
-var<storage, read> vals5: array<Node5Mask>;
-@group(3) @binding(2)
-var<storage, read> kids4: array<Node4Mask>;
-@group(3) @binding(3)
-var<storage, read> vals4: array<Node4Mask>;
-@group(3) @binding(4)
-var<storage, read> vals3: array<Node3Mask>;
-@group(3) @binding(5)
-var<storage, read> origins: array<vec3<i32>>;
+@group(6) @binding(0)
+var<storage, read> lights: array<Light>;
+// Equirectangular image with its mips, rows going from straight up to straight down. A black
+// texel while the sky lights the scene
+@group(6) @binding(1)
+var environment: texture_2d<f32>;
+@group(6) @binding(2)
+var environment_sampler: sampler;
 
 @compute @workgroup_size(8,4)
 fn cp_main(@builtin(global_invocation_id) global_id : vec3<u32>) {
-    var p = vec2<f32>(global_id.xy) + vec2(0.001);
-    var ray_dir = normalize((p.x * s.ray.u + p.y * s.ray.mv + s.ray.wp).xyz);
-    var color = vec4(ray_trace(s.camera.eye, ray_dir),1.0);
-    var val = textureLoad(node5s, vec3<i32>(0, 0, 0), 0);
+    // The last workgroups overhang sizes that aren't a multiple of theirs
+    if any(global_id.xy >= textureDimensions(texture)) {
+        return;
+    }
+
+    if s.render_mode == 6u {
+        seed_random(global_id.xy);
+        // Samples land anywhere in the pixel, which smooths the edges as they add up
+        let ray = camera_ray(vec2<f32>(global_id.xy) + vec2(random_float(), random_float()));
+        var sum = vec4(path_trace(ray.src, ray.dir), 1.0);
+        if s.frame.x > 0u {
+            sum += textureLoad(accumulated, global_id.xy, 0);
+        }
+        textureStore(accumulation, global_id.xy, sum);
+        textureStore(texture, global_id.xy, vec4(sum.xyz / sum.w, 1.0));
+        return;
+    }
+
+    // Blending over time moves the samples to new spots in the pixel every frame
+    let samples = max(s.sampling.x, 1u);
+    var first = 0u;
+    if s.sampling.y == 1u {
+        first = (s.frame.y % 1024u) * samples;
+    }
+
+    var color = vec3(0.0);
+    var center: SceneHit;
+    for (var i = 0u; i < samples; i++) {
+        let ray = camera_ray(vec2<f32>(global_id.xy) + sample_offset(first + i));
+        let hit = trace_scene(ray.src, ray.dir);
+        color += ray_trace(hit, ray.dir);
+        if i == 0u {
+            center = hit;
+        }
+    }
+    color /= f32(samples);
+
+    if s.sampling.y == 1u {
+        color = temporal_blend(global_id.xy, center, color);
+    }
+    textureStore(texture, global_id.xy, vec4(color, 1.0));
+}
+
+// Offset of sample `i` in its pixel, the R2 sequence spreads the samples evenly and starts in
+// the middle
+fn sample_offset(i: u32) -> vec2<f32> {
+    return fract(vec2(0.5) + f32(i) * vec2(0.7548776662, 0.5698402910));
+}
+
+// Blend `color` of the surface `hit` with the colour the history frame has where it saw the
+// same surface, and keep the result as the history of the next frame. Surfaces the history
+// frame didn't see, or saw at another distance, start over from `color`
+fn temporal_blend(pixel: vec2<u32>, hit: SceneHit, color: vec3<f32>) -> vec3<f32> {
+    // Misses are kept with a negative distance
+    var depth = -1.0;
+    if hit.state == 0u {
+        depth = hit.t;
+    }
+
+    var blended = color;
+    if s.sampling.z == 1u {
+        let q = (s.history_rays * vec4(hit.p - s.history_origin.xyz, 0.0)).xyz;
+        var previous = q.xy;
+        var previous_depth = q.z;
+        if s.history_origin.w == 0.0 {
+            previous = q.xy / q.z;
+            previous_depth = distance(hit.p, s.history_origin.xyz);
+        }
+
+        let size = vec2<f32>(textureDimensions(texture));
+        if q.z > 0.0 && all(previous >= vec2(0.0)) && all(previous < size) {
+            let history = textureLoad(accumulated, vec2<u32>(previous), 0);
+            var same = history.w < 0.0;
+            if depth >= 0.0 {
+                same = abs(history.w - previous_depth) < 0.02 * previous_depth + 0.5;
+            }
+            if same {
+                blended = mix(history.xyz, color, s.history_weight.x);
+            }
+        }
+    }
 
-    textureStore(texture, global_id.xy, color);
+    textureStore(accumulation, pixel, vec4(blended, depth));
+    return blended;
+}
+
+struct CameraRay {
+    src: vec3<f32>,
+    dir: vec3<f32>,
+}
+
+// World space ray through pixel coordinates `p`
+fn camera_ray(p: vec2<f32>) -> CameraRay {
+    let wp = (p.x * s.ray.u + p.y * s.ray.mv + s.ray.wp).xyz;
+    if s.ray.forward.w == 1.0 {
+        return CameraRay(wp, s.ray.forward.xyz);
+    }
+    return CameraRay(s.camera.eye, normalize(wp));
 }
 
 fn sign11(p: vec3<f32>) -> vec3<f32>{
@@ -80,9 +282,10 @@ fn modulo_vec3f(x: vec3<f32>, y:f32) -> vec3<f32> {
 }
 
 const HDDA_MAX_RAY_STEPS: u32 = 1000u;
-const scale = array<f32, 4>(1., 8., 128., 4096.);
-fn hdda_ray(src: vec3<f32>, dir: vec3<f32>) -> HDDAout {
+// `t_start` is how far along the ray `src` is, in voxels
+fn hdda_ray(src: vec3<f32>, dir: vec3<f32>, t_start: f32) -> HDDAout {
     var p: vec3<f32> = src;
+    var approach = NO_HIT;
     let step: vec3<f32> = sign11(dir);
     let step01: vec3<f32> = max(vec3(0.), step);
     let idir: vec3<f32> = 1. / dir;
@@ -94,21 +297,25 @@ fn hdda_ray(src: vec3<f32>, dir: vec3<f32>) -> HDDAout {
 
         // Return intersected voxel
         if leaf.dist == 0u {
-            return HDDAout(0u, leaf, p, mask, i);
+            return HDDAout(0u, leaf, p, mask, i, approach);
         }
 
-        // @HACK: Check for out of bounds!
-        if any(vec3(4096.) < abs(p)) {
-            return HDDAout(1u, leaf, p, mask, i);
+        if any(p < vec3<f32>(object.index_min.xyz)) || any(vec3<f32>(object.index_max.xyz) < p) {
+            return HDDAout(1u, leaf, p, mask, i, approach);
         }
 
+        // Tiles found under `num_parents` nodes are `dist` nodes of the next level wide
         var size = f32(leaf.dist);
-        switch leaf.num_parents {
-            case 3u: { size *= scale[0u]; }
-            case 2u: { size *= scale[1u]; }
-            case 1u: { size *= scale[2u]; }
-            case 0u: { size *= scale[3u]; }
-            default: { size = scale[0u]; }
+        if leaf.num_parents <= s.tree_levels {
+            size *= f32(1u << total_log_d(leaf.num_parents));
+        } else {
+            size = 1.;
+        }
+
+        // Skip the first voxel, which borders the surface a shadow ray starts from
+        let t = t_start + distance(p, src);
+        if t > 1.0 {
+            approach = min(approach, sdf_distance(leaf) / t);
         }
 
         var tMax: vec3<f32> = idir * (size * step01 - modulo_vec3f(p, size));
@@ -122,7 +329,7 @@ fn hdda_ray(src: vec3<f32>, dir: vec3<f32>) -> HDDAout {
         p += 4e-4 * step * vec3<f32>(mask);
     }
 
-    return HDDAout(2u, leaf, p, mask, HDDA_MAX_RAY_STEPS);
+    return HDDAout(2u, leaf, p, mask, HDDA_MAX_RAY_STEPS, approach);
 }
 
 struct HDDAout {
@@ -139,6 +346,489 @@ struct HDDAout {
     mask: vec3<bool>,
     // Iteration of return
     i: u32,
+    // Smallest ratio of the distance to the closest voxel over the distance along the ray
+    approach: f32,
+}
+
+struct SceneHit {
+    // Same states as HDDAout
+    state: u32,
+    // World space intersection point, or where the ray leaves the world on a miss
+    p: vec3<f32>,
+    // World space distance to p
+    t: f32,
+    // Index space intersection point
+    ip: vec3<f32>,
+    // World space normal of the hit face
+    normal: vec3<f32>,
+    // Index space direction of last step, world space exit face on a miss
+    mask: vec3<bool>,
+    // HDDA iterations over every object visited
+    i: u32,
+    // Value of the hit voxel, if it has one rather than being part of an active tile
+    value: f32,
+    has_value: bool,
+    // Smallest approach over every object visited, see HDDAout
+    approach: f32,
+    // Index of the hit object
+    object: u32,
+    // Albedo of the hit voxel, from the colour grid or the object's material
+    albedo: vec3<f32>,
+}
+
+const WORLD_BOUND: f32 = 4096.;
+const NO_HIT: f32 = 1e30;
+const BVH_STACK_SIZE: u32 = 32u;
+
+// Distance along the ray to the box [lo, hi], NO_HIT if the ray misses it
+fn ray_aabb(src: vec3<f32>, idir: vec3<f32>, lo: vec3<f32>, hi: vec3<f32>) -> f32 {
+    let t0 = (lo - src) * idir;
+    let t1 = (hi - src) * idir;
+    let t_near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), min(t0.z, t1.z));
+    let t_far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), max(t0.z, t1.z));
+
+    if t_far < max(t_near, 0.0) {
+        return NO_HIT;
+    }
+    return max(t_near, 0.0);
+}
+
+struct ClipSpan {
+    // Part of the ray that isn't clipped away, empty when t0 > t1
+    t0: f32,
+    t1: f32,
+    // Normal of the clipping surface the ray enters through at t0, zero if it starts inside
+    normal: vec3<f32>,
+}
+
+// Intersect the ray with the clip planes and the clip box
+fn clip_ray(src: vec3<f32>, dir: vec3<f32>) -> ClipSpan {
+    var span = ClipSpan(0.0, NO_HIT, vec3(0.0));
+
+    for (var i = 0u; i < 4u; i++) {
+        let plane = s.clip_planes[i];
+        if all(plane.xyz == vec3(0.0)) {
+            continue;
+        }
+
+        let along = dot(plane.xyz, dir);
+        let t = (plane.w - dot(plane.xyz, src)) / along;
+        if along < 0.0 {
+            // Entering the kept side
+            if t > span.t0 {
+                span.t0 = t;
+                span.normal = plane.xyz;
+            }
+        } else if along > 0.0 {
+            span.t1 = min(span.t1, t);
+        } else if dot(plane.xyz, src) > plane.w {
+            // Parallel to the plane on the clipped side
+            span.t1 = -1.0;
+        }
+    }
+
+    if s.clip_min.w == 1.0 {
+        let t0 = (s.clip_min.xyz - src) / dir;
+        let t1 = (s.clip_max.xyz - src) / dir;
+        let t_near = min(t0, t1);
+        let t_enter = max(max(t_near.x, t_near.y), t_near.z);
+        let t_far = max(t0, t1);
+        span.t1 = min(span.t1, min(min(t_far.x, t_far.y), t_far.z));
+        if t_enter > span.t0 {
+            span.t0 = t_enter;
+            let axis = t_near.xyz >= t_near.yzx & t_near.xyz >= t_near.zxy;
+            span.normal = -sign11(dir) * vec3<f32>(axis);
+        }
+    }
+
+    return span;
+}
+
+// Traverse the objects BVH and return the closest hit that isn't clipped away
+fn trace_scene(src: vec3<f32>, dir: vec3<f32>) -> SceneHit {
+    let step = sign11(dir);
+    let t_exit = (step * WORLD_BOUND - src) / dir;
+    let exit_mask = t_exit.xyz <= t_exit.yzx & t_exit.xyz <= t_exit.zxy;
+    let t_world = min(min(t_exit.x, t_exit.y), t_exit.z);
+
+    var closest = SceneHit(
+        1u,
+        src + t_world * dir,
+        NO_HIT,
+        vec3(0.0),
+        -step * vec3<f32>(exit_mask),
+        exit_mask,
+        0u,
+        0.0,
+        false,
+        NO_HIT,
+        0u,
+        vec3(0.0),
+    );
+
+    let span = clip_ray(src, dir);
+    if span.t0 > span.t1 {
+        return closest;
+    }
+    // Walk from where the kept part of the ray starts, so grids are cut open there
+    let clip_src = src + span.t0 * dir;
+    let t_max = span.t1 - span.t0;
+
+    let idir = 1. / dir;
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_len = 1u;
+    stack[0] = 0u;
+
+    while stack_len > 0u {
+        stack_len -= 1u;
+        let node = bvh[stack[stack_len]];
+        if ray_aabb(clip_src, idir, node.min, node.max) >= min(closest.t, t_max) {
+            continue;
+        }
+
+        if node.count == 0u {
+            if stack_len + 2u <= BVH_STACK_SIZE {
+                stack[stack_len] = node.left_first;
+                stack[stack_len + 1u] = node.left_first + 1u;
+                stack_len += 2u;
+            }
+            continue;
+        }
+
+        for (var k = node.left_first; k < node.left_first + node.count; k++) {
+            let hit = trace_object(k, clip_src, dir);
+            closest.i += hit.i;
+            closest.approach = min(closest.approach, hit.approach);
+            if hit.state == 0u && hit.t < closest.t && hit.t <= t_max {
+                let i = closest.i;
+                let approach = closest.approach;
+                closest = hit;
+                closest.i = i;
+                closest.approach = approach;
+            }
+            if hit.state == 2u && closest.state == 1u {
+                closest.state = 2u;
+            }
+        }
+    }
+
+    if closest.state == 0u {
+        closest.t += span.t0;
+        // Voxels cut open by the clipping take the normal of the cut
+        if !any(closest.mask) && any(span.normal != vec3(0.0)) {
+            closest.normal = span.normal;
+            closest.mask = abs(span.normal) > vec3(0.5);
+        }
+    }
+
+    return closest;
+}
+
+// Move the ray into the object's index space and walk its grid
+fn trace_object(k: u32, src: vec3<f32>, dir: vec3<f32>) -> SceneHit {
+    object = objects[k];
+
+    let isrc = (object.world_to_index * vec4(src, 1.0)).xyz;
+    let idir = normalize((object.world_to_index * vec4(dir, 0.0)).xyz);
+    let lo = vec3<f32>(object.index_min.xyz);
+    let hi = vec3<f32>(object.index_max.xyz);
+
+    var miss = SceneHit(
+        1u, src, NO_HIT, isrc, vec3(0.0), vec3<bool>(), 0u, 0.0, false, NO_HIT, k, vec3(0.0)
+    );
+    let t_enter = ray_aabb(isrc, 1. / idir, lo, hi);
+    if t_enter == NO_HIT {
+        return miss;
+    }
+
+    // Start just inside the grid bounds
+    let t_start = t_enter + 1e-3 * f32(t_enter > 0.0);
+    let hit = hdda_ray(isrc + t_start * idir, idir, t_start);
+    miss.i = hit.i;
+    miss.approach = hit.approach;
+    if hit.state != 0u {
+        miss.state = hit.state;
+        return miss;
+    }
+
+    let p = (object.index_to_world * vec4(hit.p, 1.0)).xyz;
+    // Normals go through the inverse transpose
+    let inormal = -sign11(idir) * vec3<f32>(hit.mask);
+    var normal = vec3(0.0);
+    // Rays starting inside a voxel have no face they stepped through
+    if any(hit.mask) {
+        normal = normalize((transpose(object.world_to_index) * vec4(inormal, 0.0)).xyz);
+    }
+
+    // Active tiles end the lookup above the leaves, without a value of their own
+    var value = 0.0;
+    var albedo = object.albedo.xyz;
+    let has_value = hit.leaf.num_parents == s.tree_levels;
+    if has_value {
+        let leaf_level = s.tree_levels - 1u;
+        var parents = hit.leaf.parents;
+        let leaf_idx = parents[leaf_level].idx;
+        let voxel = vec3<i32>(floor(hit.p));
+        let local = global_to_local(voxel, total_log_d(leaf_level));
+        let log_d = s.tree_log2_dims[leaf_level];
+        let offset = child_to_offset(local, log_d, 2u * log_d);
+        value = leaf_value(leaf_idx, offset);
+
+        // Voxels the colour grid leaves out keep the material's albedo
+        if object.colors.y == 1u {
+            let leaf = object.colors.x + leaf_idx - object.bases[leaf_level];
+            let color = unpack4x8unorm(leaf_colors[leaf * node_size(leaf_level) + offset]);
+            if color.a > 0.0 {
+                albedo = color.rgb;
+            }
+        }
+    }
+
+    return SceneHit(
+        0u,
+        p,
+        distance(src, p),
+        hit.p,
+        normal,
+        hit.mask,
+        hit.i,
+        value,
+        has_value,
+        hit.approach,
+        k,
+        albedo,
+    );
+}
+
+// Distance from an empty voxel or tile to the closest active voxel, in voxels, from the tile
+// distances of the distance field
+fn sdf_distance(leaf: VdbLeaf) -> f32 {
+    if leaf.dist == 0u {
+        return 0.0;
+    }
+
+    var cell = 1.0;
+    if leaf.num_parents <= s.tree_levels {
+        cell = f32(1u << total_log_d(leaf.num_parents));
+    }
+    return (f32(leaf.dist) - 0.5) * cell;
+}
+
+const AO_STEPS: u32 = 5u;
+
+// Share of the ambient light reaching a hit, lowered where the distance field closes in along
+// the normal. Only the grid of the hit object occludes
+fn ambient_occlusion(hit: SceneHit) -> f32 {
+    if s.shading.y == 0.0 || all(hit.normal == vec3(0.0)) {
+        return 1.0;
+    }
+
+    object = objects[hit.object];
+    let n = normalize((object.world_to_index * vec4(hit.normal, 0.0)).xyz);
+    var occlusion = 0.0;
+    var weight = 1.0;
+    var leaf: VdbLeaf;
+    for (var i = 1u; i <= AO_STEPS; i++) {
+        let d = s.shading.x * f32(i) / f32(AO_STEPS);
+        leaf = get_vdb_leaf_from_leaf(vec3<i32>(floor(hit.ip + n * d)), leaf);
+        occlusion += weight * max(d - sdf_distance(leaf), 0.0) / d;
+        weight *= 0.5;
+    }
+
+    return clamp(1.0 - s.shading.y * occlusion, 0.0, 1.0);
+}
+
+// Share of the sunlight reaching a hit, with penumbrae from how close the shadow ray passes
+// by voxels when soft shadows are on
+fn sun_visibility(hit: SceneHit) -> f32 {
+    let shadow = trace_scene(hit.p + 4e-2 * hit.normal, -s.sun_dir);
+    if shadow.state == 0u {
+        return 0.0;
+    }
+    if s.shading.z == 0.0 {
+        return 1.0;
+    }
+    return smoothstep(0.0, 1.0, s.shading.z * shadow.approach);
+}
+
+// Colour of `value` in the colour map of the value render mode
+fn value_color(value: f32) -> vec3<f32> {
+    let span = s.value_range.y - s.value_range.x;
+    var t = 0.5;
+    if span != 0.0 {
+        t = clamp((value - s.value_range.x) / span, 0.0, 1.0);
+    }
+
+    let x = t * 4.0;
+    let i = min(u32(x), 3u);
+    return mix(s.colormap[i].xyz, s.colormap[i + 1u].xyz, x - f32(i));
+}
+
+// State of the random numbers of the invocation
+var<private> rng: u32;
+
+fn pcg_hash(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Different numbers for every pixel and frame
+fn seed_random(pixel: vec2<u32>) {
+    rng = pcg_hash(pixel.x + pcg_hash(pixel.y + pcg_hash(s.frame.y)));
+}
+
+// Uniform in [0, 1)
+fn random_float() -> f32 {
+    rng = pcg_hash(rng);
+    return f32(rng >> 8u) / 16777216.0;
+}
+
+// Random direction around `n`, more likely the closer it is to `n`
+fn cosine_direction(n: vec3<f32>) -> vec3<f32> {
+    let r = sqrt(random_float());
+    let phi = 6.2831853 * random_float();
+    let up = select(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), abs(n.x) > 0.5);
+    let tangent = normalize(cross(n, up));
+    let bitangent = cross(n, tangent);
+    return normalize(
+        r * cos(phi) * tangent + r * sin(phi) * bitangent + sqrt(max(0.0, 1.0 - r * r)) * n
+    );
+}
+
+// Light coming from the sky along `dir`, white at the horizon, the sky colour overhead and
+// darker below. The environment map replaces it when there is one
+fn sky_radiance(dir: vec3<f32>) -> vec3<f32> {
+    if s.lights.y == 1u {
+        return environment_radiance(dir, 0.0);
+    }
+    let horizon = mix(s.sky.xyz, vec3(1.0), 0.7);
+    if dir.y < 0.0 {
+        return s.sky.w * horizon * mix(1.0, 0.3, sqrt(-dir.y));
+    }
+    return s.sky.w * mix(horizon, s.sky.xyz, sqrt(dir.y));
+}
+
+// Light of the environment map along `dir`, read from mip `level`
+fn environment_radiance(dir: vec3<f32>, level: f32) -> vec3<f32> {
+    let u = (atan2(dir.x, -dir.z) + s.environment.y) / 6.2831853 + 0.5;
+    let v = acos(clamp(dir.y, -1.0, 1.0)) / 3.1415927;
+    let radiance = textureSampleLevel(environment, environment_sampler, vec2(u, v), level);
+    return s.environment.x * radiance.xyz;
+}
+
+// Light reaching a surface facing `n` from every side, from one of the smallest mips of the
+// environment map
+fn ambient_light(n: vec3<f32>) -> vec3<f32> {
+    if s.lights.y == 0u {
+        return AMBIENT_COLOR;
+    }
+    let levels = f32(textureNumLevels(environment));
+    return environment_radiance(n, max(levels - 3.0, 0.0));
+}
+
+// Light of `light` reaching world space `p` on a surface facing `n`, blocked by the grids
+// when the light casts shadows
+fn light_radiance(light: Light, p: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    let kind = u32(light.position.w);
+    var l = -light.direction.xyz;
+    var dist = NO_HIT;
+    var radiance = light.color.xyz;
+    if kind != 0u {
+        let to_light = light.position.xyz - p;
+        dist = length(to_light);
+        l = to_light / dist;
+        radiance /= max(dist * dist, 1e-4);
+    }
+    if kind == 2u {
+        radiance *= smoothstep(light.cone.x, light.cone.y, dot(-l, light.direction.xyz));
+    }
+
+    let ln = dot(n, l);
+    if ln <= 0.0 || all(radiance == vec3(0.0)) {
+        return vec3(0.0);
+    }
+    if light.direction.w == 1.0 {
+        let shadow = trace_scene(p + 4e-2 * n, l);
+        if shadow.state == 0u && shadow.t < dist {
+            return vec3(0.0);
+        }
+    }
+    return radiance * ln;
+}
+
+// Light of every light in the list reaching world space `p` on a surface facing `n`
+fn lights_radiance(p: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    var radiance = vec3(0.0);
+    for (var i = 0u; i < s.lights.x; i++) {
+        radiance += light_radiance(lights[i], p, n);
+    }
+    return radiance;
+}
+
+const PATH_OFFSET: f32 = 4e-2;
+
+// Light reaching the camera along one random path. Every surface it scatters off takes the
+// light straight from the sun and the lights, paths that leave the scene take the sky light
+fn path_trace(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
+    var ray_src = src;
+    var ray_dir = dir;
+    var throughput = vec3(1.0);
+    var radiance = vec3(0.0);
+
+    for (var bounce = 0u; bounce <= s.frame.z; bounce++) {
+        let hit = trace_scene(ray_src, ray_dir);
+        if hit.state != 0u {
+            if hit.state == 1u {
+                radiance += throughput * sky_radiance(ray_dir);
+            }
+            break;
+        }
+
+        let surface = objects[hit.object];
+        let roughness = surface.albedo.w;
+        let metallic = surface.emission.w;
+        radiance += throughput * surface.emission.xyz;
+
+        var n = hit.normal;
+        // Rays starting inside a voxel have no face they stepped through
+        if all(n == vec3(0.0)) {
+            n = -ray_dir;
+        }
+        let next_src = hit.p + PATH_OFFSET * n;
+
+        // Share of the light reflected off the top of the surface, metals reflect all of it
+        let specular = mix(0.04, 1.0, metallic);
+
+        // Only the diffuse share of the surface catches the sun, the specular one only reflects
+        let ln = dot(-s.sun_dir, n);
+        if ln > 0.0 && trace_scene(next_src, -s.sun_dir).state != 0u {
+            let sun = s.sun_color.xyz * s.sun_color.a * ln;
+            radiance += throughput * hit.albedo * (1.0 - specular) * sun;
+        }
+        radiance += throughput * hit.albedo * (1.0 - specular) * lights_radiance(hit.p, n);
+
+        if bounce == s.frame.z {
+            break;
+        }
+
+        // Specular bounces scatter around the mirror direction, wider the rougher the surface,
+        // and take the albedo's tint off metals. Diffuse ones scatter around the normal.
+        let diffuse = cosine_direction(n);
+        if random_float() < specular {
+            ray_dir = normalize(mix(reflect(ray_dir, n), diffuse, roughness * roughness));
+            if dot(ray_dir, n) <= 0.0 {
+                ray_dir = diffuse;
+            }
+            throughput *= mix(vec3(1.0), hit.albedo, metallic);
+        } else {
+            ray_dir = diffuse;
+            throughput *= hit.albedo;
+        }
+        ray_src = next_src;
+    }
+
+    return radiance;
 }
 
 // MATERIAL CONSTANTS
@@ -146,22 +836,21 @@ const k_d: f32 = 0.7;
 const k_a: f32 = 0.3;
 const REFLECTIVITY: f32 = 0.9;
 const WALL_I: f32 = 0.1;
-const BASE_COLOR: vec3<f32> = vec3(0.4, 0.2, 0.2);
 const AMBIENT_COLOR: vec3<f32> = vec3(0.4, 0.4, 0.3);
 
-fn ray_trace(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
-    let hit: HDDAout = hdda_ray(src, dir);
-    let step: vec3<f32> = sign11(dir);
-
+// Colour of `hit`, seen along `dir`, in the render mode
+fn ray_trace(hit: SceneHit, dir: vec3<f32>) -> vec3<f32> {
     if hit.state == 0u {
         var grid = vec3<f32>(0.0);
-        if s.show_345[2] == 1u && any(floor(hit.p) % 4096. == 0.) {
+        // Borders of the leaves and of the two levels above them
+        let levels = s.tree_levels;
+        if levels > 2u && s.show_345[2] == 1u && any(floor(hit.ip) % f32(1u << total_log_d(levels - 3u)) == 0.) {
             grid = vec3<f32>(-0.3, -0.3, 1.0);
         }
-        else if s.show_345[1] == 1u && any(floor(hit.p) % 128. == 0.) {
+        else if levels > 1u && s.show_345[1] == 1u && any(floor(hit.ip) % f32(1u << total_log_d(levels - 2u)) == 0.) {
             grid = vec3<f32>(0.6, -0.2, -0.2);
         }
-        else if s.show_345[0] == 1u && any(floor(hit.p) % 8. == 0.) {
+        else if s.show_345[0] == 1u && any(floor(hit.ip) % f32(1u << total_log_d(levels - 1u)) == 0.) {
             grid = vec3<f32>(-0.1, 0.5, 0.3);
         }
 
@@ -180,32 +869,33 @@ fn ray_trace(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
             return grid + mix(color1, color2, t);
         }
         case 3u: {
-            let N = normalize(-step * vec3<f32>(hit.mask));
+            let N = hit.normal;
             let LN = max(0.0, s.sun_color.a * dot(-s.sun_dir, N));
-            var I_d = k_d * s.sun_color.xyz * BASE_COLOR * LN;
-            var I_a = k_a * AMBIENT_COLOR * BASE_COLOR;
+            var I_d = k_d * s.sun_color.xyz * hit.albedo * LN;
+            var I_a = k_a * ambient_light(N) * hit.albedo * ambient_occlusion(hit);
 
-            if LN != 0.0  &&
-               hdda_ray(hit.p - 4e-2 * step * vec3<f32>(hit.mask), -s.sun_dir).state == 0u {
-                return I_a;
+            if LN != 0.0 {
+                I_d *= sun_visibility(hit);
             }
+            I_d += k_d * hit.albedo * lights_radiance(hit.p, N);
 
-            return I_a + I_d;
+            return I_a + I_d + objects[hit.object].emission.xyz;
         }
         case 4u: {
-            let N = normalize(-step * vec3<f32>(hit.mask));
+            let N = hit.normal;
 
             var mcol: vec3<f32>;
             var I = s.sun_color.a * k_d * dot(-s.sun_dir, N);
             I = max(0.0, I);
 
             if I != 0.0  &&
-               hdda_ray(hit.p - 4e-2 * step * vec3<f32>(hit.mask), -s.sun_dir).state == 0u {
-                mcol = BASE_COLOR + I * s.sun_color.xyz * 0.05;
+               trace_scene(hit.p + 4e-2 * hit.normal, -s.sun_dir).state == 0u {
+                mcol = hit.albedo + I * s.sun_color.xyz * 0.05;
             }
             else {
-                mcol = BASE_COLOR + I * s.sun_color.xyz;
+                mcol = hit.albedo + I * s.sun_color.xyz;
             }
+            mcol += objects[hit.object].emission.xyz + k_d * lights_radiance(hit.p, N);
 
             // if hit.p.y < 0.0 {
             //     return mcol;
@@ -215,12 +905,20 @@ fn ray_trace(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
             let rdir = normalize(dir - 2.0 * N * dot(dir, N));
 
             // Avoid self-intersection
-            let rsrc = hit.p - 4e-2 * step * vec3<f32>(hit.mask);
+            let rsrc = hit.p + 4e-2 * hit.normal;
 
-            let rcol = reflect_ray2(rsrc, rdir);
+            let rcol = reflect_ray2(rsrc, rdir) * metal_tint(hit);
 
 
-            return mix(mcol, rcol, REFLECTIVITY);
+            return mix(mcol, rcol, reflectivity(hit));
+        }
+        case 5u: { // Value
+            var base = vec3(0.5);
+            if hit.has_value {
+                base = value_color(hit.value);
+            }
+            // Lit from the eye so the colours stay readable from every side
+            return grid + base * (0.4 + 0.6 * abs(dot(hit.normal, dir)));
         }
         default: {
             return grid + dot(vec3<f32>(hit.mask) * vec3(0.2, 0.2, 0.3), vec3(1.0));
@@ -229,6 +927,10 @@ fn ray_trace(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
     }
 
     if hit.state == 1u {
+        // The shaded modes show the environment map behind the grids
+        if s.lights.y == 1u && (s.render_mode == 3u || s.render_mode == 4u) {
+            return environment_radiance(dir, 0.0);
+        }
         switch s.render_mode {
         case 0u: {
             return vec3(0.0) + dot(vec3<f32>(hit.mask) * vec3(0.01, 0.02, 0.03), vec3(1.0));
@@ -244,7 +946,7 @@ fn ray_trace(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
             return mix(color1, color2, t) + dot(vec3<f32>(hit.mask) * vec3(0.04, 0.08, 0.12), vec3(1.0));
         }
         case 4u: {
-            let N = normalize(-step * vec3<f32>(hit.mask));
+            let N = hit.normal;
             let Np = max(vec3(0.0), N);
             let Nn = -min(vec3(0.0), N);
 
@@ -264,17 +966,26 @@ fn ray_trace(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
     return vec3<f32>(dir);
 }
 
+// Share of the reflections in the reflective mode, smooth surfaces reflect the most
+fn reflectivity(hit: SceneHit) -> f32 {
+    return REFLECTIVITY * (1.0 - objects[hit.object].albedo.w);
+}
+
+// Metals tint their reflections with their albedo
+fn metal_tint(hit: SceneHit) -> vec3<f32> {
+    return mix(vec3(1.0), hit.albedo, objects[hit.object].emission.w);
+}
+
 fn reflect_ray2(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
-    let hit: HDDAout = hdda_ray(src, dir);
-    let step: vec3<f32> = sign11(dir);
+    let hit: SceneHit = trace_scene(src, dir);
 
     if hit.state == 0u {
-        let N = normalize(-step * vec3<f32>(hit.mask));
+        let N = hit.normal;
 
         // Maybe do this only if material is reflective
 
         let rdir = normalize(dir - 2.0 * N * dot(dir, N));
-        let rsrc = hit.p - 4e-2 * step * vec3<f32>(hit.mask);
+        let rsrc = hit.p + 4e-2 * hit.normal;
         let rcol = reflect_ray1(rsrc, rdir);
         var mcol: vec3<f32>;
         var I = s.sun_color.a * k_d * dot(-s.sun_dir, N);
@@ -282,18 +993,22 @@ fn reflect_ray2(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
         I = max(0.0, I);
 
         if I != 0.0  &&
-        hdda_ray(hit.p - 4e-2 * step * vec3<f32>(hit.mask), -s.sun_dir).state == 0u {
-            mcol = BASE_COLOR + I * s.sun_color.xyz * 0.05;
+        trace_scene(hit.p + 4e-2 * hit.normal, -s.sun_dir).state == 0u {
+            mcol = hit.albedo + I * s.sun_color.xyz * 0.05;
         }
         else {
-            mcol = BASE_COLOR + I * s.sun_color.xyz;
+            mcol = hit.albedo + I * s.sun_color.xyz;
         }
+        mcol += objects[hit.object].emission.xyz + k_d * lights_radiance(hit.p, N);
 
-        return mix(mcol, rcol, REFLECTIVITY);
+        return mix(mcol, rcol * metal_tint(hit), reflectivity(hit));
     }
 
     if hit.state == 1u {
-        let N = normalize(-step * vec3<f32>(hit.mask));
+        if s.lights.y == 1u {
+            return environment_radiance(dir, 0.0);
+        }
+        let N = hit.normal;
         let Np = max(vec3(0.0), N);
         let Nn = -min(vec3(0.0), N);
 
@@ -309,24 +1024,26 @@ fn reflect_ray2(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
 }
 
 fn reflect_ray1(src: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
-    let hit: HDDAout = hdda_ray(src, dir);
-    let step: vec3<f32> = sign11(dir);
+    let hit: SceneHit = trace_scene(src, dir);
 
     if hit.state == 0u {
-        let N = normalize(-step * vec3<f32>(hit.mask));
+        let N = hit.normal;
         var I = s.sun_color.a * k_d * dot(-s.sun_dir, N);
         // If angle is obtouse, that side is in shadow
         I = max(0.0, I);
 
         if I != 0.0  &&
-        hdda_ray(hit.p - 4e-2 * step * vec3<f32>(hit.mask), -s.sun_dir).state == 0u {
-            return BASE_COLOR + I * s.sun_color.xyz * 0.05;
+        trace_scene(hit.p + 4e-2 * hit.normal, -s.sun_dir).state == 0u {
+            return hit.albedo + I * s.sun_color.xyz * 0.05;
         }
-        return BASE_COLOR + I * s.sun_color.xyz;
+        return hit.albedo + I * s.sun_color.xyz;
     }
 
     if hit.state == 1u {
-        let N = normalize(-step * vec3<f32>(hit.mask));
+        if s.lights.y == 1u {
+            return environment_radiance(dir, 0.0);
+        }
+        let N = hit.normal;
         let Np = max(vec3(0.0), N);
         let Nn = -min(vec3(0.0), N);
 
@@ -350,61 +1067,91 @@ struct VdbLeaf {
     color: vec3<f32>,
     dist: u32,
     num_parents: u32,
-    parents: array<Parent, 3>,
+    parents: array<Parent, 4>,
 }
 
-const NODE5_TOTAL_LOG_D: u32 = 12u; // 5 + 4 + 3
-const NODE4_TOTAL_LOG_D: u32 = 7u; // 4 + 3
-const NODE3_TOTAL_LOG_D: u32 = 3u; // 3
+// Log2 side length of the index space covered by a node of `level`, 0 for a voxel
+fn total_log_d(level: u32) -> u32 {
+    var total = 0u;
+    for (var l: u32 = level; l < s.tree_levels; l++) {
+        total += s.tree_log2_dims[l];
+    }
+    return total;
+}
 
-fn get_vdb_leaf_from_leaf(pos: vec3<i32>, leaff: VdbLeaf) -> VdbLeaf {
-    var leaf = leaff;
-    if leaf.num_parents == 3u {
-        let node3_global = global_to_node(pos, NODE3_TOTAL_LOG_D);
-        if all(leaf.parents[2].origin == node3_global) {
-            return get_vdb_leaf_from_node3(pos, leaf);
-        }
-        let node4_global = global_to_node(pos, NODE4_TOTAL_LOG_D);
-        if all(leaf.parents[1].origin == node4_global) {
-            return get_vdb_leaf_from_node4(pos, leaf);
-        }
-        let node5_global = global_to_node(pos, NODE5_TOTAL_LOG_D);
-        if all(leaf.parents[0].origin == node5_global) {
-            return get_vdb_leaf_from_node5(pos, leaf);
-        }
-        return get_vdb_leaf_from_nothing(pos, leaf);
+// Entries of a node of `level`
+fn node_size(level: u32) -> u32 {
+    return 1u << (3u * s.tree_log2_dims[level]);
+}
+
+// Words of the masks of a node of `level`
+fn mask_words(level: u32) -> u32 {
+    let words = node_size(level) / 32u;
+    if level + 1u < s.tree_levels {
+        return 2u * words;
     }
-    if leaf.num_parents == 2u {
-        let node4_global = global_to_node(pos, NODE4_TOTAL_LOG_D);
-        if all(leaf.parents[1].origin == node4_global) {
-            return get_vdb_leaf_from_node4(pos, leaf);
-        }
-        let node5_global = global_to_node(pos, NODE5_TOTAL_LOG_D);
-        if all(leaf.parents[0].origin == node5_global) {
-            return get_vdb_leaf_from_node5(pos, leaf);
-        }
-        return get_vdb_leaf_from_nothing(pos, leaf);
+    return words;
+}
+
+// Entry `i` of the node pool of `level`
+fn pool_entry(level: u32, i: u32) -> u32 {
+    let pages = textureDimensions(leaf_pages);
+    if level == s.tree_levels - 1u && pages.x > 1u {
+        let row = i / pages.x;
+        return textureLoad(leaf_pages, vec2(i % pages.x, row % pages.y), row / pages.y, 0).x;
+    }
+    switch level {
+        case 0u: { return nodes0[i]; }
+        case 1u: { return nodes1[i]; }
+        case 2u: { return nodes2[i]; }
+        default: { return nodes3[i]; }
+    }
+}
+
+// Word `i` of the masks of `level`
+fn mask_word(level: u32, i: u32) -> u32 {
+    switch level {
+        case 0u: { return masks0[i]; }
+        case 1u: { return masks1[i]; }
+        case 2u: { return masks2[i]; }
+        default: { return masks3[i]; }
     }
-    if leaf.num_parents == 1u {
-        let node5_global = global_to_node(pos, NODE5_TOTAL_LOG_D);
-        if all(leaf.parents[0].origin == node5_global) {
-            return get_vdb_leaf_from_node5(pos, leaf);
+}
+
+// Bit `offset` of the value mask of node `idx` of `level` when `value`, of its child mask
+// otherwise
+fn mask_bit(level: u32, idx: u32, offset: u32, value: bool) -> bool {
+    var word = idx * mask_words(level) + (offset >> 5u);
+    if value && level + 1u < s.tree_levels {
+        word += node_size(level) / 32u;
+    }
+    return bool(mask_word(level, word) & (1u << (offset & 31u)));
+}
+
+// Look `pos` up from the deepest parent of the previous lookup that still contains it
+fn get_vdb_leaf_from_leaf(pos: vec3<i32>, leaff: VdbLeaf) -> VdbLeaf {
+    var leaf = leaff;
+    for (var level = leaf.num_parents; level > 0u; level--) {
+        let node_global = global_to_node(pos, total_log_d(level - 1u));
+        if all(leaf.parents[level - 1u].origin == node_global) {
+            leaf.num_parents = level;
+            return get_vdb_leaf_from_node(pos, leaf);
         }
-        return get_vdb_leaf_from_nothing(pos, leaf);
     }
     return get_vdb_leaf_from_nothing(pos, leaf);
 }
 
 fn get_vdb_leaf_from_nothing(pos: vec3<i32>, leaff: VdbLeaf) -> VdbLeaf {
     var leaf = leaff;
-    let node5_global = global_to_node(pos, NODE5_TOTAL_LOG_D);
+    let top_global = global_to_node(pos, total_log_d(0u));
 
-    for (var node5_idx: u32 =0u; node5_idx < arrayLength(&origins); node5_idx++) {
-        if all(node5_global == origins[node5_idx]) {
-            leaf.parents[0] = Parent(node5_global, node5_idx);
+    let first_top = object.bases.x;
+    for (var top_idx: u32 = first_top; top_idx < first_top + object.top_nodes.x; top_idx++) {
+        if all(top_global == origins[top_idx]) {
+            leaf.parents[0] = Parent(top_global, top_idx);
             leaf.num_parents = 1u;
 
-            return get_vdb_leaf_from_node5(pos, leaf);
+            return get_vdb_leaf_from_node(pos, leaf);
         }
     }
 
@@ -412,87 +1159,76 @@ fn get_vdb_leaf_from_nothing(pos: vec3<i32>, leaff: VdbLeaf) -> VdbLeaf {
     return VdbLeaf(vec3<f32>(0.0), 1u, 0u, leaf.parents);
 }
 
-fn get_vdb_leaf_from_node5(pos: vec3<i32>, leaff: VdbLeaf) -> VdbLeaf {
+// Walk down from the deepest parent of `leaf` to the voxel or tile containing `pos`
+fn get_vdb_leaf_from_node(pos: vec3<i32>, leaff: VdbLeaf) -> VdbLeaf {
     var leaf = leaff;
-    let node5_local = global_to_local(pos, NODE5_TOTAL_LOG_D);
-    let node5_child = local_to_child_node(node5_local, NODE4_TOTAL_LOG_D);
-    let node5_offset = child_to_offset(node5_child, 5u, 10u);
-    let node5_idx = leaf.parents[0].idx;
-
-    let node5_mask_index = node5_offset >> 5u;
-    let node5_mask_pos = node5_offset & 31u;
-    let in_kid5 = bool( kids5[node5_idx].m[node5_mask_index] & ( 1u << node5_mask_pos));
-    let in_val5 = bool( vals5[node5_idx].m[node5_mask_index] & ( 1u << node5_mask_pos));
-
-    let node5_atlas_dim = textureDimensions(node5s).y >> 5u;
-    let node5_atlas_origin = 32u * atlas_origin_from_idx(node5_idx, node5_atlas_dim);
-    let node4_idx = textureLoad(node5s, node5_child + node5_atlas_origin, 0).r;
+    let leaf_level = s.tree_levels - 1u;
+    for (var level = leaf.num_parents - 1u; level < leaf_level; level++) {
+        let node_local = global_to_local(pos, total_log_d(level));
+        let node_child = local_to_child_node(node_local, total_log_d(level + 1u));
+        let log_d = s.tree_log2_dims[level];
+        let node_offset = child_to_offset(node_child, log_d, 2u * log_d);
+        let node_idx = leaf.parents[level].idx;
+
+        if mask_bit(level, node_idx, node_offset, true) {
+            return VdbLeaf(vec3<f32>(0.2), 0u, level + 1u, leaf.parents);
+        }
 
-    if (in_val5) {
-        return VdbLeaf(vec3<f32>(0.2), 0u, 1u, leaf.parents);
-    }
+        // Child index local to the grid, or the distance to the closest voxel for tiles
+        let node_entry = pool_entry(level, node_idx * node_size(level) + node_offset);
+        if !mask_bit(level, node_idx, node_offset, false) {
+            return VdbLeaf(vec3<f32>(0.0), node_entry, level + 1u, leaf.parents);
+        }
 
-    if (!in_kid5) {
-        return VdbLeaf(vec3<f32>(0.0), (node4_idx), 1u, leaf.parents);
+        let child_global = global_to_node(pos, total_log_d(level + 1u));
+        leaf.parents[level + 1u] = Parent(child_global, object.bases[level + 1u] + node_entry);
+        leaf.num_parents = level + 2u;
     }
 
-    let node4_global = global_to_node(pos, NODE4_TOTAL_LOG_D);
-    leaf.parents[1] = Parent(node4_global, node4_idx);
-    leaf.num_parents = 2u;
+    let leaf_local = global_to_local(pos, total_log_d(leaf_level));
+    let log_d = s.tree_log2_dims[leaf_level];
+    let leaf_offset = child_to_offset(leaf_local, log_d, 2u * log_d);
+    let leaf_idx = leaf.parents[leaf_level].idx;
 
-    return get_vdb_leaf_from_node4(pos, leaf);
+    if mask_bit(leaf_level, leaf_idx, leaf_offset, true) {
+        return VdbLeaf(vec3<f32>(0.1), 0u, s.tree_levels, leaf.parents);
+    }
+    return VdbLeaf(vec3<f32>(0.0), leaf_entry(leaf_idx, leaf_offset), s.tree_levels, leaf.parents);
 }
 
-fn get_vdb_leaf_from_node4(pos: vec3<i32>, leaff: VdbLeaf) -> VdbLeaf {
-    var leaf = leaff;
-    let node4_local = global_to_local(pos, NODE4_TOTAL_LOG_D);
-    let node4_child = local_to_child_node(node4_local, NODE3_TOTAL_LOG_D);
-    let node4_offset = child_to_offset(node4_child, 4u, 8u);
-    let node4_idx = leaf.parents[1].idx;
-
-    let node4_mask_index = node4_offset >> 5u;
-    let node4_mask_pos = node4_offset & 31u;
-    let in_kid4 = bool( kids4[node4_idx].m[node4_mask_index] & ( 1u << node4_mask_pos));
-    let in_val4 = bool( vals4[node4_idx].m[node4_mask_index] & ( 1u << node4_mask_pos));
-
 
-    let node4_atlas_dim = textureDimensions(node4s).x >> 4u;
-    let node4_atlas_origin = 16u * atlas_origin_from_idx(node4_idx, node4_atlas_dim);
-    let node3_idx = textureLoad(node4s, node4_child + node4_atlas_origin, 0).r;
-
-    if (in_val4) {
-        return VdbLeaf(vec3<f32>(0.2), 0u, 2u, leaf.parents);
-    }
-    if (!in_kid4) {
-        return VdbLeaf(vec3<f32>(0.0), node3_idx, 2u, leaf.parents);
+fn leaf_bits() -> u32 {
+    switch s.leaf_encoding {
+        case 1u, 2u: { return 16u; }
+        case 3u: { return 8u; }
+        default: { return 32u; }
     }
-
-    let node3_global = global_to_node(pos, NODE3_TOTAL_LOG_D);
-    leaf.parents[2] = Parent(node3_global, node3_idx);
-    leaf.num_parents = 3u;
-
-    return get_vdb_leaf_from_node3(pos, leaf);
 }
 
-fn get_vdb_leaf_from_node3(pos: vec3<i32>, leaff: VdbLeaf) -> VdbLeaf {
-    var leaf = leaff;
-    let node3_local = global_to_local(pos, NODE3_TOTAL_LOG_D);
-    let node3_offset = child_to_offset(node3_local, 3u, 6u);
-    let node3_idx = leaf.parents[2].idx;
-
-    let node3_mask_index = node3_offset >> 5u;
-    let node3_mask_pos = node3_offset & 31u;
-    let in_val3 = bool( vals3[node3_idx].m[node3_mask_index] & ( 1u << node3_mask_pos));
-
-    let node3_atlas_dim = textureDimensions(node3s).x >> 3u;
-    let node3_atlas_origin = 8u * atlas_origin_from_idx(node3_idx, node3_atlas_dim);
-    let voxel = textureLoad(node3s, node3_local + node3_atlas_origin, 0).r;
-    if (in_val3) {
-        return VdbLeaf(vec3<f32>(0.1), 0u, 3u, leaf.parents);
+// Raw entry of a leaf, narrow encodings pack several entries into a word, lowest bits first
+fn leaf_entry(leaf_idx: u32, offset: u32) -> u32 {
+    let bits = leaf_bits();
+    let per_word = 32u / bits;
+    let leaf_level = s.tree_levels - 1u;
+    let word = pool_entry(leaf_level, leaf_idx * (node_size(leaf_level) / per_word) + offset / per_word);
+    if bits == 32u {
+        return word;
     }
-    return VdbLeaf(vec3<f32>(0.0), voxel, 3u, leaf.parents);
+    return (word >> ((offset % per_word) * bits)) & ((1u << bits) - 1u);
 }
 
+// Value of an active leaf entry
+fn leaf_value(leaf_idx: u32, offset: u32) -> f32 {
+    let entry = leaf_entry(leaf_idx, offset);
+    switch s.leaf_encoding {
+        case 1u: { return unpack2x16float(entry).x; }
+        case 2u, 3u: {
+            let range = leaf_ranges[leaf_idx];
+            return range.x + range.y * f32(entry);
+        }
+        default: { return bitcast<f32>(entry); }
+    }
+}
 
 fn global_to_node(pos: vec3<i32>, total_log_d: u32) -> vec3<i32> {
     // Global coordinates of a node that contains position
@@ -512,8 +1248,3 @@ fn local_to_child_node(pos: vec3<u32>, child_total_log_d: u32) -> vec3<u32> {
 fn child_to_offset(pos: vec3<u32>, log_d: u32, log_dd: u32) -> u32 {
     return (pos.x << log_dd) | (pos.y << log_d) | pos.z;
 }
-
-fn atlas_origin_from_idx(idx: u32, dim: u32) -> vec3<u32> {
-    // Return node origin in atlas
-    return vec3(idx % dim, (idx / dim) % dim, idx / (dim * dim));
-}
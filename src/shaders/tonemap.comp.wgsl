@@ -0,0 +1,68 @@
+struct ToneMap {
+    // Factor the colours are multiplied with before the curve
+    exposure: vec4<f32>,
+    // Curve: 0 ACES, 1 Reinhard, 2 filmic and 3 clamp
+    tone_mapper: vec4<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> t: ToneMap;
+// Linear HDR colours of the raycaster
+@group(0) @binding(1)
+var hdr: texture_2d<f32>;
+
+// sRGB bytes shown in the window, recorded and exported as they are
+@group(1) @binding(0)
+var display: texture_storage_2d<rgba8unorm, write>;
+
+@compute @workgroup_size(8,4)
+fn tm_main(@builtin(global_invocation_id) global_id : vec3<u32>) {
+    if any(global_id.xy >= textureDimensions(display)) {
+        return;
+    }
+
+    let color = max(textureLoad(hdr, global_id.xy, 0).xyz * t.exposure.x, vec3(0.0));
+    var mapped: vec3<f32>;
+    switch t.tone_mapper.x {
+        case 0u: { mapped = aces(color); }
+        case 1u: { mapped = reinhard(color); }
+        case 2u: { mapped = filmic(color); }
+        default: { mapped = color; }
+    }
+
+    textureStore(display, global_id.xy, vec4(linear_to_srgb(saturate(mapped)), 1.0));
+}
+
+// Narkowicz's fit of the ACES reference rendering and output transforms
+fn aces(c: vec3<f32>) -> vec3<f32> {
+    let x = c * 0.6;
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+// Scales the colour by its luminance, which keeps the hue of bright colours
+fn reinhard(c: vec3<f32>) -> vec3<f32> {
+    let luminance = dot(c, vec3(0.2126, 0.7152, 0.0722));
+    return c / (1.0 + luminance);
+}
+
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+// Hable's curve from Uncharted 2, scaled so 11.2 maps to white
+fn filmic(c: vec3<f32>) -> vec3<f32> {
+    return hable(2.0 * c) / hable(vec3(11.2));
+}
+
+// The sRGB transfer function, the only place linear colours are encoded
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3(0.0031308));
+}
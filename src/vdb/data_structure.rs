@@ -4,11 +4,11 @@ use bitflags::bitflags;
 use bitvec::vec::BitVec;
 use cgmath::Vector3;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{Read, Seek, SeekFrom},
 };
 
-use super::VdbValueType;
+use super::{Map, TreeShape, VdbValueType};
 
 pub type GlobalCoordinates = Vector3<i32>;
 pub type LocalCoordinates = Vector3<u32>;
@@ -275,6 +275,32 @@ where
 {
     pub root: RootNode<ValueType, ChildType>,
     pub grid_descriptor: GridDescriptor,
+    /// Index to world transform of the grid
+    pub transform: Map,
+    /// Nodes edited since the GPU copy was last synced
+    pub dirty: DirtyNodes,
+    /// Tile distances are at most [`crate::vdb::VDB345::SDF_CAPS`], which the incremental
+    /// distance updates rely on
+    pub sdf_capped: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DirtyNodes {
+    /// Tiles were activated or nodes removed, so every node index may have changed
+    pub topology: bool,
+    /// Origins of the edited nodes on each level, top level first
+    pub nodes: [HashSet<[i32; 3]>; 3],
+    /// Origins of the nodes created on each level, in the order they were created. They
+    /// are in `nodes` as well, with their parents.
+    pub added: [Vec<[i32; 3]>; 3],
+}
+
+impl DirtyNodes {
+    pub fn is_empty(&self) -> bool {
+        !self.topology
+            && self.nodes.iter().all(HashSet::is_empty)
+            && self.added.iter().all(Vec::is_empty)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -304,6 +330,19 @@ impl GridDescriptor {
     ) -> Result<u64, std::io::Error> {
         reader.seek(SeekFrom::Start(self.block_pos))
     }
+
+    /// Scalars in each value, 3 for the `vec3s` grids colours are read from and 1 otherwise
+    pub fn value_components(&self) -> usize {
+        match self.grid_type.split('_').nth(1) {
+            Some("vec3s") => 3,
+            _ => 1,
+        }
+    }
+
+    /// Node configuration written in the grid type, 5-4-3 when it names none
+    pub fn tree_shape(&self) -> TreeShape {
+        TreeShape::from_grid_type(&self.grid_type).unwrap_or_default()
+    }
 }
 
 impl<ValueType, ChildType: Node> VDB<ValueType, ChildType>
@@ -326,6 +365,9 @@ where
         Self {
             root,
             grid_descriptor,
+            transform: Default::default(),
+            dirty: Default::default(),
+            sdf_capped: false,
         }
     }
 }
@@ -473,6 +515,20 @@ mod tests {
         assert_eq!(0, N5::global_to_offset([1, 2, 3].into()));
     }
 
+    #[test]
+    fn value_components_test() {
+        let mut descriptor = <crate::vdb::VDB345<u32>>::new().grid_descriptor;
+        for (grid_type, components) in [
+            ("Tree_float_5_4_3", 1),
+            ("Tree_float_5_4_3_HalfFloat", 1),
+            ("Tree_vec3s_5_4_3", 3),
+            ("Tree_vec3s_5_4_3_HalfFloat", 3),
+        ] {
+            descriptor.grid_type = grid_type.to_string();
+            assert_eq!(descriptor.value_components(), components, "{grid_type}");
+        }
+    }
+
     #[test]
     fn local_to_offset_test() {
         let tests = vec![[1, 2, 3], [15, 15, 0], [8, 9, 10]];
@@ -0,0 +1,395 @@
+use cgmath::{InnerSpace, Vector3};
+use itertools::iproduct;
+
+use super::{GlobalCoordinates, LeafData, Node, VdbEndpoint, VdbValueType, N3, N4, N5, VDB345};
+
+/// Active voxel hit by a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub voxel: GlobalCoordinates,
+    /// Empty voxel the ray went through right before `voxel`, new voxels are added here
+    pub previous: GlobalCoordinates,
+    /// Distance along the ray to where it enters `voxel`
+    pub t: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrushShape {
+    Sphere,
+    Cube,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrushMode {
+    /// Activate empty voxels with the brush value
+    Add,
+    /// Deactivate voxels
+    Remove,
+    /// Change the value of active voxels
+    Paint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Brush<ValueType> {
+    pub shape: BrushShape,
+    pub mode: BrushMode,
+    pub radius: u32,
+    pub value: ValueType,
+}
+
+/// State of a voxel before and after an edit, `None` if the voxel is inactive
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelChange<ValueType> {
+    pub p: GlobalCoordinates,
+    pub before: Option<ValueType>,
+    pub after: Option<ValueType>,
+}
+
+/// Every voxel changed by a single brush application
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke<ValueType> {
+    /// Index of the edited grid
+    pub grid: usize,
+    pub changes: Vec<VoxelChange<ValueType>>,
+}
+
+/// Undo and redo stacks of brush strokes
+#[derive(Debug, Clone)]
+pub struct EditHistory<ValueType> {
+    undo: Vec<Stroke<ValueType>>,
+    redo: Vec<Stroke<ValueType>>,
+    limit: usize,
+}
+
+impl<ValueType: VdbValueType> VDB345<ValueType> {
+    /// Returns `true` if the voxel at `p` holds a value
+    pub fn is_active(&self, p: GlobalCoordinates) -> bool {
+        self.node3(p).is_some_and(|node3| {
+            let bit_index_0 = <N3<ValueType>>::global_to_offset(p);
+            node3.value_mask[bit_index_0 >> 6] & (1 << (bit_index_0 & (64 - 1))) != 0
+        })
+    }
+
+    /// Turns the voxel at `p` on or off.
+    ///
+    /// Activating a voxel without a value gives it the background value. Deactivating a
+    /// voxel turns it back into a distance tile, which is only valid again after the SDF is
+    /// updated around it.
+    pub fn set_active(&mut self, p: GlobalCoordinates, active: bool) {
+        if active {
+            let value = match self.get_voxel(p) {
+                VdbEndpoint::Leaf(&value) => value,
+                _ => self.root.background,
+            };
+            self.set_voxel(p, value);
+            return;
+        }
+
+        let Some(node3) = self.node3_mut(p) else {
+            return;
+        };
+        let bit_index_0 = <N3<ValueType>>::global_to_offset(p);
+        node3.value_mask[bit_index_0 >> 6] &= !(1 << (bit_index_0 & (64 - 1)));
+        node3.data[bit_index_0] = LeafData::Tile(0);
+
+        self.dirty.nodes[2].insert(<N3<ValueType>>::global_to_node(p).into());
+    }
+
+    /// Casts a ray in index space and returns the first active voxel it hits within `max_t`.
+    ///
+    /// Like the shader HDDA, missing N5, N4 and N3 nodes are skipped in a single step.
+    pub fn raycast(&self, origin: Vector3<f32>, dir: Vector3<f32>, max_t: f32) -> Option<RayHit> {
+        let dir = dir.normalize();
+        let step = dir.map(|c| if c < 0. { -1 } else { 1 });
+
+        // Nothing can be hit once the ray leaves the tree bounds
+        let (min, max) = self.index_bounds()?;
+        let mut t_far = max_t;
+        for i in 0..3 {
+            let far = if step[i] > 0 { max[i] } else { min[i] };
+            let t_face = (far as f32 - origin[i]) / dir[i];
+            if !t_face.is_nan() {
+                t_far = t_far.min(t_face);
+            }
+        }
+
+        let mut t = 0.;
+        let mut voxel = origin.map(|c| c.floor() as i32);
+        let mut previous = voxel;
+
+        while t <= t_far {
+            let log2_dim = if self.node5(voxel).is_none() {
+                <N5<ValueType>>::TOTAL_LOG2_D
+            } else if self.node4(voxel).is_none() {
+                <N4<ValueType>>::TOTAL_LOG2_D
+            } else if self.node3(voxel).is_none() {
+                <N3<ValueType>>::TOTAL_LOG2_D
+            } else if self.is_active(voxel) {
+                return Some(RayHit { voxel, previous, t });
+            } else {
+                0
+            };
+
+            // Leave the empty cell of the current level through its closest face
+            let mask = !((1 << log2_dim) - 1);
+            let (mut t_exit, mut axis) = (f32::INFINITY, 0);
+            for i in 0..3 {
+                let low = voxel[i] & mask;
+                let face = if step[i] > 0 {
+                    low + (1 << log2_dim)
+                } else {
+                    low
+                };
+                let t_face = (face as f32 - origin[i]) / dir[i];
+                if t_face < t_exit {
+                    (t_exit, axis) = (t_face, i);
+                }
+            }
+
+            if !t_exit.is_finite() {
+                return None;
+            }
+
+            t = t.max(t_exit);
+            let p = origin + dir * t;
+            let face = (voxel[axis] & mask) + if step[axis] > 0 { 1 << log2_dim } else { 0 };
+
+            voxel = p.map(|c| c.floor() as i32);
+            voxel[axis] = if step[axis] > 0 { face } else { face - 1 };
+            previous = voxel;
+            previous[axis] -= step[axis];
+        }
+
+        None
+    }
+
+    fn active_value(&self, p: GlobalCoordinates) -> Option<ValueType> {
+        match self.get_voxel(p) {
+            VdbEndpoint::Leaf(&value) if self.is_active(p) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn write_change(&mut self, p: GlobalCoordinates, value: Option<ValueType>) {
+        match value {
+            Some(value) => self.set_voxel(p, value),
+            None => self.set_active(p, false),
+        }
+    }
+}
+
+impl<ValueType: VdbValueType> Brush<ValueType> {
+    /// Voxels covered by the brush centered on `center`
+    pub fn voxels(&self, center: GlobalCoordinates) -> Vec<GlobalCoordinates> {
+        let r = self.radius as i32;
+        iproduct!(-r..=r, -r..=r, -r..=r)
+            .filter(|&(x, y, z)| match self.shape {
+                BrushShape::Sphere => x * x + y * y + z * z <= r * r,
+                BrushShape::Cube => true,
+            })
+            .map(|(x, y, z)| center + Vector3::new(x, y, z))
+            .collect()
+    }
+
+    /// Applies the brush where `hit` landed and returns the changes needed to undo it.
+    ///
+    /// Adding is centered on the empty voxel in front of the hit, the other modes on the hit
+    /// voxel itself.
+    pub fn apply(&self, vdb: &mut VDB345<ValueType>, hit: &RayHit) -> Vec<VoxelChange<ValueType>> {
+        let center = match self.mode {
+            BrushMode::Add => hit.previous,
+            BrushMode::Remove | BrushMode::Paint => hit.voxel,
+        };
+
+        let mut changes = vec![];
+        for p in self.voxels(center) {
+            let before = vdb.active_value(p);
+            let after = match self.mode {
+                BrushMode::Add => before.or(Some(self.value)),
+                BrushMode::Remove => None,
+                BrushMode::Paint => before.map(|_| self.value),
+            };
+
+            if before != after {
+                vdb.write_change(p, after);
+                changes.push(VoxelChange { p, before, after });
+            }
+        }
+
+        changes
+    }
+}
+
+impl<ValueType: VdbValueType> EditHistory<ValueType> {
+    /// History keeping at most `limit` strokes
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo: vec![],
+            redo: vec![],
+            limit,
+        }
+    }
+
+    pub fn push(&mut self, stroke: Stroke<ValueType>) {
+        if stroke.changes.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        self.undo.push(stroke);
+        if self.undo.len() > self.limit {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Reverts the last stroke, returns `false` if there was nothing to undo
+    pub fn undo(&mut self, grids: &mut [VDB345<ValueType>]) -> bool {
+        let Some(stroke) = self.undo.pop() else {
+            return false;
+        };
+
+        if let Some(vdb) = grids.get_mut(stroke.grid) {
+            for change in stroke.changes.iter().rev() {
+                vdb.write_change(change.p, change.before);
+            }
+        }
+        self.redo.push(stroke);
+        true
+    }
+
+    /// Reapplies the last undone stroke, returns `false` if there was nothing to redo
+    pub fn redo(&mut self, grids: &mut [VDB345<ValueType>]) -> bool {
+        let Some(stroke) = self.redo.pop() else {
+            return false;
+        };
+
+        if let Some(vdb) = grids.get_mut(stroke.grid) {
+            for change in &stroke.changes {
+                vdb.write_change(change.p, change.after);
+            }
+        }
+        self.undo.push(stroke);
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn raycast_test() {
+        let builder = thread::Builder::new()
+            .name("raycast_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u32>>::new();
+                vdb.set_voxel([10, 3, -7].into(), 1);
+                vdb.set_voxel([10, 3, 900].into(), 2);
+
+                let hit = vdb
+                    .raycast(
+                        Vector3::new(10.5, 3.5, -5000.5),
+                        Vector3::new(0., 0., 1.),
+                        10000.,
+                    )
+                    .unwrap();
+                assert_eq!(hit.voxel, [10, 3, -7].into());
+                assert_eq!(hit.previous, [10, 3, -8].into());
+                assert!((hit.t - 4993.5).abs() < 1e-2);
+
+                let hit = vdb.raycast(
+                    Vector3::new(10.5, 3.5, -5000.5),
+                    Vector3::new(0., 1., 1.),
+                    10000.,
+                );
+                assert_eq!(hit, None);
+
+                let hit = vdb
+                    .raycast(
+                        Vector3::new(10.5, 3.5, 0.5),
+                        Vector3::new(0., 0., 1.),
+                        10000.,
+                    )
+                    .unwrap();
+                assert_eq!(hit.voxel, [10, 3, 900].into());
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn brush_undo_redo_test() {
+        let builder = thread::Builder::new()
+            .name("brush_undo_redo_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut grids = vec![<VDB345<u32>>::new()];
+                grids[0].set_voxel([0, 0, 0].into(), 1);
+                let original = grids[0].clone();
+
+                let hit = RayHit {
+                    voxel: [0, 0, 0].into(),
+                    previous: [0, 0, -1].into(),
+                    t: 0.,
+                };
+                let mut brush = Brush {
+                    shape: BrushShape::Sphere,
+                    mode: BrushMode::Add,
+                    radius: 1,
+                    value: 7,
+                };
+                let mut history = EditHistory::new(16);
+
+                // The sphere of radius 1 is the center and its 6 neighbours, one of which is
+                // already active
+                let changes = brush.apply(&mut grids[0], &hit);
+                assert_eq!(changes.len(), 6);
+                assert_eq!(grids[0].active_value([0, 0, -2].into()), Some(7));
+                assert_eq!(grids[0].active_value([0, 0, 0].into()), Some(1));
+                history.push(Stroke { grid: 0, changes });
+
+                brush.mode = BrushMode::Paint;
+                let changes = brush.apply(&mut grids[0], &hit);
+                history.push(Stroke { grid: 0, changes });
+                assert_eq!(grids[0].active_value([0, 0, 0].into()), Some(7));
+
+                brush.mode = BrushMode::Remove;
+                brush.shape = BrushShape::Cube;
+                let changes = brush.apply(&mut grids[0], &hit);
+                history.push(Stroke { grid: 0, changes });
+                assert!(!grids[0].is_active([0, 0, 0].into()));
+                assert!(!grids[0].is_active([0, 0, -1].into()));
+                assert!(grids[0].is_active([0, 0, -2].into()));
+
+                while history.undo(&mut grids) {}
+                assert!(!history.can_undo());
+                for p in brush.voxels([0, 0, -1].into()) {
+                    assert_eq!(grids[0].active_value(p), original.active_value(p));
+                }
+
+                assert!(history.redo(&mut grids));
+                assert_eq!(grids[0].active_value([0, 0, -2].into()), Some(7));
+                assert!(history.can_redo());
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+}
@@ -0,0 +1,420 @@
+use std::{any::TypeId, collections::HashMap, fs::File, marker::PhantomData, path::Path};
+
+use half::f16;
+use itertools::Itertools;
+use memmap2::Mmap;
+
+use super::{
+    read::GridTopology, ArchiveHeader, Compression, ErrorKind, GridDescriptor, LeafData, Map, Node,
+    NodeMetaData, TreeShape, VdbReader, VdbValueType, N3,
+    OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION,
+};
+
+/// Value mask stored before the values of every leaf
+const MASK_BYTES: usize = <N3<f32>>::SIZE / 8;
+
+/// VDB archive mapped in memory, the grids read from it borrow their leaf values from the
+/// mapping instead of copying them.
+///
+/// Only grids stored without zip or blosc compression can be mapped. Their leaves are decoded
+/// when they are touched, so opening is about as fast as reading the topology and the memory
+/// used grows with the leaves accessed, which the OS pages in on demand.
+pub struct MappedVdb {
+    map: Mmap,
+    pub header: ArchiveHeader,
+    pub grid_descriptors: HashMap<String, GridDescriptor>,
+}
+
+impl MappedVdb {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ErrorKind> {
+        let file = File::open(path)?;
+        // SAFETY: the file must not be truncated or written to while mapped, like with any
+        // mapping there is no way to prevent other processes from doing so
+        let map = unsafe { Mmap::map(&file)? };
+
+        let reader = VdbReader::from_slice(&map)?;
+        if reader.is_streaming() {
+            return Err(ErrorKind::NoGridOffsets);
+        }
+        let VdbReader {
+            header,
+            grid_descriptors,
+            ..
+        } = reader;
+
+        Ok(Self {
+            map,
+            header,
+            grid_descriptors,
+        })
+    }
+
+    /// Reads the topology of grid `name` and finds where the values of each leaf are stored
+    pub fn grid<T: VdbValueType>(&self, name: &str) -> Result<MappedGrid<'_, T>, ErrorKind> {
+        let descriptor = self
+            .grid_descriptors
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ErrorKind::InvalidGridName(name.to_owned()))?;
+        if descriptor
+            .compression
+            .intersects(Compression::ZIP | Compression::BLOSC)
+        {
+            return Err(ErrorKind::CompressedGrid(name.to_owned()));
+        }
+        let shape = TreeShape::from_grid_type(&descriptor.grid_type).unwrap_or_default();
+        if shape != TreeShape::VDB345 {
+            return Err(ErrorKind::UnmappableTreeShape(name.to_owned()));
+        }
+
+        let GridTopology {
+            transform,
+            background,
+            leaves,
+            blocks,
+        } = VdbReader::from_slice(&self.map)?.read_leaf_origins::<T>(name)?;
+
+        let half = descriptor.meta_data.is_half_float();
+        let layout = LeafLayout {
+            bytes: &self.map,
+            file_version: self.header.file_version,
+            value_size: if half { 2 } else { 4 },
+        };
+        let refs = match descriptor.compression.contains(Compression::ACTIVE_MASK) {
+            true => layout.walk(blocks as usize, leaves.len())?,
+            false => layout.fixed(blocks as usize, leaves.len())?,
+        };
+
+        Ok(MappedGrid {
+            bytes: &self.map,
+            descriptor,
+            transform,
+            background,
+            half,
+            leaves: leaves.into_iter().zip(refs).collect(),
+        })
+    }
+}
+
+/// Where the mask and values of a leaf are in the mapping
+#[derive(Debug, Clone, Copy)]
+struct LeafRef {
+    mask: usize,
+    values: usize,
+    /// Only the active values are stored
+    compacted: bool,
+}
+
+struct LeafLayout<'a> {
+    bytes: &'a [u8],
+    file_version: u32,
+    value_size: usize,
+}
+
+impl LeafLayout<'_> {
+    /// Bytes between the value mask and the values of a leaf with all its values
+    fn header_bytes(&self) -> usize {
+        match self.file_version >= OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
+            // Metadata flag
+            true => 1,
+            // Origin and buffer count
+            false => 12 + 1,
+        }
+    }
+
+    /// Every leaf stores all of its values, their position is known without touching them
+    fn fixed(&self, blocks: usize, count: usize) -> Result<Vec<LeafRef>, ErrorKind> {
+        let header = MASK_BYTES + self.header_bytes();
+        let stride = header + <N3<f32>>::SIZE * self.value_size;
+        if blocks + stride * count > self.bytes.len() {
+            return Err(unexpected_eof());
+        }
+
+        Ok((0..count)
+            .map(|i| LeafRef {
+                mask: blocks + i * stride,
+                values: blocks + i * stride + header,
+                compacted: false,
+            })
+            .collect())
+    }
+
+    /// Leaves can leave out their inactive values, each leaf header has to be read to find
+    /// the next one
+    fn walk(&self, blocks: usize, count: usize) -> Result<Vec<LeafRef>, ErrorKind> {
+        if self.file_version < OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
+            // Active masks only compress newer files
+            return self.fixed(blocks, count);
+        }
+
+        let mut refs = Vec::with_capacity(count);
+        let mut pos = blocks;
+        for _ in 0..count {
+            let mask = pos;
+            let meta_data: NodeMetaData = self.byte(mask + MASK_BYTES)?.try_into()?;
+            pos = mask + MASK_BYTES + 1;
+
+            pos += self.value_size
+                * match meta_data {
+                    NodeMetaData::NoMaskAndOneInactiveVal | NodeMetaData::MaskAndOneInactiveVal => {
+                        1
+                    }
+                    NodeMetaData::MaskAndTwoInactiveVals => 2,
+                    _ => 0,
+                };
+            if matches!(
+                meta_data,
+                NodeMetaData::MaskAndNoInactiveVals
+                    | NodeMetaData::MaskAndOneInactiveVal
+                    | NodeMetaData::MaskAndTwoInactiveVals
+            ) {
+                // Selection mask between the two inactive values
+                pos += MASK_BYTES;
+            }
+
+            let compacted = meta_data != NodeMetaData::NoMaskAndAllVals;
+            let values = match compacted {
+                true => self
+                    .bytes
+                    .get(mask..mask + MASK_BYTES)
+                    .ok_or_else(unexpected_eof)?
+                    .iter()
+                    .map(|byte| byte.count_ones() as usize)
+                    .sum(),
+                false => <N3<f32>>::SIZE,
+            };
+
+            refs.push(LeafRef {
+                mask,
+                values: pos,
+                compacted,
+            });
+            pos += values * self.value_size;
+        }
+
+        if pos > self.bytes.len() {
+            return Err(unexpected_eof());
+        }
+        Ok(refs)
+    }
+
+    fn byte(&self, pos: usize) -> Result<u8, ErrorKind> {
+        self.bytes.get(pos).copied().ok_or_else(unexpected_eof)
+    }
+}
+
+fn unexpected_eof() -> ErrorKind {
+    std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+}
+
+/// Grid whose leaf values stay in the file mapping, see [`MappedVdb`]
+pub struct MappedGrid<'a, T> {
+    bytes: &'a [u8],
+    pub descriptor: GridDescriptor,
+    pub transform: Map,
+    pub background: T,
+    half: bool,
+    leaves: HashMap<[i32; 3], LeafRef>,
+}
+
+impl<'a, T: VdbValueType> MappedGrid<'a, T> {
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn leaf_origins(&self) -> impl Iterator<Item = [i32; 3]> + '_ {
+        self.leaves.keys().copied().sorted()
+    }
+
+    /// Leaf containing the global position `p`, if there is one
+    pub fn leaf(&self, p: [i32; 3]) -> Option<MappedLeaf<'a, T>> {
+        let origin: [i32; 3] = <N3<T>>::global_to_node(p.into()).into();
+        let leaf = self.leaves.get(&origin)?;
+
+        let values = match leaf.compacted {
+            true => &self.bytes[leaf.values..],
+            false => {
+                let len = <N3<T>>::SIZE * if self.half { 2 } else { 4 };
+                &self.bytes[leaf.values..leaf.values + len]
+            }
+        };
+        Some(MappedLeaf {
+            origin,
+            value_mask: &self.bytes[leaf.mask..leaf.mask + MASK_BYTES],
+            values,
+            compacted: leaf.compacted,
+            half: self.half,
+            _value: PhantomData,
+        })
+    }
+
+    /// Value of the active voxel at `p`, decoded from the mapping
+    pub fn get_voxel(&self, p: [i32; 3]) -> Option<T> {
+        self.leaf(p)?.get(<N3<T>>::global_to_offset(p.into()))
+    }
+}
+
+/// Leaf borrowing its value mask and values from the file mapping
+pub struct MappedLeaf<'a, T> {
+    pub origin: [i32; 3],
+    value_mask: &'a [u8],
+    /// Little endian values, only the active ones when `compacted`
+    values: &'a [u8],
+    compacted: bool,
+    half: bool,
+    _value: PhantomData<T>,
+}
+
+impl<'a, T: VdbValueType> MappedLeaf<'a, T> {
+    pub fn is_active(&self, offset: usize) -> bool {
+        self.value_mask[offset >> 3] & (1 << (offset & 7)) != 0
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.value_mask
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
+    /// Value of the active voxel at `offset`
+    pub fn get(&self, offset: usize) -> Option<T> {
+        if !self.is_active(offset) {
+            return None;
+        }
+
+        let index = match self.compacted {
+            // Rank of the voxel among the active ones
+            true => {
+                let before = self.value_mask[..offset >> 3]
+                    .iter()
+                    .map(|byte| byte.count_ones() as usize)
+                    .sum::<usize>();
+                let mask = (1u8 << (offset & 7)) - 1;
+                before + (self.value_mask[offset >> 3] & mask).count_ones() as usize
+            }
+            false => offset,
+        };
+        Some(self.decode(index))
+    }
+
+    fn decode(&self, index: usize) -> T {
+        // Same conversions as the reader
+        let expected = TypeId::of::<T>();
+        if self.half {
+            let bytes = &self.values[index * 2..index * 2 + 2];
+            let value = f16::from_le_bytes([bytes[0], bytes[1]]);
+            match expected {
+                e if e == TypeId::of::<f16>() => bytemuck::cast(value),
+                e if e == TypeId::of::<f32>() => bytemuck::cast(value.to_f32()),
+                _ => T::from_f16_bites(value),
+            }
+        } else {
+            let bytes = &self.values[index * 4..index * 4 + 4];
+            let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            match expected {
+                e if e == TypeId::of::<f32>() => bytemuck::cast(value),
+                e if e == TypeId::of::<f16>() => bytemuck::cast(f16::from_f32(value)),
+                _ => T::from_f32_bites(value),
+            }
+        }
+    }
+
+    /// Decodes the whole leaf into a node like the ones [`VdbReader`] creates
+    pub fn to_node(&self) -> N3<T> {
+        let mut value_mask = [0u64; 8];
+        for (word, bytes) in value_mask.iter_mut().zip(self.value_mask.chunks_exact(8)) {
+            *word = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        let mut node = <N3<T>>::new_from_header(value_mask);
+        for (offset, data) in node.data.iter_mut().enumerate() {
+            if let Some(value) = self.get(offset) {
+                *data = LeafData::Value(value);
+            }
+        }
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::vdb::{write_vdb, WriteOptions, VDB345};
+
+    fn test_grid() -> VDB345<f32> {
+        let mut vdb = <VDB345<f32>>::new();
+        vdb.grid_descriptor.name = "mapped".to_string();
+        for (i, point) in [
+            [0, 0, 0],
+            [1, 2, 3],
+            [7, 7, 7],
+            [-9, 300, 5000],
+            [64, -1, 2],
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            vdb.set_voxel(point.into(), i as f32 + 0.25);
+        }
+        vdb
+    }
+
+    #[test]
+    fn test_mapped_grid() {
+        let builder = thread::Builder::new()
+            .name("mapped_grid_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let vdb = test_grid();
+                let path =
+                    std::env::temp_dir().join(format!("woxel_mapped_{}.vdb", std::process::id()));
+
+                for compression in [Compression::NONE, Compression::ACTIVE_MASK] {
+                    for half_float in [false, true] {
+                        let options = WriteOptions {
+                            compression,
+                            half_float,
+                            ..Default::default()
+                        };
+                        let mut b = BytesMut::new();
+                        write_vdb(&mut b, &[&vdb], &options).unwrap();
+                        fs::write(&path, &b).unwrap();
+
+                        let mapped = MappedVdb::open(&path).unwrap();
+                        let grid = mapped.grid::<f32>("mapped").unwrap();
+                        assert_eq!(grid.leaf_count(), vdb.count_nodes()[2], "{options:?}");
+                        assert_eq!(grid.transform, vdb.transform);
+
+                        for (p, value) in vdb.active_voxels() {
+                            assert_eq!(grid.get_voxel(p), Some(value), "{options:?}");
+                            assert_eq!(
+                                &grid.leaf(p).unwrap().to_node(),
+                                vdb.node3(p.into()).unwrap()
+                            );
+                        }
+                        assert_eq!(grid.get_voxel([1, 1, 1]), None);
+                        assert!(grid.leaf([1000, 0, 0]).is_none());
+                    }
+                }
+
+                let mut b = BytesMut::new();
+                write_vdb(&mut b, &[&vdb], &WriteOptions::default()).unwrap();
+                fs::write(&path, &b).unwrap();
+                let mapped = MappedVdb::open(&path).unwrap();
+                assert!(matches!(
+                    mapped.grid::<f32>("mapped"),
+                    Err(ErrorKind::CompressedGrid(_))
+                ));
+
+                fs::remove_file(&path).unwrap();
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+}
@@ -1,8 +1,16 @@
 use std::fmt::Debug;
 
 use bytemuck::Pod;
-pub trait VdbValueType =
-    From4LeBytes + Pod + Copy + CopyBytesToU32 + Debug + PartialOrd + Bounded + PartialEq;
+pub trait VdbValueType = From4LeBytes
+    + Pod
+    + Copy
+    + CopyBytesToU32
+    + Debug
+    + PartialOrd
+    + Bounded
+    + PartialEq
+    + Send
+    + Sync;
 
 mod data_structure;
 use cgmath::Bounded;
@@ -11,10 +19,31 @@ pub use data_structure::*;
 mod vdb345;
 pub use vdb345::*;
 
+mod pool;
+pub use pool::*;
+
+mod shape;
+pub use shape::*;
+
+mod edit;
+pub use edit::*;
+
 mod write;
 pub use write::*;
 
 mod read;
 pub use read::*;
 
+#[cfg(not(target_arch = "wasm32"))]
+mod mapped;
+#[cfg(not(target_arch = "wasm32"))]
+pub use mapped::*;
+
+mod progress;
+pub use progress::*;
+
 mod transform;
+pub use transform::*;
+
+mod voxelize;
+pub use voxelize::*;
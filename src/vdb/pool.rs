@@ -0,0 +1,731 @@
+use std::collections::HashMap;
+
+use half::f16;
+use itertools::Itertools;
+
+use super::{
+    InternalData, LeafData, Node, RootData, ShapedNode, ShapedTree, TileSplitError, TreeShape,
+    VdbValueType, MAX_TREE_LEVELS, N3, N4, N5, VDB345,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PoolError {
+    #[error("Node pool level {level} with {count} nodes overflows the u32 index width")]
+    IndexOverflow { level: usize, count: usize },
+    #[error("Node pool level {level} takes {bytes} bytes, more than the {max} the device binds")]
+    TooLarge {
+        level: usize,
+        bytes: usize,
+        max: usize,
+    },
+    #[error("{0}")]
+    TileSplit(#[from] TileSplitError),
+}
+
+/// Flat, node-major storage of the tree, laid out the way the GPU reads it.
+///
+/// Level 0 holds the top nodes down to the leaves on `shape.leaf_level()`, for a [`VDB345`]
+/// the N5s, N4s and N3s, and the levels past the leaves are empty. Every node occupies
+/// `shape.size(level)` consecutive entries, so entry `offset` of node `idx` lives at
+/// `idx * size + offset`. Internal entries hold the pool index of their child or the tile
+/// value, leaf entries hold the voxel value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodePool<ValueType> {
+    pub shape: TreeShape,
+    pub levels: [Vec<ValueType>; MAX_TREE_LEVELS],
+    /// Mask words of the nodes of each level in the same order, see [`mask_words`]
+    pub masks: [Vec<u32>; MAX_TREE_LEVELS],
+    pub index: PoolIndex,
+}
+
+/// How the leaf level of a pool stores its entries on the GPU.
+///
+/// Narrow encodings pack several entries into every `u32`, the lowest bits first. Active
+/// entries hold the value, either as f16 or quantized between the leaf's minimum and
+/// maximum, inactive entries hold the distance of [`VDB345::compute_sdf`] clamped to what
+/// fits in the bits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LeafEncoding {
+    #[default]
+    F32,
+    F16,
+    Unorm16,
+    Unorm8,
+}
+
+impl LeafEncoding {
+    pub const ALL: [Self; 4] = [Self::F32, Self::F16, Self::Unorm16, Self::Unorm8];
+
+    pub fn bits(self) -> usize {
+        match self {
+            Self::F32 => 32,
+            Self::F16 | Self::Unorm16 => 16,
+            Self::Unorm8 => 8,
+        }
+    }
+
+    /// Pool entries of a node on each level of `shape`, top level first and `0` past the
+    /// leaves
+    pub fn node_sizes(self, shape: &TreeShape) -> [usize; MAX_TREE_LEVELS] {
+        let leaf_level = shape.leaf_level();
+        std::array::from_fn(|level| match level {
+            _ if level < leaf_level => shape.size(level),
+            _ if level == leaf_level => shape.size(level) * self.bits() / 32,
+            _ => 0,
+        })
+    }
+
+    fn max_entry(self) -> u32 {
+        (u64::MAX >> (64 - self.bits())) as u32
+    }
+}
+
+impl std::fmt::Display for LeafEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::F32 => "f32",
+            Self::F16 => "f16",
+            Self::Unorm16 => "16 bit",
+            Self::Unorm8 => "8 bit",
+        })
+    }
+}
+
+/// A [`NodePool`] of f32 bits whose leaf level is stored in `encoding`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedPool {
+    pub pool: NodePool<u32>,
+    pub encoding: LeafEncoding,
+    /// Offset and scale of the quantized values of every leaf, `value = offset + scale * q`
+    pub leaf_ranges: Vec<[f32; 2]>,
+}
+
+/// Pool index of every node on each level, keyed by the node origin
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PoolIndex {
+    pub levels: [HashMap<[i32; 3], u32>; MAX_TREE_LEVELS],
+}
+
+impl PoolIndex {
+    pub fn get(&self, level: usize, origin: [i32; 3]) -> Option<u32> {
+        self.levels[level].get(&origin).copied()
+    }
+
+    pub fn counts(&self) -> [u32; MAX_TREE_LEVELS] {
+        std::array::from_fn(|level| self.levels[level].len() as u32)
+    }
+}
+
+/// Mask words of a node on each level of `shape` in [`NodePool::masks`], `0` past the leaves.
+///
+/// Internal nodes store their child mask and then their value mask, leaves only their value
+/// mask.
+pub fn mask_words(shape: &TreeShape) -> [usize; MAX_TREE_LEVELS] {
+    let leaf_level = shape.leaf_level();
+    std::array::from_fn(|level| match level {
+        _ if level < leaf_level => 2 * shape.size(level) / 32,
+        _ if level == leaf_level => shape.size(level) / 32,
+        _ => 0,
+    })
+}
+
+impl<ValueType: VdbValueType> NodePool<ValueType> {
+    /// Bytes used by each level, top level first
+    pub fn level_bytes(&self) -> [usize; MAX_TREE_LEVELS] {
+        std::array::from_fn(|level| self.levels[level].len() * std::mem::size_of::<ValueType>())
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.level_bytes().iter().sum()
+    }
+}
+
+impl<ValueType: VdbValueType> VDB345<ValueType> {
+    /// Number the nodes of every level.
+    ///
+    /// Nodes are numbered depth first with the root entries sorted by key, which is the same
+    /// order used by [`VDB345::masks`].
+    pub fn pool_index(&self) -> Result<PoolIndex, PoolError> {
+        let mut index = PoolIndex::default();
+
+        for (&origin5, root_data) in self.root.map.iter().sorted_by_key(|(key, _)| *key) {
+            let RootData::Node(node5) = root_data else {
+                // TODO: handle node5 tiles
+                continue;
+            };
+            insert_checked(&mut index, &TreeShape::VDB345, 0, origin5)?;
+
+            for (offset5, node5_data) in node5.data.iter().enumerate() {
+                let InternalData::Node(node4) = node5_data else {
+                    continue;
+                };
+                let origin4 = child_origin::<N5<ValueType>>(origin5, offset5);
+                insert_checked(&mut index, &TreeShape::VDB345, 1, origin4)?;
+
+                for (offset4, node4_data) in node4.data.iter().enumerate() {
+                    let InternalData::Node(_) = node4_data else {
+                        continue;
+                    };
+                    insert_checked(
+                        &mut index,
+                        &TreeShape::VDB345,
+                        2,
+                        child_origin::<N4<ValueType>>(origin4, offset4),
+                    )?;
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Pack the tree into a [`NodePool`]. Every entry is written exactly once.
+    pub fn pool(&self) -> Result<NodePool<ValueType>, PoolError> {
+        let index = self.pool_index()?;
+        let levels = std::array::from_fn(|level| self.pack_level(&index, level));
+
+        Ok(NodePool {
+            shape: TreeShape::VDB345,
+            levels,
+            masks: self.pack_masks(&index),
+            index,
+        })
+    }
+
+    fn pack_level(&self, index: &PoolIndex, level: usize) -> Vec<ValueType> {
+        let size = TreeShape::VDB345.size(level);
+        let mut entries = vec![ValueType::zeroed(); index.levels[level].len() * size];
+
+        for (&origin, &idx) in &index.levels[level] {
+            let start = idx as usize * size;
+            self.pack_node(index, level, origin, &mut entries[start..start + size]);
+        }
+
+        entries
+    }
+
+    /// Pool entries of the node at `origin` on `level`, used to patch a single node on the GPU
+    pub fn pool_brick(&self, index: &PoolIndex, level: usize, origin: [i32; 3]) -> Vec<ValueType> {
+        let mut brick = vec![ValueType::zeroed(); TreeShape::VDB345.size(level)];
+        self.pack_node(index, level, origin, &mut brick);
+        brick
+    }
+
+    /// Mask words of the node at `origin` on `level` as [`NodePool::masks`] stores them, used
+    /// to patch a single node on the GPU. Zeroed when there is no such node.
+    pub fn node_masks(&self, level: usize, origin: [i32; 3]) -> Vec<u32> {
+        let p = origin.into();
+        let masks = match level {
+            0 => self
+                .node5(p)
+                .map(|node5| [&node5.child_mask[..], &node5.value_mask].concat()),
+            1 => self
+                .node4(p)
+                .map(|node4| [&node4.child_mask[..], &node4.value_mask].concat()),
+            _ => self.node3(p).map(|node3| node3.value_mask.to_vec()),
+        };
+
+        match masks {
+            Some(masks) => bytemuck::cast_slice(&masks).to_vec(),
+            None => vec![0; mask_words(&TreeShape::VDB345)[level]],
+        }
+    }
+
+    fn pack_masks(&self, index: &PoolIndex) -> [Vec<u32>; MAX_TREE_LEVELS] {
+        let words = mask_words(&TreeShape::VDB345);
+        std::array::from_fn(|level| {
+            let mut masks = vec![0; index.levels[level].len() * words[level]];
+            for (&origin, &idx) in &index.levels[level] {
+                let start = idx as usize * words[level];
+                masks[start..start + words[level]].copy_from_slice(&self.node_masks(level, origin));
+            }
+            masks
+        })
+    }
+
+    /// Leaf values of this grid at the leaves of another grid of `shape` numbered by `index`,
+    /// in pool order. Gives the colours of a grid with the same topology, leaves and voxels
+    /// this grid lacks are zero.
+    pub fn leaf_bricks(&self, index: &PoolIndex, shape: &TreeShape) -> Vec<ValueType> {
+        let leaf_level = shape.leaf_level();
+        let size = shape.size(leaf_level);
+        let mut bricks = vec![ValueType::zeroed(); index.levels[leaf_level].len() * size];
+
+        for (&origin, &idx) in &index.levels[leaf_level] {
+            let brick = &mut bricks[idx as usize * size..(idx as usize + 1) * size];
+            if *shape == TreeShape::VDB345 {
+                let Some(node3) = self.node3(origin.into()) else {
+                    continue;
+                };
+                for (entry, node3_data) in brick.iter_mut().zip(node3.data.iter()) {
+                    if let &LeafData::Value(value) = node3_data {
+                        *entry = value;
+                    }
+                }
+                continue;
+            }
+
+            // Leaves of other shapes overlap several N3s, or only part of one
+            for (offset, entry) in brick.iter_mut().enumerate() {
+                let p = shape.child_origin(leaf_level, origin, offset).into();
+                let Some(node3) = self.node3(p) else {
+                    continue;
+                };
+                if let LeafData::Value(value) = node3.data[<N3<ValueType>>::global_to_offset(p)] {
+                    *entry = value;
+                }
+            }
+        }
+
+        bricks
+    }
+
+    fn pack_node(
+        &self,
+        index: &PoolIndex,
+        level: usize,
+        origin: [i32; 3],
+        brick: &mut [ValueType],
+    ) {
+        let p = origin.into();
+        match level {
+            0 => {
+                let Some(node5) = self.node5(p) else { return };
+                for (offset5, node5_data) in node5.data.iter().enumerate() {
+                    brick[offset5] = match node5_data {
+                        InternalData::Node(_) => {
+                            let origin4 = child_origin::<N5<ValueType>>(origin, offset5);
+                            pool_entry(index.get(1, origin4).unwrap())
+                        }
+                        &InternalData::Tile(node4_tile) => pool_entry(node4_tile),
+                    };
+                }
+            }
+            1 => {
+                let Some(node4) = self.node4(p) else { return };
+                for (offset4, node4_data) in node4.data.iter().enumerate() {
+                    brick[offset4] = match node4_data {
+                        InternalData::Node(_) => {
+                            let origin3 = child_origin::<N4<ValueType>>(origin, offset4);
+                            pool_entry(index.get(2, origin3).unwrap())
+                        }
+                        &InternalData::Tile(node3_tile) => pool_entry(node3_tile),
+                    };
+                }
+            }
+            _ => {
+                let Some(node3) = self.node3(p) else { return };
+                for (offset3, node3_data) in node3.data.iter().enumerate() {
+                    brick[offset3] = match node3_data {
+                        LeafData::Value(value) => *value,
+                        LeafData::Tile(offset) => pool_entry(*offset as u32),
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl VDB345<u32> {
+    /// Pack the tree like [`VDB345::pool`], with the f32 values of the leaves stored in
+    /// `encoding`
+    pub fn encoded_pool(&self, encoding: LeafEncoding) -> Result<EncodedPool, PoolError> {
+        let index = self.pool_index()?;
+        let words = encoding.node_sizes(&TreeShape::VDB345)[2];
+        let leaf_count = index.levels[2].len();
+
+        let mut leaves = vec![0; leaf_count * words];
+        let mut leaf_ranges = vec![[0., 1.]; leaf_count];
+        for (&origin, &idx) in &index.levels[2] {
+            let idx = idx as usize;
+            let leaf = &mut leaves[idx * words..(idx + 1) * words];
+            leaf_ranges[idx] = self.encode_leaf(origin, encoding, leaf);
+        }
+
+        let [n5s, n4s] = [0, 1].map(|level| self.pack_level(&index, level));
+        Ok(EncodedPool {
+            pool: NodePool {
+                shape: TreeShape::VDB345,
+                levels: [n5s, n4s, leaves, vec![]],
+                masks: self.pack_masks(&index),
+                index,
+            },
+            encoding,
+            leaf_ranges,
+        })
+    }
+
+    /// Encoded entries and range of the leaf at `origin`, used to patch a single leaf on the
+    /// GPU
+    pub fn encoded_leaf(&self, origin: [i32; 3], encoding: LeafEncoding) -> (Vec<u32>, [f32; 2]) {
+        let mut words = vec![0; encoding.node_sizes(&TreeShape::VDB345)[2]];
+        let range = self.encode_leaf(origin, encoding, &mut words);
+        (words, range)
+    }
+
+    fn encode_leaf(&self, origin: [i32; 3], encoding: LeafEncoding, words: &mut [u32]) -> [f32; 2] {
+        match self.node3(origin.into()) {
+            Some(node3) => encode_entries(&node3.data, encoding, words),
+            None => [0., 1.],
+        }
+    }
+}
+
+impl<ValueType: VdbValueType> ShapedTree<ValueType> {
+    /// Number the nodes of every level like [`VDB345::pool_index`], depth first with the top
+    /// nodes in origin order and the children in offset order
+    pub fn pool_index(&self) -> Result<PoolIndex, PoolError> {
+        let mut index = PoolIndex::default();
+
+        let mut stack = self.nodes.values().rev().collect_vec();
+        while let Some(node) = stack.pop() {
+            insert_checked(&mut index, &self.shape, node.level, node.origin)?;
+            stack.extend(node.children.values().rev());
+        }
+
+        Ok(index)
+    }
+}
+
+impl ShapedTree<u32> {
+    /// Pack the tree in its own shape like [`VDB345::encoded_pool`]. Inactive entries hold
+    /// the distances of [`ShapedNode::distances`], root tiles are left out like the ones of
+    /// a [`VDB345`].
+    pub fn encoded_pool(&self, encoding: LeafEncoding) -> Result<EncodedPool, PoolError> {
+        let shape = self.shape;
+        let index = self.pool_index()?;
+        let counts = index.counts().map(|count| count as usize);
+        let sizes = encoding.node_sizes(&shape);
+        let words = mask_words(&shape);
+
+        let mut levels: [Vec<u32>; MAX_TREE_LEVELS] =
+            std::array::from_fn(|level| vec![0; counts[level] * sizes[level]]);
+        let mut masks: [Vec<u32>; MAX_TREE_LEVELS] =
+            std::array::from_fn(|level| vec![0; counts[level] * words[level]]);
+        let mut leaf_ranges = vec![[0., 1.]; counts[shape.leaf_level()]];
+
+        let mut stack = self.nodes.values().collect_vec();
+        while let Some(node) = stack.pop() {
+            let level = node.level;
+            let idx = index.get(level, node.origin).unwrap() as usize;
+            let entries = &mut levels[level][idx * sizes[level]..(idx + 1) * sizes[level]];
+            let distances = node.distances(&shape);
+
+            if node.is_leaf(&shape) {
+                let data = node
+                    .values
+                    .iter()
+                    .zip(distances)
+                    .zip(node.value_mask.iter())
+                    .map(|((&value, dist), active)| match *active {
+                        true => LeafData::Value(value),
+                        false => LeafData::Tile(dist as usize),
+                    })
+                    .collect_vec();
+                leaf_ranges[idx] = encode_entries(&data, encoding, entries);
+            } else {
+                for (offset, entry) in entries.iter_mut().enumerate() {
+                    *entry = match node.children.get(&offset) {
+                        Some(child) => index.get(level + 1, child.origin).unwrap(),
+                        None => distances[offset],
+                    };
+                }
+            }
+
+            masks[level][idx * words[level]..(idx + 1) * words[level]]
+                .copy_from_slice(&shaped_masks(node, &shape));
+            stack.extend(node.children.values());
+        }
+
+        Ok(EncodedPool {
+            pool: NodePool {
+                shape,
+                levels,
+                masks,
+                index,
+            },
+            encoding,
+            leaf_ranges,
+        })
+    }
+}
+
+/// Mask words of a node of a [`ShapedTree`] as [`NodePool::masks`] stores them
+fn shaped_masks<ValueType: VdbValueType>(
+    node: &ShapedNode<ValueType>,
+    shape: &TreeShape,
+) -> Vec<u32> {
+    let masks = match node.is_leaf(shape) {
+        true => node.value_mask.as_raw_slice().to_vec(),
+        false => [
+            node.child_mask.as_raw_slice(),
+            node.value_mask.as_raw_slice(),
+        ]
+        .concat(),
+    };
+    bytemuck::cast_slice(&masks).to_vec()
+}
+
+/// Encodes the entries of a leaf into `words`, returning the range of its quantized values
+fn encode_entries(
+    entries: &[LeafData<u32>],
+    encoding: LeafEncoding,
+    words: &mut [u32],
+) -> [f32; 2] {
+    let (min, max) = entries
+        .iter()
+        .filter_map(|data| match data {
+            &LeafData::Value(value) => Some(f32::from_bits(value)),
+            LeafData::Tile(_) => None,
+        })
+        .filter(|value| value.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+    let max_entry = encoding.max_entry();
+    let range = match encoding {
+        LeafEncoding::Unorm16 | LeafEncoding::Unorm8 if min < max => {
+            [min, (max - min) / max_entry as f32]
+        }
+        LeafEncoding::Unorm16 | LeafEncoding::Unorm8 if min == max => [min, 0.],
+        _ => [0., 1.],
+    };
+
+    let bits = encoding.bits();
+    let per_word = 32 / bits;
+    for (offset, data) in entries.iter().enumerate() {
+        let entry = match (data, encoding) {
+            (&LeafData::Tile(dist), _) => dist.min(max_entry as usize) as u32,
+            (&LeafData::Value(value), LeafEncoding::F32) => value,
+            (&LeafData::Value(value), LeafEncoding::F16) => {
+                f16::from_f32(f32::from_bits(value)).to_bits() as u32
+            }
+            (&LeafData::Value(value), _) if range[1] > 0. => {
+                let q = (f32::from_bits(value) - range[0]) / range[1];
+                q.round().clamp(0., max_entry as f32) as u32
+            }
+            (LeafData::Value(_), _) => 0,
+        };
+        words[offset / per_word] |= entry << ((offset % per_word) * bits);
+    }
+
+    range
+}
+
+fn pool_entry<ValueType: VdbValueType>(entry: u32) -> ValueType {
+    ValueType::from_4_le_bytes(entry.to_le_bytes())
+}
+
+/// Global origin of the child at `offset` in the node at `origin`
+pub(crate) fn child_origin<N: Node>(origin: [i32; 3], offset: usize) -> [i32; 3] {
+    let child = N::offset_to_child(offset).map(|c| (c << N::CHILD_TOTAL_LOG2_D) as i32);
+    [
+        origin[0] + child.x,
+        origin[1] + child.y,
+        origin[2] + child.z,
+    ]
+}
+
+fn insert_checked(
+    index: &mut PoolIndex,
+    shape: &TreeShape,
+    level: usize,
+    origin: [i32; 3],
+) -> Result<(), PoolError> {
+    let count = index.levels[level].len();
+    // Every entry of the level has to be addressable with a u32 on the GPU
+    let entries = (count + 1).checked_mul(shape.size(level));
+    if !entries.is_some_and(|e| e <= u32::MAX as usize) {
+        return Err(PoolError::IndexOverflow { level, count });
+    }
+    index.levels[level].insert(origin, count as u32);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::vdb::GlobalCoordinates;
+
+    #[test]
+    fn pool_follows_tree_test() {
+        let builder = thread::Builder::new()
+            .name("pool_follows_tree_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u32>>::new();
+                let points = [[0, 0, 0], [123, 78, 3], [-34, 123, 46], [5000, 79, 28]];
+                for (i, &point) in points.iter().enumerate() {
+                    vdb.set_voxel(point.into(), i as u32 + 1);
+                }
+
+                let pool = vdb.pool().unwrap();
+                assert_eq!(pool.index.counts(), [3, 3, 4, 0]);
+                assert_eq!(
+                    pool.level_bytes(),
+                    [3 * 32768 * 4, 3 * 4096 * 4, 4 * 512 * 4, 0]
+                );
+
+                for (i, &point) in points.iter().enumerate() {
+                    let p: GlobalCoordinates = point.into();
+                    let origin5: [i32; 3] = <N5<u32>>::global_to_node(p).into();
+                    let n5_idx = vdb.origins().iter().position(|&o| o == origin5);
+                    let n5_idx = n5_idx.unwrap();
+
+                    let n4_idx =
+                        pool.levels[0][n5_idx * 32768 + <N5<u32>>::global_to_offset(p)] as usize;
+                    let n3_idx =
+                        pool.levels[1][n4_idx * 4096 + <N4<u32>>::global_to_offset(p)] as usize;
+                    let value = pool.levels[2][n3_idx * 512 + <N3<u32>>::global_to_offset(p)];
+
+                    assert_eq!(value, i as u32 + 1);
+
+                    let origin3 = <N3<u32>>::global_to_node(p).into();
+                    let brick = vdb.pool_brick(&pool.index, 2, origin3);
+                    assert_eq!(brick[..], pool.levels[2][n3_idx * 512..(n3_idx + 1) * 512]);
+                    let masks = vdb.node_masks(2, origin3);
+                    assert_eq!(masks[..], pool.masks[2][n3_idx * 16..(n3_idx + 1) * 16]);
+                    let offset = <N3<u32>>::global_to_offset(p);
+                    assert_eq!(masks[offset / 32] >> (offset % 32) & 1, 1);
+                }
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn leaf_bricks_test() {
+        let builder = thread::Builder::new()
+            .name("leaf_bricks_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u32>>::new();
+                let mut colors = <VDB345<u32>>::new();
+                let points = [[0, 0, 0], [123, 78, 3], [5000, 79, 28]];
+                for (i, &point) in points.iter().enumerate() {
+                    vdb.set_voxel(point.into(), 1f32.to_bits());
+                    // The last voxel has no colour
+                    if i < 2 {
+                        colors.set_voxel(point.into(), i as u32 + 10);
+                    }
+                }
+
+                let index = vdb.pool_index().unwrap();
+                let bricks = colors.leaf_bricks(&index, &TreeShape::VDB345);
+                assert_eq!(bricks.len(), 3 * 512);
+                for (i, &point) in points.iter().enumerate() {
+                    let p: GlobalCoordinates = point.into();
+                    let origin3 = <N3<u32>>::global_to_node(p).into();
+                    let n3_idx = index.get(2, origin3).unwrap() as usize;
+                    let color = bricks[n3_idx * 512 + <N3<u32>>::global_to_offset(p)];
+                    assert_eq!(color, if i < 2 { i as u32 + 10 } else { 0 });
+                }
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn encoded_pool_test() {
+        let builder = thread::Builder::new()
+            .name("encoded_pool_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u32>>::new();
+                let values = [-2.5f32, 0.25, 1., 7.5];
+                for (i, &value) in values.iter().enumerate() {
+                    vdb.set_voxel([i as i32, 0, 0].into(), value.to_bits());
+                }
+                vdb.compute_sdf();
+
+                let pool = vdb.pool().unwrap();
+                for encoding in LeafEncoding::ALL {
+                    let encoded = vdb.encoded_pool(encoding).unwrap();
+                    assert_eq!(encoded.pool.levels[..2], pool.levels[..2]);
+                    assert_eq!(encoded.pool.levels[2].len(), 512 * encoding.bits() / 32);
+
+                    let bits = encoding.bits();
+                    let per_word = 32 / bits;
+                    let [offset, scale] = encoded.leaf_ranges[0];
+                    for (i, &value) in values.iter().enumerate() {
+                        let entry = <N3<u32>>::global_to_offset([i as i32, 0, 0].into());
+                        let word = encoded.pool.levels[2][entry / per_word];
+                        let q = (word >> ((entry % per_word) * bits)) & encoding.max_entry();
+                        let decoded = match encoding {
+                            LeafEncoding::F32 => f32::from_bits(q),
+                            LeafEncoding::F16 => f16::from_bits(q as u16).to_f32(),
+                            _ => offset + scale * q as f32,
+                        };
+                        let tolerance = (values[3] - values[0]) / encoding.max_entry() as f32;
+                        assert!(
+                            (decoded - value).abs() <= tolerance,
+                            "{encoding}: {decoded}"
+                        );
+                    }
+
+                    let (brick, range) = vdb.encoded_leaf([0, 0, 0], encoding);
+                    assert_eq!(brick, encoded.pool.levels[2]);
+                    assert_eq!(range, encoded.leaf_ranges[0]);
+                }
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn shaped_pool_test() {
+        let builder = thread::Builder::new()
+            .name("shaped_pool_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let shape = TreeShape::new(&[4, 3, 2]).unwrap();
+                let mut tree = ShapedTree::new(shape, 0);
+                let points = [[0, 0, 0], [5, 70, 3], [-34, 123, 46], [600, 9, 28]];
+                for (i, &point) in points.iter().enumerate() {
+                    tree.set_voxel(point, (i as f32 + 1.).to_bits());
+                }
+                let mut colors = <VDB345<u32>>::new();
+                colors.set_voxel(points[1].into(), 7);
+
+                let encoded = tree.encoded_pool(LeafEncoding::F32).unwrap();
+                let pool = &encoded.pool;
+                // The first two points share a top node
+                assert_eq!(pool.index.counts(), [3, 4, 4, 0]);
+                assert_eq!(
+                    pool.level_bytes(),
+                    [3 * 4096 * 4, 4 * 512 * 4, 4 * 64 * 4, 0]
+                );
+                let bricks = colors.leaf_bricks(&pool.index, &shape);
+
+                // Walk down from the top nodes like the shader does
+                for (i, &p) in points.iter().enumerate() {
+                    let origin = p.map(|c| (c >> 9) << 9);
+                    let mut idx = pool.index.get(0, origin).unwrap() as usize;
+                    for level in 0..2 {
+                        let offset = shape.offset(level, p);
+                        let words = &pool.masks[level][idx * 2 * shape.size(level) / 32..];
+                        assert_eq!(words[offset / 32] >> (offset % 32) & 1, 1);
+                        idx = pool.levels[level][idx * shape.size(level) + offset] as usize;
+                    }
+
+                    let offset = shape.offset(2, p);
+                    let value = pool.levels[2][idx * 64 + offset];
+                    assert_eq!(f32::from_bits(value), i as f32 + 1.);
+                    assert_eq!(pool.masks[2][idx * 2 + offset / 32] >> (offset % 32) & 1, 1);
+                    assert_eq!(bricks[idx * 64 + offset], if i == 1 { 7 } else { 0 });
+
+                    // The neighbour of the voxel is empty and next to it
+                    let neighbour = offset ^ 1;
+                    assert_eq!(pool.levels[2][idx * 64 + neighbour], 1);
+                }
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+/// Progress of a load running on another thread, along with a flag to cancel it.
+///
+/// Totals grow as the work is discovered, so the fraction done can move backwards when a
+/// new grid is opened.
+#[derive(Debug, Default)]
+pub struct Progress {
+    bytes_read: AtomicU64,
+    bytes_total: AtomicU64,
+    nodes_done: AtomicU64,
+    nodes_total: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    pub fn add_bytes_total(&self, bytes: u64) {
+        self.bytes_total.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_nodes_total(&self, nodes: u64) {
+        self.nodes_total.fetch_add(nodes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn node_done(&self) {
+        self.nodes_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Bytes read and bytes to read
+    pub fn bytes(&self) -> (u64, u64) {
+        (
+            self.bytes_read.load(Ordering::Relaxed),
+            self.bytes_total.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Nodes processed and nodes to process
+    pub fn nodes(&self) -> (u64, u64) {
+        (
+            self.nodes_done.load(Ordering::Relaxed),
+            self.nodes_total.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Reading and processing count as one half each
+    pub fn fraction(&self) -> f32 {
+        let ratio = |(done, total): (u64, u64)| match total {
+            0 => 0.,
+            total => done.min(total) as f32 / total as f32,
+        };
+        (ratio(self.bytes()) + ratio(self.nodes())) / 2.
+    }
+
+    /// Ask the load to stop, it notices on its next read or node
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts the bytes read through it in `progress`, and fails every read once the load is
+/// cancelled
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a Progress,
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    pub fn new(inner: R, progress: &'a Progress) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.progress.is_cancelled() {
+            return Err(io::Error::other("Load cancelled"));
+        }
+
+        let read = self.inner.read(buf)?;
+        self.progress
+            .bytes_read
+            .fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, thread};
+
+    use super::*;
+    use crate::vdb::{ErrorKind, VDB345};
+
+    #[test]
+    fn test_progress_reader() {
+        let progress = Progress::default();
+        progress.add_bytes_total(8);
+        let mut reader = ProgressReader::new(Cursor::new(vec![7u8; 8]), &progress);
+
+        let mut buf = [0; 6];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(progress.bytes(), (6, 8));
+        assert_eq!(progress.fraction(), 6. / 8. / 2.);
+
+        progress.cancel();
+        assert!(reader.read(&mut buf).is_err());
+        assert_eq!(progress.bytes(), (6, 8));
+    }
+
+    #[test]
+    fn test_cancel_sdf() {
+        let builder = thread::Builder::new()
+            .name("cancel_sdf_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u32>>::new();
+                vdb.set_voxel([0, 0, 0].into(), 1);
+                vdb.set_voxel([200, 0, 0].into(), 1);
+
+                let progress = Progress::default();
+                vdb.clone().compute_sdf_with_progress(&progress).unwrap();
+                // Both passes visit both Node4s
+                assert_eq!(progress.nodes(), (4, 4));
+
+                let progress = Progress::default();
+                progress.cancel();
+                assert!(matches!(
+                    vdb.compute_sdf_with_progress(&progress),
+                    Err(ErrorKind::Cancelled)
+                ));
+                assert_eq!(progress.nodes().0, 0);
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+}
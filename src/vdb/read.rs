@@ -1,26 +1,35 @@
 use std::{
     collections::HashMap,
-    io::{Read, Seek, SeekFrom},
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    num::NonZeroUsize,
+    panic,
     string::FromUtf8Error,
+    thread,
 };
 
 use bitvec::prelude::*;
 use blosc_src::blosc_cbuffer_sizes;
-use bytemuck::{bytes_of_mut, cast_slice_mut, Zeroable};
+use bytemuck::{bytes_of_mut, cast_slice_mut, Pod, Zeroable};
 use byteorder::{LittleEndian, ReadBytesExt};
+use bytes::Bytes;
 use cgmath::Vector3;
 use half::f16;
 use log::{trace, warn};
 
 use crate::vdb::{
     transform::Map, ArchiveHeader, Compression, GridDescriptor, InternalData, LeafData, Metadata,
-    MetadataValue, Node, NodeHeader, NodeMetaData, Root345, RootData, RootNode, N3, N4, N5, VDB345,
+    MetadataValue, Node, NodeHeader, NodeMetaData, Root345, RootData, RootNode, ShapedNode,
+    ShapedTree, TileSplitError, TreeShape, N3, N4, N5, VDB345,
 };
 
-use super::VdbValueType;
+use super::{pool::child_origin, VdbValueType};
 
 type Result<T> = std::result::Result<T, ErrorKind>;
 
+/// Leaves read for each thread before they are decompressed together, bounds the compressed
+/// data held at once
+const LEAVES_PER_THREAD: usize = 256;
+
 const OPENVDB_MIN_SUPPORTED_VERSION: u32 = OPENVDB_FILE_VERSION_BOOST_UUID;
 
 const OPENVDB_FILE_VERSION_PER_GRID_COMPRESSION: u32 = 223;
@@ -50,16 +59,85 @@ pub enum ErrorKind {
     InvalidBloscData,
     #[error("Unexpected Mask length")]
     UnexpectedMaskLength,
+    #[error("Load cancelled")]
+    Cancelled,
+    #[error("Grid {0} was already passed by the streaming reader")]
+    GridPassed(String),
+    #[error("Grid {0} has no offsets and a type that can't be skipped")]
+    UnskippableGrid(String),
+    #[error("Grid {0} is compressed and can't be memory mapped")]
+    CompressedGrid(String),
+    #[error("The archive has no grid offsets")]
+    NoGridOffsets,
+    #[error("Grid {0} isn't a 5-4-3 tree and can't be memory mapped")]
+    UnmappableTreeShape(String),
+    #[error("Trees with {0} buffers aren't supported")]
+    UnsupportedBufferCount(u32),
+    #[error("{0}")]
+    TileSplit(#[from] TileSplitError),
 }
 
 pub struct VdbReader<R: Read + Seek> {
     reader: R,
     pub header: ArchiveHeader,
+    /// Every grid of the archive, in streaming mode only the grids reached so far
     pub grid_descriptors: HashMap<String, GridDescriptor>,
+    /// Set when the archive is read in order instead of seeking to the grids
+    stream: Option<StreamState>,
+    /// Threads decompressing leaves, see [`VdbReader::set_threads`]
+    threads: usize,
+}
+
+/// Topology of a grid without its nodes, see [`VdbReader::read_leaf_origins`]
+pub(super) struct GridTopology<T> {
+    pub transform: Map,
+    pub background: T,
+    /// Leaf origins in block order
+    pub leaves: Vec<[i32; 3]>,
+    /// Position of the first leaf block
+    pub blocks: u64,
+}
+
+/// Where a streaming reader is in the archive
+struct StreamState {
+    /// Grids whose descriptor wasn't read yet
+    grids_left: u32,
+    /// Grid whose descriptor was just read, the reader sits at its transform
+    current: Option<GridDescriptor>,
+}
+
+impl<R: Read> VdbReader<ForwardReader<R>> {
+    /// Reads the archive in order without ever seeking back, for sources like stdin,
+    /// decompression streams or network responses.
+    ///
+    /// Grids have to be read in the order they are stored, the grids before them are skipped.
+    pub fn streaming(reader: R) -> Result<Self> {
+        Self::open(ForwardReader::new(reader), true)
+    }
+}
+
+impl<'a> VdbReader<Cursor<&'a [u8]>> {
+    pub fn from_slice(bytes: &'a [u8]) -> Result<Self> {
+        Self::new(Cursor::new(bytes))
+    }
+}
+
+impl VdbReader<Cursor<Bytes>> {
+    pub fn from_bytes(bytes: Bytes) -> Result<Self> {
+        Self::new(Cursor::new(bytes))
+    }
 }
 
 impl<R: Read + Seek> VdbReader<R> {
-    pub fn new(mut reader: R) -> Result<Self> {
+    /// Reads the header and every grid descriptor.
+    ///
+    /// Archives written without grid offsets can't be seeked through, they are read in order
+    /// as with [`VdbReader::streaming`].
+    pub fn new(reader: R) -> Result<Self> {
+        Self::open(reader, false)
+    }
+
+    fn open(mut reader: R, streaming: bool) -> Result<Self> {
         let magic = reader.read_u64::<LittleEndian>()?;
         if magic != 0x56444220 {
             return Err(ErrorKind::MagicMismatch);
@@ -111,16 +189,46 @@ impl<R: Read + Seek> VdbReader<R> {
             meta_data,
         };
 
+        if streaming || !header.has_grid_offsets {
+            return Ok(Self {
+                reader,
+                stream: Some(StreamState {
+                    grids_left: header.grid_number,
+                    current: None,
+                }),
+                header,
+                grid_descriptors: HashMap::new(),
+                threads: default_threads(),
+            });
+        }
+
         let grid_descriptors = Self::read_grid_descriptors(&mut reader, &header)?;
 
         Ok(Self {
             reader,
             header,
             grid_descriptors,
+            stream: None,
+            threads: default_threads(),
         })
     }
 
+    /// Caps the threads decompressing leaf buffers, every core is used by default and `1`
+    /// decompresses them on the calling thread. The grids read are the same either way.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// The archive is read in order, grids stored before the last grid reached can't be read
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
     pub fn read_vdb345_grid<T: VdbValueType>(&mut self, name: &str) -> Result<VDB345<T>> {
+        if self.stream.is_some() {
+            return self.stream_vdb345_grid(name);
+        }
+
         let grid_descriptor = self.grid_descriptors.get(name).cloned();
         let grid_descriptor =
             grid_descriptor.ok_or_else(|| ErrorKind::InvalidGridName(name.to_owned()))?;
@@ -131,15 +239,101 @@ impl<R: Read + Seek> VdbReader<R> {
         }
         let _ = Self::read_metadata(&mut self.reader)?;
 
+        self.read_grid_body(&grid_descriptor)
+    }
+
+    /// Reads the transform and tree of a grid, the reader must be right after its metadata
+    fn read_grid_body<T: VdbValueType>(
+        &mut self,
+        grid_descriptor: &GridDescriptor,
+    ) -> Result<VDB345<T>> {
         // @TODO: Make a Grid struct to store the descriptors and the VDB
-        let _transform = Self::read_transform(&mut self.reader)?;
+        let transform = Self::read_transform(&mut self.reader)?;
 
-        let mut vdb = self.read_tree_topology::<T>(&grid_descriptor)?;
-        self.read_tree_data::<T>(&grid_descriptor, &mut vdb)?;
+        let shape = grid_descriptor.tree_shape();
+        let mut vdb = if shape == TreeShape::VDB345 {
+            let (mut vdb, node5_order) = self.read_tree_topology::<T>(grid_descriptor)?;
+            self.read_tree_data::<T>(grid_descriptor, &mut vdb, &node5_order)?;
+            vdb
+        } else {
+            let mut vdb = self
+                .read_shaped_tree::<T>(grid_descriptor, shape)?
+                .into_vdb345()?;
+            vdb.grid_descriptor = grid_descriptor.clone();
+            vdb
+        };
+        vdb.transform = transform;
 
         Ok(vdb)
     }
 
+    /// Skips grids until `name`, looking for a grid that isn't in the archive skips them all
+    fn stream_vdb345_grid<T: VdbValueType>(&mut self, name: &str) -> Result<VDB345<T>> {
+        loop {
+            let stream = self.stream.as_mut().unwrap();
+            match &stream.current {
+                Some(current) if current.name == name => {
+                    let grid_descriptor = stream.current.take().unwrap();
+                    let vdb = self.read_grid_body(&grid_descriptor)?;
+                    if self.header.has_grid_offsets {
+                        // Skip trailing data of the grid, if any
+                        self.reader.seek(SeekFrom::Start(grid_descriptor.end_pos))?;
+                    }
+                    return Ok(vdb);
+                }
+                _ if self.grid_descriptors.contains_key(name) => {
+                    return Err(ErrorKind::GridPassed(name.to_owned()));
+                }
+                _ => {}
+            }
+
+            if self.next_grid_descriptor()?.is_none() {
+                return Err(ErrorKind::InvalidGridName(name.to_owned()));
+            }
+        }
+    }
+
+    /// In streaming mode, skips the rest of the current grid and reads the descriptor of the
+    /// next one, which [`VdbReader::read_vdb345_grid`] can then read.
+    ///
+    /// Returns `None` after the last grid, and always when not streaming since every
+    /// descriptor is read up front.
+    pub fn next_grid_descriptor(&mut self) -> Result<Option<GridDescriptor>> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(None);
+        };
+
+        if let Some(current) = stream.current.take() {
+            self.skip_grid_body(&current)?;
+        }
+
+        let stream = self.stream.as_mut().unwrap();
+        if stream.grids_left == 0 {
+            return Ok(None);
+        }
+        stream.grids_left -= 1;
+
+        let grid_descriptor = Self::read_grid_descriptor(&mut self.reader, &self.header)?;
+        self.grid_descriptors
+            .insert(grid_descriptor.name.clone(), grid_descriptor.clone());
+        self.stream.as_mut().unwrap().current = Some(grid_descriptor.clone());
+
+        Ok(Some(grid_descriptor))
+    }
+
+    /// Moves past a grid whose metadata was just read, without offsets that means parsing it
+    fn skip_grid_body(&mut self, grid_descriptor: &GridDescriptor) -> Result<()> {
+        if self.header.has_grid_offsets {
+            self.reader.seek(SeekFrom::Start(grid_descriptor.end_pos))?;
+            return Ok(());
+        }
+
+        match grid_descriptor.grid_type.starts_with("Tree_float_") {
+            true => self.read_grid_body::<f32>(grid_descriptor).map(|_| ()),
+            false => Err(ErrorKind::UnskippableGrid(grid_descriptor.name.clone())),
+        }
+    }
+
     fn read_transform(reader: &mut R) -> Result<Map> {
         let transform_name = read_len_string(reader)?;
 
@@ -167,36 +361,12 @@ impl<R: Read + Seek> VdbReader<R> {
         reader: &mut R,
         header: &ArchiveHeader,
     ) -> Result<HashMap<String, GridDescriptor>> {
-        // Guaranteed by minimum file version
-        assert!(header.has_grid_offsets);
-
         let mut grid_descriptors = HashMap::new();
 
         for _ in 0..header.grid_number {
-            let name = read_len_string(reader)?;
-            let grid_type = read_len_string(reader)?;
-            let instance_parent = read_len_string(reader)?;
-
-            let grid_pos = reader.read_u64::<LittleEndian>()?;
-            let block_pos = reader.read_u64::<LittleEndian>()?;
-            let end_pos = reader.read_u64::<LittleEndian>()?;
-
-            let mut grid_descriptor = GridDescriptor {
-                name: name.clone(),
-                grid_type,
-                instance_parent,
-                grid_pos,
-                block_pos,
-                end_pos,
-                compression: header.compression,
-                meta_data: Default::default(),
-            };
-
-            if header.file_version >= OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
-                grid_descriptor.compression = reader.read_u32::<LittleEndian>()?.try_into()?;
-            }
-
-            grid_descriptor.meta_data = Self::read_metadata(reader)?;
+            let grid_descriptor = Self::read_grid_descriptor(reader, header)?;
+            let name = grid_descriptor.name.clone();
+            let end_pos = grid_descriptor.end_pos;
 
             assert!(
                 grid_descriptors
@@ -211,6 +381,37 @@ impl<R: Read + Seek> VdbReader<R> {
         Ok(grid_descriptors)
     }
 
+    /// Reads a grid descriptor along with the compression and metadata of its grid
+    fn read_grid_descriptor(reader: &mut R, header: &ArchiveHeader) -> Result<GridDescriptor> {
+        let name = read_len_string(reader)?;
+        let grid_type = read_len_string(reader)?;
+        let instance_parent = read_len_string(reader)?;
+
+        // Zero when the archive has no grid offsets
+        let grid_pos = reader.read_u64::<LittleEndian>()?;
+        let block_pos = reader.read_u64::<LittleEndian>()?;
+        let end_pos = reader.read_u64::<LittleEndian>()?;
+
+        let mut grid_descriptor = GridDescriptor {
+            name,
+            grid_type,
+            instance_parent,
+            grid_pos,
+            block_pos,
+            end_pos,
+            compression: header.compression,
+            meta_data: Default::default(),
+        };
+
+        if header.file_version >= OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
+            grid_descriptor.compression = reader.read_u32::<LittleEndian>()?.try_into()?;
+        }
+
+        grid_descriptor.meta_data = Self::read_metadata(reader)?;
+
+        Ok(grid_descriptor)
+    }
+
     fn read_metadata(reader: &mut R) -> Result<Metadata> {
         let meta_data_number = reader.read_u32::<LittleEndian>()?;
         let mut meta_data = Metadata::default();
@@ -267,31 +468,32 @@ impl<R: Read + Seek> VdbReader<R> {
         Ok(meta_data)
     }
 
+    /// Reads the tree without leaf values, along with the root keys of the N5s in file order
     fn read_tree_topology<T: VdbValueType>(
         &mut self,
         grid_descriptor: &GridDescriptor,
-    ) -> Result<VDB345<T>> {
+    ) -> Result<(VDB345<T>, Vec<[i32; 3]>)> {
         let buffer_count = self.reader.read_u32::<LittleEndian>()?;
         if buffer_count != 1 {
             todo!("Multi-buffer trees not implemented");
         }
 
         // @TODO: What is the logic here for half_float? this takes up 4 bytes?
-        let rn_bytes = self.reader.read_u32::<LittleEndian>()?;
-        let root_node_background = T::from_4_le_bytes(rn_bytes.to_le_bytes());
+        let components = grid_descriptor.value_components();
+        let root_node_background = self.read_value::<T>(components)?;
 
         let number_of_tiles = self.reader.read_u32::<LittleEndian>()?;
         let number_of_node5s = self.reader.read_u32::<LittleEndian>()?;
 
         let mut node5_entries = vec![];
+        let mut node5_order = vec![];
 
         // Iterate Node5 tiles
         for _ in 0..number_of_tiles {
             let origin = read_vec3i(&mut self.reader)?;
             let root_key = <Root345<T>>::root_key_from_coords(origin);
 
-            let value_bytes = self.reader.read_u32::<LittleEndian>()?;
-            let value = u32::from_4_le_bytes(value_bytes.to_le_bytes());
+            let value = self.read_value::<u32>(components)?;
             let active = self.reader.read_u8()? == 1;
 
             let node5_tile = RootData::Tile::<N5<T>>(value, active);
@@ -335,6 +537,7 @@ impl<R: Read + Seek> VdbReader<R> {
 
             let root_data = RootData::Node(Box::new(node_5));
             node5_entries.push((root_key, root_data));
+            node5_order.push(root_key);
         }
 
         let root = RootNode {
@@ -342,9 +545,76 @@ impl<R: Read + Seek> VdbReader<R> {
             background: root_node_background,
         };
 
-        Ok(VDB345 {
+        let vdb = VDB345 {
             root,
             grid_descriptor: grid_descriptor.clone(),
+            transform: Default::default(),
+            dirty: Default::default(),
+            sdf_capped: false,
+        };
+
+        Ok((vdb, node5_order))
+    }
+
+    /// Reads the topology of grid `name` down to its leaves without creating any node.
+    ///
+    /// Returns the origins of the leaves in the order their blocks are stored.
+    pub(super) fn read_leaf_origins<T: VdbValueType>(
+        &mut self,
+        name: &str,
+    ) -> Result<GridTopology<T>> {
+        let grid_descriptor = self.grid_descriptors.get(name).cloned();
+        let grid_descriptor =
+            grid_descriptor.ok_or_else(|| ErrorKind::InvalidGridName(name.to_owned()))?;
+        grid_descriptor.seek_to_grid(&mut self.reader)?;
+
+        if self.header.file_version >= OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
+            let _: Compression = self.reader.read_u32::<LittleEndian>()?.try_into()?;
+        }
+        let _ = Self::read_metadata(&mut self.reader)?;
+        let transform = Self::read_transform(&mut self.reader)?;
+
+        let buffer_count = self.reader.read_u32::<LittleEndian>()?;
+        if buffer_count != 1 {
+            return Err(ErrorKind::UnsupportedBufferCount(buffer_count));
+        }
+        let components = grid_descriptor.value_components();
+        let background = self.read_value::<T>(components)?;
+
+        let number_of_tiles = self.reader.read_u32::<LittleEndian>()?;
+        let number_of_node5s = self.reader.read_u32::<LittleEndian>()?;
+
+        // Origin, value and active flag of each tile
+        for _ in 0..number_of_tiles {
+            let _origin = read_vec3i(&mut self.reader)?;
+            let _value = self.read_value::<u32>(components)?;
+            let _active = self.reader.read_u8()?;
+        }
+
+        let mut leaves = vec![];
+        for _ in 0..number_of_node5s {
+            let origin: [i32; 3] = read_vec3i(&mut self.reader)?.into();
+            let node_5_header = self.read_internal_node_header::<T, N5<T>>(&grid_descriptor)?;
+
+            for idx in node_5_header.child_mask.iter_ones() {
+                let origin = child_origin::<N5<T>>(origin, idx);
+                let node_4_header = self.read_internal_node_header::<T, N4<T>>(&grid_descriptor)?;
+
+                for idx in node_4_header.child_mask.iter_ones() {
+                    // Value masks are repeated with the leaf values
+                    let mut value_mask = bitvec![u64, Lsb0; 0; <N3<T>>::SIZE];
+                    self.reader
+                        .read_u64_into::<LittleEndian>(value_mask.as_raw_mut_slice())?;
+                    leaves.push(child_origin::<N4<T>>(origin, idx));
+                }
+            }
+        }
+
+        Ok(GridTopology {
+            transform,
+            background,
+            leaves,
+            blocks: grid_descriptor.block_pos,
         })
     }
 
@@ -352,8 +622,19 @@ impl<R: Read + Seek> VdbReader<R> {
         &mut self,
         grid_descriptor: &GridDescriptor,
     ) -> Result<NodeHeader<T>> {
-        let mut child_mask = bitvec![u64, Lsb0; 0; N::SIZE];
-        let mut value_mask = bitvec![u64, Lsb0; 0; N::SIZE];
+        self.read_node_header(grid_descriptor, N::LOG2_D)
+    }
+
+    /// Reads the masks and tile values of an internal node with `1 << log_2_dim` children
+    /// along each axis
+    fn read_node_header<T: VdbValueType>(
+        &mut self,
+        grid_descriptor: &GridDescriptor,
+        log_2_dim: u64,
+    ) -> Result<NodeHeader<T>> {
+        let node_size = 1 << (3 * log_2_dim);
+        let mut child_mask = bitvec![u64, Lsb0; 0; node_size];
+        let mut value_mask = bitvec![u64, Lsb0; 0; node_size];
         self.reader
             .read_u64_into::<LittleEndian>(child_mask.as_raw_mut_slice())?;
         self.reader
@@ -362,7 +643,7 @@ impl<R: Read + Seek> VdbReader<R> {
         let size = if self.header.file_version < OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
             child_mask.count_zeros()
         } else {
-            N::SIZE
+            node_size
         };
 
         let data = self.read_compressed(grid_descriptor, size, value_mask.as_bitslice())?;
@@ -371,7 +652,7 @@ impl<R: Read + Seek> VdbReader<R> {
             child_mask,
             value_mask,
             data,
-            log_2_dim: N::LOG2_D,
+            log_2_dim,
         })
     }
 
@@ -381,20 +662,33 @@ impl<R: Read + Seek> VdbReader<R> {
         size: usize,
         value_mask: &BitSlice<u64>,
     ) -> Result<Vec<T>> {
+        self.read_compressed_block(grid_descriptor, size, value_mask.to_bitvec())?
+            .decode()
+    }
+
+    /// Reads the values of a node as they are stored, [`CompressedBlock::decode`] does the
+    /// decompression so it can run on another thread
+    fn read_compressed_block<T: VdbValueType>(
+        &mut self,
+        grid_descriptor: &GridDescriptor,
+        size: usize,
+        value_mask: BitVec<u64>,
+    ) -> Result<CompressedBlock<T>> {
         let mut meta_data: NodeMetaData = NodeMetaData::NoMaskAndAllVals;
         if self.header.file_version >= OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
             meta_data = self.reader.read_u8()?.try_into()?;
         }
 
+        let components = grid_descriptor.value_components();
         let mut inactive_val0 = T::zeroed();
         let mut inactive_val1 = T::zeroed();
         match meta_data {
             NodeMetaData::MaskAndOneInactiveVal | NodeMetaData::NoMaskAndOneInactiveVal => {
-                self.reader.read_exact(bytes_of_mut(&mut inactive_val0))?;
+                inactive_val0 = self.read_inactive_value(components)?;
             }
             NodeMetaData::MaskAndTwoInactiveVals => {
-                self.reader.read_exact(bytes_of_mut(&mut inactive_val0))?;
-                self.reader.read_exact(bytes_of_mut(&mut inactive_val1))?;
+                inactive_val0 = self.read_inactive_value(components)?;
+                inactive_val1 = self.read_inactive_value(components)?;
             }
             _ => {}
         }
@@ -420,206 +714,186 @@ impl<R: Read + Seek> VdbReader<R> {
             size
         };
 
-        let expected = std::any::TypeId::of::<T>();
-        let f16_type = std::any::TypeId::of::<f16>();
-        let f32_type = std::any::TypeId::of::<f32>();
+        let half = grid_descriptor.meta_data.is_half_float();
+        let value_size = match half {
+            true => std::mem::size_of::<f16>(),
+            false => std::mem::size_of::<f32>(),
+        } * components;
+        let data = self.read_block_data(grid_descriptor.compression, count, value_size)?;
 
-        let real = if grid_descriptor.meta_data.is_half_float() {
-            f16_type
-        } else {
-            f32_type
-        };
+        Ok(CompressedBlock {
+            size,
+            count,
+            value_mask,
+            selection_mask,
+            inactive_vals: [inactive_val0, inactive_val1],
+            active_mask: grid_descriptor
+                .compression
+                .contains(Compression::ACTIVE_MASK),
+            half,
+            components,
+            data,
+        })
+    }
 
-        let data: Vec<T> = match (expected, real) {
-            (e, r) if e == r => self.read_compressed_data(grid_descriptor, count)?,
-            (e, r) if e == f16_type && r == f32_type => {
-                let data = self.read_compressed_data::<f32>(grid_descriptor, count)?;
-                bytemuck::cast_vec(data.into_iter().map(f16::from_f32).collect::<Vec<_>>())
-            }
-            (e, r) if e == f32_type && r == f16_type => {
-                let data = self.read_compressed_data::<f16>(grid_descriptor, count)?;
-                bytemuck::cast_vec(data.into_iter().map(f16::to_f32).collect::<Vec<f32>>())
-            }
-            (_, r) if r == f16_type => {
-                let data = self.read_compressed_data::<f16>(grid_descriptor, count)?;
-                bytemuck::cast_vec(
-                    data.into_iter()
-                        .map(|c| T::from_f16_bites(c))
-                        .collect::<Vec<_>>(),
-                )
-            }
-            (_, r) if r == f32_type => {
-                let data = self.read_compressed_data::<f32>(grid_descriptor, count)?;
-                bytemuck::cast_vec(
-                    data.into_iter()
-                        .map(|c| T::from_f32_bites(c))
-                        .collect::<Vec<_>>(),
-                )
+    /// Reads a tile or background value, which is stored at full precision. Vectors are
+    /// packed with [`pack_rgb`]
+    fn read_value<V: VdbValueType>(&mut self, components: usize) -> Result<V> {
+        let bits = match components {
+            1 => self.reader.read_u32::<LittleEndian>()?,
+            _ => {
+                let mut rgb = [0f32; 3];
+                self.reader.read_f32_into::<LittleEndian>(&mut rgb)?;
+                pack_rgb(rgb)
             }
-            _ => unreachable!(),
         };
+        Ok(V::from_4_le_bytes(bits.to_le_bytes()))
+    }
 
-        Ok(
-            if grid_descriptor
-                .compression
-                .contains(Compression::ACTIVE_MASK)
-                && data.len() != size
-            {
-                trace!("Expanding active maska data {} to {}", data.len(), size);
-
-                let mut expanded = vec![T::zeroed(); size];
-                let mut read_idx = 0;
-                for dest_idx in 0..size {
-                    expanded[dest_idx] = if value_mask[dest_idx] {
-                        let v = data[read_idx];
-                        read_idx += 1;
-                        v
-                    } else if selection_mask[dest_idx] {
-                        inactive_val1
-                    } else {
-                        inactive_val0
-                    }
-                }
-                expanded
-            } else {
-                data
-            },
-        )
+    /// Reads one of the inactive values of a compressed block
+    fn read_inactive_value<V: VdbValueType>(&mut self, components: usize) -> Result<V> {
+        if components > 1 {
+            return self.read_value(components);
+        }
+        let mut value = V::zeroed();
+        self.reader.read_exact(bytes_of_mut(&mut value))?;
+        Ok(value)
     }
 
-    fn read_compressed_data<T: VdbValueType>(
+    fn read_block_data(
         &mut self,
-        grid_descriptor: &GridDescriptor,
+        compression: Compression,
         count: usize,
-    ) -> Result<Vec<T>> {
-        Ok(match grid_descriptor.compression {
+        value_size: usize,
+    ) -> Result<BlockData> {
+        Ok(match compression {
             c if c.contains(Compression::BLOSC) => {
                 let num_compressed_bytes = self.reader.read_i64::<LittleEndian>()?;
-                let compressed_count = num_compressed_bytes / std::mem::size_of::<T>() as i64;
+                let compressed_count = num_compressed_bytes / value_size as i64;
 
                 trace!("Reading blosc data, {} bytes", num_compressed_bytes);
                 if num_compressed_bytes <= 0 {
-                    let mut data = vec![T::zeroed(); (-compressed_count) as usize];
-                    self.reader.read_exact(cast_slice_mut(&mut data))?;
+                    let mut data = vec![0u8; (-compressed_count) as usize * value_size];
+                    self.reader.read_exact(&mut data)?;
                     assert_eq!(-compressed_count as usize, count);
-                    data
+                    BlockData::Raw(data)
                 } else {
                     let mut blosc_data = vec![0u8; num_compressed_bytes as usize];
                     self.reader.read_exact(&mut blosc_data)?;
                     if count > 0 {
-                        let mut nbytes: usize = 0;
-                        let mut cbytes: usize = 0;
-                        let mut blocksize: usize = 0;
-                        unsafe {
-                            blosc_cbuffer_sizes(
-                                blosc_data.as_ptr().cast(),
-                                &mut nbytes,
-                                &mut cbytes,
-                                &mut blocksize,
-                            )
-                        };
-                        if nbytes == 0 {
-                            return Err(ErrorKind::UnsupportedBloscFormat);
-                        }
-                        let dest_size = nbytes / std::mem::size_of::<T>();
-                        let mut dest: Vec<T> = vec![Zeroable::zeroed(); dest_size];
-                        let error = unsafe {
-                            blosc_src::blosc_decompress_ctx(
-                                blosc_data.as_ptr().cast(),
-                                dest.as_mut_ptr().cast(),
-                                nbytes,
-                                1,
-                            )
-                        };
-                        if error < 1 {
-                            return Err(ErrorKind::InvalidBloscData);
-                        }
-                        dest
+                        BlockData::Blosc(blosc_data)
                     } else {
                         trace!(
                             "Skipping blosc decompression because of a {}-count read",
                             count
                         );
-                        vec![T::zeroed(); 0]
+                        BlockData::Raw(vec![])
                     }
                 }
             }
             c if c.contains(Compression::ZIP) => {
                 let num_zipped_bytes = self.reader.read_i64::<LittleEndian>()?;
-                let compressed_count = num_zipped_bytes / std::mem::size_of::<T>() as i64;
+                let compressed_count = num_zipped_bytes / value_size as i64;
 
                 trace!("Reading zipped data, {} bytes", num_zipped_bytes);
                 if num_zipped_bytes <= 0 {
-                    let mut data = vec![T::zeroed(); (-compressed_count) as usize];
-                    self.reader.read_exact(cast_slice_mut(&mut data))?;
-                    data
+                    let mut data = vec![0u8; (-compressed_count) as usize * value_size];
+                    self.reader.read_exact(&mut data)?;
+                    BlockData::Raw(data)
                 } else {
                     let mut zipped_data = vec![0u8; num_zipped_bytes as usize];
                     self.reader.read_exact(&mut zipped_data)?;
-
-                    let mut zip_reader = flate2::read::ZlibDecoder::new(zipped_data.as_slice());
-                    let mut data = vec![T::zeroed(); count];
-                    zip_reader.read_exact(cast_slice_mut(&mut data))?;
-                    data
+                    BlockData::Zip(zipped_data)
                 }
             }
             _ => {
                 trace!("Reading uncompressed data, {} elements", count);
 
-                let mut data = vec![T::zeroed(); count];
-                self.reader.read_exact(cast_slice_mut(&mut data))?;
-                data
+                let mut data = vec![0u8; count * value_size];
+                self.reader.read_exact(&mut data)?;
+                BlockData::Raw(data)
             }
         })
     }
 
+    /// Reads the value mask and values of the next leaf, which has `size` voxels
+    fn read_leaf_block<T: VdbValueType>(
+        &mut self,
+        grid_descriptor: &GridDescriptor,
+        size: usize,
+    ) -> Result<CompressedBlock<T>> {
+        let mut value_mask = bitvec![u64, Lsb0; 0; size];
+        self.reader
+            .read_u64_into::<LittleEndian>(value_mask.as_raw_mut_slice())?;
+
+        if self.header.file_version < OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
+            let _origin = read_vec3i(&mut self.reader)?;
+            let num_buffers = self.reader.read_u8()?;
+            assert_eq!(num_buffers, 1);
+        }
+
+        self.read_compressed_block(grid_descriptor, size, value_mask)
+    }
+
     fn read_tree_data<T: VdbValueType>(
         &mut self,
         grid_descriptor: &GridDescriptor,
         vdb: &mut VDB345<T>,
+        node5_order: &[[i32; 3]],
     ) -> Result<()> {
-        grid_descriptor.seek_to_blocks(&mut self.reader)?;
+        // Streamed grids are read in order, the blocks follow the topology
+        if self.stream.is_none() {
+            grid_descriptor.seek_to_blocks(&mut self.reader)?;
+        }
 
-        for node_5 in vdb.root.map.values_mut() {
-            let RootData::Node(node_5) = node_5 else {
+        // Leaves are stored in the same order as the topology
+        let mut leaves = vec![];
+        for root_key in node5_order {
+            let Some(RootData::Node(node_5)) = vdb.root.map.get(root_key) else {
                 continue;
             };
 
-            for node_4 in node_5.data.iter_mut() {
+            for (n4_idx, node_4) in node_5.data.iter().enumerate() {
                 let InternalData::Node(node_4) = node_4 else {
                     continue;
                 };
 
-                for node_3 in node_4.data.iter_mut() {
-                    let InternalData::Node(node_3) = node_3 else {
-                        continue;
-                    };
+                for (n3_idx, node_3) in node_4.data.iter().enumerate() {
+                    if let InternalData::Node(_) = node_3 {
+                        leaves.push((*root_key, n4_idx, n3_idx));
+                    }
+                }
+            }
+        }
 
-                    let mut value_mask = bitvec![u64, Lsb0; 0; <N3<T>>::SIZE];
-                    self.reader
-                        .read_u64_into::<LittleEndian>(value_mask.as_raw_mut_slice())?;
+        // Reading stays sequential, a batch of leaves is read then decompressed in parallel
+        for batch in leaves.chunks(self.threads * LEAVES_PER_THREAD) {
+            let blocks = batch
+                .iter()
+                .map(|_| self.read_leaf_block::<T>(grid_descriptor, <N3<T>>::SIZE))
+                .collect::<Result<Vec<_>>>()?;
 
-                    if self.header.file_version < OPENVDB_FILE_VERSION_NODE_MASK_COMPRESSION {
-                        let _origin = read_vec3i(&mut self.reader)?;
-                        let num_buffers = self.reader.read_u8()?;
-                        assert_eq!(num_buffers, 1);
-                    }
+            for (&(root_key, n4_idx, n3_idx), data) in
+                batch.iter().zip(decode_blocks(&blocks, self.threads)?)
+            {
+                let Some(RootData::Node(node_5)) = vdb.root.map.get_mut(&root_key) else {
+                    unreachable!()
+                };
+                let InternalData::Node(node_4) = &mut node_5.data[n4_idx] else {
+                    unreachable!()
+                };
+                let InternalData::Node(node_3) = &mut node_4.data[n3_idx] else {
+                    unreachable!()
+                };
 
-                    let data: Vec<T> = self.read_compressed(
-                        &grid_descriptor,
-                        <N3<T>>::SIZE,
-                        value_mask.as_bitslice(),
-                    )?;
-
-                    for idx in 0..data.len() {
-                        // HACK:
-                        // It looks like the initial value mask given in the previous Node4 section is the correct one,
-                        // I am not sure that the actual data of the voxel is being read properly (it is probably not)
-                        // I think using the previous value mask just gives the correct topology but probably the value of the voxels
-                        // is not the one that was intended, I need to reinvestigate how the file encoding is done
-                        if (node_3.value_mask[idx >> 6] & (1 << (idx & 63))) != 0 {
-                            node_3.data[idx] = LeafData::Value(data[idx]);
-                        }
+                for idx in 0..data.len() {
+                    // HACK:
+                    // It looks like the initial value mask given in the previous Node4 section is the correct one,
+                    // I am not sure that the actual data of the voxel is being read properly (it is probably not)
+                    // I think using the previous value mask just gives the correct topology but probably the value of the voxels
+                    // is not the one that was intended, I need to reinvestigate how the file encoding is done
+                    if (node_3.value_mask[idx >> 6] & (1 << (idx & 63))) != 0 {
+                        node_3.data[idx] = LeafData::Value(data[idx]);
                     }
                 }
             }
@@ -627,6 +901,332 @@ impl<R: Read + Seek> VdbReader<R> {
 
         Ok(())
     }
+
+    /// Reads the tree of a grid that isn't 5-4-3, the reader must be right after its transform
+    fn read_shaped_tree<T: VdbValueType>(
+        &mut self,
+        grid_descriptor: &GridDescriptor,
+        shape: TreeShape,
+    ) -> Result<ShapedTree<T>> {
+        let buffer_count = self.reader.read_u32::<LittleEndian>()?;
+        if buffer_count != 1 {
+            return Err(ErrorKind::UnsupportedBufferCount(buffer_count));
+        }
+        let components = grid_descriptor.value_components();
+        let background = self.read_value::<T>(components)?;
+
+        let number_of_tiles = self.reader.read_u32::<LittleEndian>()?;
+        let number_of_children = self.reader.read_u32::<LittleEndian>()?;
+
+        let mut tree = ShapedTree::new(shape, background);
+        for _ in 0..number_of_tiles {
+            let origin = read_vec3i(&mut self.reader)?.into();
+            let value = self.read_value::<T>(components)?;
+            let active = self.reader.read_u8()? == 1;
+            tree.tiles.push((origin, value, active));
+        }
+
+        // Kept in file order until the leaves are read
+        let mut children = vec![];
+        for _ in 0..number_of_children {
+            let origin = read_vec3i(&mut self.reader)?.into();
+            children.push(self.read_shaped_node(grid_descriptor, &shape, 0, origin, background)?);
+        }
+
+        let mut leaves = vec![];
+        for child in children.iter_mut() {
+            child.leaves_mut(&shape, &mut leaves);
+        }
+
+        let leaf_size = shape.size(shape.levels() - 1);
+        for batch in leaves.chunks_mut(self.threads * LEAVES_PER_THREAD) {
+            let blocks = batch
+                .iter()
+                .map(|_| self.read_leaf_block::<T>(grid_descriptor, leaf_size))
+                .collect::<Result<Vec<_>>>()?;
+
+            for (leaf, data) in batch.iter_mut().zip(decode_blocks(&blocks, self.threads)?) {
+                // Same as the 5-4-3 leaves, the value mask of the topology is the one used
+                for idx in leaf.value_mask.iter_ones().filter(|&idx| idx < data.len()) {
+                    leaf.values[idx] = data[idx];
+                }
+            }
+        }
+
+        tree.nodes
+            .extend(children.into_iter().map(|child| (child.origin, child)));
+        Ok(tree)
+    }
+
+    /// Reads the topology of a node of `level` and of its children, leaves only get their
+    /// value mask
+    fn read_shaped_node<T: VdbValueType>(
+        &mut self,
+        grid_descriptor: &GridDescriptor,
+        shape: &TreeShape,
+        level: usize,
+        origin: [i32; 3],
+        background: T,
+    ) -> Result<ShapedNode<T>> {
+        let mut node = ShapedNode::new(shape, level, origin, background);
+        if node.is_leaf(shape) {
+            self.reader
+                .read_u64_into::<LittleEndian>(node.value_mask.as_raw_mut_slice())?;
+            return Ok(node);
+        }
+
+        let header =
+            self.read_node_header::<T>(grid_descriptor, shape.log2_dims()[level] as u64)?;
+        // Older files only store the values of the tiles
+        if header.data.len() == node.values.len() {
+            node.values = header.data;
+        }
+        node.child_mask = header.child_mask;
+        node.value_mask = header.value_mask;
+
+        for offset in node.child_mask.iter_ones().collect::<Vec<_>>() {
+            let origin = shape.child_origin(level, origin, offset);
+            let child =
+                self.read_shaped_node(grid_descriptor, shape, level + 1, origin, background)?;
+            node.children.insert(offset, child);
+        }
+
+        Ok(node)
+    }
+}
+
+/// Values of a node as stored in the file
+struct CompressedBlock<T> {
+    /// Values in the node
+    size: usize,
+    /// Values stored
+    count: usize,
+    value_mask: BitVec<u64>,
+    /// Picks between the two inactive values
+    selection_mask: BitVec<u64>,
+    inactive_vals: [T; 2],
+    /// Only the active values may be stored
+    active_mask: bool,
+    half: bool,
+    /// Scalars in each value, vectors are packed with [`pack_rgb`]
+    components: usize,
+    data: BlockData,
+}
+
+enum BlockData {
+    Raw(Vec<u8>),
+    Blosc(Vec<u8>),
+    Zip(Vec<u8>),
+}
+
+impl<T: VdbValueType> CompressedBlock<T> {
+    /// Decompresses the values, converts them to `T` and fills in the inactive ones
+    fn decode(&self) -> Result<Vec<T>> {
+        let expected = std::any::TypeId::of::<T>();
+        let f16_type = std::any::TypeId::of::<f16>();
+        let f32_type = std::any::TypeId::of::<f32>();
+
+        let real = if self.half { f16_type } else { f32_type };
+
+        let data: Vec<T> = match (expected, real) {
+            _ if self.components > 1 => self.decode_rgb()?,
+            (e, r) if e == r => self.data.decode(self.count)?,
+            (e, r) if e == f16_type && r == f32_type => {
+                let data = self.data.decode::<f32>(self.count)?;
+                bytemuck::cast_vec(data.into_iter().map(f16::from_f32).collect::<Vec<_>>())
+            }
+            (e, r) if e == f32_type && r == f16_type => {
+                let data = self.data.decode::<f16>(self.count)?;
+                bytemuck::cast_vec(data.into_iter().map(f16::to_f32).collect::<Vec<f32>>())
+            }
+            (_, r) if r == f16_type => {
+                let data = self.data.decode::<f16>(self.count)?;
+                bytemuck::cast_vec(
+                    data.into_iter()
+                        .map(|c| T::from_f16_bites(c))
+                        .collect::<Vec<_>>(),
+                )
+            }
+            (_, r) if r == f32_type => {
+                let data = self.data.decode::<f32>(self.count)?;
+                bytemuck::cast_vec(
+                    data.into_iter()
+                        .map(|c| T::from_f32_bites(c))
+                        .collect::<Vec<_>>(),
+                )
+            }
+            _ => unreachable!(),
+        };
+
+        let size = self.size;
+        Ok(if self.active_mask && data.len() != size {
+            trace!("Expanding active maska data {} to {}", data.len(), size);
+
+            let [inactive_val0, inactive_val1] = self.inactive_vals;
+            let mut expanded = vec![T::zeroed(); size];
+            let mut read_idx = 0;
+            for dest_idx in 0..size {
+                expanded[dest_idx] = if self.value_mask[dest_idx] {
+                    let v = data[read_idx];
+                    read_idx += 1;
+                    v
+                } else if self.selection_mask[dest_idx] {
+                    inactive_val1
+                } else {
+                    inactive_val0
+                }
+            }
+            expanded
+        } else {
+            data
+        })
+    }
+
+    /// Decodes vectors of `components` scalars and packs them with [`pack_rgb`]
+    fn decode_rgb(&self) -> Result<Vec<T>> {
+        let scalars = self.count * self.components;
+        let data: Vec<f32> = match self.half {
+            true => (self.data.decode::<f16>(scalars)?.into_iter())
+                .map(f16::to_f32)
+                .collect(),
+            false => self.data.decode(scalars)?,
+        };
+
+        Ok(data
+            .chunks_exact(self.components)
+            .map(|c| T::from_4_le_bytes(pack_rgb([c[0], c[1], c[2]]).to_le_bytes()))
+            .collect())
+    }
+}
+
+impl BlockData {
+    fn decode<T: Pod>(&self, count: usize) -> Result<Vec<T>> {
+        Ok(match self {
+            BlockData::Raw(data) => bytemuck::pod_collect_to_vec(data),
+            BlockData::Blosc(blosc_data) => {
+                let mut nbytes: usize = 0;
+                let mut cbytes: usize = 0;
+                let mut blocksize: usize = 0;
+                unsafe {
+                    blosc_cbuffer_sizes(
+                        blosc_data.as_ptr().cast(),
+                        &mut nbytes,
+                        &mut cbytes,
+                        &mut blocksize,
+                    )
+                };
+                if nbytes == 0 {
+                    return Err(ErrorKind::UnsupportedBloscFormat);
+                }
+                let dest_size = nbytes / std::mem::size_of::<T>();
+                let mut dest: Vec<T> = vec![Zeroable::zeroed(); dest_size];
+                let error = unsafe {
+                    blosc_src::blosc_decompress_ctx(
+                        blosc_data.as_ptr().cast(),
+                        dest.as_mut_ptr().cast(),
+                        nbytes,
+                        1,
+                    )
+                };
+                if error < 1 {
+                    return Err(ErrorKind::InvalidBloscData);
+                }
+                dest
+            }
+            BlockData::Zip(zipped_data) => {
+                let mut zip_reader = flate2::read::ZlibDecoder::new(zipped_data.as_slice());
+                let mut data = vec![T::zeroed(); count];
+                zip_reader.read_exact(cast_slice_mut(&mut data))?;
+                data
+            }
+        })
+    }
+}
+
+fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Decodes `blocks` in order, split between up to `threads` threads
+fn decode_blocks<T: VdbValueType>(
+    blocks: &[CompressedBlock<T>],
+    threads: usize,
+) -> Result<Vec<Vec<T>>> {
+    if threads <= 1 || blocks.len() < 2 {
+        return blocks.iter().map(CompressedBlock::decode).collect();
+    }
+
+    let chunk_size = blocks.len().div_ceil(threads);
+    thread::scope(|scope| {
+        let handles = blocks
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .map(CompressedBlock::decode)
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut decoded = Vec::with_capacity(blocks.len());
+        for handle in handles {
+            decoded.extend(handle.join().unwrap_or_else(|e| panic::resume_unwind(e))?);
+        }
+        Ok(decoded)
+    })
+}
+
+/// Lets a reader that can't seek stand in where seeking is expected: seeking forward reads and
+/// drops the skipped bytes, seeking back fails
+pub struct ForwardReader<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read> ForwardReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, position: 0 }
+    }
+}
+
+impl<R: Read> Read for ForwardReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read> Seek for ForwardReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(target) => Some(target),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(_) => None,
+        };
+        let Some(target) = target.filter(|&target| target >= self.position) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Streams can only seek forward",
+            ));
+        };
+
+        let skip = target - self.position;
+        let skipped = io::copy(&mut self.by_ref().take(skip), &mut io::sink())?;
+        if skipped < skip {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(self.position)
+    }
+}
+
+/// Packs a colour into RGBA8 with an opaque alpha, the way `vec3s` grids are held in `u32`
+/// grids. Channels are clamped to `[0, 1]`, the shader unpacks them with `unpack4x8unorm`
+pub fn pack_rgb(rgb: [f32; 3]) -> u32 {
+    let [r, g, b] = rgb.map(|c| (c.clamp(0., 1.) * 255.).round() as u32);
+    r | g << 8 | b << 16 | 255 << 24
 }
 
 pub trait From4LeBytes {
@@ -650,19 +1250,19 @@ pub trait From4LeBytes {
         Self::from_4_le_bytes(bytes)
     }
 }
-fn read_len_string<R: Read + Seek>(reader: &mut R) -> Result<String> {
+fn read_len_string<R: Read>(reader: &mut R) -> Result<String> {
     let len = reader.read_u32::<LittleEndian>()? as usize;
     read_string(reader, len)
 }
 
-fn read_string<R: Read + Seek>(reader: &mut R, len: usize) -> Result<String> {
+fn read_string<R: Read>(reader: &mut R, len: usize) -> Result<String> {
     let mut buf = vec![0; len];
     reader.read_exact(&mut buf)?;
     let string = String::from_utf8(buf.to_vec())?;
     Ok(string)
 }
 
-fn read_vec3i<R: Read + Seek>(reader: &mut R) -> Result<Vector3<i32>> {
+fn read_vec3i<R: Read>(reader: &mut R) -> Result<Vector3<i32>> {
     let x = reader.read_i32::<LittleEndian>()?;
     let y = reader.read_i32::<LittleEndian>()?;
     let z = reader.read_i32::<LittleEndian>()?;
@@ -670,7 +1270,7 @@ fn read_vec3i<R: Read + Seek>(reader: &mut R) -> Result<Vector3<i32>> {
     Ok(Vector3 { x, y, z })
 }
 
-fn read_vec3d<R: Read + Seek>(reader: &mut R) -> Result<Vector3<f64>> {
+fn read_vec3d<R: Read>(reader: &mut R) -> Result<Vector3<f64>> {
     let x = reader.read_f64::<LittleEndian>()?;
     let y = reader.read_f64::<LittleEndian>()?;
     let z = reader.read_f64::<LittleEndian>()?;
@@ -728,7 +1328,11 @@ fn try_from_bitvec<const LOG2_D: u64>(
 mod tests {
     use std::{io::BufReader, thread};
 
+    use bytes::BytesMut;
+    use itertools::Itertools;
+
     use super::*;
+    use crate::vdb::{write_vdb, WriteOptions};
 
     #[test]
     fn test_read_utahteapot() {
@@ -757,6 +1361,199 @@ mod tests {
         handler.join().unwrap_or_else(|_| panic!("Test Failed"));
     }
 
+    /// Archive with grids `a`, `b` and `c`, each with one more voxel than the previous one
+    fn three_grids() -> (Vec<VDB345<f32>>, Vec<u8>) {
+        let grids = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let mut vdb = <VDB345<f32>>::new();
+                vdb.grid_descriptor.name = name.to_string();
+                for v in 0..=i as i32 {
+                    vdb.set_voxel([v * 100, -v, 3].into(), v as f32);
+                }
+                vdb
+            })
+            .collect_vec();
+
+        let mut b = BytesMut::new();
+        write_vdb(
+            &mut b,
+            &grids.iter().collect_vec(),
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        (grids, b.to_vec())
+    }
+
+    fn voxels(vdb: &VDB345<f32>) -> Vec<([i32; 3], f32)> {
+        vdb.active_voxels().collect()
+    }
+
+    #[test]
+    fn test_streaming_reader() {
+        let builder = thread::Builder::new()
+            .name("streaming_reader_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let (grids, bytes) = three_grids();
+
+                let mut reader = VdbReader::streaming(bytes.as_slice()).unwrap();
+                assert!(reader.is_streaming());
+                assert!(reader.grid_descriptors.is_empty());
+
+                let b = reader.read_vdb345_grid::<f32>("b").unwrap();
+                assert_eq!(voxels(&b), voxels(&grids[1]));
+                assert_eq!(
+                    reader.grid_descriptors.keys().sorted().collect_vec(),
+                    ["a", "b"]
+                );
+                assert!(matches!(
+                    reader.read_vdb345_grid::<f32>("a"),
+                    Err(ErrorKind::GridPassed(_))
+                ));
+
+                let c = reader.next_grid_descriptor().unwrap().unwrap();
+                assert_eq!(c.name, "c");
+                let c = reader.read_vdb345_grid::<f32>("c").unwrap();
+                assert_eq!(voxels(&c), voxels(&grids[2]));
+                assert!(reader.next_grid_descriptor().unwrap().is_none());
+                assert!(matches!(
+                    reader.read_vdb345_grid::<f32>("d"),
+                    Err(ErrorKind::InvalidGridName(_))
+                ));
+
+                let mut reader = VdbReader::from_bytes(Bytes::from(bytes)).unwrap();
+                assert!(!reader.is_streaming());
+                let a = reader.read_vdb345_grid::<f32>("a").unwrap();
+                assert_eq!(voxels(&a), voxels(&grids[0]));
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn test_read_without_grid_offsets() {
+        let builder = thread::Builder::new()
+            .name("grid_offsets_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let (grids, mut bytes) = three_grids();
+
+                // Clear the flag after the library version and the offsets before each grid,
+                // like archives written to a stream
+                let grid_positions = VdbReader::from_slice(&bytes)
+                    .unwrap()
+                    .grid_descriptors
+                    .values()
+                    .map(|descriptor| descriptor.grid_pos as usize)
+                    .collect_vec();
+                bytes[20] = 0;
+                for grid_pos in grid_positions {
+                    bytes[grid_pos - 24..grid_pos].fill(0);
+                }
+
+                let mut reader = VdbReader::from_slice(&bytes).unwrap();
+                assert!(reader.is_streaming());
+                // Skipping `a` and `b` means parsing them
+                let c = reader.read_vdb345_grid::<f32>("c").unwrap();
+                assert_eq!(voxels(&c), voxels(&grids[2]));
+                assert_eq!(reader.grid_descriptors["b"].end_pos, 0);
+
+                let mut reader = VdbReader::streaming(bytes.as_slice()).unwrap();
+                let b = reader.read_vdb345_grid::<f32>("b").unwrap();
+                assert_eq!(voxels(&b), voxels(&grids[1]));
+                let c = reader.read_vdb345_grid::<f32>("c").unwrap();
+                assert_eq!(voxels(&c), voxels(&grids[2]));
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn test_forward_reader() {
+        let mut reader = ForwardReader::new([0, 1, 2, 3, 4, 5].as_slice());
+        assert_eq!(reader.read_u8().unwrap(), 0);
+        assert_eq!(reader.seek(SeekFrom::Start(3)).unwrap(), 3);
+        assert_eq!(reader.read_u8().unwrap(), 3);
+        assert_eq!(reader.seek(SeekFrom::Current(1)).unwrap(), 5);
+        assert_eq!(reader.stream_position().unwrap(), 5);
+        assert!(reader.seek(SeekFrom::Start(2)).is_err());
+        assert!(reader.seek(SeekFrom::End(0)).is_err());
+        assert!(reader.seek(SeekFrom::Start(10)).is_err());
+    }
+
+    #[test]
+    fn test_parallel_read() {
+        let builder = thread::Builder::new()
+            .name("parallel_read_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let f = std::fs::File::open("assets/cube.vdb").unwrap();
+                let mut vdb = VdbReader::new(BufReader::new(f))
+                    .unwrap()
+                    .read_vdb345_grid::<f32>("ls_cube")
+                    .unwrap();
+                vdb.grid_descriptor.name = "ls_cube".to_string();
+
+                for options in [
+                    WriteOptions::default(),
+                    WriteOptions {
+                        compression: Compression::BLOSC | Compression::ACTIVE_MASK,
+                        half_float: true,
+                        ..Default::default()
+                    },
+                    WriteOptions {
+                        compression: Compression::ZIP,
+                        ..Default::default()
+                    },
+                    WriteOptions {
+                        compression: Compression::NONE,
+                        half_float: true,
+                        ..Default::default()
+                    },
+                    WriteOptions {
+                        shape: TreeShape::new(&[4, 3, 2]).unwrap(),
+                        ..Default::default()
+                    },
+                ] {
+                    let mut b = BytesMut::new();
+                    write_vdb(&mut b, &[&vdb], &options).unwrap();
+
+                    let read = |threads| {
+                        let mut reader = VdbReader::from_slice(&b).unwrap();
+                        assert_eq!(reader.header.grid_number, 1);
+                        assert!(reader.grid_descriptors.contains_key("ls_cube"));
+                        reader.set_threads(threads);
+                        reader.read_vdb345_grid::<f32>("ls_cube").unwrap()
+                    };
+                    let serial = read(1);
+                    // More leaves than a single batch
+                    assert!(serial.count_nodes()[2] > 3 * LEAVES_PER_THREAD);
+                    let parallel = read(3);
+                    assert_eq!(serial, parallel, "{options:?}");
+
+                    let [mut serial_bytes, mut parallel_bytes] = [BytesMut::new(), BytesMut::new()];
+                    write_vdb(&mut serial_bytes, &[&serial], &options).unwrap();
+                    write_vdb(&mut parallel_bytes, &[&parallel], &options).unwrap();
+                    assert_eq!(serial_bytes, parallel_bytes, "{options:?}");
+                }
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn pack_rgb_test() {
+        assert_eq!(pack_rgb([0., 0., 0.]), 0xff000000);
+        assert_eq!(pack_rgb([1., 0.5, 0.]), 0xff0080ff);
+        // Out of range channels are clamped
+        assert_eq!(pack_rgb([-1., 2., 1.]), 0xffffff00);
+    }
+
     fn test_read_vdb(name: &'static str) {
         let f = std::fs::File::open(format!("assets/{name}.vdb")).unwrap();
         let b = BufReader::new(f);
@@ -0,0 +1,547 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use bitvec::prelude::*;
+use itertools::{iproduct, Itertools};
+
+use super::{
+    child_origin, sdf_neighbours, InternalData, Node, RootData, VdbValueType, N4, N5, VDB345,
+};
+
+/// Most levels a [`TreeShape`] can have below the root
+pub const MAX_TREE_LEVELS: usize = 4;
+
+/// Most tiles or voxels a tile is split into when another shape has no tile of its size, a
+/// root tile of `6_5_4_3` becomes this many 5-4-3 root tiles
+pub const MAX_TILE_CELLS: usize = 1 << 18;
+
+#[derive(Debug, thiserror::Error)]
+#[error("A tile of side 2^{log2_dim} splits into too many cells of a {shape} tree")]
+pub struct TileSplitError {
+    pub log2_dim: u32,
+    pub shape: TreeShape,
+}
+
+/// Log2 side lengths of the node levels of a tree below its root, leaves last. OpenVDB
+/// writes them in the grid type, `Tree_float_6_5_4_3` has 4 levels with 64, 32, 16 and 8
+/// children along each axis.
+///
+/// Grids are [`VDB345`]s in memory, other shapes are converted through a [`ShapedTree`]
+/// when they are read or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TreeShape {
+    log2_dims: [u32; MAX_TREE_LEVELS],
+    levels: usize,
+}
+
+impl TreeShape {
+    pub const VDB345: Self = Self {
+        log2_dims: [5, 4, 3, 0],
+        levels: 3,
+    };
+
+    /// `None` without 1 to [`MAX_TREE_LEVELS`] levels, for nodes with less than 64 or more
+    /// than 2^21 children, and for trees whose index space doesn't fit in an `i32`
+    pub fn new(log2_dims: &[u32]) -> Option<Self> {
+        if log2_dims.is_empty() || log2_dims.len() > MAX_TREE_LEVELS {
+            return None;
+        }
+        // Masks are stored as whole u64s
+        if log2_dims.iter().any(|d| !(2..=7).contains(d)) || log2_dims.iter().sum::<u32>() > 30 {
+            return None;
+        }
+
+        let mut shape = Self {
+            log2_dims: [0; MAX_TREE_LEVELS],
+            levels: log2_dims.len(),
+        };
+        shape.log2_dims[..log2_dims.len()].copy_from_slice(log2_dims);
+        Some(shape)
+    }
+
+    /// Shape written in an OpenVDB grid type such as `Tree_float_5_4_3_HalfFloat`
+    pub fn from_grid_type(grid_type: &str) -> Option<Self> {
+        let mut parts = grid_type.split('_');
+        if parts.next()? != "Tree" {
+            return None;
+        }
+        let _value_type = parts.next()?;
+
+        Self::new(&parts.map_while(|part| part.parse().ok()).collect_vec())
+    }
+
+    /// OpenVDB grid type of a tree of this shape holding `value_type`s
+    pub fn grid_type(&self, value_type: &str) -> String {
+        format!("Tree_{value_type}_{self}")
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    /// Level of the leaves, the last one
+    pub fn leaf_level(&self) -> usize {
+        self.levels - 1
+    }
+
+    /// Log2 side length of the nodes of each level, leaves last
+    pub fn log2_dims(&self) -> &[u32] {
+        &self.log2_dims[..self.levels]
+    }
+
+    /// Log2 side length of the index space covered by a node of `level`, `levels()` gives
+    /// the `0` of a voxel
+    pub fn total_log2_dim(&self, level: usize) -> u32 {
+        self.log2_dims[level..self.levels].iter().sum()
+    }
+
+    /// Children or voxels of a node of `level`
+    pub fn size(&self, level: usize) -> usize {
+        1 << (3 * self.log2_dims[level])
+    }
+
+    /// Index of the child containing `p` in the node of `level` containing `p`
+    pub fn offset(&self, level: usize, p: [i32; 3]) -> usize {
+        let log2_dim = self.log2_dims[level];
+        let child_log2_dim = self.total_log2_dim(level + 1);
+        let mask = (1 << log2_dim) - 1;
+        let [x, y, z] = p.map(|c| ((c >> child_log2_dim) & mask) as usize);
+
+        (x << (2 * log2_dim)) | (y << log2_dim) | z
+    }
+
+    /// Global origin of the child at `offset` in the node of `level` at `origin`
+    pub fn child_origin(&self, level: usize, origin: [i32; 3], offset: usize) -> [i32; 3] {
+        let log2_dim = self.log2_dims[level];
+        let child_log2_dim = self.total_log2_dim(level + 1);
+        let mask = (1 << log2_dim) - 1;
+        let child = [offset >> (2 * log2_dim), offset >> log2_dim, offset].map(|c| c & mask);
+
+        [0, 1, 2].map(|i| origin[i] + ((child[i] as i32) << child_log2_dim))
+    }
+
+    /// Splits the active tile of side `1 << log2_dim` at `origin` into the largest tiles this
+    /// shape can hold, returning their level and origins. Level `0` are root tiles and
+    /// `levels()` voxels, the others are children of the level above.
+    ///
+    /// Fails for tiles split into more than [`MAX_TILE_CELLS`].
+    pub fn tile_cells(
+        &self,
+        origin: [i32; 3],
+        log2_dim: u32,
+    ) -> Result<(usize, Vec<[i32; 3]>), TileSplitError> {
+        let level = (0..=self.levels)
+            .find(|&level| self.total_log2_dim(level) <= log2_dim)
+            .unwrap();
+        let cell_log2_dim = self.total_log2_dim(level);
+        if 3 * (log2_dim - cell_log2_dim) > MAX_TILE_CELLS.trailing_zeros() {
+            return Err(TileSplitError {
+                log2_dim,
+                shape: *self,
+            });
+        }
+        let cells = 1 << (log2_dim - cell_log2_dim);
+
+        let origins = iproduct!(0..cells, 0..cells, 0..cells)
+            .map(|(x, y, z)| {
+                [
+                    origin[0] + (x << cell_log2_dim),
+                    origin[1] + (y << cell_log2_dim),
+                    origin[2] + (z << cell_log2_dim),
+                ]
+            })
+            .collect();
+
+        Ok((level, origins))
+    }
+}
+
+impl Default for TreeShape {
+    fn default() -> Self {
+        Self::VDB345
+    }
+}
+
+impl fmt::Display for TreeShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.log2_dims().iter().join("_"))
+    }
+}
+
+impl FromStr for TreeShape {
+    type Err = String;
+
+    /// Parses log2 dimensions separated by `_` or `-`, like `6_5_4_3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let log2_dims = s
+            .split(['_', '-'])
+            .map(|d| d.parse().map_err(|_| format!("Invalid log2 dimension {d}")))
+            .collect::<Result<Vec<u32>, _>>()?;
+
+        Self::new(&log2_dims).ok_or_else(|| format!("Unsupported tree shape {s}"))
+    }
+}
+
+/// Tree of any [`TreeShape`] along with the values of its nodes. Grids of other shapes than
+/// 5-4-3 are read into one before becoming a [`VDB345`], and written from one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapedTree<T> {
+    pub shape: TreeShape,
+    pub background: T,
+    /// Origin, value and active state of the root tiles
+    pub tiles: Vec<([i32; 3], T, bool)>,
+    /// Children of the root by origin
+    pub nodes: BTreeMap<[i32; 3], ShapedNode<T>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapedNode<T> {
+    pub level: usize,
+    pub origin: [i32; 3],
+    /// No bits are set for leaves
+    pub child_mask: BitVec<u64>,
+    pub value_mask: BitVec<u64>,
+    /// Tile values of internal nodes and voxel values of leaves
+    pub values: Vec<T>,
+    /// Children by offset
+    pub children: BTreeMap<usize, ShapedNode<T>>,
+}
+
+impl<T: VdbValueType> ShapedNode<T> {
+    pub fn new(shape: &TreeShape, level: usize, origin: [i32; 3], background: T) -> Self {
+        let size = shape.size(level);
+        Self {
+            level,
+            origin,
+            child_mask: bitvec![u64, Lsb0; 0; size],
+            value_mask: bitvec![u64, Lsb0; 0; size],
+            values: vec![background; size],
+            children: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_leaf(&self, shape: &TreeShape) -> bool {
+        self.level == shape.leaf_level()
+    }
+
+    /// Chebyshev distance, in entries, from every entry to the closest child or active entry.
+    /// Entries past the border of the node count as occupied since this node can't see
+    /// them, so the distances are the same or smaller than the ones [`VDB345::compute_sdf`]
+    /// gives a 5-4-3 tree.
+    pub fn distances(&self, shape: &TreeShape) -> Vec<u32> {
+        let log2_dim = shape.log2_dims()[self.level];
+        let dim = 1 << log2_dim;
+        let mut distances = self
+            .child_mask
+            .iter()
+            .zip(self.value_mask.iter())
+            .map(|(child, active)| if *child || *active { 0 } else { u32::MAX })
+            .collect_vec();
+
+        let (f_neighbours, b_neighbours) = sdf_neighbours();
+        let size = distances.len();
+        for (neighbours, offsets) in [
+            (f_neighbours, (0..size).collect_vec()),
+            (b_neighbours, (0..size).rev().collect_vec()),
+        ] {
+            for offset in offsets {
+                if distances[offset] == 0 {
+                    continue;
+                }
+                let child = [offset >> (2 * log2_dim), offset >> log2_dim, offset]
+                    .map(|c| (c & (dim - 1)) as i32);
+
+                for dn in &neighbours {
+                    let n = [child[0] + dn.x, child[1] + dn.y, child[2] + dn.z];
+                    let neighbour = match n.iter().all(|&c| (0..dim as i32).contains(&c)) {
+                        true => {
+                            let [x, y, z] = n.map(|c| c as usize);
+                            distances[(x << (2 * log2_dim)) | (y << log2_dim) | z]
+                        }
+                        false => 0,
+                    };
+                    distances[offset] = distances[offset].min(neighbour.saturating_add(1));
+                }
+            }
+        }
+
+        distances
+    }
+
+    /// Leaves under this node in the order they are stored, children in offset order
+    pub fn leaves<'a>(&'a self, shape: &TreeShape, leaves: &mut Vec<&'a ShapedNode<T>>) {
+        match self.is_leaf(shape) {
+            true => leaves.push(self),
+            false => self
+                .children
+                .values()
+                .for_each(|child| child.leaves(shape, leaves)),
+        }
+    }
+
+    pub fn leaves_mut<'a>(
+        &'a mut self,
+        shape: &TreeShape,
+        leaves: &mut Vec<&'a mut ShapedNode<T>>,
+    ) {
+        match self.is_leaf(shape) {
+            true => leaves.push(self),
+            false => self
+                .children
+                .values_mut()
+                .for_each(|child| child.leaves_mut(shape, leaves)),
+        }
+    }
+
+    /// Active voxels, and the active tiles with their side log2, under this node
+    fn active_values(&self, shape: &TreeShape, values: &mut Vec<([i32; 3], u32, T)>) {
+        let log2_dim = shape.total_log2_dim(self.level + 1);
+        for offset in self.value_mask.iter_ones() {
+            if !self.children.contains_key(&offset) {
+                let origin = shape.child_origin(self.level, self.origin, offset);
+                values.push((origin, log2_dim, self.values[offset]));
+            }
+        }
+
+        for child in self.children.values() {
+            child.active_values(shape, values);
+        }
+    }
+}
+
+impl<T: VdbValueType> ShapedTree<T> {
+    pub fn new(shape: TreeShape, background: T) -> Self {
+        Self {
+            shape,
+            background,
+            tiles: vec![],
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    /// Leaves in the order they are stored, root children in origin order
+    pub fn leaves(&self) -> Vec<&ShapedNode<T>> {
+        let mut leaves = vec![];
+        for node in self.nodes.values() {
+            node.leaves(&self.shape, &mut leaves);
+        }
+        leaves
+    }
+
+    /// Node of `level` containing `p`, created along with its parents if needed
+    fn node_mut(&mut self, p: [i32; 3], level: usize) -> &mut ShapedNode<T> {
+        let shape = self.shape;
+        let background = self.background;
+
+        let root_log2_dim = shape.total_log2_dim(0);
+        let origin = p.map(|c| (c >> root_log2_dim) << root_log2_dim);
+        let mut node = self
+            .nodes
+            .entry(origin)
+            .or_insert_with(|| ShapedNode::new(&shape, 0, origin, background));
+
+        for child_level in 1..=level {
+            let offset = shape.offset(child_level - 1, p);
+            let origin = shape.child_origin(child_level - 1, node.origin, offset);
+            node.child_mask.set(offset, true);
+            node.value_mask.set(offset, false);
+            node = node
+                .children
+                .entry(offset)
+                .or_insert_with(|| ShapedNode::new(&shape, child_level, origin, background));
+        }
+
+        node
+    }
+
+    /// Sets the value of the voxel at `p` and makes it active
+    pub fn set_voxel(&mut self, p: [i32; 3], value: T) {
+        self.set_cell(p, self.shape.levels(), value);
+    }
+
+    /// Makes the tile of side `1 << log2_dim` at `origin` active with `value`, split into
+    /// smaller tiles or voxels where the shape has no tile of that size
+    pub fn set_tile(
+        &mut self,
+        origin: [i32; 3],
+        log2_dim: u32,
+        value: T,
+    ) -> Result<(), TileSplitError> {
+        let (level, origins) = self.shape.tile_cells(origin, log2_dim)?;
+        for origin in origins {
+            self.set_cell(origin, level, value);
+        }
+        Ok(())
+    }
+
+    /// Sets an entry of the node of `level - 1`, a root tile for level `0`
+    fn set_cell(&mut self, p: [i32; 3], level: usize, value: T) {
+        if level == 0 {
+            self.tiles.push((p, value, true));
+            return;
+        }
+
+        let offset = self.shape.offset(level - 1, p);
+        let node = self.node_mut(p, level - 1);
+        node.value_mask.set(offset, true);
+        node.values[offset] = value;
+    }
+
+    /// Active voxels and tiles, with the side log2 of the tiles and `0` for voxels
+    pub fn active_values(&self) -> Vec<([i32; 3], u32, T)> {
+        let root_log2_dim = self.shape.total_log2_dim(0);
+        let mut values = self
+            .tiles
+            .iter()
+            .filter(|(_, _, active)| *active)
+            .map(|&(origin, value, _)| (origin, root_log2_dim, value))
+            .collect_vec();
+
+        for node in self.nodes.values() {
+            node.active_values(&self.shape, &mut values);
+        }
+        values
+    }
+
+    /// Converts `vdb` to `shape`. Tile values only hold distances in memory, so the active
+    /// tiles are given the background.
+    pub fn from_vdb345(vdb: &VDB345<T>, shape: TreeShape) -> Result<Self, TileSplitError> {
+        let background = vdb.root.background;
+        let mut tree = Self::new(shape, background);
+
+        for (&origin5, root_data) in &vdb.root.map {
+            let node5 = match root_data {
+                RootData::Tile(_, true) => {
+                    tree.set_tile(origin5, <N5<T>>::TOTAL_LOG2_D as u32, background)?;
+                    continue;
+                }
+                RootData::Tile(_, false) => continue,
+                RootData::Node(node5) => node5,
+            };
+
+            for offset in node5.value_mask.view_bits::<Lsb0>().iter_ones() {
+                if let InternalData::Tile(_) = node5.data[offset] {
+                    let origin = child_origin::<N5<T>>(origin5, offset);
+                    tree.set_tile(origin, <N4<T>>::TOTAL_LOG2_D as u32, background)?;
+                }
+            }
+
+            for (offset5, node5_data) in node5.data.iter().enumerate() {
+                let InternalData::Node(node4) = node5_data else {
+                    continue;
+                };
+                let origin4 = child_origin::<N5<T>>(origin5, offset5);
+
+                for offset in node4.value_mask.view_bits::<Lsb0>().iter_ones() {
+                    if let InternalData::Tile(_) = node4.data[offset] {
+                        let origin = child_origin::<N4<T>>(origin4, offset);
+                        tree.set_tile(origin, <N4<T>>::CHILD_TOTAL_LOG2_D as u32, background)?;
+                    }
+                }
+            }
+        }
+
+        for (p, value) in vdb.active_voxels() {
+            tree.set_voxel(p, value);
+        }
+
+        Ok(tree)
+    }
+
+    /// Converts the tree to 5-4-3, active tiles of sizes it has no tiles for are split
+    pub fn into_vdb345(self) -> Result<VDB345<T>, TileSplitError> {
+        let mut vdb = <VDB345<T>>::new();
+        vdb.root.background = self.background;
+
+        for (origin, log2_dim, value) in self.active_values() {
+            if log2_dim == 0 {
+                vdb.set_voxel(origin.into(), value);
+                continue;
+            }
+
+            let (level, origins) = TreeShape::VDB345.tile_cells(origin, log2_dim)?;
+            for origin in origins {
+                vdb.set_active_tile(origin.into(), level);
+            }
+        }
+
+        vdb.dirty = Default::default();
+        Ok(vdb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vdb::N3;
+
+    #[test]
+    fn grid_type_test() {
+        let shape = TreeShape::from_grid_type("Tree_float_6_5_4_3_HalfFloat").unwrap();
+        assert_eq!(shape.log2_dims(), &[6, 5, 4, 3]);
+        assert_eq!(shape.grid_type("float"), "Tree_float_6_5_4_3");
+        assert_eq!(
+            TreeShape::from_grid_type("Tree_float_5_4_3"),
+            Some(TreeShape::VDB345)
+        );
+        assert_eq!(
+            "4-3-2".parse::<TreeShape>(),
+            Ok(TreeShape::new(&[4, 3, 2]).unwrap())
+        );
+
+        assert_eq!(TreeShape::from_grid_type("Tree_float_1_1"), None);
+        assert_eq!(TreeShape::from_grid_type("Tree_float_7_7_7_7_7"), None);
+        assert_eq!(TreeShape::from_grid_type("Tree_float"), None);
+    }
+
+    #[test]
+    fn vdb345_offsets_test() {
+        let shape = TreeShape::VDB345;
+        assert_eq!(shape.total_log2_dim(0), 12);
+        assert_eq!(shape.total_log2_dim(3), 0);
+
+        for p in [
+            [0, 0, 0],
+            [1, 2, 3],
+            [121321, 212123, 3121],
+            [-142, 2431, 102],
+        ] {
+            let offsets = [
+                <N5<f32>>::global_to_offset(p.into()),
+                <N4<f32>>::global_to_offset(p.into()),
+                <N3<f32>>::global_to_offset(p.into()),
+            ];
+            assert_eq!([0, 1, 2].map(|level| shape.offset(level, p)), offsets);
+
+            let origin = <N4<f32>>::global_to_node(p.into()).into();
+            assert_eq!(
+                shape.child_origin(1, origin, offsets[1]),
+                child_origin::<N4<f32>>(origin, offsets[1])
+            );
+        }
+    }
+
+    #[test]
+    fn tile_cells_test() {
+        let shape = TreeShape::new(&[4, 3, 2]).unwrap();
+
+        // Fits exactly as a tile of the top nodes
+        assert_eq!(
+            shape.tile_cells([32, 0, 0], 5).unwrap(),
+            (1, vec![[32, 0, 0]])
+        );
+        // No tile of 8, split into 8 leaf sized tiles
+        let (level, origins) = shape.tile_cells([8, 0, 0], 3).unwrap();
+        assert_eq!(level, 2);
+        assert_eq!(origins.len(), 8);
+        assert!(origins.contains(&[12, 4, 4]));
+        // Into voxels
+        let (level, origins) = TreeShape::VDB345.tile_cells([0, 4, 0], 2).unwrap();
+        assert_eq!(level, 3);
+        assert_eq!(origins.len(), 64);
+        // A root tile of 6-5-4-3 fits, a tile of the top nodes of 7-7-7-7 doesn't
+        let (level, origins) = TreeShape::VDB345.tile_cells([0, 0, 0], 18).unwrap();
+        assert_eq!(level, 0);
+        assert_eq!(origins.len(), MAX_TILE_CELLS);
+        assert!(TreeShape::VDB345.tile_cells([0, 0, 0], 21).is_err());
+    }
+}
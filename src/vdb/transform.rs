@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use cgmath::{Matrix4, Vector3};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Map {
     UniformScaleMap {
         scale_values: cgmath::Vector3<f64>,
@@ -16,3 +18,52 @@ pub enum Map {
         inv_twice_scale: cgmath::Vector3<f64>,
     },
 }
+
+impl Map {
+    /// Matrix taking index space coordinates to world space
+    pub fn index_to_world(&self) -> Matrix4<f64> {
+        match self {
+            Map::UniformScaleMap { scale_values, .. } => {
+                Matrix4::from_nonuniform_scale(scale_values.x, scale_values.y, scale_values.z)
+            }
+            Map::ScaleTranslateMap {
+                translation,
+                scale_values,
+                ..
+            } => {
+                Matrix4::from_translation(*translation)
+                    * Matrix4::from_nonuniform_scale(
+                        scale_values.x,
+                        scale_values.y,
+                        scale_values.z,
+                    )
+            }
+        }
+    }
+
+    /// Cubic voxels of side `voxel_size`, index `[0, 0, 0]` sits at the world origin
+    pub fn uniform_scale(voxel_size: f64) -> Self {
+        let scale = Vector3::new(voxel_size, voxel_size, voxel_size);
+        Map::UniformScaleMap {
+            scale_values: scale,
+            voxel_size: scale,
+            scale_values_inverse: scale.map(|s| 1. / s),
+            inv_scale_sqr: scale.map(|s| 1. / (s * s)),
+            inv_twice_scale: scale.map(|s| 0.5 / s),
+        }
+    }
+
+    pub fn voxel_size(&self) -> Vector3<f64> {
+        match self {
+            Map::UniformScaleMap { voxel_size, .. } => *voxel_size,
+            Map::ScaleTranslateMap { voxel_size, .. } => *voxel_size,
+        }
+    }
+}
+
+impl Default for Map {
+    /// Index space is world space
+    fn default() -> Self {
+        Map::uniform_scale(1.)
+    }
+}
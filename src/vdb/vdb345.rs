@@ -1,9 +1,9 @@
 use cgmath::Vector3;
-use itertools::Itertools;
+use itertools::{iproduct, Itertools};
 
 use crate::vdb::data_structure::*;
 
-use super::VdbValueType;
+use super::{pool::child_origin, ErrorKind, Progress, VdbValueType};
 
 pub type N3<ValueType> = LeafNode<ValueType, 3>;
 pub type N4<ValueType> = InternalNode<ValueType, N3<ValueType>, 4>;
@@ -15,20 +15,45 @@ pub type N3Cube<ValueType> = [[[ValueType; 1 << 3]; 1 << 3]; 1 << 3];
 pub type N4Cube<ValueType> = [[[ValueType; 1 << 4]; 1 << 4]; 1 << 4];
 pub type N5Cube<ValueType> = [[[ValueType; 1 << 5]; 1 << 5]; 1 << 5];
 
-const N5DIM: usize = <N5<u32>>::DIM as usize;
-const N4DIM: usize = <N4<u32>>::DIM as usize;
-const N3DIM: usize = <N3<u32>>::DIM as usize;
+/// What the tree stores about a single voxel
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelInfo<ValueType> {
+    pub voxel: GlobalCoordinates,
+    pub active: bool,
+    /// Where the lookup of the voxel ends, a leaf value or the tile above it
+    pub endpoint: VdbEndpoint<ValueType>,
+    /// Origins of the N5, N4 and N3 nodes containing the voxel, `None` below a tile
+    pub nodes: [Option<GlobalCoordinates>; 3],
+}
+
 impl<'a, ValueType> VDB345<ValueType>
 where
     ValueType: VdbValueType,
 {
+    /// Largest distance kept in the tiles of the N5s, the N4s and the leaves by the incremental
+    /// distance updates, counted in children of the node. [`VDB345::compute_sdf`] keeps the
+    /// full distances.
+    ///
+    /// A node and its 26 neighbours hold every tile closer than this to a child of the node,
+    /// so an edit only changes distances there.
+    pub const SDF_CAPS: [usize; 3] = [
+        <N5<ValueType>>::DIM as usize + 1,
+        <N4<ValueType>>::DIM as usize + 1,
+        <N3<ValueType>>::DIM as usize + 1,
+    ];
+
     /// Sets the value `v` of a single voxel in the VDB at point `p`.
+    ///
+    /// The edited nodes are recorded in `dirty` so the GPU copy can be patched.
     pub fn set_voxel(&mut self, p: GlobalCoordinates, v: ValueType) {
         let root_key = <Root345<ValueType>>::root_key_from_coords(p);
         let bit_index_4 = <N5<ValueType>>::global_to_offset(p);
         let bit_index_3 = <N4<ValueType>>::global_to_offset(p);
         let bit_index_0 = <N3<ValueType>>::global_to_offset(p);
 
+        // Whether the N5, N4 and N3 containing `p` are new
+        let mut created = [!matches!(self.root.map.get(&root_key), Some(RootData::Node(_))); 3];
+
         let root_entry = self
             .root
             .map
@@ -46,6 +71,8 @@ where
         let node_5_entry = &mut node_5.data[bit_index_4];
         if let InternalData::Tile(..) = node_5_entry {
             *node_5_entry = InternalData::Node(Box::new(<N4<ValueType>>::new(p)));
+            created[1] = true;
+            created[2] = true;
         }
 
         node_5.child_mask[bit_index_4 >> 6] |= 1 << (bit_index_4 & (64 - 1));
@@ -53,6 +80,7 @@ where
             let node_4_entry = &mut node_4.data[bit_index_3];
             if let InternalData::Tile(..) = node_4_entry {
                 *node_4_entry = InternalData::Node(Box::new(<N3<ValueType>>::new()));
+                created[2] = true;
             }
 
             node_4.child_mask[bit_index_3 >> 6] |= 1 << (bit_index_3 & (64 - 1));
@@ -63,6 +91,72 @@ where
         } else {
             unreachable!();
         }
+
+        let origins: [[i32; 3]; 3] = [
+            root_key,
+            <N4<ValueType>>::global_to_node(p).into(),
+            <N3<ValueType>>::global_to_node(p).into(),
+        ];
+        for level in 0..3 {
+            if created[level] {
+                self.dirty.added[level].push(origins[level]);
+            }
+            // A new child changes the entries and masks of its parent
+            if level == 2 || created[level + 1] {
+                self.dirty.nodes[level].insert(origins[level]);
+            }
+        }
+    }
+
+    /// Makes the tile of `level` containing point `p` active, creating its parents.
+    ///
+    /// Level 0 is a root tile, 1 and 2 are tiles of a Node5 and of a Node4 and 3 is a voxel,
+    /// which gets the background value.
+    pub fn set_active_tile(&mut self, p: GlobalCoordinates, level: usize) {
+        let root_key = <Root345<ValueType>>::root_key_from_coords(p);
+        match level {
+            0 => {
+                self.root
+                    .map
+                    .entry(root_key)
+                    .or_insert(RootData::Tile(0, true));
+            }
+            1 | 2 => {
+                let root_entry = self
+                    .root
+                    .map
+                    .entry(root_key)
+                    .or_insert(RootData::Node(Box::new(<N5<ValueType>>::new(p))));
+                if let RootData::Tile(..) = root_entry {
+                    *root_entry = RootData::Node(Box::new(<N5<ValueType>>::new(p)));
+                }
+                let RootData::Node(node_5) = root_entry else {
+                    unreachable!()
+                };
+
+                let bit_index_4 = <N5<ValueType>>::global_to_offset(p);
+                if level == 1 {
+                    node_5.value_mask[bit_index_4 >> 6] |= 1 << (bit_index_4 & (64 - 1));
+                } else {
+                    let node_5_entry = &mut node_5.data[bit_index_4];
+                    if let InternalData::Tile(..) = node_5_entry {
+                        *node_5_entry = InternalData::Node(Box::new(<N4<ValueType>>::new(p)));
+                    }
+                    node_5.child_mask[bit_index_4 >> 6] |= 1 << (bit_index_4 & (64 - 1));
+                    node_5.value_mask[bit_index_4 >> 6] &= !(1 << (bit_index_4 & (64 - 1)));
+
+                    let InternalData::Node(node_4) = node_5_entry else {
+                        unreachable!()
+                    };
+                    let bit_index_3 = <N4<ValueType>>::global_to_offset(p);
+                    node_4.value_mask[bit_index_3 >> 6] |= 1 << (bit_index_3 & (64 - 1));
+                }
+            }
+            _ => self.set_voxel(p, self.root.background),
+        }
+
+        self.dirty.topology = true;
+        self.dirty.nodes[0].insert(root_key);
     }
 
     /// Returns the value of a single voxel in the VDB at point `p`.
@@ -105,6 +199,76 @@ where
         }
     }
 
+    /// Returns the value, activity and containing nodes of the voxel at `p`
+    pub fn inspect(&self, p: GlobalCoordinates) -> VoxelInfo<ValueType> {
+        let endpoint = match self.get_voxel(p) {
+            VdbEndpoint::Offs(offset) => VdbEndpoint::Offs(offset),
+            VdbEndpoint::Leaf(&value) => VdbEndpoint::Leaf(value),
+            VdbEndpoint::Innr(value, level) => VdbEndpoint::Innr(value, level),
+            VdbEndpoint::Root(value) => VdbEndpoint::Root(value),
+            VdbEndpoint::Bkgr(&value) => VdbEndpoint::Bkgr(value),
+        };
+        // The lookup went through every node above where it ended
+        let depth = match endpoint {
+            VdbEndpoint::Bkgr(_) | VdbEndpoint::Root(_) => 0,
+            VdbEndpoint::Innr(_, 5) => 1,
+            VdbEndpoint::Innr(..) => 2,
+            VdbEndpoint::Offs(_) | VdbEndpoint::Leaf(_) => 3,
+        };
+        let origins = [
+            <N5<ValueType>>::global_to_node(p),
+            <N4<ValueType>>::global_to_node(p),
+            <N3<ValueType>>::global_to_node(p),
+        ];
+
+        VoxelInfo {
+            voxel: p,
+            active: self.is_active(p),
+            endpoint,
+            nodes: [0, 1, 2].map(|level| (level < depth).then_some(origins[level])),
+        }
+    }
+
+    /// Returns the Node5 containing point `p`, if it is not a tile
+    pub fn node5(&self, p: GlobalCoordinates) -> Option<&N5<ValueType>> {
+        let root_key = <Root345<ValueType>>::root_key_from_coords(p);
+        match self.root.map.get(&root_key)? {
+            RootData::Node(node5) => Some(node5),
+            RootData::Tile(..) => None,
+        }
+    }
+
+    /// Returns the Node4 containing point `p`, if it is not a tile
+    pub fn node4(&self, p: GlobalCoordinates) -> Option<&N4<ValueType>> {
+        match &self.node5(p)?.data[<N5<ValueType>>::global_to_offset(p)] {
+            InternalData::Node(node4) => Some(node4),
+            InternalData::Tile(..) => None,
+        }
+    }
+
+    /// Returns the Node3 containing point `p`, if it is not a tile
+    pub fn node3(&self, p: GlobalCoordinates) -> Option<&N3<ValueType>> {
+        match &self.node4(p)?.data[<N4<ValueType>>::global_to_offset(p)] {
+            InternalData::Node(node3) => Some(node3),
+            InternalData::Tile(..) => None,
+        }
+    }
+
+    pub(crate) fn node3_mut(&mut self, p: GlobalCoordinates) -> Option<&mut N3<ValueType>> {
+        let root_key = <Root345<ValueType>>::root_key_from_coords(p);
+        let RootData::Node(node5) = self.root.map.get_mut(&root_key)? else {
+            return None;
+        };
+        let InternalData::Node(node4) = &mut node5.data[<N5<ValueType>>::global_to_offset(p)]
+        else {
+            return None;
+        };
+        match &mut node4.data[<N4<ValueType>>::global_to_offset(p)] {
+            InternalData::Node(node3) => Some(node3),
+            InternalData::Tile(..) => None,
+        }
+    }
+
     pub fn origins(&self) -> Vec<[i32; 3]> {
         let mut origins = vec![];
         for (origin, root_data) in self.root.map.iter().sorted_by_key(|(key, _)| *key) {
@@ -116,6 +280,20 @@ where
         origins
     }
 
+    /// Index space box `[min, max)` covered by the N5 nodes, `None` if the tree is empty
+    pub fn index_bounds(&self) -> Option<([i32; 3], [i32; 3])> {
+        let dim = <N5<ValueType>>::TOTAL_DIM as i32;
+        let origins = self.origins();
+        let first = *origins.first()?;
+
+        Some(origins.iter().fold((first, first.map(|c| c + dim)), |(min, max), o| {
+            (
+                [0, 1, 2].map(|i| min[i].min(o[i])),
+                [0, 1, 2].map(|i| max[i].max(o[i] + dim)),
+            )
+        }))
+    }
+
     pub fn masks(
         &self,
     ) -> (
@@ -160,109 +338,6 @@ where
         (n5_kids, n5_vals, n4_kids, n4_vals, n3_vals)
     }
 
-    pub fn atlas(&self) -> [Vec<Vec<Vec<ValueType>>>; 3] {
-        let [count_n5, count_n4, count_n3] = self.count_nodes();
-
-        let n5_atlas_dim = closest_power_of_3(count_n5);
-        let n4_atlas_dim = closest_power_of_3(count_n4);
-        let n3_atlas_dim = closest_power_of_3(count_n3);
-
-        let mut n5_atlas =
-            vec![
-                vec![vec![ValueType::zeroed(); N5DIM * n5_atlas_dim]; N5DIM * n5_atlas_dim];
-                N5DIM * n5_atlas_dim
-            ];
-        let mut n4_atlas =
-            vec![
-                vec![vec![ValueType::zeroed(); N4DIM * n4_atlas_dim]; N4DIM * n4_atlas_dim];
-                N4DIM * n4_atlas_dim
-            ];
-        let mut n3_atlas =
-            vec![
-                vec![vec![ValueType::zeroed(); N3DIM * n3_atlas_dim]; N3DIM * n3_atlas_dim];
-                N3DIM * n3_atlas_dim
-            ];
-
-        let mut n5_idx: usize = 0;
-        let mut n4_idx: usize = 0;
-        let mut n3_idx: usize = 0;
-
-        for (_, root_data) in self.root.map.iter().sorted_by_key(|(key, _)| *key) {
-            let RootData::Node(node5) = root_data else {
-                // TODO: handle node5 tiles
-                continue;
-            };
-            let n5_atlas_origin: Vector3<usize> =
-                origin_from_idx(n5_idx, n5_atlas_dim) * <N5<ValueType>>::DIM as usize;
-
-            for (offset5, node5_data) in node5.data.iter().enumerate() {
-                let n5_data_rel: Vector3<usize> = <N5<ValueType>>::offset_to_child(offset5)
-                    .map(|c| c as usize)
-                    .into();
-
-                let n5_atlas_data_pos = n5_atlas_origin + n5_data_rel;
-
-                let InternalData::Node(node4) = node5_data else {
-                    let &InternalData::Tile(node4_tile) = node5_data else {
-                        unreachable!();
-                    };
-                    n5_atlas[n5_atlas_data_pos.x][n5_atlas_data_pos.y][n5_atlas_data_pos.z] =
-                        ValueType::from_4_le_bytes(node4_tile.to_le_bytes());
-                    continue;
-                };
-                let n4_atlas_origin: Vector3<usize> =
-                    origin_from_idx(n4_idx, n4_atlas_dim) * <N4<ValueType>>::DIM as usize;
-
-                for (offset4, node4_data) in node4.data.iter().enumerate() {
-                    let n4_data_rel: Vector3<usize> = <N4<ValueType>>::offset_to_child(offset4)
-                        .map(|c| c as usize)
-                        .into();
-
-                    let n4_atlas_data_pos = n4_atlas_origin + n4_data_rel;
-
-                    let InternalData::Node(node3) = node4_data else {
-                        let &InternalData::Tile(node3_tile) = node4_data else {
-                            unreachable!();
-                        };
-                        n4_atlas[n4_atlas_data_pos.x][n4_atlas_data_pos.y][n4_atlas_data_pos.z] =
-                            ValueType::from_4_le_bytes(node3_tile.to_le_bytes());
-                        continue;
-                    };
-                    let n3_atlas_origin: Vector3<usize> =
-                        origin_from_idx(n3_idx, n3_atlas_dim) * <N3<ValueType>>::DIM as usize;
-
-                    for (offset3, node3_data) in node3.data.iter().enumerate() {
-                        let n3_data_rel: Vector3<usize> = <N3<ValueType>>::offset_to_child(offset3)
-                            .map(|c| c as usize)
-                            .into();
-
-                        let n3_atlas_data_pos = n3_atlas_origin + n3_data_rel;
-
-                        n3_atlas[n3_atlas_data_pos.x][n3_atlas_data_pos.y][n3_atlas_data_pos.z] =
-                            match node3_data {
-                                &LeafData::Value(value) => value,
-                                &LeafData::Tile(offset) => {
-                                    ValueType::from_4_le_bytes((offset as u32).to_le_bytes())
-                                }
-                            };
-                    }
-                    n4_atlas[n4_atlas_data_pos.x][n4_atlas_data_pos.y][n4_atlas_data_pos.z] =
-                        ValueType::from_4_le_bytes((n3_idx as u32).to_le_bytes());
-                    // HACK: Handle if cast to u32 overflows !!
-                    n3_idx += 1;
-                }
-                n5_atlas[n5_atlas_data_pos.x][n5_atlas_data_pos.y][n5_atlas_data_pos.z] =
-                    ValueType::from_4_le_bytes((n4_idx as u32).to_le_bytes());
-                // HACK: Handle if cast to u32 overflows !!
-                n4_idx += 1;
-            }
-
-            n5_idx += 1;
-        }
-
-        [n5_atlas, n4_atlas, n3_atlas]
-    }
-
     pub fn count_nodes(&self) -> [usize; 3] {
         let mut count: [usize; 3] = [0, 0, 0];
         for (_, root_data) in self.root.map.iter() {
@@ -286,8 +361,147 @@ where
         count
     }
 
+    /// Active voxels and their values, N5s in key order and children in offset order
+    pub fn active_voxels(&self) -> impl Iterator<Item = ([i32; 3], ValueType)> + '_ {
+        self.root
+            .map
+            .iter()
+            .sorted_by_key(|(key, _)| *key)
+            .filter_map(|(&origin5, root_data)| match root_data {
+                RootData::Node(node5) => Some((origin5, node5)),
+                RootData::Tile(..) => None,
+            })
+            .flat_map(|(origin5, node5)| {
+                node5
+                    .data
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(offset, node5_data)| match node5_data {
+                        InternalData::Node(node4) => {
+                            Some((child_origin::<N5<ValueType>>(origin5, offset), node4))
+                        }
+                        InternalData::Tile(_) => None,
+                    })
+            })
+            .flat_map(|(origin4, node4)| {
+                node4
+                    .data
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(offset, node4_data)| match node4_data {
+                        InternalData::Node(node3) => {
+                            Some((child_origin::<N4<ValueType>>(origin4, offset), node3))
+                        }
+                        InternalData::Tile(_) => None,
+                    })
+            })
+            .flat_map(|(origin3, node3)| {
+                node3
+                    .data
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(offset, node3_data)| match node3_data {
+                        LeafData::Value(value) => {
+                            Some((child_origin::<N3<ValueType>>(origin3, offset), *value))
+                        }
+                        LeafData::Tile(_) => None,
+                    })
+            })
+    }
+
+    /// Active voxels on the plane `p[axis] == position`, only visiting the nodes it crosses
+    pub fn plane_voxels(
+        &self,
+        axis: usize,
+        position: i32,
+    ) -> impl Iterator<Item = ([i32; 3], ValueType)> + '_ {
+        let crosses = move |origin: [i32; 3], log2_dim: u64| {
+            (origin[axis]..origin[axis] + (1 << log2_dim)).contains(&position)
+        };
+
+        self.root
+            .map
+            .iter()
+            .sorted_by_key(|(key, _)| *key)
+            .filter_map(move |(&origin5, root_data)| match root_data {
+                RootData::Node(node5) if crosses(origin5, <N5<ValueType>>::TOTAL_LOG2_D) => {
+                    Some((origin5, node5))
+                }
+                _ => None,
+            })
+            .flat_map(move |(origin5, node5)| {
+                node5
+                    .data
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(offset, node5_data)| {
+                        let origin4 = child_origin::<N5<ValueType>>(origin5, offset);
+                        match node5_data {
+                            InternalData::Node(node4)
+                                if crosses(origin4, <N4<ValueType>>::TOTAL_LOG2_D) =>
+                            {
+                                Some((origin4, node4))
+                            }
+                            _ => None,
+                        }
+                    })
+            })
+            .flat_map(move |(origin4, node4)| {
+                node4
+                    .data
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(offset, node4_data)| {
+                        let origin3 = child_origin::<N4<ValueType>>(origin4, offset);
+                        match node4_data {
+                            InternalData::Node(node3)
+                                if crosses(origin3, <N3<ValueType>>::TOTAL_LOG2_D) =>
+                            {
+                                Some((origin3, node3))
+                            }
+                            _ => None,
+                        }
+                    })
+            })
+            .flat_map(move |(origin3, node3)| {
+                node3
+                    .data
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(offset, node3_data)| {
+                        let p = child_origin::<N3<ValueType>>(origin3, offset);
+                        match node3_data {
+                            LeafData::Value(value) if p[axis] == position => Some((p, *value)),
+                            _ => None,
+                        }
+                    })
+            })
+    }
+
+    /// Inclusive index space bounding box of the active voxels
+    pub fn active_bounds(&self) -> Option<([i32; 3], [i32; 3])> {
+        self.active_voxels().fold(None, |bounds, (p, _)| {
+            let (min, max) = bounds.unwrap_or((p, p));
+            Some((
+                [0, 1, 2].map(|i| min[i].min(p[i])),
+                [0, 1, 2].map(|i| max[i].max(p[i])),
+            ))
+        })
+    }
+
     /// Store signed distance field information in empty voxels
     pub fn compute_sdf(&mut self) {
+        self.compute_sdf_with_progress(&Progress::default())
+            .expect("Nobody else can cancel this progress");
+    }
+
+    /// [`VDB345::compute_sdf`] counting the Node4s of both passes in `progress`.
+    ///
+    /// Stops with [`ErrorKind::Cancelled`] when `progress` is cancelled, the distances are then
+    /// only partially updated.
+    pub fn compute_sdf_with_progress(&mut self, progress: &Progress) -> Result<(), ErrorKind> {
+        progress.add_nodes_total(2 * self.count_nodes()[1] as u64);
+
         // Intialize with infinite distance
         for (_, root_data) in self.root.map.iter_mut() {
             let RootData::Node(node5) = root_data else {
@@ -324,23 +538,9 @@ where
             }
         }
 
-        let mut f_neighbours: Vec<Vector3<i32>> = vec![];
-        let mut b_neighbours: Vec<Vector3<i32>> = vec![];
+        self.sdf_capped = false;
 
-        for dy in [-1, 0, 1] {
-            for dz in [-1, 0, 1] {
-                f_neighbours.push([-1, dy, dz].into());
-                b_neighbours.push([1, dy, dz].into());
-            }
-        }
-
-        for dz in [-1, 0, 1] {
-            f_neighbours.push([0, -1, dz].into());
-            b_neighbours.push([0, 1, dz].into());
-        }
-
-        f_neighbours.push([0, 0, -1].into());
-        b_neighbours.push([0, 0, 1].into());
+        let (f_neighbours, b_neighbours) = sdf_neighbours();
 
         // Strap yourself in for the ugliest code in the world!
         // If I wanted to use safe code I would either have to use Rc<Refcell<T>> instead of Box on the VDB or
@@ -395,6 +595,11 @@ where
                             }
                         }
                         InternalData::Node(node4) => {
+                            if progress.is_cancelled() {
+                                return Err(ErrorKind::Cancelled);
+                            }
+                            progress.node_done();
+
                             let node4_ptr = &*node4 as *const Box<N4<ValueType>>;
                             for (n3i, node4_data) in node4.data.iter_mut().enumerate() {
                                 let child4 = <N4<ValueType>>::offset_to_child(n3i);
@@ -535,6 +740,11 @@ where
                             }
                         }
                         InternalData::Node(node4) => {
+                            if progress.is_cancelled() {
+                                return Err(ErrorKind::Cancelled);
+                            }
+                            progress.node_done();
+
                             let node4_ptr = &*node4 as *const Box<N4<ValueType>>;
                             for (n3i, node4_data) in node4.data.iter_mut().enumerate().rev() {
                                 let child4 = <N4<ValueType>>::offset_to_child(n3i);
@@ -667,9 +877,257 @@ where
                 }
             }
         }
+
+        Ok(())
+    }
+
+    /// Clamp the tile distances to [`VDB345::SDF_CAPS`], as the incremental distance updates
+    /// expect them
+    pub fn cap_sdf(&mut self) {
+        for (_, root_data) in self.root.map.iter_mut() {
+            let RootData::Node(node5) = root_data else {
+                continue;
+            };
+
+            for node5_data in node5.data.iter_mut() {
+                let node4 = match node5_data {
+                    InternalData::Tile(tile_value) => {
+                        *tile_value = (*tile_value).min(Self::SDF_CAPS[0] as u32);
+                        continue;
+                    }
+                    InternalData::Node(node4) => node4,
+                };
+
+                for node4_data in node4.data.iter_mut() {
+                    let node3 = match node4_data {
+                        InternalData::Tile(tile_value) => {
+                            *tile_value = (*tile_value).min(Self::SDF_CAPS[1] as u32);
+                            continue;
+                        }
+                        InternalData::Node(node3) => node3,
+                    };
+
+                    for node3_data in node3.data.iter_mut() {
+                        if let LeafData::Tile(tile_value) = node3_data {
+                            *tile_value = (*tile_value).min(Self::SDF_CAPS[2]);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.sdf_capped = true;
+    }
+
+    /// Recompute the signed distance field of the leaves around the Node3s at `origins`.
+    ///
+    /// Only those leaves and their 26 neighbours are visited, so small edits don't need a full
+    /// [`VDB345::compute_sdf`]. The distances must be capped at [`VDB345::SDF_CAPS`] with
+    /// [`VDB345::cap_sdf`] first, so the leaves further away can't get any closer to the edit.
+    /// Returns the origins of the updated leaves.
+    pub fn update_sdf_around<'b>(
+        &mut self,
+        origins: impl IntoIterator<Item = &'b [i32; 3]>,
+    ) -> Vec<[i32; 3]> {
+        let dim = <N3<ValueType>>::DIM as i32;
+        let leaves = origins
+            .into_iter()
+            .flat_map(|&[x, y, z]| {
+                iproduct!(-1..=1, -1..=1, -1..=1)
+                    .map(move |(dx, dy, dz)| [x + dx * dim, y + dy * dim, z + dz * dim])
+            })
+            .filter(|&origin| self.node3(origin.into()).is_some())
+            .sorted()
+            .dedup()
+            .collect_vec();
+
+        for &origin in &leaves {
+            let node3 = self.node3_mut(origin.into()).unwrap();
+            for node3_data in node3.data.iter_mut() {
+                if let LeafData::Tile(tile_value) = node3_data {
+                    *tile_value = Self::SDF_CAPS[2];
+                }
+            }
+        }
+
+        let (f_neighbours, b_neighbours) = sdf_neighbours();
+        let offsets = 0..<N3<ValueType>>::SIZE;
+
+        for &origin in &leaves {
+            self.update_leaf_sdf(origin.into(), offsets.clone(), &f_neighbours);
+        }
+        for &origin in leaves.iter().rev() {
+            self.update_leaf_sdf(origin.into(), offsets.clone().rev(), &b_neighbours);
+        }
+
+        leaves
+    }
+
+    fn update_leaf_sdf(
+        &mut self,
+        origin: GlobalCoordinates,
+        offsets: impl Iterator<Item = usize>,
+        neighbours: &[Vector3<i32>],
+    ) {
+        for offset in offsets {
+            let global = origin + <N3<ValueType>>::offset_to_child(offset).map(|c| c as i32);
+            let VdbEndpoint::Offs(mut tile_value) = self.get_voxel(global) else {
+                continue;
+            };
+
+            for dn in neighbours {
+                tile_value = match self.get_voxel(global + dn) {
+                    VdbEndpoint::Offs(v) => tile_value.min(v + 1),
+                    _ => 1,
+                };
+                if tile_value == 1 {
+                    break;
+                }
+            }
+
+            self.node3_mut(origin).unwrap().data[offset] = LeafData::Tile(tile_value);
+        }
+    }
+
+    /// Recompute the tile distances of the nodes of `level` around the ones containing
+    /// `points`, level 0 being the N5s and 1 the N4s.
+    ///
+    /// A new child takes the place of a tile of its parent, which brings the tiles around it
+    /// closer to a child, so `points` are the origins of the new children. Like
+    /// [`VDB345::update_sdf_around`] only the nodes and their 26 neighbours are visited.
+    /// Returns the origins of the updated nodes.
+    pub fn update_tile_sdf_around<'b>(
+        &mut self,
+        level: usize,
+        points: impl IntoIterator<Item = &'b [i32; 3]>,
+    ) -> Vec<[i32; 3]> {
+        let (dim, size) = match level {
+            0 => (<N5<ValueType>>::TOTAL_DIM, <N5<ValueType>>::SIZE),
+            _ => (<N4<ValueType>>::TOTAL_DIM, <N4<ValueType>>::SIZE),
+        };
+        let dim = dim as i32;
+        let nodes = points
+            .into_iter()
+            .map(|&point| match level {
+                0 => <N5<ValueType>>::global_to_node(point.into()).into(),
+                _ => <N4<ValueType>>::global_to_node(point.into()).into(),
+            })
+            .flat_map(|[x, y, z]: [i32; 3]| {
+                iproduct!(-1..=1, -1..=1, -1..=1)
+                    .map(move |(dx, dy, dz)| [x + dx * dim, y + dy * dim, z + dz * dim])
+            })
+            .filter(|&origin| match level {
+                0 => self.node5(origin.into()).is_some(),
+                _ => self.node4(origin.into()).is_some(),
+            })
+            .sorted()
+            .dedup()
+            .collect_vec();
+
+        for &origin in &nodes {
+            for offset in 0..size {
+                if let Some(tile_value) = self.tile_mut(level, origin, offset) {
+                    *tile_value = Self::SDF_CAPS[level] as u32;
+                }
+            }
+        }
+
+        let (f_neighbours, b_neighbours) = sdf_neighbours();
+
+        for &origin in &nodes {
+            self.update_tile_sdf(level, origin, 0..size, &f_neighbours);
+        }
+        for &origin in nodes.iter().rev() {
+            self.update_tile_sdf(level, origin, (0..size).rev(), &b_neighbours);
+        }
+
+        nodes
+    }
+
+    fn update_tile_sdf(
+        &mut self,
+        level: usize,
+        origin: [i32; 3],
+        offsets: impl Iterator<Item = usize>,
+        neighbours: &[Vector3<i32>],
+    ) {
+        let (child_dim, tile_level) = match level {
+            0 => (<N4<ValueType>>::TOTAL_DIM as i32, 5),
+            _ => (<N3<ValueType>>::TOTAL_DIM as i32, 4),
+        };
+
+        for offset in offsets {
+            let Some(&mut mut tile_value) = self.tile_mut(level, origin, offset) else {
+                continue;
+            };
+            let global: GlobalCoordinates = match level {
+                0 => child_origin::<N5<ValueType>>(origin, offset),
+                _ => child_origin::<N4<ValueType>>(origin, offset),
+            }
+            .into();
+
+            for dn in neighbours {
+                tile_value = match self.get_voxel(global + dn * child_dim) {
+                    VdbEndpoint::Innr(v, l) if l == tile_level => tile_value.min(v + 1),
+                    _ => 1,
+                };
+                if tile_value == 1 {
+                    break;
+                }
+            }
+
+            *self.tile_mut(level, origin, offset).unwrap() = tile_value;
+        }
+    }
+
+    /// Distance held by entry `offset` of the N5 (level 0) or N4 (level 1) at `origin`, `None`
+    /// when the entry is a child
+    fn tile_mut(&mut self, level: usize, origin: [i32; 3], offset: usize) -> Option<&mut u32> {
+        let p: GlobalCoordinates = origin.into();
+        let root_key = <Root345<ValueType>>::root_key_from_coords(p);
+        let RootData::Node(node5) = self.root.map.get_mut(&root_key)? else {
+            return None;
+        };
+        let node5_data = match level {
+            0 => &mut node5.data[offset],
+            _ => &mut node5.data[<N5<ValueType>>::global_to_offset(p)],
+        };
+        let data = match (level, node5_data) {
+            (0, InternalData::Tile(tile_value)) => return Some(tile_value),
+            (0, InternalData::Node(_)) => return None,
+            (_, InternalData::Node(node4)) => &mut node4.data[offset],
+            (_, InternalData::Tile(_)) => return None,
+        };
+        match data {
+            InternalData::Tile(tile_value) => Some(tile_value),
+            InternalData::Node(_) => None,
+        }
     }
 }
 
+/// Neighbours visited by the forward and backward passes of the distance transform
+pub(crate) fn sdf_neighbours() -> (Vec<Vector3<i32>>, Vec<Vector3<i32>>) {
+    let mut f_neighbours: Vec<Vector3<i32>> = vec![];
+    let mut b_neighbours: Vec<Vector3<i32>> = vec![];
+
+    for dy in [-1, 0, 1] {
+        for dz in [-1, 0, 1] {
+            f_neighbours.push([-1, dy, dz].into());
+            b_neighbours.push([1, dy, dz].into());
+        }
+    }
+
+    for dz in [-1, 0, 1] {
+        f_neighbours.push([0, -1, dz].into());
+        b_neighbours.push([0, 1, dz].into());
+    }
+
+    f_neighbours.push([0, 0, -1].into());
+    b_neighbours.push([0, 0, 1].into());
+
+    (f_neighbours, b_neighbours)
+}
+
 fn arr32_from_arr64<const SIZE: usize>(arr: &[u64; SIZE]) -> [u32; SIZE * 2] {
     let mut result = [0u32; SIZE * 2];
 
@@ -681,18 +1139,6 @@ fn arr32_from_arr64<const SIZE: usize>(arr: &[u64; SIZE]) -> [u32; SIZE * 2] {
     result
 }
 
-fn closest_power_of_3(n: usize) -> usize {
-    let mut i = 0;
-    while i * i * i < n {
-        i += 1;
-    }
-    i
-}
-
-fn origin_from_idx(idx: usize, dim: usize) -> Vector3<usize> {
-    (idx % dim, (idx / dim) % dim, idx / (dim * dim)).into()
-}
-
 #[cfg(test)]
 mod tests {
     use std::thread;
@@ -722,6 +1168,79 @@ mod tests {
         handler.join().unwrap_or_else(|_| panic!("Test Failed"));
     }
 
+    #[test]
+    fn inspect_test() {
+        let builder = thread::Builder::new()
+            .name("inspect_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u8>>::new();
+                vdb.set_voxel([-3, 9, 4100].into(), 7);
+
+                let info = vdb.inspect([-3, 9, 4100].into());
+                assert!(info.active);
+                assert_eq!(info.endpoint, VdbEndpoint::Leaf(7));
+                assert_eq!(
+                    info.nodes,
+                    [
+                        Some([-4096, 0, 4096].into()),
+                        Some([-128, 0, 4096].into()),
+                        Some([-8, 8, 4096].into()),
+                    ]
+                );
+
+                let info = vdb.inspect([100, 100, 100].into());
+                assert!(!info.active);
+                assert_eq!(info.endpoint, VdbEndpoint::Bkgr(0));
+                assert_eq!(info.nodes, [None; 3]);
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn plane_voxels_test() {
+        let builder = thread::Builder::new()
+            .name("plane_voxels_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u8>>::new();
+                let points = [[0, 0, 0], [3, 9, -40], [3, 5000, 7], [4, 1, 1], [-3, 2, 2]];
+                for (i, &point) in points.iter().enumerate() {
+                    vdb.set_voxel(point.into(), i as u8);
+                }
+
+                let plane = vdb.plane_voxels(0, 3).sorted().collect_vec();
+                assert_eq!(plane, vec![([3, 9, -40], 1), ([3, 5000, 7], 2)]);
+
+                let expected = vdb.active_voxels().filter(|(p, _)| p[1] == 1).collect_vec();
+                assert_eq!(vdb.plane_voxels(1, 1).collect_vec(), expected);
+                assert_eq!(vdb.plane_voxels(2, 100).count(), 0);
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn index_bounds_test() {
+        let builder = thread::Builder::new()
+            .name("index_bounds_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut vdb = <VDB345<u8>>::new();
+                assert_eq!(vdb.index_bounds(), None);
+
+                vdb.set_voxel([5, 6, 7].into(), 1);
+                vdb.set_voxel([-1, 5000, 0].into(), 1);
+                assert_eq!(vdb.index_bounds(), Some(([-4096, 0, 0], [4096, 8192, 4096])));
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
     #[test]
     fn compute_sdf_test() {
         let builder = thread::Builder::new()
@@ -739,4 +1258,62 @@ mod tests {
             .unwrap();
         handler.join().unwrap_or_else(|_| panic!("Test Failed"));
     }
+
+    #[test]
+    fn update_sdf_around_test() {
+        let builder = thread::Builder::new()
+            .name("update_sdf_around_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let mut edited = <VDB345<u8>>::new();
+                edited.set_voxel([1, 2, 3].into(), 1);
+                assert_eq!(edited.dirty.added, [[[0, 0, 0]]; 3].map(Vec::from));
+                edited.compute_sdf();
+                edited.cap_sdf();
+                edited.dirty = Default::default();
+
+                // Same leaf, so no node is created
+                edited.set_voxel([6, 5, 4].into(), 2);
+                assert!(!edited.dirty.topology);
+                assert!(edited.dirty.added.iter().all(Vec::is_empty));
+                assert_eq!(edited.dirty.nodes[2].len(), 1);
+                let updated = edited.update_sdf_around(&edited.dirty.nodes[2].clone());
+                assert_eq!(updated, vec![[0, 0, 0]]);
+
+                // A new leaf in the same N4, which gets its entry rewritten
+                edited.dirty = Default::default();
+                edited.set_voxel([20, 2, 3].into(), 3);
+                assert_eq!(edited.dirty.added, [vec![], vec![], vec![[16, 0, 0]]]);
+                assert!(edited.dirty.nodes[0].is_empty());
+                assert!(edited.dirty.nodes[1].contains(&[0, 0, 0]));
+                let added = edited.dirty.added[2].clone();
+                let updated = edited.update_tile_sdf_around(1, &added);
+                assert_eq!(updated, vec![[0, 0, 0]]);
+                let updated = edited.update_sdf_around(&edited.dirty.nodes[2].clone());
+                assert_eq!(updated, vec![[16, 0, 0]]);
+
+                let mut full = <VDB345<u8>>::new();
+                full.set_voxel([1, 2, 3].into(), 1);
+                full.set_voxel([6, 5, 4].into(), 2);
+                full.set_voxel([20, 2, 3].into(), 3);
+                full.compute_sdf();
+                full.cap_sdf();
+
+                assert_eq!(edited.root, full.root);
+                let max_leaf_distance = full
+                    .node3([16, 0, 0].into())
+                    .unwrap()
+                    .data
+                    .iter()
+                    .filter_map(|data| match data {
+                        &LeafData::Tile(distance) => Some(distance),
+                        LeafData::Value(_) => None,
+                    })
+                    .max();
+                assert!(max_leaf_distance <= Some(<VDB345<u8>>::SDF_CAPS[2]));
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
 }
@@ -0,0 +1,326 @@
+use cgmath::{InnerSpace, Vector3};
+
+use super::{Map, VDB345};
+
+/// Furthest a voxel center can be from a triangle while the voxel still touches it
+const HALF_DIAGONAL: f64 = 0.8660254037844386;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MeshError {
+    #[error("Line {0}: invalid vertex")]
+    InvalidVertex(usize),
+    #[error("Line {0}: invalid face")]
+    InvalidFace(usize),
+    #[error("Line {line}: vertex {index} does not exist")]
+    MissingVertex { line: usize, index: i64 },
+}
+
+/// Triangle mesh, faces index into `vertices`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<Vector3<f64>>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    /// Parses the vertices and faces of a Wavefront OBJ file, everything else is ignored.
+    ///
+    /// Polygons are split into triangle fans.
+    pub fn from_obj(source: &str) -> Result<Self, MeshError> {
+        let mut mesh = Mesh::default();
+
+        for (idx, line) in source.lines().enumerate() {
+            let line_number = idx + 1;
+            let mut tokens = line.split_whitespace();
+
+            match tokens.next() {
+                Some("v") => {
+                    let coords = tokens
+                        .take(3)
+                        .map(str::parse::<f64>)
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| MeshError::InvalidVertex(line_number))?;
+                    let &[x, y, z] = coords.as_slice() else {
+                        return Err(MeshError::InvalidVertex(line_number));
+                    };
+                    mesh.vertices.push(Vector3::new(x, y, z));
+                }
+                Some("f") => {
+                    let face = tokens
+                        .map(|token| mesh.vertex_index(token, line_number))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if face.len() < 3 {
+                        return Err(MeshError::InvalidFace(line_number));
+                    }
+                    for i in 1..face.len() - 1 {
+                        mesh.triangles.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    /// Index of a face vertex like `3`, `3/1/2` or `-1`, negative indices count back
+    fn vertex_index(&self, token: &str, line: usize) -> Result<usize, MeshError> {
+        let index = token
+            .split('/')
+            .next()
+            .and_then(|index| index.parse::<i64>().ok())
+            .ok_or(MeshError::InvalidFace(line))?;
+
+        let count = self.vertices.len() as i64;
+        let resolved = match index {
+            i if i > 0 => i - 1,
+            i => count + i,
+        };
+        match (0..count).contains(&resolved) {
+            true => Ok(resolved as usize),
+            false => Err(MeshError::MissingVertex { line, index }),
+        }
+    }
+}
+
+impl VDB345<f32> {
+    /// Activates the voxels of a `dims` sized dense volume that differ from `background`.
+    ///
+    /// `values` are stored x major and z minor, the same order as node offsets.
+    pub fn from_dense(dims: [usize; 3], values: &[f32], background: f32) -> Self {
+        assert_eq!(values.len(), dims.iter().product::<usize>());
+
+        let mut vdb = Self::new();
+        vdb.root.background = background;
+
+        let mut values = values.iter();
+        for x in 0..dims[0] {
+            for y in 0..dims[1] {
+                for z in 0..dims[2] {
+                    let &value = values.next().unwrap();
+                    if value != background {
+                        vdb.set_voxel([x as i32, y as i32, z as i32].into(), value);
+                    }
+                }
+            }
+        }
+
+        vdb
+    }
+
+    /// Activates every voxel that touches a triangle of `mesh`, with its world space distance
+    /// to the closest triangle as value.
+    ///
+    /// This is a thin shell, not a signed level set: the inside is not filled and distances
+    /// are only stored within half a voxel diagonal of the surface. Each triangle visits the
+    /// voxels of its bounding box, so very large triangles need a coarse `voxel_size`.
+    pub fn from_mesh(mesh: &Mesh, voxel_size: f64) -> Self {
+        let mut vdb = Self::new();
+        vdb.transform = Map::uniform_scale(voxel_size);
+        vdb.root.background = (HALF_DIAGONAL * voxel_size) as f32;
+
+        for triangle in &mesh.triangles {
+            // Index space, voxel centers are on integer coordinates
+            let [a, b, c] = triangle.map(|i| mesh.vertices[i] / voxel_size);
+            let lowest = |i: usize| a[i].min(b[i]).min(c[i]) - HALF_DIAGONAL;
+            let highest = |i: usize| a[i].max(b[i]).max(c[i]) + HALF_DIAGONAL;
+            let min = [0, 1, 2].map(|i| lowest(i).floor() as i32);
+            let max = [0, 1, 2].map(|i| highest(i).ceil() as i32);
+
+            for x in min[0]..=max[0] {
+                for y in min[1]..=max[1] {
+                    for z in min[2]..=max[2] {
+                        let p = Vector3::new(x as f64, y as f64, z as f64);
+                        let distance = (closest_point_on_triangle(p, a, b, c) - p).magnitude();
+                        if distance > HALF_DIAGONAL {
+                            continue;
+                        }
+
+                        let value = (distance * voxel_size) as f32;
+                        let voxel = [x, y, z].into();
+                        let closer = match vdb.get_voxel(voxel) {
+                            super::VdbEndpoint::Leaf(&current) => value < current,
+                            _ => true,
+                        };
+                        if closer {
+                            vdb.set_voxel(voxel, value);
+                        }
+                    }
+                }
+            }
+        }
+
+        vdb
+    }
+}
+
+/// Closest point to `p` on triangle `abc`, from Ericson's Real-Time Collision Detection
+fn closest_point_on_triangle(
+    p: Vector3<f64>,
+    a: Vector3<f64>,
+    b: Vector3<f64>,
+    c: Vector3<f64>,
+) -> Vector3<f64> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0. && d2 <= 0. {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0. && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0. && d1 >= 0. && d3 <= 0. {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0. && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0. && d2 >= 0. && d6 <= 0. {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0. && d4 - d3 >= 0. && d5 - d6 >= 0. {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    // Degenerate triangles have no area to project onto
+    let denom = va + vb + vc;
+    if denom == 0. {
+        return a;
+    }
+    a + ab * (vb / denom) + ac * (vc / denom)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use itertools::Itertools;
+
+    use super::*;
+
+    fn spawn_test(name: &str, test: fn()) {
+        let builder = thread::Builder::new()
+            .name(name.into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder.spawn(test).unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn test_from_dense() {
+        spawn_test("from_dense_test", || {
+            let dims = [3, 20, 2];
+            let values = (0..dims.iter().product::<usize>())
+                .map(|i| if i % 7 == 0 { i as f32 } else { -1. })
+                .collect_vec();
+
+            let vdb = VDB345::from_dense(dims, &values, -1.);
+
+            let expected = values
+                .iter()
+                .enumerate()
+                .filter(|(_, &v)| v != -1.)
+                .map(|(i, &v)| {
+                    let (x, y, z) = (i / (dims[1] * dims[2]), i / dims[2] % dims[1], i % dims[2]);
+                    ([x as i32, y as i32, z as i32], v)
+                })
+                .sorted_by_key(|(p, _)| *p)
+                .collect_vec();
+            let voxels = vdb.active_voxels().sorted_by_key(|(p, _)| *p).collect_vec();
+            assert_eq!(voxels, expected);
+            assert_eq!(vdb.root.background, -1.);
+        });
+    }
+
+    #[test]
+    fn test_from_obj() {
+        let source = "
+            # quad with texture and normal indices
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            f 1/1/1 2/1/1 3/1/1 -1/1/1
+        ";
+        let mesh = Mesh::from_obj(source).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+
+        assert!(matches!(
+            Mesh::from_obj("v 0 0 0\nf 1 2 3"),
+            Err(MeshError::MissingVertex { line: 2, index: 2 })
+        ));
+        assert!(matches!(
+            Mesh::from_obj("v 0 zero 0"),
+            Err(MeshError::InvalidVertex(1))
+        ));
+        assert!(matches!(
+            Mesh::from_obj("v 0 0 0\nf 1 1"),
+            Err(MeshError::InvalidFace(2))
+        ));
+    }
+
+    #[test]
+    fn test_from_mesh() {
+        spawn_test("from_mesh_test", || {
+            // Square in the z = 0 plane, 10 by 10 voxels of size 0.5
+            let mesh = Mesh {
+                vertices: vec![
+                    Vector3::new(0., 0., 0.),
+                    Vector3::new(5., 0., 0.),
+                    Vector3::new(5., 5., 0.),
+                    Vector3::new(0., 5., 0.),
+                ],
+                triangles: vec![[0, 1, 2], [0, 2, 3]],
+            };
+            let vdb = VDB345::from_mesh(&mesh, 0.5);
+            assert_eq!(vdb.transform, Map::uniform_scale(0.5));
+
+            let voxels = vdb.active_voxels().collect_vec();
+            // The plane goes through the voxel centers, the neighbour layers are 1 voxel away
+            assert_eq!(voxels.len(), 11 * 11);
+            for (p, value) in voxels {
+                assert!((0..=10).contains(&p[0]) && (0..=10).contains(&p[1]));
+                assert_eq!(p[2], 0);
+                assert_eq!(value, 0.);
+            }
+        });
+    }
+
+    #[test]
+    fn test_closest_point_on_triangle() {
+        let [a, b, c] = [
+            Vector3::new(0., 0., 0.),
+            Vector3::new(2., 0., 0.),
+            Vector3::new(0., 2., 0.),
+        ];
+        let closest = |p| closest_point_on_triangle(p, a, b, c);
+
+        assert_eq!(
+            closest(Vector3::new(0.5, 0.5, 3.)),
+            Vector3::new(0.5, 0.5, 0.)
+        );
+        assert_eq!(closest(Vector3::new(-1., -1., 0.)), a);
+        assert_eq!(closest(Vector3::new(3., -1., 0.)), b);
+        assert_eq!(closest(Vector3::new(1., -1., 0.)), Vector3::new(1., 0., 0.));
+        assert_eq!(closest(Vector3::new(2., 2., 0.)), Vector3::new(1., 1., 0.));
+    }
+}
@@ -1,41 +1,95 @@
-use core::fmt;
-use std::{fmt::Write, mem::size_of};
+use std::{collections::BTreeMap, io::Write};
 
 use bytes::{self, BufMut, BytesMut};
-use cgmath::num_traits::ToBytes;
+use cgmath::Vector3;
+use flate2::write::ZlibEncoder;
 use half::f16;
+use itertools::Itertools;
+
+use super::{
+    Compression, InternalData, LeafData, Map, MetadataValue, NodeMetaData, RootData, ShapedNode,
+    ShapedTree, TileSplitError, TreeShape, VdbValueType, VDB345,
+};
+
+/// Version of the file format that is written, the reader needs at least 222 for masks
+const FILE_VERSION: u32 = 224;
+
+/// Level used by OpenVDB for both zip and blosc
+const COMPRESSION_LEVEL: u32 = 9;
+
+/// Blosc adds at most this many bytes to its input
+const BLOSC_MAX_OVERHEAD: usize = 16;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WriteError {
+    #[error("Could not zip node values: {0}")]
+    Zip(#[from] std::io::Error),
+    #[error("Blosc and zip compression can't be combined")]
+    InvalidCompression,
+    #[error("{0}")]
+    TileSplit(#[from] TileSplitError),
+}
 
-use super::{InternalData, LeafData, RootData, VdbValueType, N3, N4, N5, VDB345};
+/// How the grids of a file are encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WriteOptions {
+    pub compression: Compression,
+    /// Store values as 16 bit floats, they are read back as f32
+    pub half_float: bool,
+    /// Node configuration of the trees in the file, grids are converted to it
+    pub shape: TreeShape,
+}
 
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::ZIP | Compression::ACTIVE_MASK,
+            half_float: false,
+            shape: TreeShape::VDB345,
+        }
+    }
+}
+
+/// Encodes `grids` as a VDB file the [`VdbReader`](super::VdbReader) and OpenVDB can read.
+///
+/// Grids keep the name of their descriptor. Their values must be `f32`s or the bits of
+/// `f32`s, as read from float grids. Leaf voxels that are not active are written as the
+/// background, and so are the internal tiles since they only hold distances in memory.
 pub fn write_vdb<T: VdbValueType>(
     b: &mut BytesMut,
-    vdb: &VDB345<T>,
-    mat: [[f64; 4]; 4],
-) -> fmt::Result {
+    grids: &[&VDB345<T>],
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    if options
+        .compression
+        .contains(Compression::ZIP | Compression::BLOSC)
+    {
+        return Err(WriteError::InvalidCompression);
+    }
+
     // Magic number
-    b.write_str(" BDV")?;
+    b.put_slice(b" BDV");
     b.put_bytes(0, 4);
 
-    // File version
-    b.put_u32_le(224);
+    b.put_u32_le(FILE_VERSION);
 
     // Library Version
     b.put_u32_le(8);
     b.put_u32_le(1);
 
-    // No grid offsets
-    b.put_u8(0);
+    // Grid offsets are always written
+    b.put_u8(1);
 
     // Temporary UUID
-    b.write_str("d2b59639-ac2f-4047-9c50-9648f951180c")?;
+    b.put_slice(b"d2b59639-ac2f-4047-9c50-9648f951180c");
 
     // Metadata
     b.put_u32_le(0);
 
-    // # of grids
-    b.put_u32_le(1);
-
-    write_grid(b, vdb, mat)?;
+    b.put_u32_le(grids.len() as u32);
+    for vdb in grids {
+        write_grid(b, vdb, options)?;
+    }
 
     Ok(())
 }
@@ -43,86 +97,308 @@ pub fn write_vdb<T: VdbValueType>(
 fn write_grid<T: VdbValueType>(
     b: &mut BytesMut,
     vdb: &VDB345<T>,
-    mat: [[f64; 4]; 4],
-) -> fmt::Result {
-    // Grid name
-    // @HACK: Actually use the grid descriptor here
-    write_len_based_str(b, "woxel")?;
-
-    // Grid Type
-    // @HACK: This should be related to the generic T type not just a random float
-    write_len_based_str(b, "Tree_float_5_4_3")?;
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    write_len_based_str(b, &vdb.grid_descriptor.name);
+    write_len_based_str(b, &grid_type(options));
 
     // No instance parent
-    b.put_u32_le(0);
+    write_len_based_str(b, "");
+
+    // Grid, block and end positions are patched once they are known
+    let positions = b.len();
+    b.put_u64_le((positions + 3 * std::mem::size_of::<u64>()) as u64);
+    b.put_u64_le(0);
+    b.put_u64_le(0);
+
+    b.put_u32_le(options.compression.bits());
+
+    write_metadata(b, vdb, options);
+    write_transform(b, &vdb.transform);
+
+    if options.shape == TreeShape::VDB345 {
+        write_topology(b, vdb, options)?;
+        patch_u64(b, positions + 8, b.len() as u64);
+        write_leaves(b, vdb, options)?;
+    } else {
+        let tree = ShapedTree::from_vdb345(vdb, options.shape)?;
+        write_shaped_topology(b, &tree, options)?;
+        patch_u64(b, positions + 8, b.len() as u64);
+        write_shaped_leaves(b, &tree, options)?;
+    }
+    patch_u64(b, positions + 16, b.len() as u64);
+
+    Ok(())
+}
 
-    // Grid descriptor stream position
-    b.put_u64((b.len() + (size_of::<u64>() * 3)) as u64);
-    b.put_u64(0);
-    b.put_u64(0);
+fn grid_type(options: &WriteOptions) -> String {
+    let grid_type = options.shape.grid_type("float");
+    match options.half_float {
+        true => grid_type + "_HalfFloat",
+        false => grid_type,
+    }
+}
 
-    // No compression
-    b.put_u32_le(0);
+fn patch_u64(b: &mut BytesMut, pos: usize, value: u64) {
+    b[pos..pos + 8].copy_from_slice(&value.to_le_bytes());
+}
 
-    write_metadata(b)?;
-    write_transform(b, mat)?;
-    write_tree(b, vdb)?;
+fn write_metadata<T: VdbValueType>(b: &mut BytesMut, vdb: &VDB345<T>, options: &WriteOptions) {
+    // Sorted so the same grid is always encoded the same way
+    let mut meta_data: BTreeMap<&str, MetadataValue> = vdb
+        .grid_descriptor
+        .meta_data
+        .0
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.clone()))
+        .collect();
+
+    meta_data
+        .entry("class")
+        .or_insert(MetadataValue::String("unknown".to_string()));
+    meta_data.insert(
+        "file_compression",
+        MetadataValue::String(compression_name(options.compression)),
+    );
+    meta_data.insert(
+        "is_saved_as_half_float",
+        MetadataValue::Bool(options.half_float),
+    );
+    meta_data.insert(
+        "file_voxel_count",
+        MetadataValue::I64(vdb.active_voxels().count() as i64),
+    );
+    match vdb.active_bounds() {
+        Some((min, max)) => {
+            meta_data.insert("file_bbox_min", MetadataValue::Vec3i(min.into()));
+            meta_data.insert("file_bbox_max", MetadataValue::Vec3i(max.into()));
+        }
+        None => {
+            meta_data.remove("file_bbox_min");
+            meta_data.remove("file_bbox_max");
+        }
+    }
 
-    Ok(())
+    b.put_u32_le(meta_data.len() as u32);
+    for (name, value) in &meta_data {
+        write_len_based_str(b, name);
+        write_metadata_value(b, value);
+    }
 }
 
-fn write_tree<T: VdbValueType>(b: &mut BytesMut, vdb: &VDB345<T>) -> fmt::Result {
-    // Magic
-    b.put_u32_le(1);
+fn write_metadata_value(b: &mut BytesMut, value: &MetadataValue) {
+    match value {
+        MetadataValue::String(string) => {
+            write_len_based_str(b, "string");
+            write_len_based_str(b, string);
+        }
+        MetadataValue::Vec3i(v) => {
+            write_len_based_str(b, "vec3i");
+            b.put_u32_le(12);
+            b.put_i32_le(v.x);
+            b.put_i32_le(v.y);
+            b.put_i32_le(v.z);
+        }
+        MetadataValue::I32(v) => {
+            write_len_based_str(b, "int32");
+            b.put_u32_le(4);
+            b.put_i32_le(*v);
+        }
+        MetadataValue::I64(v) => {
+            write_len_based_str(b, "int64");
+            b.put_u32_le(8);
+            b.put_i64_le(*v);
+        }
+        MetadataValue::Float(v) => {
+            write_len_based_str(b, "float");
+            b.put_u32_le(4);
+            b.put_f32_le(*v);
+        }
+        MetadataValue::Bool(v) => {
+            write_len_based_str(b, "bool");
+            b.put_u32_le(1);
+            b.put_u8(*v as u8);
+        }
+        MetadataValue::Unknown { name, data } => {
+            write_len_based_str(b, name);
+            b.put_u32_le(data.len() as u32);
+            b.put_slice(data);
+        }
+    }
+}
 
-    // Root node background value
-    b.put_u32_le(vdb.root.background.copy_bytes_to_u32());
+/// Name OpenVDB gives to `compression` in the grid metadata
+pub fn compression_name(compression: Compression) -> String {
+    let names = [
+        (Compression::ZIP, "zip"),
+        (Compression::BLOSC, "blosc"),
+        (Compression::ACTIVE_MASK, "active values"),
+    ];
+    let name = names
+        .iter()
+        .filter(|(flag, _)| compression.contains(*flag))
+        .map(|(_, name)| *name)
+        .join(" + ");
+
+    match name.is_empty() {
+        true => "none".to_string(),
+        false => name,
+    }
+}
 
-    // Number of top level tiles
-    b.put_u32_le(0);
+fn write_transform(b: &mut BytesMut, map: &Map) {
+    match map {
+        Map::UniformScaleMap {
+            scale_values,
+            voxel_size,
+            scale_values_inverse,
+            inv_scale_sqr,
+            inv_twice_scale,
+        } => {
+            write_len_based_str(b, "UniformScaleMap");
+            for v in [
+                scale_values,
+                voxel_size,
+                scale_values_inverse,
+                inv_scale_sqr,
+                inv_twice_scale,
+            ] {
+                write_vec3d(b, v);
+            }
+        }
+        Map::ScaleTranslateMap {
+            translation,
+            scale_values,
+            voxel_size,
+            scale_values_inverse,
+            inv_scale_sqr,
+            inv_twice_scale,
+        } => {
+            write_len_based_str(b, "ScaleTranslateMap");
+            for v in [
+                translation,
+                scale_values,
+                voxel_size,
+                scale_values_inverse,
+                inv_scale_sqr,
+                inv_twice_scale,
+            ] {
+                write_vec3d(b, v);
+            }
+        }
+    }
+}
 
-    // Number of 5 nodes
-    b.put_u32_le(vdb.root.map.len() as u32);
+fn write_vec3d(b: &mut BytesMut, v: &Vector3<f64>) {
+    b.put_f64_le(v.x);
+    b.put_f64_le(v.y);
+    b.put_f64_le(v.z);
+}
 
-    // Iterate node 5s
-    for (_, node5_data) in &vdb.root.map {
-        let RootData::Node(node5) = node5_data else {
-            continue;
-        };
-        write_node5_header(b, node5);
+fn write_topology<T: VdbValueType>(
+    b: &mut BytesMut,
+    vdb: &VDB345<T>,
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    // Buffer count
+    b.put_u32_le(1);
+
+    let background = value_to_f32(vdb.root.background);
+    b.put_f32_le(background);
+
+    let sorted_root = vdb.root.map.iter().sorted_by_key(|(key, _)| *key);
+    let tiles = sorted_root
+        .clone()
+        .filter_map(|(key, root_data)| match root_data {
+            RootData::Tile(value, active) => Some((key, value, active)),
+            RootData::Node(_) => None,
+        })
+        .collect_vec();
+    let node5s = sorted_root
+        .filter_map(|(key, root_data)| match root_data {
+            RootData::Node(node5) => Some((key, node5)),
+            RootData::Tile(..) => None,
+        })
+        .collect_vec();
+
+    b.put_u32_le(tiles.len() as u32);
+    b.put_u32_le(node5s.len() as u32);
+
+    for (key, value, active) in tiles {
+        key.iter().for_each(|&c| b.put_i32_le(c));
+        b.put_u32_le(*value);
+        b.put_u8(*active as u8);
+    }
 
-        for node4_data in &node5.data {
-            let InternalData::Node(node4) = node4_data else {
+    for (key, node5) in node5s {
+        key.iter().for_each(|&c| b.put_i32_le(c));
+        write_internal_header(b, &node5.child_mask, &node5.value_mask, background, options)?;
+
+        for node5_data in &node5.data {
+            let InternalData::Node(node4) = node5_data else {
                 continue;
             };
+            write_internal_header(b, &node4.child_mask, &node4.value_mask, background, options)?;
 
-            write_node4_header(b, node4);
-            for node3_data in &node4.data {
-                let InternalData::Node(node3) = node3_data else {
+            for node4_data in &node4.data {
+                let InternalData::Node(node3) = node4_data else {
                     continue;
                 };
-
-                write_node3_header(b, node3);
+                write_mask(b, &node3.value_mask);
             }
         }
     }
 
-    for (_, node5_data) in &vdb.root.map {
-        let RootData::Node(node5) = node5_data else {
+    Ok(())
+}
+
+fn write_internal_header(
+    b: &mut BytesMut,
+    child_mask: &[u64],
+    value_mask: &[u64],
+    background: f32,
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    write_mask(b, child_mask);
+    write_mask(b, value_mask);
+
+    let values = vec![background; value_mask.len() * 64];
+    write_values(b, &values, value_mask, options)
+}
+
+fn write_leaves<T: VdbValueType>(
+    b: &mut BytesMut,
+    vdb: &VDB345<T>,
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    let background = value_to_f32(vdb.root.background);
+
+    // Same order as the topology, which is the order the leaves are read in
+    for (_, root_data) in vdb.root.map.iter().sorted_by_key(|(key, _)| *key) {
+        let RootData::Node(node5) = root_data else {
             continue;
         };
 
-        for node4_data in &node5.data {
-            let InternalData::Node(node4) = node4_data else {
+        for node5_data in &node5.data {
+            let InternalData::Node(node4) = node5_data else {
                 continue;
             };
 
-            for node3_data in &node4.data {
-                let InternalData::Node(node3) = node3_data else {
+            for node4_data in &node4.data {
+                let InternalData::Node(node3) = node4_data else {
                     continue;
                 };
 
-                write_node3_data(b, node3);
+                write_mask(b, &node3.value_mask);
+                let values = node3
+                    .data
+                    .iter()
+                    .map(|data| match data {
+                        LeafData::Value(value) => value_to_f32(*value),
+                        LeafData::Tile(_) => background,
+                    })
+                    .collect_vec();
+                write_values(b, &values, &node3.value_mask, options)?;
             }
         }
     }
@@ -130,120 +406,189 @@ fn write_tree<T: VdbValueType>(b: &mut BytesMut, vdb: &VDB345<T>) -> fmt::Result
     Ok(())
 }
 
-fn write_node5_header<T: VdbValueType>(b: &mut BytesMut, node5: &Box<N5<T>>) {
-    b.put_i32(node5.origin[0]);
-    b.put_i32(node5.origin[1]);
-    b.put_i32(node5.origin[2]);
+fn write_shaped_topology<T: VdbValueType>(
+    b: &mut BytesMut,
+    tree: &ShapedTree<T>,
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    // Buffer count
+    b.put_u32_le(1);
+    b.put_f32_le(value_to_f32(tree.background));
 
-    for word in node5.child_mask {
-        b.put_u64(word);
-    }
+    b.put_u32_le(tree.tiles.len() as u32);
+    b.put_u32_le(tree.nodes.len() as u32);
 
-    for word in node5.value_mask {
-        b.put_u64(word);
+    for (origin, value, active) in &tree.tiles {
+        origin.iter().for_each(|&c| b.put_i32_le(c));
+        b.put_f32_le(value_to_f32(*value));
+        b.put_u8(*active as u8);
     }
 
-    // Write uncompressed node values, 6 = no compression
-    b.put_u8(6);
-
-    // Write values of the tiles
-    for i in 0..(node5.value_mask.len() * 64) {
-        let value = &node5.data[i];
-        if let InternalData::Tile(value) = value {
-            b.put_u32_le(value.copy_bytes_to_u32());
-        }
+    for (origin, node) in &tree.nodes {
+        origin.iter().for_each(|&c| b.put_i32_le(c));
+        write_shaped_node(b, node, &tree.shape, options)?;
     }
-}
 
-fn write_node4_header<T: VdbValueType>(b: &mut BytesMut, node4: &Box<N4<T>>) {
-    for word in node4.child_mask {
-        b.put_u64(word);
-    }
+    Ok(())
+}
 
-    for word in node4.value_mask {
-        b.put_u64(word);
+/// Writes the header of an internal node followed by its children, or the value mask of a leaf
+fn write_shaped_node<T: VdbValueType>(
+    b: &mut BytesMut,
+    node: &ShapedNode<T>,
+    shape: &TreeShape,
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    if node.is_leaf(shape) {
+        write_mask(b, node.value_mask.as_raw_slice());
+        return Ok(());
     }
 
-    // Write uncompressed node values, 6 = no compression
-    b.put_u8(6);
+    write_mask(b, node.child_mask.as_raw_slice());
+    write_mask(b, node.value_mask.as_raw_slice());
+    let values = node.values.iter().map(|&v| value_to_f32(v)).collect_vec();
+    write_values(b, &values, node.value_mask.as_raw_slice(), options)?;
 
-    // Write values of the tiles
-    for i in 0..(node4.value_mask.len() * 64) {
-        let value = &node4.data[i];
-        if let InternalData::Tile(value) = value {
-            b.put_u32_le(value.copy_bytes_to_u32());
-        }
+    for child in node.children.values() {
+        write_shaped_node(b, child, shape, options)?;
     }
-}
 
-fn write_node3_header<T: VdbValueType>(b: &mut BytesMut, node3: &Box<N3<T>>) {
-    for word in node3.value_mask {
-        b.put_u64(word);
-    }
+    Ok(())
 }
 
-fn write_node3_data<T: VdbValueType>(b: &mut BytesMut, node3: &Box<N3<T>>) {
-    for word in node3.value_mask {
-        b.put_u64(word);
+fn write_shaped_leaves<T: VdbValueType>(
+    b: &mut BytesMut,
+    tree: &ShapedTree<T>,
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    for leaf in tree.leaves() {
+        write_mask(b, leaf.value_mask.as_raw_slice());
+        let values = leaf.values.iter().map(|&v| value_to_f32(v)).collect_vec();
+        write_values(b, &values, leaf.value_mask.as_raw_slice(), options)?;
     }
-    // No compression
-    b.put_u8(6);
 
-    for data in &node3.data {
-        match data {
-            // @HACK: figure out the offsets size and shape and purpose =)))
-            LeafData::Tile(_) => b.put_u32_le(0),
-            LeafData::Value(val) => b.put_u32_le(val.copy_bytes_to_u32()),
-        }
-    }
+    Ok(())
 }
 
-fn write_transform(b: &mut BytesMut, mat: [[f64; 4]; 4]) -> fmt::Result {
-    write_len_based_str(b, "Affine Map")?;
-
-    for j in 0..=3 {
-        for i in 0..=2 {
-            b.put_f64(mat[j][i]);
-        }
-        b.put_f64((j == 3) as u8 as f64);
-    }
+fn write_mask(b: &mut BytesMut, mask: &[u64]) {
+    mask.iter().for_each(|&word| b.put_u64_le(word));
+}
 
-    Ok(())
+/// Writes the node metadata byte and the values of a node.
+///
+/// With [`Compression::ACTIVE_MASK`] only the active values are kept, the inactive ones are
+/// all background.
+fn write_values(
+    b: &mut BytesMut,
+    values: &[f32],
+    value_mask: &[u64],
+    options: &WriteOptions,
+) -> Result<(), WriteError> {
+    let values = if options.compression.contains(Compression::ACTIVE_MASK) {
+        b.put_u8(NodeMetaData::NoMaskOrInactiveVals as u8);
+        values
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| value_mask[idx >> 6] & (1 << (idx & 63)) != 0)
+            .map(|(_, &value)| value)
+            .collect_vec()
+    } else {
+        b.put_u8(NodeMetaData::NoMaskAndAllVals as u8);
+        values.to_vec()
+    };
+
+    let (bytes, type_size) = match options.half_float {
+        true => (
+            values
+                .iter()
+                .flat_map(|&v| f16::from_f32(v).to_le_bytes())
+                .collect_vec(),
+            std::mem::size_of::<f16>(),
+        ),
+        false => (
+            values.iter().flat_map(|v| v.to_le_bytes()).collect_vec(),
+            std::mem::size_of::<f32>(),
+        ),
+    };
+
+    write_compressed_data(b, &bytes, type_size, options.compression)
 }
 
-fn write_metadata(b: &mut BytesMut) -> fmt::Result {
-    b.put_u32_le(4);
+/// Compressed data is prefixed with its size, a negative size marks uncompressed data
+fn write_compressed_data(
+    b: &mut BytesMut,
+    bytes: &[u8],
+    type_size: usize,
+    compression: Compression,
+) -> Result<(), WriteError> {
+    if compression.contains(Compression::BLOSC) {
+        match blosc_compress(bytes, type_size) {
+            Some(compressed) => {
+                b.put_i64_le(compressed.len() as i64);
+                b.put_slice(&compressed);
+            }
+            None => {
+                b.put_i64_le(-(bytes.len() as i64));
+                b.put_slice(bytes);
+            }
+        }
+    } else if compression.contains(Compression::ZIP) {
+        if bytes.is_empty() {
+            b.put_i64_le(0);
+            return Ok(());
+        }
 
-    write_meta_string(b, "class", "unknown")?;
-    write_meta_string(b, "file_compression", "none")?;
-    write_meta_bool(b, "is_saved_as_half_float", false)?;
-    write_meta_string(b, "name", "woxel")?;
+        let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::new(COMPRESSION_LEVEL));
+        encoder.write_all(bytes)?;
+        let zipped = encoder.finish()?;
+        b.put_i64_le(zipped.len() as i64);
+        b.put_slice(&zipped);
+    } else {
+        b.put_slice(bytes);
+    }
 
     Ok(())
 }
 
-fn write_meta_string(b: &mut BytesMut, name: &str, string: &str) -> fmt::Result {
-    write_len_based_str(b, name)?;
-    write_len_based_str(b, "string")?;
-    write_len_based_str(b, string)?;
+/// `None` if blosc could not compress `bytes`, they are then stored as is
+fn blosc_compress(bytes: &[u8], type_size: usize) -> Option<Vec<u8>> {
+    if bytes.is_empty() {
+        return None;
+    }
 
-    Ok(())
-}
+    let mut compressed = vec![0u8; bytes.len() + BLOSC_MAX_OVERHEAD];
+    let size = unsafe {
+        blosc_src::blosc_compress_ctx(
+            COMPRESSION_LEVEL as i32,
+            // Byte shuffle
+            1,
+            type_size,
+            bytes.len(),
+            bytes.as_ptr().cast(),
+            compressed.as_mut_ptr().cast(),
+            compressed.len(),
+            b"blosclz\0".as_ptr().cast(),
+            // Automatic block size
+            0,
+            1,
+        )
+    };
+    if size <= 0 {
+        return None;
+    }
 
-fn write_meta_bool(b: &mut BytesMut, name: &str, v: bool) -> fmt::Result {
-    write_len_based_str(b, name)?;
-    write_len_based_str(b, "bool")?;
-    b.put_u32_le(1);
-    b.put_u8(v as u8);
+    compressed.truncate(size as usize);
+    Some(compressed)
+}
 
-    Ok(())
+/// Grid values are `f32`s or their bits
+fn value_to_f32<T: VdbValueType>(value: T) -> f32 {
+    f32::from_bits(value.copy_bytes_to_u32())
 }
 
-fn write_len_based_str(b: &mut BytesMut, s: &str) -> fmt::Result {
+fn write_len_based_str(b: &mut BytesMut, s: &str) {
     b.put_u32_le(s.len() as u32);
-    b.write_str(s)?;
-
-    Ok(())
+    b.put_slice(s.as_bytes());
 }
 
 pub trait CopyBytesToU32 {
@@ -300,9 +645,10 @@ impl CopyBytesToU32 for u128 {
 
 #[cfg(test)]
 mod tests {
-    use std::{fs, io::Write, thread};
+    use std::{io::Cursor, thread};
 
     use super::*;
+    use crate::vdb::VdbReader;
 
     #[test]
     fn test_vdb_write_wrapper() {
@@ -313,29 +659,135 @@ mod tests {
         handler.join().unwrap_or_else(|_| panic!("Test Failed"));
     }
 
-    fn test_vdb_write() {
+    fn test_grid() -> VDB345<f32> {
         let mut vdb = <VDB345<f32>>::new();
-        let points = [[0, 0, 0], [123, 78, 3], [34, 123, 46], [102, 79, 28]];
+        vdb.grid_descriptor.name = "woxel".to_string();
+        // Spread over several N5s, including negative ones, to check the leaf order
+        let points = [
+            [0, 0, 0],
+            [123, 78, 3],
+            [34, 123, 46],
+            [102, 79, 28],
+            [-5, 4000, -1],
+            [9000, -300, 12],
+        ];
         for (i, &point) in points.iter().enumerate() {
-            vdb.set_voxel(point.into(), i as f32);
+            vdb.set_voxel(point.into(), i as f32 + 0.5);
         }
+        vdb
+    }
 
-        let mut b: BytesMut = BytesMut::new();
-        let mat = [
-            [1., 0., 0., 0.],
-            [0., 1., 0., 0.],
-            [0., 0., 1., 0.],
-            [0., 0., 0., 1.],
-        ];
-        let _ = write_vdb(&mut b, &vdb, mat);
+    fn round_trip(vdb: &VDB345<f32>, options: WriteOptions) -> VDB345<f32> {
+        let mut b = BytesMut::new();
+        write_vdb(&mut b, &[vdb], &options).unwrap();
+
+        let mut reader = VdbReader::new(Cursor::new(b.to_vec())).unwrap();
+        let descriptor = &reader.grid_descriptors["woxel"];
+        assert_eq!(descriptor.compression, options.compression);
+        assert_eq!(descriptor.meta_data.is_half_float(), options.half_float);
+        assert_eq!(
+            descriptor.meta_data.0.get("file_voxel_count"),
+            Some(&MetadataValue::I64(6))
+        );
 
-        println!("{:#x}", b);
+        reader.read_vdb345_grid::<f32>("woxel").unwrap()
+    }
 
-        let mut file = fs::File::create("assets/test.vdb").unwrap();
-        let res = file.write_all(&b);
-        match res {
-            Err(_) => assert!(false),
-            Ok(()) => {}
+    fn test_vdb_write() {
+        let vdb = test_grid();
+        let expected = vdb.active_voxels().collect_vec();
+
+        for compression in [
+            Compression::NONE,
+            Compression::ACTIVE_MASK,
+            Compression::ZIP,
+            Compression::ZIP | Compression::ACTIVE_MASK,
+            Compression::BLOSC,
+            Compression::DEFAULT_COMPRESSION,
+        ] {
+            for half_float in [false, true] {
+                let options = WriteOptions {
+                    compression,
+                    half_float,
+                    ..Default::default()
+                };
+                let read = round_trip(&vdb, options);
+
+                // The test values are exact in half precision
+                assert_eq!(read.active_voxels().collect_vec(), expected, "{options:?}");
+                assert_eq!(read.count_nodes(), vdb.count_nodes());
+                assert_eq!(read.transform, vdb.transform);
+            }
         }
     }
+
+    #[test]
+    fn test_vdb_write_is_deterministic() {
+        let builder = thread::Builder::new()
+            .name("set_voxel_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let vdb = test_grid();
+                let encode = |vdb: &VDB345<f32>| {
+                    let mut b = BytesMut::new();
+                    write_vdb(&mut b, &[vdb], &WriteOptions::default()).unwrap();
+                    b
+                };
+
+                assert_eq!(encode(&vdb), encode(&vdb.clone()));
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn test_vdb_write_shapes() {
+        let builder = thread::Builder::new()
+            .name("write_shapes_test".into())
+            .stack_size(80 * 1024 * 1024); // @HACK to increase stack size of this test
+        let handler = builder
+            .spawn(|| {
+                let read = |vdb: &VDB345<f32>, shape: &str| {
+                    let options = WriteOptions {
+                        shape: shape.parse().unwrap(),
+                        ..Default::default()
+                    };
+                    let mut b = BytesMut::new();
+                    write_vdb(&mut b, &[vdb], &options).unwrap();
+
+                    let mut reader = VdbReader::new(Cursor::new(b.to_vec())).unwrap();
+                    let grid_type = &reader.grid_descriptors["woxel"].grid_type;
+                    assert_eq!(grid_type, &format!("Tree_float_{shape}"));
+                    reader.read_vdb345_grid::<f32>("woxel").unwrap()
+                };
+
+                let vdb = test_grid();
+                for shape in ["5_4_3", "6_5_4_3", "4_3_2", "3_3_3_3"] {
+                    let expected = vdb.active_voxels().collect_vec();
+                    assert_eq!(read(&vdb, shape).active_voxels().collect_vec(), expected);
+                }
+
+                // 6-5-4-3 has tiles of the size of a Node4 and of a Node3
+                let mut vdb = test_grid();
+                vdb.set_active_tile([8192, 0, 0].into(), 1);
+                vdb.set_active_tile([-64, 8, 16].into(), 2);
+                let shaped = read(&vdb, "6_5_4_3");
+                assert_eq!(shaped.masks(), vdb.masks());
+                assert_eq!(shaped.count_nodes(), vdb.count_nodes());
+            })
+            .unwrap();
+        handler.join().unwrap_or_else(|_| panic!("Test Failed"));
+    }
+
+    #[test]
+    fn test_invalid_compression() {
+        let vdb = <VDB345<f32>>::new();
+        let options = WriteOptions {
+            compression: Compression::ZIP | Compression::BLOSC,
+            ..Default::default()
+        };
+        let result = write_vdb(&mut BytesMut::new(), &[&vdb], &options);
+        assert!(matches!(result, Err(WriteError::InvalidCompression)));
+    }
 }